@@ -0,0 +1,70 @@
+//! Build script: embeds build metadata for `flakecache version`
+//!
+//! Sets `FLAKECACHE_GIT_COMMIT`, `FLAKECACHE_RUSTC_VERSION`,
+//! `FLAKECACHE_TARGET`, and `FLAKECACHE_FEATURES` for the crate. Anything
+//! that cannot be determined (a source tarball without `.git`, say) is left
+//! unset and reported as unknown.
+
+// Build scripts talk to Cargo on stdout
+#![allow(clippy::print_stdout)]
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    if let Some(commit) = git_commit() {
+        println!("cargo:rustc-env=FLAKECACHE_GIT_COMMIT={commit}");
+    }
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Some(version) = run(&rustc, &["--version"]) {
+        println!("cargo:rustc-env=FLAKECACHE_RUSTC_VERSION={version}");
+    }
+    if let Ok(target) = std::env::var("TARGET") {
+        println!("cargo:rustc-env=FLAKECACHE_TARGET={target}");
+    }
+    println!(
+        "cargo:rustc-env=FLAKECACHE_FEATURES={}",
+        features().join(",")
+    );
+
+    // A missing path would make Cargo rerun the script on every build
+    for path in [".git/HEAD", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Abbreviated commit hash, with `-dirty` for uncommitted changes
+fn git_commit() -> Option<String> {
+    let commit = run("git", &["rev-parse", "--short=12", "HEAD"])?;
+    let dirty = run("git", &["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    Some(if dirty {
+        format!("{commit}-dirty")
+    } else {
+        commit
+    })
+}
+
+/// Enabled Cargo features, from the `CARGO_FEATURE_*` variables
+fn features() -> Vec<String> {
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            name.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    features
+}
+
+/// Trimmed stdout of a successful command
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,213 @@
+//! On-disk cache of store path closures
+//!
+//! `nix-store --query --requisites` walks the whole reference graph, which
+//! is slow for large closures and is repeated with the same roots on every
+//! CI run. Results are kept as CBOR under `<cache dir>/closures`, keyed on
+//! the closure kind and the sorted root paths.
+
+use crate::client::cbor::{decode, encode};
+use crate::config::Config;
+use crate::error::Result;
+use crate::nix::{ClosureKind, Nix};
+use crate::utils::output;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Nix database of the default local store; it changes whenever a path is
+/// registered
+const NIX_DB: &str = "/nix/var/nix/db/db.sqlite";
+
+/// One cached closure
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ClosureEntry {
+    /// Sorted roots the closure was computed for
+    roots: Vec<String>,
+    /// Closure kind and the `nix-store` arguments that computed it
+    kind: Vec<String>,
+    /// Modification time of the Nix database when computed, for closures
+    /// that can grow as paths are built
+    db_stamp: Option<u64>,
+    /// The closure, in `nix-store` order
+    paths: Vec<String>,
+}
+
+/// Directory of cached closures
+#[derive(Debug, Clone)]
+pub struct ClosureCache {
+    dir: PathBuf,
+}
+
+impl ClosureCache {
+    /// Cache under `<cache dir>/closures` (see [`Config::cache_dir`])
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Internal`](crate::CliError::Internal) if no cache
+    /// directory can be determined.
+    pub fn open() -> Result<Self> {
+        Ok(Self::at(Config::cache_dir()?.join("closures")))
+    }
+
+    /// Cache in `dir`
+    #[must_use]
+    pub const fn at(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Closure of `roots`, reused from the cache when still valid
+    ///
+    /// A runtime closure never changes once its roots are valid, so it is
+    /// always reused. A closure with derivation outputs grows as outputs
+    /// are built, so it is only reused while the Nix database is unchanged.
+    /// Closures in a non-default store are never cached. Cache read and
+    /// write failures only fall back to querying Nix.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`](crate::CliError::StoreError) if the
+    /// query fails.
+    pub fn query(&self, nix: &Nix, roots: &[String], kind: ClosureKind) -> Result<Vec<String>> {
+        if nix.store().is_some() || roots.is_empty() {
+            return nix.query_closure(roots, kind);
+        }
+        let mut sorted = roots.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        let db_stamp = match kind {
+            ClosureKind::Runtime => None,
+            ClosureKind::WithOutputs | ClosureKind::Build => match db_stamp(Path::new(NIX_DB)) {
+                Some(stamp) => Some(stamp),
+                None => return nix.query_closure(roots, kind),
+            },
+        };
+        let file = self.dir.join(format!("{}.cbor", entry_key(&sorted, kind)));
+
+        if let Some(entry) = read_entry(&file) {
+            if entry.roots == sorted && entry.kind == kind_args(kind) && entry.db_stamp == db_stamp
+            {
+                output::debug(format_args!(
+                    "closure of {} roots reused from {}",
+                    sorted.len(),
+                    file.display()
+                ));
+                return Ok(entry.paths);
+            }
+        }
+
+        let paths = nix.query_closure(roots, kind)?;
+        let entry = ClosureEntry {
+            roots: sorted,
+            kind: kind_args(kind),
+            db_stamp,
+            paths,
+        };
+        if let Err(e) = write_entry(&file, &entry) {
+            output::debug(format_args!(
+                "could not cache closure in {}: {e}",
+                file.display()
+            ));
+        }
+        Ok(entry.paths)
+    }
+}
+
+/// Name of the closure kind followed by its `nix-store` arguments
+///
+/// Build closures query with the same arguments as closures with outputs,
+/// but from different roots, so the name keeps their entries apart.
+fn kind_args(kind: ClosureKind) -> Vec<String> {
+    std::iter::once(kind.name())
+        .chain(kind.query_args().iter().copied())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// File name for the closure of sorted `roots`
+fn entry_key(roots: &[String], kind: ClosureKind) -> String {
+    let mut hasher = Sha256::new();
+    for arg in kind_args(kind) {
+        hasher.update(arg.as_bytes());
+        hasher.update([0]);
+    }
+    for root in roots {
+        hasher.update(root.as_bytes());
+        hasher.update([b'\n']);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Modification time of `db` in nanoseconds, if it can be read
+fn db_stamp(db: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(db).ok()?.modified().ok()?;
+    u64::try_from(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos()).ok()
+}
+
+/// A cached entry; missing or corrupt files are a miss
+fn read_entry(file: &Path) -> Option<ClosureEntry> {
+    decode(&std::fs::read(file).ok()?).ok()
+}
+
+/// Write `entry` through a temporary file, so readers never see a partial
+/// entry
+fn write_entry(file: &Path, entry: &ClosureEntry) -> Result<()> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = file.with_extension(format!("cbor.tmp-{}", std::process::id()));
+    let written = std::fs::write(&tmp, encode(entry)?).and_then(|()| std::fs::rename(&tmp, file));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    Ok(written?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_key_is_per_kind_and_roots() {
+        let roots = vec!["/nix/store/aaa-hello".to_string()];
+        let other = vec!["/nix/store/bbb-hello".to_string()];
+        assert_eq!(
+            entry_key(&roots, ClosureKind::Runtime),
+            entry_key(&roots, ClosureKind::Runtime)
+        );
+        assert_ne!(
+            entry_key(&roots, ClosureKind::Runtime),
+            entry_key(&roots, ClosureKind::WithOutputs)
+        );
+        assert_ne!(
+            entry_key(&roots, ClosureKind::WithOutputs),
+            entry_key(&roots, ClosureKind::Build)
+        );
+        assert_ne!(
+            entry_key(&roots, ClosureKind::Runtime),
+            entry_key(&other, ClosureKind::Runtime)
+        );
+    }
+
+    #[test]
+    fn test_entry_round_trip() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flakecache-closure-{}", std::process::id()));
+        let file = dir.join("entry.cbor");
+        let entry = ClosureEntry {
+            roots: vec!["/nix/store/aaa-hello".to_string()],
+            kind: kind_args(ClosureKind::Runtime),
+            db_stamp: None,
+            paths: vec![
+                "/nix/store/aaa-hello".to_string(),
+                "/nix/store/ccc-glibc".to_string(),
+            ],
+        };
+        write_entry(&file, &entry)?;
+        assert_eq!(read_entry(&file), Some(entry));
+
+        std::fs::write(&file, b"not cbor")?;
+        assert_eq!(read_entry(&file), None);
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
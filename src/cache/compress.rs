@@ -0,0 +1,699 @@
+//! NAR compression
+//!
+//! Streams `nix-store --dump` through an external compressor (`xz`, `zstd`,
+//! `gzip`) into a temporary file, hashing both the uncompressed NAR and the
+//! exact compressed byte stream in a single pass. Compressed files are read
+//! back through the matching decompressor.
+//!
+//! The compressors are separate programs, so a push first checks which of
+//! them can be run ([`available_compressors`]) and picks one with
+//! [`choose_compression`] rather than failing on the first spawn.
+
+use crate::error::{CliError, Result};
+use crate::nix::store::sha256_nix;
+use crate::nix::Nix;
+use crate::utils::streaming::HashingWriter;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::str::FromStr;
+
+/// NAR compression format, as written to the NARInfo `Compression` field
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// `xz` (the Nix default)
+    #[default]
+    Xz,
+    /// `zstd`
+    Zstd,
+    /// `gzip`, for machines without xz or zstd
+    Gzip,
+    /// Uncompressed NAR
+    None,
+}
+
+impl Compression {
+    /// Name used in NARInfo files and on the command line
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+            Self::None => "none",
+        }
+    }
+
+    /// File extension appended to `.nar`
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Xz => ".xz",
+            Self::Zstd => ".zst",
+            Self::Gzip => ".gz",
+            Self::None => "",
+        }
+    }
+
+    /// Program that compresses and decompresses this format, if any
+    #[must_use]
+    pub const fn program(self) -> Option<&'static str> {
+        match self {
+            Self::Xz => Some("xz"),
+            Self::Zstd => Some("zstd"),
+            Self::Gzip => Some("gzip"),
+            Self::None => None,
+        }
+    }
+
+    /// Whether the program for this format can be run (`<program>
+    /// --version`); always `true` for [`Self::None`]
+    #[must_use]
+    pub fn is_available(self) -> bool {
+        self.program().is_none_or(|program| {
+            Command::new(program)
+                .arg("--version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+    }
+
+    /// Compressor reading a NAR on stdin and writing to stdout
+    ///
+    /// `None` means the NAR is stored as-is. gzip has no worker threads.
+    fn command(self, threads: usize, level: CompressLevel) -> Option<Command> {
+        let mut cmd = Command::new(self.program()?);
+        let _ = cmd.args(["-c", "-q"]);
+        if self != Self::Gzip {
+            let _ = cmd.arg(format!("-T{threads}"));
+        }
+        let _ = cmd.args(level.args(self));
+        Some(cmd)
+    }
+
+    /// Detect the format of a file from its first bytes
+    ///
+    /// An uncompressed NAR is recognised by its `nix-archive-1` header.
+    #[must_use]
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+        const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+        const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+        const NAR_MAGIC: &[u8] = b"\x0d\0\0\0\0\0\0\0nix-archive-1";
+
+        if header.starts_with(XZ_MAGIC) {
+            Some(Self::Xz)
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else if header.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if header.starts_with(NAR_MAGIC) {
+            Some(Self::None)
+        } else {
+            None
+        }
+    }
+
+    /// Decompressor reading compressed data on stdin and writing the NAR to
+    /// stdout
+    fn decompress_command(self) -> Option<Command> {
+        let mut cmd = Command::new(self.program()?);
+        let _ = cmd.args(["-d", "-c", "-q"]);
+        Some(cmd)
+    }
+
+    /// Reason a spawn of this format's program failed, with what to do
+    /// about a missing program
+    fn spawn_error(self, e: &std::io::Error) -> String {
+        match self.program() {
+            Some(program) if e.kind() == std::io::ErrorKind::NotFound => {
+                format!("{program} not found (install {program})")
+            }
+            _ => format!("failed to spawn: {e}"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "xz" => Ok(Self::Xz),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "none" => Ok(Self::None),
+            other => Err(CliError::InvalidArgument(format!(
+                "unsupported compression '{other}' (expected xz, zstd, gzip or none)"
+            ))),
+        }
+    }
+}
+
+/// Compressors in the order a push falls back to them
+pub const COMPRESSORS: [Compression; 3] = [Compression::Xz, Compression::Zstd, Compression::Gzip];
+
+/// Compressors whose program can be run on this machine
+#[must_use]
+pub fn available_compressors() -> Vec<Compression> {
+    COMPRESSORS
+        .into_iter()
+        .filter(|compression| compression.is_available())
+        .collect()
+}
+
+/// Compression for a push, given the compressors in `available`
+///
+/// A `requested` format (`--compression`) is used only if its program is
+/// available. Without one the default is used, or else the first available
+/// compressor in [`COMPRESSORS`] order.
+///
+/// # Errors
+///
+/// Returns [`CliError::CacheError`] naming what to install or which
+/// `--compression` to use instead.
+pub fn choose_compression(
+    requested: Option<Compression>,
+    available: &[Compression],
+) -> Result<Compression> {
+    let wanted = requested.unwrap_or_default();
+    if wanted == Compression::None || available.contains(&wanted) {
+        return Ok(wanted);
+    }
+    let program = wanted.program().unwrap_or_default();
+    match available.first() {
+        Some(&fallback) if requested.is_none() => Ok(fallback),
+        Some(fallback) => Err(CliError::CacheError(format!(
+            "{program} not found: install {program} or use --compression {fallback}"
+        ))),
+        None => Err(CliError::CacheError(format!(
+            "no compressor found (xz, zstd or gzip): install {program} or use --compression none"
+        ))),
+    }
+}
+
+/// zstd level for each `--compress-level` from 0 to 9
+const ZSTD_LEVELS: [u8; 10] = [1, 2, 3, 5, 7, 9, 12, 15, 17, 19];
+
+/// Speed/ratio trade-off for the compressor (`--compress-level`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressLevel {
+    /// Fastest: xz `-0`, zstd `-1`, gzip `-1`
+    Fast,
+    /// The compressor's own default (xz `-6`, zstd `-3`, gzip `-6`)
+    ///
+    /// Keeps NARs byte-identical to earlier pushes, which
+    /// `--narinfo-only` relies on.
+    #[default]
+    Default,
+    /// Smallest output: xz `-9`, zstd `--ultra -22`, gzip `-9`
+    Max,
+    /// `0` (fastest) to `9` (smallest): xz `-0`..`-9`, zstd `-1`..`-19`,
+    /// gzip `-1`..`-9`
+    Level(u8),
+}
+
+impl CompressLevel {
+    /// Compressor arguments selecting this level for `compression`
+    #[must_use]
+    pub fn args(self, compression: Compression) -> Vec<String> {
+        match (compression, self) {
+            (Compression::None, _) | (_, Self::Default) => Vec::new(),
+            (Compression::Xz, Self::Fast) => vec!["-0".to_string()],
+            (Compression::Xz | Compression::Gzip, Self::Max) => vec!["-9".to_string()],
+            (Compression::Xz, Self::Level(level)) => vec![format!("-{level}")],
+            (Compression::Zstd | Compression::Gzip, Self::Fast) => vec!["-1".to_string()],
+            (Compression::Zstd, Self::Max) => vec!["--ultra".to_string(), "-22".to_string()],
+            (Compression::Zstd, Self::Level(level)) => {
+                let zstd = ZSTD_LEVELS[usize::from(level.min(9))];
+                vec![format!("-{zstd}")]
+            }
+            (Compression::Gzip, Self::Level(level)) => vec![format!("-{}", level.clamp(1, 9))],
+        }
+    }
+}
+
+impl fmt::Display for CompressLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fast => f.write_str("fast"),
+            Self::Default => f.write_str("default"),
+            Self::Max => f.write_str("max"),
+            Self::Level(level) => write!(f, "{level}"),
+        }
+    }
+}
+
+impl FromStr for CompressLevel {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fast" => Ok(Self::Fast),
+            "default" => Ok(Self::Default),
+            "max" => Ok(Self::Max),
+            other => other
+                .parse::<u8>()
+                .ok()
+                .filter(|level| *level <= 9)
+                .map(Self::Level)
+                .ok_or_else(|| {
+                    CliError::InvalidArgument(format!(
+                        "invalid compression level '{other}' (expected 0-9, fast, default or max)"
+                    ))
+                }),
+        }
+    }
+}
+
+/// How NARs are compressed before upload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressOptions {
+    /// Compression format
+    pub compression: Compression,
+    /// Compressor worker threads (`-T<n>`)
+    pub threads: usize,
+    /// Speed/ratio trade-off
+    pub level: CompressLevel,
+}
+
+impl CompressOptions {
+    /// Default format with `threads` workers (`None` = one per CPU)
+    #[must_use]
+    pub fn with_threads(threads: Option<usize>) -> Self {
+        Self {
+            threads: threads.unwrap_or_else(num_cpus::get).max(1),
+            ..Self::default()
+        }
+    }
+
+    /// These options at compression `level`
+    #[must_use]
+    pub const fn with_level(self, level: CompressLevel) -> Self {
+        Self { level, ..self }
+    }
+
+    /// These options with format `compression`
+    #[must_use]
+    pub const fn with_compression(self, compression: Compression) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::default(),
+            threads: num_cpus::get(),
+            level: CompressLevel::default(),
+        }
+    }
+}
+
+/// A compressed NAR on disk together with its NARInfo hashes and sizes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedNar {
+    /// Temporary file holding the compressed NAR
+    pub path: PathBuf,
+    /// Compression the file was written with
+    pub compression: Compression,
+    /// Hash of the compressed file (`sha256:<base32>`)
+    pub file_hash: String,
+    /// Size of the compressed file in bytes
+    pub file_size: u64,
+    /// Hash of the uncompressed NAR (`sha256:<base32>`)
+    pub nar_hash: String,
+    /// Size of the uncompressed NAR in bytes
+    pub nar_size: u64,
+}
+
+/// Dump, compress, and hash a store path into a file under `temp_dir`
+///
+/// The NAR is hashed as it is fed to the compressor and the compressed
+/// output is hashed as it is written to disk, so `file_hash` always
+/// describes the exact bytes that will be uploaded.
+///
+/// # Errors
+///
+/// Returns [`CliError::StoreError`] if the dump fails, a
+/// [`CliError::CacheError`] if the compressor fails, or a
+/// [`CliError::FileError`] if the temporary file cannot be written. On
+/// error the partial file is removed.
+pub fn compress_and_hash_nar(
+    nix: &Nix,
+    store_path: &str,
+    options: &CompressOptions,
+    temp_dir: &Path,
+) -> Result<CompressedNar> {
+    let dest = temp_dir.join(format!(
+        "flakecache-{}.nar{}",
+        uuid::Uuid::now_v7(),
+        options.compression.extension()
+    ));
+
+    let result = write_compressed(nix, store_path, options, &dest);
+    if result.is_err() {
+        let _ = fs::remove_file(&dest);
+    }
+    let ((nar_digest, nar_size), (file_digest, file_size)) = result?;
+
+    Ok(CompressedNar {
+        path: dest,
+        compression: options.compression,
+        file_hash: sha256_nix(&file_digest),
+        file_size,
+        nar_hash: sha256_nix(&nar_digest),
+        nar_size,
+    })
+}
+
+/// SHA-256 digest and byte count of a stream
+type Hashed = ([u8; 32], u64);
+
+/// Write the compressed NAR to `dest`, returning the NAR and file digests
+fn write_compressed(
+    nix: &Nix,
+    store_path: &str,
+    options: &CompressOptions,
+    dest: &Path,
+) -> Result<(Hashed, Hashed)> {
+    let file_error = |e: std::io::Error| CliError::FileError {
+        path: dest.to_path_buf(),
+        reason: e.to_string(),
+    };
+    let file = File::create(dest).map_err(file_error)?;
+    let mut out = HashingWriter::new(BufWriter::new(file));
+
+    let nar = match options.compression.command(options.threads, options.level) {
+        None => {
+            let mut nar = HashingWriter::new(&mut out);
+            let _ = nix.dump_to(store_path, &mut nar)?;
+            let (_, digest, size) = nar.finish();
+            (digest, size)
+        }
+        Some(cmd) => pipe_through(cmd, nix, store_path, &mut out, options.compression)?,
+    };
+
+    out.flush().map_err(file_error)?;
+    let (_, file_digest, file_size) = out.finish();
+    Ok((nar, (file_digest, file_size)))
+}
+
+/// Feed the NAR into the compressor's stdin from a separate thread while the
+/// compressed output is copied into `out`
+fn pipe_through<W: Write>(
+    mut cmd: Command,
+    nix: &Nix,
+    store_path: &str,
+    out: &mut W,
+    compression: Compression,
+) -> Result<Hashed> {
+    let compressor_error = |reason: String| {
+        CliError::CacheError(format!("{compression} compression failed: {reason}"))
+    };
+
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| compressor_error(compression.spawn_error(&e)))?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let (Some(stdin), Some(mut stdout)) = (stdin, stdout) else {
+        return Err(compressor_error("missing stdio pipes".to_string()));
+    };
+
+    let nix = nix.clone();
+    let path = store_path.to_string();
+    let dumper = std::thread::spawn(move || -> Result<Hashed> {
+        let mut nar = HashingWriter::new(stdin);
+        let _ = nix.dump_to(&path, &mut nar)?;
+        // Dropping stdin here signals end of input to the compressor
+        let (_, digest, size) = nar.finish();
+        Ok((digest, size))
+    });
+
+    let copied = std::io::copy(&mut stdout, out);
+    let dump_result = dumper
+        .join()
+        .map_err(|_| CliError::Internal("NAR dump thread panicked".to_string()))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|e| compressor_error(e.to_string()))?;
+
+    // A dump failure usually makes the compressor fail too; report the cause
+    let nar = dump_result?;
+    if !output.status.success() {
+        return Err(compressor_error(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let _ = copied.map_err(|e| compressor_error(e.to_string()))?;
+    Ok(nar)
+}
+
+/// An uncompressed NAR read from a (possibly compressed) file
+#[derive(Debug)]
+pub struct DecompressedNar {
+    compression: Compression,
+    source: NarSource,
+}
+
+#[derive(Debug)]
+enum NarSource {
+    File(File),
+    Decompressor { child: Child, stdout: ChildStdout },
+}
+
+impl DecompressedNar {
+    /// Open `path`, detecting its compression from the file contents
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if the file cannot be read,
+    /// [`CliError::InvalidArgument`] if it is neither a NAR nor a supported
+    /// compressed NAR, or [`CliError::CacheError`] if the decompressor cannot
+    /// be started.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file_error = |e: std::io::Error| CliError::FileError {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        };
+        let mut file = File::open(path).map_err(file_error)?;
+        // Long enough for every magic; the NAR header is the longest
+        let mut header = Vec::with_capacity(21);
+        let _ = Read::by_ref(&mut file)
+            .take(21)
+            .read_to_end(&mut header)
+            .map_err(file_error)?;
+        file.rewind().map_err(file_error)?;
+
+        let compression = Compression::detect(&header).ok_or_else(|| {
+            CliError::InvalidArgument(format!(
+                "{}: not a NAR, or compressed with an unsupported format (expected xz, zstd, gzip or none)",
+                path.display()
+            ))
+        })?;
+        let Some(mut cmd) = compression.decompress_command() else {
+            return Ok(Self {
+                compression,
+                source: NarSource::File(file),
+            });
+        };
+
+        let mut child = cmd
+            .stdin(Stdio::from(file))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                CliError::CacheError(format!(
+                    "{compression} decompression failed: {}",
+                    compression.spawn_error(&e)
+                ))
+            })?;
+        let stdout = child.stdout.take().ok_or_else(|| {
+            CliError::CacheError(format!("{compression} decompression: missing stdout pipe"))
+        })?;
+        Ok(Self {
+            compression,
+            source: NarSource::Decompressor { child, stdout },
+        })
+    }
+
+    /// Detected compression of the file
+    #[must_use]
+    pub const fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Wait for the decompressor and report whether it succeeded
+    ///
+    /// Call this after reading the whole NAR; a corrupt file may only show
+    /// up as a decompressor error at the end.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::CacheError`] if decompression failed.
+    pub fn finish(self) -> Result<()> {
+        let NarSource::Decompressor { child, stdout } = self.source else {
+            return Ok(());
+        };
+        drop(stdout);
+        let compression = self.compression;
+        let output = child.wait_with_output().map_err(|e| {
+            CliError::CacheError(format!("{compression} decompression failed: {e}"))
+        })?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(CliError::CacheError(format!(
+                "{compression} decompression failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+}
+
+impl Read for DecompressedNar {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.source {
+            NarSource::File(file) => file.read(buf),
+            NarSource::Decompressor { stdout, .. } => stdout.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_names_round_trip() -> Result<()> {
+        for compression in [
+            Compression::Xz,
+            Compression::Zstd,
+            Compression::Gzip,
+            Compression::None,
+        ] {
+            assert_eq!(compression.as_str().parse::<Compression>()?, compression);
+        }
+        assert!("lz4".parse::<Compression>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_threads_passed_to_compressor() {
+        let cmd = Compression::Zstd.command(8, CompressLevel::Default);
+        let args: Vec<_> = cmd
+            .as_ref()
+            .map(|c| c.get_args().collect())
+            .unwrap_or_default();
+        assert_eq!(args, ["-c", "-q", "-T8"]);
+        assert!(Compression::None.command(8, CompressLevel::Max).is_none());
+
+        let cmd = Compression::Gzip.command(8, CompressLevel::Max);
+        let args: Vec<_> = cmd
+            .as_ref()
+            .map(|c| c.get_args().collect())
+            .unwrap_or_default();
+        assert_eq!(args, ["-c", "-q", "-9"]);
+    }
+
+    #[test]
+    fn test_choose_available_compression() -> Result<()> {
+        let all = COMPRESSORS;
+        let gzip_only = [Compression::Gzip];
+        assert_eq!(choose_compression(None, &all)?, Compression::Xz);
+        assert_eq!(choose_compression(None, &gzip_only)?, Compression::Gzip);
+        assert_eq!(
+            choose_compression(Some(Compression::Gzip), &gzip_only)?,
+            Compression::Gzip
+        );
+        assert_eq!(
+            choose_compression(Some(Compression::None), &[])?,
+            Compression::None
+        );
+
+        let err = choose_compression(Some(Compression::Xz), &gzip_only)
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(
+            err.contains("install xz or use --compression gzip"),
+            "{err}"
+        );
+        let err = choose_compression(None, &[])
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(err.contains("--compression none"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_level_args() -> Result<()> {
+        assert_eq!(CompressLevel::Fast.args(Compression::Xz), ["-0"]);
+        assert_eq!(
+            "max".parse::<CompressLevel>()?.args(Compression::Zstd),
+            ["--ultra", "-22"]
+        );
+        assert_eq!(
+            "9".parse::<CompressLevel>()?.args(Compression::Zstd),
+            ["-19"]
+        );
+        assert_eq!("2".parse::<CompressLevel>()?.args(Compression::Xz), ["-2"]);
+        assert!(CompressLevel::Default.args(Compression::Xz).is_empty());
+        assert!(CompressLevel::Max.args(Compression::None).is_empty());
+        for bad in ["10", "-1", "best"] {
+            assert!(bad.parse::<CompressLevel>().is_err(), "{bad}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_threads_default_to_cpu_count() {
+        assert_eq!(CompressOptions::with_threads(None).threads, num_cpus::get());
+        assert_eq!(CompressOptions::with_threads(Some(0)).threads, 1);
+        assert_eq!(CompressOptions::with_threads(Some(4)).threads, 4);
+    }
+
+    #[test]
+    fn test_detect_compression_from_magic() {
+        assert_eq!(
+            Compression::detect(&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00]),
+            Some(Compression::Xz)
+        );
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x24]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::detect(b"\x0d\0\0\0\0\0\0\0nix-archive-1\0\0\0"),
+            Some(Compression::None)
+        );
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 0x08, 0x00]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(Compression::detect(b"BZh91AY&SY"), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! On-disk cache of compressed NARs (`--reuse-compressed`)
+//!
+//! Warming overlapping closures compresses the same store paths again on
+//! every run. With `--reuse-compressed` each compressed NAR is kept under
+//! `<cache dir>/compressed`, keyed on its NAR hash, format, and level, so a
+//! later push links the kept file instead of running the compressor. The
+//! directory is capped in size; the least recently used entries are
+//! evicted first.
+
+use super::compress::{CompressLevel, CompressOptions, CompressedNar, Compression};
+use crate::client::cbor::{decode, encode};
+use crate::config::Config;
+use crate::error::Result;
+use crate::utils::output;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default size cap for `--reuse-compressed` (10 GiB)
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+/// Metadata kept next to each compressed NAR
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    nar_hash: String,
+    nar_size: u64,
+    file_hash: String,
+    file_size: u64,
+    compression: String,
+    level: String,
+}
+
+/// Directory of reusable compressed NARs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl CompressedCache {
+    /// Cache under `<cache dir>/compressed` (see [`Config::cache_dir`]),
+    /// holding at most `max_bytes` of compressed NARs
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Internal`](crate::CliError::Internal) if no cache
+    /// directory can be determined.
+    pub fn open(max_bytes: u64) -> Result<Self> {
+        Ok(Self::at(Config::cache_dir()?.join("compressed"), max_bytes))
+    }
+
+    /// Cache in `dir`
+    #[must_use]
+    pub const fn at(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    /// A kept copy of the NAR with `nar_hash` compressed with `options`,
+    /// linked (or copied) into `temp_dir`
+    ///
+    /// An entry whose file no longer has the recorded size is a miss. Read
+    /// failures are a miss too; the caller compresses as usual.
+    #[must_use]
+    pub fn lookup(
+        &self,
+        nar_hash: &str,
+        options: &CompressOptions,
+        temp_dir: &Path,
+    ) -> Option<CompressedNar> {
+        let key = entry_key(nar_hash, options.compression, options.level);
+        let entry: Entry = decode(&fs::read(self.entry_file(&key)).ok()?).ok()?;
+        let blob = self.blob_file(&key);
+        if entry.nar_hash != nar_hash || fs::metadata(&blob).ok()?.len() != entry.file_size {
+            return None;
+        }
+
+        let dest = temp_dir.join(format!(
+            "flakecache-{}.nar{}",
+            uuid::Uuid::now_v7(),
+            options.compression.extension()
+        ));
+        link_or_copy(&blob, &dest).ok()?;
+        // The modification time orders entries for eviction
+        if let Err(e) = File::options()
+            .write(true)
+            .open(&blob)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            output::debug(format_args!("could not touch {}: {e}", blob.display()));
+        }
+        output::debug(format_args!(
+            "reused compressed NAR {} for {nar_hash}",
+            blob.display()
+        ));
+        Some(CompressedNar {
+            path: dest,
+            compression: options.compression,
+            file_hash: entry.file_hash,
+            file_size: entry.file_size,
+            nar_hash: entry.nar_hash,
+            nar_size: entry.nar_size,
+        })
+    }
+
+    /// Keep a copy of `nar`, compressed at `level`, then evict the least
+    /// recently used entries beyond the size cap
+    ///
+    /// Failures are only logged; the push goes on without the copy.
+    pub fn store(&self, nar: &CompressedNar, level: CompressLevel) {
+        if nar.file_size > self.max_bytes {
+            return;
+        }
+        if let Err(e) = self.write(nar, level) {
+            output::debug(format_args!(
+                "could not keep compressed NAR in {}: {e}",
+                self.dir.display()
+            ));
+        }
+        if let Err(e) = self.evict() {
+            output::debug(format_args!(
+                "could not evict from {}: {e}",
+                self.dir.display()
+            ));
+        }
+    }
+
+    /// Write the blob, then its entry, each through a temporary file, so an
+    /// entry always has its complete blob
+    fn write(&self, nar: &CompressedNar, level: CompressLevel) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let key = entry_key(&nar.nar_hash, nar.compression, level);
+        let entry = Entry {
+            nar_hash: nar.nar_hash.clone(),
+            nar_size: nar.nar_size,
+            file_hash: nar.file_hash.clone(),
+            file_size: nar.file_size,
+            compression: nar.compression.to_string(),
+            level: level.to_string(),
+        };
+        let suffix = format!("tmp-{}", std::process::id());
+
+        let blob = self.blob_file(&key);
+        let tmp_blob = blob.with_extension(format!("blob.{suffix}"));
+        let written =
+            link_or_copy(&nar.path, &tmp_blob).and_then(|()| fs::rename(&tmp_blob, &blob));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp_blob);
+        }
+        written?;
+
+        let file = self.entry_file(&key);
+        let tmp_entry = file.with_extension(format!("cbor.{suffix}"));
+        let written =
+            fs::write(&tmp_entry, encode(&entry)?).and_then(|()| fs::rename(&tmp_entry, &file));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp_entry);
+        }
+        Ok(written?)
+    }
+
+    /// Remove the least recently used entries until the blobs fit the cap
+    fn evict(&self) -> Result<()> {
+        let mut blobs: Vec<(SystemTime, u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "blob") {
+                let metadata = fs::metadata(&path)?;
+                blobs.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+        let mut total: u64 = blobs.iter().map(|(_, size, _)| size).sum();
+        blobs.sort();
+        for (_, size, blob) in blobs {
+            if total <= self.max_bytes {
+                break;
+            }
+            let _ = fs::remove_file(blob.with_extension("cbor"));
+            fs::remove_file(&blob)?;
+            total -= size;
+        }
+        Ok(())
+    }
+
+    fn entry_file(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.cbor"))
+    }
+
+    fn blob_file(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.blob"))
+    }
+}
+
+/// File name stem for a NAR compressed with `compression` at `level`
+fn entry_key(nar_hash: &str, compression: Compression, level: CompressLevel) -> String {
+    let mut hasher = Sha256::new();
+    for part in [nar_hash, &compression.to_string(), &level.to_string()] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Hard-link `from` to `to`, copying when they are on different file
+/// systems
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nar(path: PathBuf, file_size: u64) -> CompressedNar {
+        CompressedNar {
+            path,
+            compression: Compression::default(),
+            file_hash: "sha256:file".to_string(),
+            file_size,
+            nar_hash: "sha256:nar".to_string(),
+            nar_size: 120,
+        }
+    }
+
+    #[test]
+    fn test_entry_key_is_per_format_and_level() {
+        let key = |level| entry_key("sha256:nar", Compression::default(), level);
+        assert_eq!(key(CompressLevel::Default), key(CompressLevel::Default));
+        assert_ne!(key(CompressLevel::Default), key(CompressLevel::Max));
+    }
+
+    #[test]
+    fn test_store_lookup_and_evict() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("flakecache-compressed-{}", std::process::id()));
+        let temp = dir.join("tmp");
+        fs::create_dir_all(&temp)?;
+        let original = temp.join("original.nar.xz");
+        fs::write(&original, b"compressed")?;
+        let options = CompressOptions::default();
+
+        let cache = CompressedCache::at(dir.join("store"), 64);
+        assert_eq!(cache.lookup("sha256:nar", &options, &temp), None);
+        cache.store(&nar(original, 10), options.level);
+
+        let reused = cache.lookup("sha256:nar", &options, &temp);
+        assert_eq!(reused.as_ref().map(|nar| nar.file_size), Some(10));
+        if let Some(reused) = reused {
+            assert_eq!(fs::read(&reused.path)?, b"compressed");
+        }
+        assert_eq!(
+            cache.lookup(
+                "sha256:nar",
+                &options.with_level(CompressLevel::Fast),
+                &temp
+            ),
+            None
+        );
+
+        // Over the cap, the entry is evicted
+        CompressedCache::at(dir.join("store"), 4).evict()?;
+        assert_eq!(cache.lookup("sha256:nar", &options, &temp), None);
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
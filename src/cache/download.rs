@@ -0,0 +1,245 @@
+//! Offline downloads (`flakecache download`)
+//!
+//! Saves a store path's compressed NAR and its NARInfo to a directory laid
+//! out like a `file://` binary cache: `<hash>.narinfo` next to the NAR at
+//! the NARInfo's own `URL` (`nar/<file hash>.nar.<ext>`). The directory can
+//! be carried to a machine without network access and imported there.
+
+use super::transfer::{fetch_narinfo_by_hash_cached, nar_hash_matches};
+use crate::client::cbor::CborClient;
+use crate::client::layout::narinfo_url_base;
+use crate::error::{CliError, Result};
+use crate::nix::store::{parse_store_hash, parse_store_path, sha256_nix, store_path_hash};
+use crate::nix::NarInfo;
+use crate::utils::streaming::HashingWriter;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Which store path to download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadTarget {
+    /// The path with this hash part (`--hash`)
+    Hash(String),
+    /// This store path (`--store-path`)
+    StorePath(String),
+}
+
+impl DownloadTarget {
+    /// Validated hash part of the target
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidStorePath`] for a malformed hash or path.
+    pub fn hash(&self) -> Result<&str> {
+        match self {
+            Self::Hash(hash) => parse_store_hash(hash),
+            Self::StorePath(path) => parse_store_path(path),
+        }
+    }
+
+    /// Whether `info` describes this target
+    fn matches(&self, info: &NarInfo) -> bool {
+        match self {
+            Self::Hash(hash) => store_path_hash(&info.store_path) == hash,
+            Self::StorePath(path) => info.store_path == *path,
+        }
+    }
+}
+
+/// A NAR and NARInfo saved by [`download_pair`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadedPair {
+    /// The downloaded NARInfo
+    pub narinfo: NarInfo,
+    /// Where the NARInfo was written (`<dir>/<hash>.narinfo`)
+    pub narinfo_file: PathBuf,
+    /// Where the compressed NAR was written (`<dir>/<URL>`)
+    pub nar_file: PathBuf,
+}
+
+/// Relative file a NARInfo `URL` names, refusing anything that could
+/// escape the output directory
+///
+/// # Errors
+///
+/// Returns [`CliError::CacheError`] unless the URL is `nar/<file>`.
+pub fn nar_file_name(info: &NarInfo) -> Result<&str> {
+    info.url
+        .strip_prefix("nar/")
+        .filter(|file| !file.is_empty() && !file.contains(['/', '\\']) && !file.starts_with('.'))
+        .map(|_| info.url.as_str())
+        .ok_or_else(|| {
+            CliError::CacheError(format!(
+                "NARInfo for {} has an unexpected URL '{}'",
+                info.store_path, info.url
+            ))
+        })
+}
+
+/// Download `target` from `cache` into `output_dir`
+///
+/// The NAR is streamed to disk and checked against the NARInfo `FileSize`
+/// and `FileHash` before the NARInfo is written, so a NARInfo in `output_dir` always has
+/// its NAR next to it.
+///
+/// # Errors
+///
+/// Returns [`CliError::StorePathNotFound`] if the cache does not have the
+/// path, [`CliError::TransferInterrupted`] for a truncated NAR,
+/// [`CliError::ChecksumMismatch`] if the NAR does not match its NARInfo, or
+/// a network or file error.
+pub async fn download_pair(
+    client: &CborClient,
+    cache: &str,
+    target: &DownloadTarget,
+    output_dir: &Path,
+) -> Result<DownloadedPair> {
+    let hash = target.hash()?;
+    let not_found = || CliError::StorePathNotFound {
+        path: match target {
+            DownloadTarget::Hash(hash) => hash.clone(),
+            DownloadTarget::StorePath(path) => path.clone(),
+        },
+    };
+    let narinfo = fetch_narinfo_by_hash_cached(client, cache, hash)
+        .await?
+        .filter(|info| target.matches(info))
+        .ok_or_else(not_found)?;
+
+    let nar_file = output_dir.join(nar_file_name(&narinfo)?);
+    let narinfo_file = output_dir.join(format!("{hash}.narinfo"));
+    if let Some(dir) = nar_file.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| file_error(dir, &e))?;
+    }
+
+    let partial = nar_file.with_extension("part");
+    let fetched = fetch_nar(client, cache, &narinfo, &partial).await;
+    if fetched.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    fetched?;
+    std::fs::rename(&partial, &nar_file).map_err(|e| file_error(&nar_file, &e))?;
+    std::fs::write(&narinfo_file, narinfo.to_string())
+        .map_err(|e| file_error(&narinfo_file, &e))?;
+
+    Ok(DownloadedPair {
+        narinfo,
+        narinfo_file,
+        nar_file,
+    })
+}
+
+/// Stream the NAR `info` points at into `dest`, checking its `FileSize`
+/// and `FileHash`
+async fn fetch_nar(client: &CborClient, cache: &str, info: &NarInfo, dest: &Path) -> Result<()> {
+    let file = File::create(dest).map_err(|e| file_error(dest, &e))?;
+    let mut writer = HashingWriter::new(file);
+    let _ = client
+        .get_to_writer(&narinfo_url_base().nar_path(cache, &info.url), &mut writer)
+        .await?;
+    let (mut file, digest, size) = writer.finish();
+    file.flush().map_err(|e| file_error(dest, &e))?;
+    check_file_size(info, size)?;
+
+    match &info.file_hash {
+        Some(expected) if !nar_hash_matches(expected, &digest) => Err(CliError::ChecksumMismatch {
+            path: info.store_path.clone(),
+            expected: expected.clone(),
+            actual: sha256_nix(&digest),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Check a compressed NAR's length against the NARInfo `FileSize`, if it
+/// has one
+///
+/// Catches a truncated transfer before it is hashed or decompressed.
+///
+/// # Errors
+///
+/// Returns [`CliError::TransferInterrupted`] if the sizes differ.
+pub(crate) fn check_file_size(info: &NarInfo, size: u64) -> Result<()> {
+    match info.file_size {
+        Some(expected) if expected != size => Err(CliError::TransferInterrupted(format!(
+            "{}: NAR is {size} bytes, NARInfo FileSize is {expected}",
+            info.store_path
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn file_error(path: &Path, e: &std::io::Error) -> CliError {
+    CliError::FileError {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn narinfo(url: &str) -> NarInfo {
+        NarInfo {
+            store_path: "/nix/store/0c7k7ysmldzsb6vyaqx4rj8gd5m44j3a-hello".to_string(),
+            url: url.to_string(),
+            compression: "xz".to_string(),
+            file_hash: None,
+            file_size: None,
+            nar_hash: "sha256:abc".to_string(),
+            nar_size: 120,
+            references: Vec::new(),
+            deriver: None,
+            system: None,
+            sigs: Vec::new(),
+            ca: None,
+        }
+    }
+
+    #[test]
+    fn test_nar_file_name_stays_inside_output() {
+        assert_eq!(
+            nar_file_name(&narinfo("nar/1abc.nar.xz")).ok(),
+            Some("nar/1abc.nar.xz")
+        );
+        for url in [
+            "../etc/passwd",
+            "nar/../x",
+            "nar/",
+            "/nar/x.nar",
+            "nar/.hidden",
+        ] {
+            assert!(nar_file_name(&narinfo(url)).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_file_size_check() {
+        let mut info = narinfo("nar/1abc.nar.xz");
+        assert!(check_file_size(&info, 10).is_ok());
+
+        info.file_size = Some(4096);
+        assert!(check_file_size(&info, 4096).is_ok());
+        assert!(matches!(
+            check_file_size(&info, 1024),
+            Err(CliError::TransferInterrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_target_hash_and_match() -> Result<()> {
+        let info = narinfo("nar/1abc.nar.xz");
+        let by_hash = DownloadTarget::Hash("0c7k7ysmldzsb6vyaqx4rj8gd5m44j3a".to_string());
+        assert_eq!(by_hash.hash()?, "0c7k7ysmldzsb6vyaqx4rj8gd5m44j3a");
+        assert!(by_hash.matches(&info));
+
+        let by_path = DownloadTarget::StorePath(info.store_path.clone());
+        assert!(by_path.matches(&info));
+        assert!(DownloadTarget::Hash("not-a-hash".to_string())
+            .hash()
+            .is_err());
+        Ok(())
+    }
+}
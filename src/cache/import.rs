@@ -0,0 +1,215 @@
+//! Offline imports (`flakecache import`)
+//!
+//! Reads the NAR and NARInfo pairs [`download`](super::download) writes,
+//! checks them against their hashes and signatures, and imports them into
+//! the local store with `nix-store --import`.
+
+use super::compress::DecompressedNar;
+use super::download::{check_file_size, nar_file_name};
+use super::signing::{verify_narinfo, PublicKey};
+use super::transfer::{nar_hash_matches, verify_nar};
+use crate::error::{CliError, Result};
+use crate::nix::store::{sha256_nix, store_path_basename};
+use crate::nix::{NarInfo, Nix};
+use crate::utils::streaming::HashingWriter;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A NARInfo in an import directory and the NAR it points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportPair {
+    /// The parsed NARInfo
+    pub narinfo: NarInfo,
+    /// The compressed NAR named by its `URL`
+    pub nar_file: PathBuf,
+}
+
+/// Every `*.narinfo` in `dir`, ordered so references come first
+///
+/// References outside the directory are expected to be in the store
+/// already; `nix-store --import` fails on a path whose references are
+/// missing.
+///
+/// # Errors
+///
+/// Returns [`CliError::FileError`] if `dir` or a NARInfo cannot be read, or
+/// a parse error naming the bad NARInfo.
+pub fn find_pairs(dir: &Path) -> Result<Vec<ImportPair>> {
+    let file_error = |path: &Path, e: &io::Error| CliError::FileError {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(|e| file_error(dir, &e))? {
+        let path = entry.map_err(|e| file_error(dir, &e))?.path();
+        if path.extension().is_some_and(|ext| ext == "narinfo") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut pairs = Vec::with_capacity(files.len());
+    for file in files {
+        let text = std::fs::read_to_string(&file).map_err(|e| file_error(&file, &e))?;
+        let narinfo = text.parse::<NarInfo>().map_err(|e| CliError::FileError {
+            path: file.clone(),
+            reason: e.to_string(),
+        })?;
+        let nar_file = dir.join(nar_file_name(&narinfo)?);
+        pairs.push(ImportPair { narinfo, nar_file });
+    }
+    Ok(dependency_order(pairs))
+}
+
+/// Sort `pairs` so each comes after the pairs it references
+///
+/// Cycles other than self-references cannot occur in a store, but are
+/// tolerated by keeping their original order.
+fn dependency_order(mut pending: Vec<ImportPair>) -> Vec<ImportPair> {
+    let in_set: HashSet<String> = pending
+        .iter()
+        .map(|pair| store_path_basename(&pair.narinfo.store_path).to_string())
+        .collect();
+    let mut placed: HashSet<String> = HashSet::new();
+    let mut ordered = Vec::with_capacity(pending.len());
+
+    while !pending.is_empty() {
+        let (ready, blocked): (Vec<_>, Vec<_>) = pending.into_iter().partition(|pair| {
+            let own = store_path_basename(&pair.narinfo.store_path);
+            pair.narinfo.references.iter().all(|reference| {
+                reference == own || !in_set.contains(reference) || placed.contains(reference)
+            })
+        });
+        if ready.is_empty() {
+            ordered.extend(blocked);
+            break;
+        }
+        placed.extend(
+            ready
+                .iter()
+                .map(|pair| store_path_basename(&pair.narinfo.store_path).to_string()),
+        );
+        ordered.extend(ready);
+        pending = blocked;
+    }
+    ordered
+}
+
+/// Verify one pair and import it into the local store
+///
+/// With `trusted` keys the NARInfo must carry a valid signature by one of
+/// them; `None` skips the signature check (`--no-check-sigs`). The
+/// compressed file must match `FileSize` and `FileHash`, and the
+/// decompressed NAR its `NarSize` and `NarHash`, before anything reaches
+/// the store.
+///
+/// # Errors
+///
+/// Returns [`CliError::SignatureError`] for an unsigned or untrusted
+/// NARInfo, [`CliError::TransferInterrupted`] for a truncated NAR,
+/// [`CliError::ChecksumMismatch`] if the NAR does not match the NARInfo, or
+/// a file, decompression, or store error.
+pub fn import_pair(nix: &Nix, pair: &ImportPair, trusted: Option<&[PublicKey]>) -> Result<()> {
+    let info = &pair.narinfo;
+    if let Some(keys) = trusted {
+        if !verify_narinfo(info, keys) {
+            return Err(CliError::SignatureError(format!(
+                "{} has no valid signature by a trusted key",
+                info.store_path
+            )));
+        }
+    }
+    let size = std::fs::metadata(&pair.nar_file)
+        .map_err(|e| CliError::FileError {
+            path: pair.nar_file.clone(),
+            reason: e.to_string(),
+        })?
+        .len();
+    check_file_size(info, size)?;
+    if let Some(expected) = &info.file_hash {
+        let digest = hash_file(&pair.nar_file)?;
+        if !nar_hash_matches(expected, &digest) {
+            return Err(CliError::ChecksumMismatch {
+                path: info.store_path.clone(),
+                expected: expected.clone(),
+                actual: sha256_nix(&digest),
+            });
+        }
+    }
+
+    // Decompress twice rather than keep the whole NAR on disk: once to
+    // verify, once to import
+    let mut nar = DecompressedNar::open(&pair.nar_file)?;
+    verify_nar(&info.store_path, info, &mut nar)?;
+    nar.finish()?;
+    let mut nar = DecompressedNar::open(&pair.nar_file)?;
+    nix.import_nar(info, &mut nar)?;
+    nar.finish()
+}
+
+/// SHA-256 of a file's contents
+fn hash_file(path: &Path) -> Result<[u8; 32]> {
+    let file_error = |e: io::Error| CliError::FileError {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    };
+    let mut file = File::open(path).map_err(file_error)?;
+    let mut hasher = HashingWriter::new(io::sink());
+    let _ = io::copy(&mut file, &mut hasher).map_err(file_error)?;
+    let (_, digest, _) = hasher.finish();
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(name: &str, references: &[&str]) -> ImportPair {
+        ImportPair {
+            narinfo: NarInfo {
+                store_path: format!("/nix/store/{name}"),
+                url: format!("nar/{name}.nar.xz"),
+                compression: "xz".to_string(),
+                file_hash: None,
+                file_size: None,
+                nar_hash: "sha256:abc".to_string(),
+                nar_size: 120,
+                references: references.iter().map(ToString::to_string).collect(),
+                deriver: None,
+                system: None,
+                sigs: Vec::new(),
+                ca: None,
+            },
+            nar_file: PathBuf::from(format!("nar/{name}.nar.xz")),
+        }
+    }
+
+    fn names(pairs: &[ImportPair]) -> Vec<&str> {
+        pairs
+            .iter()
+            .map(|pair| store_path_basename(&pair.narinfo.store_path))
+            .collect()
+    }
+
+    #[test]
+    fn test_references_are_imported_first() {
+        let ordered = dependency_order(vec![
+            pair("aaa-app", &["aaa-app", "bbb-lib", "zzz-outside"]),
+            pair("bbb-lib", &["ccc-glibc"]),
+            pair("ccc-glibc", &["ccc-glibc"]),
+        ]);
+        assert_eq!(names(&ordered), ["ccc-glibc", "bbb-lib", "aaa-app"]);
+    }
+
+    #[test]
+    fn test_cycles_keep_every_pair() {
+        let ordered = dependency_order(vec![
+            pair("aaa-x", &["bbb-y"]),
+            pair("bbb-y", &["aaa-x"]),
+            pair("ccc-z", &[]),
+        ]);
+        assert_eq!(names(&ordered), ["ccc-z", "aaa-x", "bbb-y"]);
+    }
+}
@@ -1,5 +1,11 @@
-//! Cache operations (signing, transfer, warming)
+//! Cache operations (signing, transfer, offline download and import, warming)
 
+pub mod closure;
+pub mod compress;
+pub mod compressed;
+pub mod download;
+pub mod import;
+pub mod narinfo_cache;
 pub mod signing;
 pub mod transfer;
 pub mod warm;
@@ -0,0 +1,204 @@
+//! In-process cache of NARInfo lookups (`--narinfo-cache-ttl`)
+//!
+//! A pull can ask for one NARInfo more than once: shared dependencies of
+//! several outputs, or an estimate followed by the download. Answers,
+//! including "the cache does not have it", are kept for a TTL, keyed by
+//! the NARInfo URL (so by server, cache and hash), and the oldest are
+//! dropped past [`DEFAULT_CAPACITY`] entries. Uploading a NARInfo drops
+//! its entry; checks that must see what the server serves right now, such
+//! as `--verify-after-push`, never read from it.
+
+use crate::error::{CliError, Result};
+use crate::nix::NarInfo;
+use crate::utils::time::parse_duration;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Environment variable for `--narinfo-cache-ttl`
+pub const TTL_ENV: &str = "FLAKECACHE_NARINFO_CACHE_TTL";
+
+/// How long an answer is reused by default
+pub const DEFAULT_TTL: Duration = Duration::from_mins(5);
+
+/// Most answers kept at once
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Process-wide cache set from `--narinfo-cache-ttl`
+static GLOBAL: OnceLock<NarInfoCache> = OnceLock::new();
+
+/// One remembered answer
+#[derive(Debug)]
+struct Entry {
+    /// `None` when the server answered 404
+    narinfo: Option<NarInfo>,
+    stored_at: Instant,
+}
+
+/// NARInfo answers by URL, bounded in age and number
+#[derive(Debug)]
+pub struct NarInfoCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl NarInfoCache {
+    /// Keep up to `capacity` answers for `ttl` each; a zero TTL or
+    /// capacity keeps nothing
+    #[must_use]
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            capacity,
+        }
+    }
+
+    /// The answer for `url` if it is still fresh: `Some(None)` for a
+    /// remembered 404, `None` if the server has to be asked
+    #[must_use]
+    pub fn get(&self, url: &str) -> Option<Option<NarInfo>> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries
+            .get(url)
+            .filter(|entry| entry.stored_at.elapsed() < self.ttl)
+            .map(|entry| entry.narinfo.clone())
+    }
+
+    /// Remember the server's answer for `url`
+    pub fn insert(&self, url: &str, narinfo: Option<NarInfo>) {
+        if self.ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() >= self.capacity && !entries.contains_key(url) {
+            entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+            if entries.len() >= self.capacity {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.stored_at)
+                    .map(|(url, _)| url.clone());
+                if let Some(oldest) = oldest {
+                    let _ = entries.remove(&oldest);
+                }
+            }
+        }
+        let _ = entries.insert(
+            url.to_string(),
+            Entry {
+                narinfo,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Forget the answer for `url`, e.g. after uploading its NARInfo
+    pub fn invalidate(&self, url: &str) {
+        let _ = self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(url);
+    }
+}
+
+impl Default for NarInfoCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_CAPACITY)
+    }
+}
+
+/// Parse a TTL: a duration such as `30s` or `10m`, or `0` to turn the
+/// cache off
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] for anything else.
+pub fn parse_ttl(s: &str) -> Result<Duration> {
+    if s.trim() == "0" {
+        Ok(Duration::ZERO)
+    } else {
+        parse_duration(s)
+    }
+}
+
+/// TTL from `--narinfo-cache-ttl` or [`TTL_ENV`], if either is set
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if the environment variable is
+/// not a TTL.
+pub fn resolve_ttl(flag: Option<Duration>) -> Result<Option<Duration>> {
+    if flag.is_some() {
+        return Ok(flag);
+    }
+    std::env::var(TTL_ENV)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            parse_ttl(&value).map_err(|_| {
+                CliError::InvalidArgument(format!(
+                    "{TTL_ENV} must be a duration such as 10m, or 0, got '{value}'"
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Use `cache` for the rest of the process
+///
+/// Called once at startup; later calls are ignored.
+pub fn set_global(cache: NarInfoCache) {
+    let _ = GLOBAL.set(cache);
+}
+
+/// The process-wide cache, with [`DEFAULT_TTL`] unless
+/// `--narinfo-cache-ttl` was given
+#[must_use]
+pub fn global() -> &'static NarInfoCache {
+    GLOBAL.get_or_init(NarInfoCache::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "StorePath: /nix/store/0c7k7ysmldzsb6vyaqx4rj8gd5m44j3a-hello-2.12.1\n\
+                          URL: nar/abc.nar.xz\n\
+                          Compression: xz\n\
+                          NarHash: sha256:1b8m03r63zqhnjf7l5wnldhh7c134ap5vpj0850ymkq1iyzicy5s\n\
+                          NarSize: 226560\n";
+
+    #[test]
+    fn test_remembers_hits_and_misses() -> Result<()> {
+        let cache = NarInfoCache::new(DEFAULT_TTL, 2);
+        let narinfo: NarInfo = SAMPLE.parse()?;
+        assert_eq!(cache.get("a"), None);
+
+        cache.insert("a", Some(narinfo.clone()));
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("b", None);
+        assert_eq!(cache.get("a"), Some(Some(narinfo)));
+        assert_eq!(cache.get("b"), Some(None));
+
+        // Full: the oldest answer makes room
+        cache.insert("c", None);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("c"), Some(None));
+
+        cache.invalidate("c");
+        assert_eq!(cache.get("c"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_ttl_disables() -> Result<()> {
+        let cache = NarInfoCache::new(parse_ttl("0")?, DEFAULT_CAPACITY);
+        cache.insert("a", None);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(parse_ttl("10m")?, Duration::from_mins(10));
+        assert!(parse_ttl("soon").is_err());
+        Ok(())
+    }
+}
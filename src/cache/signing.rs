@@ -1,3 +1,218 @@
 //! NAR signing and signature verification
 //!
 //! Implements cryptographic signing and verification of Nix Archives (NARs).
+//! Signatures cover the same fingerprint Nix checks against
+//! `trusted-public-keys`, so signed paths substitute without
+//! `--no-require-sigs`.
+
+use crate::commands::key::SecretKey;
+use crate::error::{CliError, Result};
+use crate::nix::store::STORE_DIR;
+use crate::nix::NarInfo;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use ed25519_dalek::{Signature, Signer as _, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+/// The string Nix signs for a path:
+/// `1;<store path>;<nar hash>;<nar size>;<comma-separated references>`
+///
+/// References are full store paths, in the NARInfo's order.
+#[must_use]
+pub fn fingerprint(info: &NarInfo) -> String {
+    let references: Vec<String> = info
+        .references
+        .iter()
+        .map(|basename| format!("{STORE_DIR}/{basename}"))
+        .collect();
+    format!(
+        "1;{};{};{};{}",
+        info.store_path,
+        info.nar_hash,
+        info.nar_size,
+        references.join(",")
+    )
+}
+
+/// Sign `info` with `key`, replacing any earlier signature by the same key
+pub fn sign_narinfo(info: &mut NarInfo, key: &SecretKey) {
+    let signature = key.signing_key().sign(fingerprint(info).as_bytes());
+    let prefix = format!("{}:", key.name());
+    info.sigs.retain(|sig| !sig.starts_with(&prefix));
+    info.sigs
+        .push(format!("{prefix}{}", STANDARD.encode(signature.to_bytes())));
+}
+
+/// A public key as listed in `trusted-public-keys` (`<name>:<base64>`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    name: String,
+    key: VerifyingKey,
+}
+
+impl PublicKey {
+    /// Key name, matched against the name in each signature
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Short digest of the key for comparing it by eye or over the phone:
+    /// `SHA256:` and the unpadded base64 SHA-256 of the key bytes, like
+    /// OpenSSH key fingerprints
+    #[must_use]
+    pub fn fingerprint(&self) -> String {
+        let digest = STANDARD.encode(Sha256::digest(self.key.as_bytes()));
+        format!("SHA256:{}", digest.trim_end_matches('='))
+    }
+
+    /// Whether `signature` (`<name>:<base64>`) is this key's signature of
+    /// `info`
+    fn verifies(&self, info: &NarInfo, signature: &str) -> bool {
+        let Some((name, encoded)) = signature.split_once(':') else {
+            return false;
+        };
+        let Ok(Ok(bytes)) = STANDARD.decode(encoded).map(<[u8; 64]>::try_from) else {
+            return false;
+        };
+        name == self.name
+            && self
+                .key
+                .verify_strict(fingerprint(info).as_bytes(), &Signature::from_bytes(&bytes))
+                .is_ok()
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || CliError::InvalidArgument(format!("invalid public key '{s}'"));
+        let (name, encoded) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let bytes: [u8; 32] = STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(invalid)?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|_| invalid())?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            key,
+        })
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.name, STANDARD.encode(self.key.as_bytes()))
+    }
+}
+
+/// Keys a binary cache advertises in the `PublicKeys` field of its
+/// `nix-cache-info` (space separated)
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if an advertised key is malformed.
+pub fn advertised_keys(nix_cache_info: &str) -> Result<Vec<PublicKey>> {
+    nix_cache_info
+        .lines()
+        .filter_map(|line| line.strip_prefix("PublicKeys:"))
+        .flat_map(str::split_whitespace)
+        .map(str::parse)
+        .collect()
+}
+
+/// Whether `info` carries a valid signature by any of `keys`
+#[must_use]
+pub fn verify_narinfo(info: &NarInfo, keys: &[PublicKey]) -> bool {
+    info.sigs
+        .iter()
+        .any(|sig| keys.iter().any(|key| key.verifies(info, sig)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+
+    fn narinfo() -> NarInfo {
+        NarInfo {
+            store_path: "/nix/store/aaa-hello".to_string(),
+            url: "nar/xyz.nar.xz".to_string(),
+            compression: "xz".to_string(),
+            file_hash: None,
+            file_size: None,
+            nar_hash: "sha256:abc".to_string(),
+            nar_size: 120,
+            references: vec!["aaa-hello".to_string(), "bbb-glibc".to_string()],
+            deriver: None,
+            system: None,
+            sigs: Vec::new(),
+            ca: None,
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_format() {
+        assert_eq!(
+            fingerprint(&narinfo()),
+            "1;/nix/store/aaa-hello;sha256:abc;120;/nix/store/aaa-hello,/nix/store/bbb-glibc"
+        );
+    }
+
+    #[test]
+    fn test_sign_replaces_own_signature() -> Result<()> {
+        let key = SecretKey::generate("test-1")?;
+        let mut info = narinfo();
+        info.sigs.push("other-1:xyz".to_string());
+        sign_narinfo(&mut info, &key);
+        sign_narinfo(&mut info, &key);
+
+        assert_eq!(info.sigs.len(), 2);
+        assert!(info.sigs[1].starts_with("test-1:"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_signed_narinfo() -> Result<()> {
+        let key = SecretKey::generate("test-1")?;
+        let public: PublicKey = key.public_key().parse()?;
+        assert_eq!(public.name(), "test-1");
+
+        let mut info = narinfo();
+        assert!(!verify_narinfo(&info, std::slice::from_ref(&public)));
+        sign_narinfo(&mut info, &key);
+        assert!(verify_narinfo(&info, std::slice::from_ref(&public)));
+
+        info.nar_size += 1;
+        assert!(!verify_narinfo(&info, &[public]));
+        assert!("no-colon".parse::<PublicKey>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_advertised_keys() -> Result<()> {
+        let first = SecretKey::generate("team-1")?.public_key();
+        let second = SecretKey::generate("team-2")?.public_key();
+        let info =
+            format!("StoreDir: /nix/store\nWantMassQuery: 1\nPublicKeys: {first} {second}\n");
+        let keys = advertised_keys(&info)?;
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].to_string(), first);
+        assert_eq!(keys[1].name(), "team-2");
+
+        let fingerprint = keys[0].fingerprint();
+        assert!(fingerprint.starts_with("SHA256:") && !fingerprint.ends_with('='));
+        assert_ne!(fingerprint, keys[1].fingerprint());
+
+        assert!(advertised_keys("StoreDir: /nix/store\n")?.is_empty());
+        assert!(advertised_keys("PublicKeys: bogus\n").is_err());
+        Ok(())
+    }
+}
@@ -1,3 +1,666 @@
 //! Upload and download operations
 //!
 //! Handles efficient transfer of store paths with progress tracking and error recovery.
+
+use super::compress::{compress_and_hash_nar, CompressOptions, CompressedNar, DecompressedNar};
+use super::compressed::CompressedCache;
+use super::narinfo_cache;
+use super::signing::{advertised_keys, sign_narinfo, verify_narinfo, PublicKey};
+use crate::client::cbor::CborClient;
+use crate::client::layout::narinfo_url_base;
+use crate::commands::key::SecretKey;
+use crate::error::{CliError, Result};
+use crate::nix::store::{nix_base32_encode, parse_store_path, sha256_nix, store_path_basename};
+use crate::nix::{NarInfo, Nix};
+use crate::utils::streaming::HashingWriter;
+use crate::utils::trace;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Content type of `.narinfo` documents
+pub const NARINFO_CONTENT_TYPE: &str = "text/x-nix-narinfo";
+
+/// A store path uploaded to a cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedPath {
+    /// The NARInfo that was uploaded
+    pub narinfo: NarInfo,
+    /// Time spent dumping and compressing the NAR
+    pub compress_time: Duration,
+    /// Time spent uploading the NAR and NARInfo
+    pub upload_time: Duration,
+}
+
+/// Base32 digest of a `sha256:<base32>` hash, as used in NAR URLs
+fn hash_digest(hash: &str) -> &str {
+    hash.split_once(':').map_or(hash, |(_, digest)| digest)
+}
+
+/// Build the NARInfo describing a freshly compressed NAR
+#[must_use]
+pub fn narinfo_for(
+    store_path: &str,
+    nar: &CompressedNar,
+    references: &[String],
+    deriver: Option<&str>,
+) -> NarInfo {
+    NarInfo {
+        store_path: store_path.to_string(),
+        url: format!(
+            "nar/{}.nar{}",
+            hash_digest(&nar.file_hash),
+            nar.compression.extension()
+        ),
+        compression: nar.compression.to_string(),
+        file_hash: Some(nar.file_hash.clone()),
+        file_size: Some(nar.file_size),
+        nar_hash: nar.nar_hash.clone(),
+        nar_size: nar.nar_size,
+        references: references
+            .iter()
+            .map(|r| store_path_basename(r).to_string())
+            .collect(),
+        deriver: deriver.map(|d| store_path_basename(d).to_string()),
+        system: None,
+        sigs: Vec::new(),
+        ca: None,
+    }
+}
+
+/// Upload a compressed NAR (`PUT /api/v1/{cache}/nar/{file_hash}/{compression}`)
+///
+/// The file is streamed from disk. It has to exist on disk first because
+/// the URL contains the hash of the compressed bytes, which is only known
+/// once compression has finished.
+///
+/// # Errors
+///
+/// Returns a file, network, or HTTP status error.
+pub async fn upload_nar(client: &CborClient, cache: &str, nar: &CompressedNar) -> Result<()> {
+    let path = nar_api_path(cache, &nar.file_hash, &nar.compression.to_string());
+    client.put_file(&path, &nar.path).await
+}
+
+/// API path of a compressed NAR, shared by upload and existence checks
+fn nar_api_path(cache: &str, file_hash: &str, compression: &str) -> String {
+    format!(
+        "api/v1/{}/nar/{}/{compression}",
+        urlencoding::encode(cache),
+        hash_digest(file_hash)
+    )
+}
+
+/// API path of a NARInfo upload
+fn narinfo_api_path(cache: &str, file_hash: &str) -> String {
+    format!(
+        "api/v1/{}/{}",
+        urlencoding::encode(cache),
+        hash_digest(file_hash)
+    )
+}
+
+/// Upload a NARInfo (`PUT /api/v1/{cache}/{file_hash}`)
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if the NARInfo has no file hash, or
+/// a network or HTTP status error.
+pub async fn upload_narinfo(client: &CborClient, cache: &str, narinfo: &NarInfo) -> Result<()> {
+    let file_hash = narinfo.file_hash.as_deref().ok_or_else(|| {
+        CliError::InvalidArgument(format!(
+            "NARInfo for {} has no FileHash",
+            narinfo.store_path
+        ))
+    })?;
+    let path = narinfo_api_path(cache, file_hash);
+    client
+        .put_text(&path, NARINFO_CONTENT_TYPE, narinfo.to_string())
+        .await?;
+    if let Ok(hash) = parse_store_path(&narinfo.store_path) {
+        narinfo_cache::global().invalidate(&narinfo_url(client, cache, hash));
+    }
+    Ok(())
+}
+
+/// Fetch the NARInfo of `store_path` (`GET /{cache}/{hash}.narinfo`, or
+/// under `--narinfo-url-base`)
+///
+/// # Returns
+///
+/// `None` if the cache does not have the path
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidStorePath`] for a malformed path, a network
+/// or HTTP status error other than 404, or a parse error.
+pub async fn fetch_narinfo(
+    client: &CborClient,
+    cache: &str,
+    store_path: &str,
+) -> Result<Option<NarInfo>> {
+    fetch_narinfo_by_hash(client, cache, parse_store_path(store_path)?).await
+}
+
+/// Fetch the NARInfo of the store path with hash part `hash`
+///
+/// # Returns
+///
+/// `None` if the cache does not have the path
+///
+/// # Errors
+///
+/// Returns a network or HTTP status error other than 404, or a parse error.
+pub async fn fetch_narinfo_by_hash(
+    client: &CborClient,
+    cache: &str,
+    hash: &str,
+) -> Result<Option<NarInfo>> {
+    match client
+        .get_text(&narinfo_url_base().narinfo_path(cache, hash))
+        .await
+    {
+        Ok(text) => text.parse().map(Some),
+        Err(CliError::ApiError { status: 404, .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// [`fetch_narinfo`], answered from the process-wide
+/// [`NarInfoCache`](narinfo_cache::NarInfoCache) when it can be
+///
+/// For lookups that may repeat within a run; anything checking what the
+/// server serves right now must use [`fetch_narinfo`].
+///
+/// # Errors
+///
+/// As for [`fetch_narinfo`]; errors are not cached.
+pub async fn fetch_narinfo_cached(
+    client: &CborClient,
+    cache: &str,
+    store_path: &str,
+) -> Result<Option<NarInfo>> {
+    fetch_narinfo_by_hash_cached(client, cache, parse_store_path(store_path)?).await
+}
+
+/// [`fetch_narinfo_by_hash`] through the process-wide NARInfo cache
+///
+/// # Errors
+///
+/// As for [`fetch_narinfo_by_hash`]; errors are not cached.
+pub async fn fetch_narinfo_by_hash_cached(
+    client: &CborClient,
+    cache: &str,
+    hash: &str,
+) -> Result<Option<NarInfo>> {
+    let url = narinfo_url(client, cache, hash);
+    if let Some(narinfo) = narinfo_cache::global().get(&url) {
+        return Ok(narinfo);
+    }
+    let narinfo = fetch_narinfo_by_hash(client, cache, hash).await?;
+    narinfo_cache::global().insert(&url, narinfo.clone());
+    Ok(narinfo)
+}
+
+/// Absolute URL of a NARInfo, the key of the NARInfo cache
+fn narinfo_url(client: &CborClient, cache: &str, hash: &str) -> String {
+    client.url(&narinfo_url_base().narinfo_path(cache, hash))
+}
+
+/// Public keys `cache` advertises in its `nix-cache-info`
+///
+/// The server chooses what it advertises, so these are only candidates
+/// for trust, never trusted by themselves.
+///
+/// # Errors
+///
+/// Returns a network or HTTP status error, or
+/// [`CliError::InvalidArgument`] for a malformed key.
+pub async fn fetch_cache_public_keys(client: &CborClient, cache: &str) -> Result<Vec<PublicKey>> {
+    let path = format!("{}/nix-cache-info", narinfo_url_base().root(cache));
+    advertised_keys(&client.get_text(&path).await?)
+}
+
+/// Check that the server serves a path that was just uploaded
+/// (`--verify-after-push`)
+///
+/// Re-fetches the NARInfo, which must describe the same NAR, and checks
+/// the NAR itself with a `HEAD`. Uploads are processed asynchronously, so
+/// a 2xx from the upload alone does not prove the path is retrievable.
+///
+/// # Errors
+///
+/// Returns [`CliError::CacheError`] if the NARInfo or NAR is not served or
+/// describes a different NAR, or a network or HTTP status error.
+pub async fn verify_served(client: &CborClient, cache: &str, narinfo: &NarInfo) -> Result<()> {
+    let not_served = |what: &str| {
+        CliError::CacheError(format!("{} was uploaded but {what}", narinfo.store_path))
+    };
+    let served = fetch_narinfo(client, cache, &narinfo.store_path)
+        .await?
+        .ok_or_else(|| not_served("its NARInfo is not served"))?;
+    if served.nar_hash != narinfo.nar_hash {
+        return Err(not_served(&format!(
+            "the server's NARInfo has NarHash {}",
+            served.nar_hash
+        )));
+    }
+    if let Some(file_hash) = &narinfo.file_hash {
+        let path = nar_api_path(cache, file_hash, &narinfo.compression);
+        if !client.exists(&path).await? {
+            return Err(not_served(&format!(
+                "its NAR is not served ({})",
+                narinfo.url
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A path whose local NAR differs from the copy already in the cache
+/// (`push --check-reproducible`), a sign of a non-deterministic build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NarHashMismatch {
+    /// Store path that was rebuilt
+    pub store_path: String,
+    /// NarHash of the local build
+    pub local: String,
+    /// NarHash of the cache's copy
+    pub cached: String,
+}
+
+impl fmt::Display for NarHashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: local NarHash {}, cached {}",
+            self.store_path, self.local, self.cached
+        )
+    }
+}
+
+/// Compare the NarHash of a prepared path with the cache's copy of it
+///
+/// # Returns
+///
+/// `None` if the cache does not have the path or has the same NAR
+///
+/// # Errors
+///
+/// Returns a network or HTTP status error other than 404, or a parse error.
+pub async fn check_reproducible(
+    client: &CborClient,
+    cache: &str,
+    narinfo: &NarInfo,
+) -> Result<Option<NarHashMismatch>> {
+    let cached = fetch_narinfo(client, cache, &narinfo.store_path).await?;
+    Ok(cached
+        .filter(|cached| cached.nar_hash != narinfo.nar_hash)
+        .map(|cached| NarHashMismatch {
+            store_path: narinfo.store_path.clone(),
+            local: narinfo.nar_hash.clone(),
+            cached: cached.nar_hash,
+        }))
+}
+
+/// Which parts of a store path to upload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UploadMode {
+    /// The NAR, then its NARInfo
+    #[default]
+    Full,
+    /// Only the NARInfo, for a NAR already on the server (`--narinfo-only`)
+    NarInfoOnly {
+        /// Check the NAR exists before publishing a NARInfo pointing at it
+        verify_nar: bool,
+    },
+}
+
+/// A store path dumped and compressed, waiting to be uploaded
+///
+/// The compressed temporary file is removed when this is dropped, whether
+/// or not it was uploaded.
+#[derive(Debug)]
+pub struct PreparedPath {
+    /// The compressed NAR
+    pub nar: CompressedNar,
+    /// The NARInfo to publish for it, signed if a key was given
+    pub narinfo: NarInfo,
+    /// Time spent dumping and compressing the NAR
+    pub compress_time: Duration,
+}
+
+impl Drop for PreparedPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.nar.path);
+    }
+}
+
+/// Dump and compress one store path and build its NARInfo
+///
+/// This is the CPU-bound half of an upload; it runs on a blocking thread.
+/// With `reuse`, a NAR compressed earlier with the same settings is reused
+/// and a fresh one is kept for next time. With a `signing_key`, the NARInfo
+/// is signed.
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidStorePath`] for a malformed path, or the
+/// first store or compression error.
+pub async fn prepare_store_path(
+    nix: &Nix,
+    store_path: &str,
+    compress: CompressOptions,
+    temp_dir: &Path,
+    reuse: Option<&CompressedCache>,
+    signing_key: Option<&SecretKey>,
+) -> Result<PreparedPath> {
+    let _ = parse_store_path(store_path)?;
+    let started = Instant::now();
+    let (nar, references, deriver) = {
+        let nix = nix.clone();
+        let path = store_path.to_string();
+        let temp_dir = temp_dir.to_path_buf();
+        let reuse = reuse.cloned();
+        tokio::task::spawn_blocking(move || -> Result<_> {
+            let _span = trace::span("compress", "dump and compress", &path);
+            let references = nix.query_references(&path)?;
+            let deriver = nix.query_deriver(&path)?;
+            let nar = match reuse {
+                Some(cache) => {
+                    let nar_hash = nix.query_hash(&path)?;
+                    if let Some(nar) = cache.lookup(&nar_hash, &compress, &temp_dir) {
+                        nar
+                    } else {
+                        let nar = compress_and_hash_nar(&nix, &path, &compress, &temp_dir)?;
+                        cache.store(&nar, compress.level);
+                        nar
+                    }
+                }
+                None => compress_and_hash_nar(&nix, &path, &compress, &temp_dir)?,
+            };
+            Ok((nar, references, deriver))
+        })
+        .await
+        .map_err(|e| CliError::Internal(format!("compression task failed: {e}")))??
+    };
+    let compress_time = started.elapsed();
+
+    let mut narinfo = narinfo_for(store_path, &nar, &references, deriver.as_deref());
+    if let Some(key) = signing_key {
+        sign_narinfo(&mut narinfo, key);
+    }
+    Ok(PreparedPath {
+        nar,
+        narinfo,
+        compress_time,
+    })
+}
+
+/// Upload a prepared store path: its NAR, then its NARInfo
+///
+/// The NAR is uploaded before the NARInfo so the cache never advertises a
+/// path whose NAR is missing.
+///
+/// In [`UploadMode::NarInfoOnly`] the NAR was still compressed locally,
+/// because the NARInfo's `URL` and `FileHash` are derived from the
+/// compressed bytes, but only the NARInfo is uploaded. This matches the
+/// existing NAR when it was compressed with the same settings.
+///
+/// # Errors
+///
+/// Returns [`CliError::CacheError`] if `verify_nar` is set and the NAR is
+/// missing, or the first upload error.
+pub async fn publish_prepared(
+    client: &CborClient,
+    cache: &str,
+    prepared: PreparedPath,
+    mode: UploadMode,
+) -> Result<UploadedPath> {
+    let PreparedPath { nar, narinfo, .. } = &prepared;
+    let started = Instant::now();
+    let _network = trace::span("network", "upload", &narinfo.store_path);
+    match mode {
+        UploadMode::Full => upload_nar(client, cache, nar).await?,
+        UploadMode::NarInfoOnly { verify_nar: false } => {}
+        UploadMode::NarInfoOnly { verify_nar: true } => {
+            let path = nar_api_path(cache, &nar.file_hash, &nar.compression.to_string());
+            if !client.exists(&path).await? {
+                return Err(CliError::CacheError(format!(
+                    "NAR for {} is missing on the server ({})",
+                    narinfo.store_path, narinfo.url
+                )));
+            }
+        }
+    }
+    upload_narinfo(client, cache, narinfo).await?;
+
+    Ok(UploadedPath {
+        narinfo: narinfo.clone(),
+        compress_time: prepared.compress_time,
+        upload_time: started.elapsed(),
+    })
+}
+
+/// Whether a NARInfo `NarHash` (`sha256:` in base32 or hex) is `digest`
+pub(crate) fn nar_hash_matches(expected: &str, digest: &[u8; 32]) -> bool {
+    match expected.split_once(':') {
+        Some(("sha256", encoded)) => {
+            encoded == nix_base32_encode(digest)
+                || encoded.eq_ignore_ascii_case(&hex::encode(digest))
+        }
+        _ => false,
+    }
+}
+
+/// Check that a decompressed NAR is exactly the one `requested` names
+///
+/// The NARInfo must describe `requested`, and the NAR's size and SHA-256
+/// must match its `NarSize` and `NarHash`. This catches a corrupt or
+/// truncated download, but the NARInfo comes from the same server as the
+/// NAR: only a trusted signature on it (see [`import_verified`]) shows the
+/// content is genuine.
+///
+/// # Errors
+///
+/// Returns [`CliError::ChecksumMismatch`] on any mismatch, or
+/// [`CliError::DownloadFailed`] if the NAR cannot be read.
+pub fn verify_nar<R: Read>(requested: &str, info: &NarInfo, nar: &mut R) -> Result<()> {
+    let mismatch = |expected: String, actual: String| CliError::ChecksumMismatch {
+        path: requested.to_string(),
+        expected,
+        actual,
+    };
+
+    if info.store_path != requested {
+        return Err(mismatch(requested.to_string(), info.store_path.clone()));
+    }
+
+    let mut hasher = HashingWriter::new(io::sink());
+    let _ = io::copy(nar, &mut hasher)
+        .map_err(|e| CliError::DownloadFailed(format!("{requested}: {e}")))?;
+    let (_, digest, size) = hasher.finish();
+
+    if size != info.nar_size {
+        return Err(mismatch(
+            format!("NarSize {}", info.nar_size),
+            format!("NarSize {size}"),
+        ));
+    }
+    if !nar_hash_matches(&info.nar_hash, &digest) {
+        return Err(mismatch(info.nar_hash.clone(), sha256_nix(&digest)));
+    }
+    Ok(())
+}
+
+/// Verify a NAR file fetched for `requested` against `info`, then import it
+///
+/// `info` must carry a valid signature by one of `trusted`:
+/// `nix-store --import` does not check signatures for trusted users, so
+/// without this anyone controlling the server or the connection could
+/// place arbitrary content under the path. The file may be compressed, as
+/// the cache serves it; it is decompressed once to verify and once to
+/// import, as [`super::import::import_pair`] does.
+///
+/// # Errors
+///
+/// Returns [`CliError::SignatureError`] for an unsigned or untrusted
+/// NARInfo, [`CliError::ChecksumMismatch`] if the NAR does not match it
+/// (nothing is imported in either case), or a file, decompression, or
+/// store error.
+pub fn import_verified(
+    nix: &Nix,
+    requested: &str,
+    info: &NarInfo,
+    nar: &Path,
+    trusted: &[PublicKey],
+) -> Result<()> {
+    if !verify_narinfo(info, trusted) {
+        return Err(CliError::SignatureError(format!(
+            "{requested} has no valid signature by a trusted key"
+        )));
+    }
+    let mut decompressed = DecompressedNar::open(nar)?;
+    verify_nar(requested, info, &mut decompressed)?;
+    decompressed.finish()?;
+    let mut decompressed = DecompressedNar::open(nar)?;
+    nix.import_nar(info, &mut decompressed)?;
+    decompressed.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::compress::Compression;
+    use crate::nix::store::write_nix_string;
+    use sha2::{Digest, Sha256};
+    use std::path::PathBuf;
+
+    const HELLO: &str = "/nix/store/0c75sid0a2r1dpmnwnbnp8ingjbmr3pl-hello-2.12.1";
+
+    /// A NAR holding a single regular file
+    fn crafted_nar(contents: &str) -> io::Result<Vec<u8>> {
+        let mut nar = Vec::new();
+        for token in [
+            "nix-archive-1",
+            "(",
+            "type",
+            "regular",
+            "contents",
+            contents,
+            ")",
+        ] {
+            write_nix_string(&mut nar, token)?;
+        }
+        Ok(nar)
+    }
+
+    fn narinfo_describing(nar: &[u8]) -> NarInfo {
+        NarInfo {
+            store_path: HELLO.to_string(),
+            url: "nar/x.nar".to_string(),
+            compression: "none".to_string(),
+            file_hash: None,
+            file_size: None,
+            nar_hash: sha256_nix(&Sha256::digest(nar).into()),
+            nar_size: nar.len() as u64,
+            references: Vec::new(),
+            deriver: None,
+            system: None,
+            sigs: Vec::new(),
+            ca: None,
+        }
+    }
+
+    #[test]
+    fn test_verify_nar_accepts_matching_nar() -> Result<()> {
+        let nar = crafted_nar("hello\n")?;
+        let info = narinfo_describing(&nar);
+        verify_nar(HELLO, &info, &mut nar.as_slice())?;
+
+        let hex_info = NarInfo {
+            nar_hash: format!("sha256:{}", hex::encode(Sha256::digest(&nar))),
+            ..info
+        };
+        verify_nar(HELLO, &hex_info, &mut nar.as_slice())
+    }
+
+    #[test]
+    fn test_verify_nar_rejects_tampered_content() -> Result<()> {
+        let info = narinfo_describing(&crafted_nar("hello\n")?);
+        let tampered = crafted_nar("pwned\n")?;
+        assert!(matches!(
+            verify_nar(HELLO, &info, &mut tampered.as_slice()),
+            Err(CliError::ChecksumMismatch { expected, .. }) if expected == info.nar_hash
+        ));
+
+        let other_path = "/nix/store/x3j5d4wrv2s0hs5n1hz1alm5y35xh0vq-glibc-2.38";
+        let nar = crafted_nar("hello\n")?;
+        assert!(matches!(
+            verify_nar(other_path, &info, &mut nar.as_slice()),
+            Err(CliError::ChecksumMismatch { actual, .. }) if actual == HELLO
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_verified_rejects_tampered_file() -> Result<()> {
+        let key = SecretKey::generate("test-1")?;
+        let trusted: PublicKey = key.public_key().parse()?;
+        let mut info = narinfo_describing(&crafted_nar("hello\n")?);
+        let file =
+            std::env::temp_dir().join(format!("flakecache-import-{}.nar", std::process::id()));
+        std::fs::write(&file, crafted_nar("pwned\n")?)?;
+        // Both are rejected before Nix is ever asked to import anything
+        let unsigned = import_verified(&Nix::new(), HELLO, &info, &file, &[trusted.clone()]);
+        sign_narinfo(&mut info, &key);
+        let tampered = import_verified(&Nix::new(), HELLO, &info, &file, &[trusted]);
+        std::fs::remove_file(&file)?;
+        assert!(matches!(unsigned, Err(CliError::SignatureError(_))));
+        assert!(matches!(tampered, Err(CliError::ChecksumMismatch { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_narinfo_for_compressed_nar() {
+        let nar = CompressedNar {
+            path: PathBuf::from("/tmp/x.nar.xz"),
+            compression: Compression::Xz,
+            file_hash: "sha256:1mqn5ywqwhbvm9y6gv5k9mdgxzjwlhjr8c8sgqycms1syr0xb9gp".to_string(),
+            file_size: 50088,
+            nar_hash: "sha256:0n9nigwkfpc4mkhcgrmhf4h3ywxh8gsw1x4cfb7ffmxjzc7ly8rj".to_string(),
+            nar_size: 226_560,
+        };
+        let info = narinfo_for(
+            "/nix/store/0c75sid0a2r1dpmnwnbnp8ingjbmr3pl-hello-2.12.1",
+            &nar,
+            &["/nix/store/x3j5d4wrv2s0hs5n1hz1alm5y35xh0vq-glibc-2.38".to_string()],
+            Some("/nix/store/7vmbwv8a8hp3z6r9p7k46wqlg0zl0i3d-hello-2.12.1.drv"),
+        );
+
+        assert_eq!(
+            info.url,
+            "nar/1mqn5ywqwhbvm9y6gv5k9mdgxzjwlhjr8c8sgqycms1syr0xb9gp.nar.xz"
+        );
+        assert_eq!(info.compression, "xz");
+        assert_eq!(
+            info.references,
+            ["x3j5d4wrv2s0hs5n1hz1alm5y35xh0vq-glibc-2.38"]
+        );
+        assert_eq!(
+            info.deriver.as_deref(),
+            Some("7vmbwv8a8hp3z6r9p7k46wqlg0zl0i3d-hello-2.12.1.drv")
+        );
+    }
+
+    #[test]
+    fn test_api_paths_encode_cache() {
+        assert_eq!(
+            nar_api_path("my team", "sha256:1mqn", "xz"),
+            "api/v1/my%20team/nar/1mqn/xz"
+        );
+        assert_eq!(narinfo_api_path("a/b", "sha256:1mqn"), "api/v1/a%2Fb/1mqn");
+    }
+}
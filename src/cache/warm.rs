@@ -1,3 +1,283 @@
 //! Cache warming operations
 //!
 //! Implements cache warming to pre-populate frequently-used dependencies.
+
+use crate::error::{CliError, Result};
+use crate::nix::{flake, BuildActivity, Nix};
+use std::fmt;
+use std::io::BufRead;
+
+/// One installable to warm, with the `--from-file` line it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmEntry {
+    /// Line number in the warm file, or `None` for a command-line argument
+    pub line: Option<usize>,
+    /// Flake output to build (may contain `{system}`)
+    pub installable: String,
+}
+
+impl WarmEntry {
+    /// Entry for an installable given on the command line
+    #[must_use]
+    pub fn argument(installable: &str) -> Self {
+        Self {
+            line: None,
+            installable: installable.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for WarmEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.installable),
+            None => f.write_str(&self.installable),
+        }
+    }
+}
+
+/// Read a warm file (`--from-file`): one installable per line
+///
+/// Blank lines and `#` comments are ignored. A comment must start the line
+/// or follow whitespace, since `#` also separates a flake from its
+/// attribute (`.#hello`).
+///
+/// # Errors
+///
+/// Returns an error if reading from `reader` fails.
+pub fn read_warm_file<R: BufRead>(reader: R) -> Result<Vec<WarmEntry>> {
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let content = line
+            .match_indices('#')
+            .find(|&(at, _)| at == 0 || line[..at].ends_with(char::is_whitespace))
+            .map_or(line.as_str(), |(at, _)| &line[..at])
+            .trim();
+        if !content.is_empty() {
+            entries.push(WarmEntry {
+                line: Some(index + 1),
+                installable: content.to_string(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Target system for a warm: `--system`, else the local Nix `system`
+/// setting, else the system this binary was built for
+#[must_use]
+pub fn target_system(nix: &Nix, requested: Option<String>) -> String {
+    requested
+        .or_else(|| nix.current_system().ok())
+        .unwrap_or_else(flake::host_system)
+}
+
+/// Every system the flakes of `installables` provide outputs for
+/// (`--all-systems`)
+///
+/// # Errors
+///
+/// Returns [`CliError::StoreError`] if a flake cannot be evaluated, or
+/// [`CliError::FlakeResolutionError`] if no systems are found.
+pub fn flake_systems(nix: &Nix, installables: &[String]) -> Result<Vec<String>> {
+    let mut flakes: Vec<&str> = installables.iter().map(|i| flake::flake_ref(i)).collect();
+    flakes.dedup();
+
+    let mut systems = Vec::new();
+    for flake_ref in &flakes {
+        systems.extend(flake::systems_in(&nix.flake_show(flake_ref)?));
+    }
+    systems.sort();
+    systems.dedup();
+    if systems.is_empty() {
+        return Err(CliError::FlakeResolutionError {
+            flake: flakes.join(", "),
+            reason: "no per-system outputs found".to_string(),
+        });
+    }
+    Ok(systems)
+}
+
+/// `nix build` options warm sets itself, with what to use instead; a
+/// `--nix-arg` repeating one would break reading the build's output
+const RESERVED_NIX_ARGS: &[(&str, &str)] = &[
+    ("--json", "warm reads the build's JSON output itself"),
+    ("--no-link", "warm never creates result links"),
+    ("--out-link", "warm never creates result links"),
+    ("-o", "warm never creates result links"),
+    (
+        "--print-out-paths",
+        "warm reads the build's JSON output itself",
+    ),
+    (
+        "--dry-run",
+        "use `pull --estimate` to see what would be fetched",
+    ),
+    ("--system", "use --system or --all-systems"),
+];
+
+/// Check `--nix-arg` values before passing them to every `nix build` and
+/// `nix eval`
+///
+/// Arguments are passed through as given, never dropped: one that would
+/// conflict with the options warm sets is an error instead.
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] for an option warm sets itself.
+pub fn check_nix_args(args: &[String]) -> Result<()> {
+    for arg in args {
+        let option = arg
+            .split_once('=')
+            .map_or(arg.as_str(), |(option, _)| option);
+        if let Some((_, instead)) = RESERVED_NIX_ARGS.iter().find(|(name, _)| *name == option) {
+            return Err(CliError::InvalidArgument(format!(
+                "--nix-arg {arg} cannot be passed through: {instead}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Build `installables` for `system` and return their output paths
+///
+/// `nix_args` (see [`check_nix_args`]) are passed to every evaluation and
+/// build. With `activity`, what the builds substituted and built is added
+/// to it (`--report-cache-hits`).
+///
+/// # Errors
+///
+/// Returns the errors of [`resolve_installables`], or
+/// [`CliError::StoreError`] if a build fails (e.g. no builder for
+/// `system` is available).
+pub fn build_for_system(
+    nix: &Nix,
+    installables: &[String],
+    system: &str,
+    nix_args: &[String],
+    mut activity: Option<&mut BuildActivity>,
+) -> Result<Vec<String>> {
+    let args = nix_command_args(system, nix_args);
+    let mut paths = Vec::new();
+    for installable in resolve_installables(nix, installables, system, nix_args)? {
+        let built = match activity.as_deref_mut() {
+            Some(activity) => {
+                let (built, build_activity) = nix.build_json_with_activity(&installable, &args)?;
+                activity.merge(build_activity);
+                built
+            }
+            None => nix.build_json(&installable, &args)?,
+        };
+        paths.extend(flake::extract_store_paths(&installable, &built)?);
+    }
+    Ok(paths)
+}
+
+/// Arguments that make Nix evaluate and build for `system`
+#[must_use]
+pub fn system_args(system: &str) -> Vec<String> {
+    vec!["--system".to_string(), system.to_string()]
+}
+
+/// [`system_args`] followed by the user's `nix_args`
+fn nix_command_args(system: &str, nix_args: &[String]) -> Vec<String> {
+    let mut args = system_args(system);
+    args.extend_from_slice(nix_args);
+    args
+}
+
+/// Substitute `system` into each installable and check the output exists,
+/// evaluating with `nix_args`
+///
+/// # Returns
+///
+/// The installables with `{system}` replaced, in order
+///
+/// # Errors
+///
+/// Returns [`CliError::FlakeResolutionError`] if an installable has no
+/// output for `system`, or [`CliError::StoreError`] if evaluation fails.
+pub fn resolve_installables(
+    nix: &Nix,
+    installables: &[String],
+    system: &str,
+    nix_args: &[String],
+) -> Result<Vec<String>> {
+    let args = nix_command_args(system, nix_args);
+    installables
+        .iter()
+        .map(|installable| {
+            let expanded = flake::with_system(installable, system);
+            if nix.has_output(&expanded, &args)? {
+                Ok(expanded)
+            } else {
+                Err(CliError::FlakeResolutionError {
+                    flake: expanded,
+                    reason: format!("the flake has no such output for system {system}"),
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requested_system_wins() {
+        let nix = Nix::with_store("/nonexistent");
+        assert_eq!(
+            target_system(&nix, Some("aarch64-darwin".to_string())),
+            "aarch64-darwin"
+        );
+        assert_eq!(system_args("x86_64-linux"), ["--system", "x86_64-linux"]);
+    }
+
+    #[test]
+    fn test_nix_args_follow_system() -> Result<()> {
+        let nix_args = [
+            "--impure",
+            "--override-input",
+            "nixpkgs",
+            "../nixpkgs",
+            "-j4",
+        ]
+        .map(ToString::to_string);
+        check_nix_args(&nix_args)?;
+        assert_eq!(
+            nix_command_args("x86_64-linux", &nix_args[..1]),
+            ["--system", "x86_64-linux", "--impure"]
+        );
+
+        for reserved in ["--json", "--out-link=result", "--system"] {
+            assert!(
+                check_nix_args(&[reserved.to_string()]).is_err(),
+                "{reserved}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_warm_file() -> Result<()> {
+        let file = "# toolchain\n.#packages.{system}.default\n\n  nixpkgs#hello  # greeter\n";
+        let entries = read_warm_file(file.as_bytes())?;
+        assert_eq!(
+            entries,
+            [
+                WarmEntry {
+                    line: Some(2),
+                    installable: ".#packages.{system}.default".to_string(),
+                },
+                WarmEntry {
+                    line: Some(4),
+                    installable: "nixpkgs#hello".to_string(),
+                },
+            ]
+        );
+        assert_eq!(entries[1].to_string(), "line 4: nixpkgs#hello");
+        Ok(())
+    }
+}
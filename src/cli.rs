@@ -2,7 +2,22 @@
 //!
 //! Defines all CLI commands and their arguments using Clap.
 
+use crate::cache::compress::{CompressLevel, Compression};
+use crate::cache::narinfo_cache::parse_ttl;
+use crate::cache::signing::PublicKey;
+use crate::client::layout::NarInfoUrlBase;
+use crate::client::request::ExtraHeader;
+use crate::commands::cache_management::ListSort;
+use crate::commands::doctor::SubstituterPriority;
+use crate::nix::ClosureKind;
+use crate::utils::output::OutputFormat;
+use crate::utils::parallel::Parallelism;
+use crate::utils::progress::parse_byte_size;
+use crate::utils::time::{parse_duration, parse_time_bound};
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// FlakeCache CLI - Fast, production-grade Nix binary cache client
 #[derive(Parser, Debug)]
@@ -20,10 +35,81 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub verbose: bool,
 
+    /// Print only warnings, errors, and requested results
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
     /// FlakeCache server URL
     #[arg(long, global = true, default_value = "https://c.flakecache.com")]
     pub api_url: String,
 
+    /// Allow a plain http:// --api-url (sends the token unencrypted;
+    /// loopback hosts are always allowed)
+    #[arg(long, global = true)]
+    pub allow_insecure_http: bool,
+
+    /// Extra header for every API request, e.g. for an authenticating
+    /// proxy (repeatable; also read from $FLAKECACHE_EXTRA_HEADERS, one per
+    /// line)
+    #[arg(long = "header", global = true, value_name = "NAME: VALUE")]
+    pub headers: Vec<ExtraHeader>,
+
+    /// Let an extra Authorization header replace the FlakeCache token
+    #[arg(long, global = true)]
+    pub allow_authorization_header: bool,
+
+    /// Where the server serves NARInfos and NARs, relative to --api-url;
+    /// `{cache}` is replaced with the cache name (default: `{cache}`, also
+    /// settable as narinfo_url_base in the config file)
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    pub narinfo_url_base: Option<NarInfoUrlBase>,
+
+    /// Never contact the FlakeCache server; network commands fail immediately
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Directory for cached data (default: $FLAKECACHE_CACHE_DIR, then
+    /// $XDG_CACHE_HOME/flakecache, then ~/.cache/flakecache)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Scratch directory for compressing and downloading NARs (default:
+    /// $FLAKECACHE_TMPDIR, then $TMPDIR, then the system temp directory)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Cap total upload and download rate, in megabits per second
+    /// (default: $FLAKECACHE_MAX_BANDWIDTH, else unlimited)
+    #[arg(long, global = true, value_name = "MBPS")]
+    pub max_bandwidth: Option<u64>,
+
+    /// Reuse NARInfo lookups for this long within a run, e.g. 10m; 0 turns
+    /// the cache off (default: $FLAKECACHE_NARINFO_CACHE_TTL, else 5m)
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_ttl)]
+    pub narinfo_cache_ttl: Option<Duration>,
+
+    /// Write a Chrome trace of Nix, compression, and network phases to
+    /// FILE, for chrome://tracing (also enabled by FLAKECACHE_TRACE=chrome)
+    #[arg(long, global = true, value_name = "FILE")]
+    pub profile_output: Option<PathBuf>,
+
+    /// Output format for command results
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Print text results without color or table borders (also set by
+    /// $NO_COLOR; the default when stdout is not a terminal)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Print timestamps in UTC (the default)
+    #[arg(long, global = true, overrides_with = "local")]
+    pub utc: bool,
+
+    /// Print timestamps in the local time zone
+    #[arg(long, global = true, overrides_with = "utc")]
+    pub local: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -35,14 +121,20 @@ pub enum Commands {
     ///
     /// Interactive login flow via OAuth. Saves credentials to ~/.config/flakecache/config.toml
     ///
-    /// Example:
+    /// Examples:
     ///   flakecache login
+    ///   flakecache login --device-code    # On a machine without a browser
     #[command(visible_alias = "auth")]
     #[command(display_order = 1)]
     Login {
         /// Optional cache name to use by default
         #[arg(long)]
         cache: Option<String>,
+
+        /// Print a URL and code to approve on another device, then wait
+        /// for the approval (for SSH sessions and containers)
+        #[arg(long)]
+        device_code: bool,
     },
 
     /// Logout and clear saved credentials
@@ -61,7 +153,9 @@ pub enum Commands {
     ///   flakecache pull                    # Auto-detect and pull all dependencies
     ///   flakecache pull .#myapp            # Pull dependencies for .#myapp
     ///   flakecache pull nixpkgs#hello      # Pull dependencies for hello
-    #[command(visible_alias = "download")]
+    ///   flakecache pull --no-build .#myapp # Download only, never build
+    ///   flakecache pull --no-build --to-store ssh-ng://builder .#myapp
+    ///   flakecache resolve --report-missing --out missing.txt .#myapp
     #[command(visible_alias = "resolve")]
     #[command(display_order = 3)]
     Pull {
@@ -73,20 +167,95 @@ pub enum Commands {
         #[arg(long)]
         cache: Option<String>,
 
-        /// Maximum parallel downloads
+        /// Maximum parallel downloads, or `auto` to derive it from the CPU
+        /// count and --max-bandwidth
+        #[arg(long, value_name = "N|auto")]
+        parallelism: Option<Parallelism>,
+
+        /// Attempts per store path [env: FLAKECACHE_RETRIES] [default: 3]
+        #[arg(long)]
+        retries: Option<u32>,
+
+        /// Per-attempt download timeout in seconds [env: FLAKECACHE_TIMEOUT] [default: 300]
+        #[arg(long)]
+        timeout: Option<u64>,
+
+        /// Seconds to wait between attempts [env: FLAKECACHE_RETRY_DELAY] [default: 2]
+        #[arg(long)]
+        retry_delay: Option<u64>,
+
+        /// Report up front how much of the closure is already in the local
+        /// store (paths already valid are never fetched again)
+        #[arg(long)]
+        only_missing: bool,
+
+        /// Only download what the cache already has; never build locally,
+        /// and report the paths that would have needed a build
         #[arg(long)]
-        parallelism: Option<usize>,
+        no_build: bool,
+
+        /// Closure to fetch: `runtime` (the default) is what running the
+        /// output needs; `build` adds what building it needs (the outputs of
+        /// its input derivations), to prepare a machine to build it rather
+        /// than run it, often many times the runtime closure;
+        /// `with-outputs` adds the outputs of derivations in the closure
+        #[arg(long, value_name = "KIND")]
+        closure: Option<ClosureKind>,
+
+        /// Keep running, and resolve again whenever flake.nix or flake.lock
+        /// changes (stop with Ctrl-C)
+        #[arg(long)]
+        watch: bool,
+
+        /// Print a timing breakdown (decompression, network, total) at the end
+        #[arg(long)]
+        time: bool,
+
+        /// Append CI metrics (paths, bytes, cache-hit ratio, duration) to
+        /// FILE: Markdown for .md, else key=value (default:
+        /// $GITHUB_STEP_SUMMARY and $GITHUB_OUTPUT when set)
+        #[arg(long, value_name = "FILE")]
+        summary_file: Option<PathBuf>,
+
+        /// Write Prometheus metrics of the run to FILE, replacing it (for
+        /// node_exporter's textfile collector)
+        #[arg(long, value_name = "FILE")]
+        metrics_file: Option<PathBuf>,
+
+        /// Report the closure's download size and path count from the
+        /// cache's NARInfos, without downloading anything
+        #[arg(long)]
+        estimate: bool,
+
+        /// Refuse to pull when the estimated download exceeds SIZE
+        /// (e.g. 500M, 2G)
+        #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+        max_download_size: Option<u64>,
+
+        /// After resolving, copy the fetched paths and their closures to
+        /// this store with `nix copy --to` (e.g. ssh-ng://builder)
+        #[arg(long, value_name = "STORE_URI")]
+        to_store: Option<String>,
+
+        /// List the closure's paths that the cache does not have (their
+        /// NARInfo is a 404), one per line; other lookup errors still fail
+        #[arg(long)]
+        report_missing: bool,
+
+        /// Write the --report-missing list to FILE instead of stdout
+        #[arg(long, value_name = "FILE", requires = "report_missing")]
+        out: Option<PathBuf>,
     },
 
     /// Upload build artifacts to the cache
     ///
-    /// Uploads specified store paths (NARs) to FlakeCache.
-    /// Supports multiple input formats.
+    /// Uploads specified store paths (NARs) and their closures to
+    /// FlakeCache. Supports multiple input formats.
     ///
     /// Examples:
-    ///   flakecache push --cache my-cache
     ///   flakecache push --cache my-cache .#myapp
     ///   flakecache push --cache my-cache --store-path /nix/store/abc123-hello
+    ///   flakecache push --cache my-cache --closure with-outputs --store-path /nix/store/abc123-hello.drv
     #[command(visible_alias = "upload")]
     #[command(display_order = 4)]
     Push {
@@ -94,21 +263,146 @@ pub enum Commands {
         #[arg(long, required = true)]
         cache: String,
 
-        /// Optional flake output to push (e.g., .#hello)
-        /// If omitted, uploads all recent build outputs
+        /// Flake output to build and push (e.g., .#hello); at least one of
+        /// it, --store-path and --stdin must name something to push
         flake_output: Option<String>,
 
         /// Specific store path to upload
         #[arg(long)]
         store_path: Option<String>,
 
-        /// Maximum parallel uploads
+        /// Read additional whitespace-separated store paths from stdin
         #[arg(long)]
-        parallelism: Option<usize>,
+        stdin: bool,
+
+        /// Closure to push: `runtime` (the default) is what running the
+        /// paths needs; `with-outputs` adds the outputs of derivations in
+        /// it, which for `.drv` paths is the full build closure a remote
+        /// builder needs; `build` is the closure of the derivations that
+        /// produced the paths with every output in the store, so another
+        /// machine can rebuild them without downloading anything. Both are
+        /// often many times larger than the runtime closure
+        #[arg(long, value_name = "KIND")]
+        closure: Option<ClosureKind>,
+
+        /// Maximum parallel uploads, or `auto` to derive it from the CPU
+        /// count and --max-bandwidth
+        #[arg(long, value_name = "N|auto")]
+        parallelism: Option<Parallelism>,
+
+        /// Worker threads for xz/zstd compression (default: CPU count)
+        #[arg(long, value_name = "N")]
+        compression_threads: Option<usize>,
+
+        /// Refuse to push when the paths total more than SIZE uncompressed
+        /// (e.g. 2G), listing the largest ones
+        #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+        max_closure_size: Option<u64>,
+
+        /// Push even if --max-closure-size is exceeded
+        #[arg(long, requires = "max_closure_size")]
+        force: bool,
+
+        /// NAR compression: xz, zstd, gzip, or none (default: xz, or the
+        /// first of zstd and gzip that is installed)
+        #[arg(long, value_name = "FORMAT")]
+        compression: Option<Compression>,
+
+        /// Compression level: 0-9, fast, default, or max (xz -0..-9,
+        /// zstd -1..-19, gzip -1..-9, max = xz -9 / zstd --ultra -22)
+        #[arg(long, value_name = "LEVEL", default_value_t = CompressLevel::Default)]
+        compress_level: CompressLevel,
+
+        /// Keep compressed NARs under the cache directory and reuse them
+        /// for paths compressed earlier with the same settings
+        #[arg(long)]
+        reuse_compressed: bool,
+
+        /// Size cap for --reuse-compressed, evicting the least recently
+        /// used NARs first (e.g. 20G) [default: 10G]
+        #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, requires = "reuse_compressed")]
+        reuse_compressed_max_size: Option<u64>,
 
         /// Skip signature verification
         #[arg(long)]
         skip_verification: bool,
+
+        /// Sign each NARInfo with the Nix secret key in FILE
+        #[arg(long, value_name = "FILE", conflicts_with = "signing_key_env")]
+        signing_key: Option<PathBuf>,
+
+        /// Sign each NARInfo with the secret key in environment variable
+        /// VAR (Nix format, or a base64-encoded key file), e.g. a CI secret
+        #[arg(long, value_name = "VAR")]
+        signing_key_env: Option<String>,
+
+        /// Abort on the first path that fails to upload
+        #[arg(long, conflicts_with = "keep_going")]
+        fail_fast: bool,
+
+        /// Upload as many paths as possible and exit successfully even if some fail
+        #[arg(long)]
+        keep_going: bool,
+
+        /// Upload only the NARInfo, for NARs already on the server (e.g.
+        /// uploaded out-of-band); NARs are compressed locally to compute it
+        #[arg(long)]
+        narinfo_only: bool,
+
+        /// With --narinfo-only, check each NAR exists on the server first
+        #[arg(long, requires = "narinfo_only")]
+        verify_nar: bool,
+
+        /// After uploading each path, fetch its NARInfo and check its NAR
+        /// from the server; paths that are not served count as failed
+        #[arg(long)]
+        verify_after_push: bool,
+
+        /// Before uploading, compare each path's NarHash with the cache's
+        /// copy; a different NAR is reported as a non-reproducible build
+        /// and left alone instead of overwriting the cached one
+        #[arg(long)]
+        check_reproducible: bool,
+
+        /// With --check-reproducible, exit non-zero if any path differs
+        #[arg(long, requires = "check_reproducible")]
+        fail_on_mismatch: bool,
+
+        /// Wait until the server has persisted each upload (it processes
+        /// them asynchronously), for at most TIMEOUT per path (default 5m)
+        #[arg(
+            long,
+            value_name = "TIMEOUT",
+            num_args = 0..=1,
+            default_missing_value = "5m",
+            value_parser = parse_duration
+        )]
+        wait: Option<Duration>,
+
+        /// Print a timing breakdown (dump, compression, network, total) at the end
+        #[arg(long)]
+        time: bool,
+
+        /// Write a manifest of every uploaded path (hashes, sizes,
+        /// compression, timestamps); CBOR if the name ends in .cbor, else JSON
+        #[arg(long, value_name = "FILE")]
+        manifest: Option<PathBuf>,
+
+        /// Refuse to push from a flake whose git tree has uncommitted
+        /// changes (by default they only cause a warning)
+        #[arg(long, requires = "flake_output")]
+        require_clean: bool,
+
+        /// Append CI metrics (paths, bytes, compression, duration) to FILE:
+        /// Markdown for .md, else key=value (default: $GITHUB_STEP_SUMMARY
+        /// and $GITHUB_OUTPUT when set)
+        #[arg(long, value_name = "FILE")]
+        summary_file: Option<PathBuf>,
+
+        /// Write Prometheus metrics of the run to FILE, replacing it (for
+        /// node_exporter's textfile collector)
+        #[arg(long, value_name = "FILE")]
+        metrics_file: Option<PathBuf>,
     },
 
     /// List contents of a cache
@@ -118,57 +412,499 @@ pub enum Commands {
     /// Examples:
     ///   flakecache list --cache my-cache
     ///   flakecache list --cache my-cache --limit 50
+    ///   flakecache list --cache my-cache --output jsonl
+    ///   flakecache list --cache my-cache --since 2024-05-14 --until 2024-05-15
     #[command(display_order = 5)]
     List {
         /// Name of the cache to list
         #[arg(long, required = true)]
         cache: String,
 
-        /// Maximum number of results
+        /// Maximum number of results (page size with `--output jsonl`,
+        /// which streams every page)
         #[arg(long, default_value = "100")]
         limit: usize,
 
         /// Pagination cursor (from previous result)
         #[arg(long)]
         after: Option<String>,
+
+        /// Only paths uploaded since this time (e.g. 7d, 2024-05-14)
+        #[arg(long, value_parser = parse_time_bound)]
+        since: Option<DateTime<Utc>>,
+
+        /// Only paths uploaded before this time (e.g. 12h, 2024-05-15T09:00:00Z)
+        #[arg(long, value_parser = parse_time_bound)]
+        until: Option<DateTime<Utc>>,
+
+        /// Order of the listed paths; `--output jsonl` streams in server
+        /// order and ignores it
+        #[arg(long, value_enum, default_value_t = ListSort::Name)]
+        sort: ListSort,
+    },
+
+    /// Search one or more caches for store paths
+    ///
+    /// Searches every given cache, or every cache your token can access if
+    /// none are given, and prints the matches grouped by cache.
+    ///
+    /// Examples:
+    ///   flakecache search hello
+    ///   flakecache search openssl --cache team --cache staging
+    #[command(display_order = 11)]
+    Search {
+        /// Substring to match against store path names
+        pattern: String,
+
+        /// Cache to search (repeatable; default: all accessible caches)
+        #[arg(long = "cache")]
+        caches: Vec<String>,
+
+        /// Maximum number of matches per cache
+        #[arg(long, default_value = "100")]
+        limit: usize,
+    },
+
+    /// List the caches you have access to
+    ///
+    /// Examples:
+    ///   flakecache caches
+    ///   flakecache caches --output json
+    #[command(display_order = 12)]
+    Caches,
+
+    /// Manage binary cache signing keys
+    ///
+    /// Keys use the same format as `nix-store --generate-binary-cache-key`.
+    ///
+    /// Examples:
+    ///   flakecache key generate --name my-cache-1 --out ~/.config/flakecache
+    ///   flakecache key show-public ~/.config/flakecache/my-cache-1.sec
+    #[command(display_order = 13)]
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+
+    /// Save a store path's NAR and NARInfo for offline import
+    ///
+    /// Writes `<hash>.narinfo` and the compressed NAR it points at
+    /// (`nar/<file>`) into DIR, laid out like a file:// binary cache, so the
+    /// directory can be copied to a machine without network access.
+    ///
+    /// Examples:
+    ///   flakecache download --cache my-cache --store-path /nix/store/abc...-hello-2.12.1 --output-dir ./offline
+    ///   flakecache download --cache my-cache --hash abc... --output-dir ./offline
+    #[command(display_order = 17)]
+    Download {
+        /// Name of the cache to download from
+        #[arg(long, required = true)]
+        cache: String,
+
+        /// Hash part of the store path to download
+        #[arg(long, required_unless_present = "store_path", conflicts_with = "store_path")]
+        hash: Option<String>,
+
+        /// Store path to download
+        #[arg(long)]
+        store_path: Option<String>,
+
+        /// Directory to write the NAR and NARInfo to (created if missing)
+        #[arg(long, required = true, value_name = "DIR")]
+        output_dir: PathBuf,
+    },
+
+    /// Import NAR and NARInfo pairs saved by `download` into the local store
+    ///
+    /// Every `*.narinfo` in DIR is checked against its signature, FileHash,
+    /// NarHash and NarSize, then imported with `nix-store --import`.
+    /// Signatures must be by a key in nix.conf `trusted-public-keys` or one
+    /// pinned with `doctor --pin-key`, unless --trusted-key or
+    /// --no-check-sigs is given.
+    ///
+    /// Examples:
+    ///   flakecache import ./offline
+    ///   flakecache import ./offline --trusted-key my-cache-1:abc...=
+    #[command(display_order = 18)]
+    Import {
+        /// Directory written by `flakecache download`
+        dir: PathBuf,
+
+        /// Trust signatures by this public key (`name:base64`; repeatable)
+        #[arg(long = "trusted-key", value_name = "KEY")]
+        trusted_keys: Vec<PublicKey>,
+
+        /// Import without checking signatures
+        #[arg(long, conflicts_with = "trusted_keys")]
+        no_check_sigs: bool,
+    },
+
+    /// Inspect NAR files without importing them
+    ///
+    /// Accepts `.nar`, `.nar.xz`, and `.nar.zst` files; the compression is
+    /// detected from the file contents.
+    ///
+    /// Examples:
+    ///   flakecache nar extract hello.nar.xz --to ./hello
+    ///   flakecache nar cat hello.nar.xz bin/hello
+    #[command(display_order = 14)]
+    Nar {
+        #[command(subcommand)]
+        command: NarCommand,
     },
 
     /// Warm the cache with commonly-used store paths
     ///
     /// Pre-populate cache with dependencies to speed up future builds.
+    /// `{system}` in a flake output is replaced by the target system.
     ///
     /// Examples:
     ///   flakecache warm --cache my-cache
+    ///   flakecache warm --cache my-cache '.#packages.{system}.default'
+    ///   flakecache warm --cache my-cache --system aarch64-darwin '.#devShells.{system}.default'
+    ///   flakecache warm --cache my-cache --all-systems '.#packages.{system}.default'
+    ///   flakecache warm --cache my-cache --from-file warm.txt
+    ///   flakecache warm --cache my-cache --nix-arg=--impure '.#default' -- -j 4
     #[command(display_order = 6)]
     Warm {
         /// Name of the cache to warm
         #[arg(long, required = true)]
         cache: String,
 
-        /// Maximum parallel downloads
+        /// Flake outputs to build and push (e.g. .#packages.{system}.default)
+        installables: Vec<String>,
+
+        /// Also warm the installables listed in FILE, one per line
+        /// (`#` starts a comment); each is built and reported separately
+        #[arg(long, value_name = "FILE")]
+        from_file: Option<PathBuf>,
+
+        /// Target system (default: the local Nix `system` setting)
+        #[arg(long, value_name = "SYSTEM")]
+        system: Option<String>,
+
+        /// Build for every system the flake provides outputs for; systems
+        /// that cannot be built here (no remote builder) are skipped
+        #[arg(long, conflicts_with = "system")]
+        all_systems: bool,
+
+        /// Pass ARG to every `nix build` and `nix eval` (repeatable), e.g.
+        /// --nix-arg=--impure; arguments after `--` are passed too
+        #[arg(long = "nix-arg", value_name = "ARG", allow_hyphen_values = true)]
+        nix_args: Vec<String>,
+
+        /// Arguments after `--`, passed like --nix-arg
+        #[arg(last = true, value_name = "NIX_ARGS")]
+        nix_args_tail: Vec<String>,
+
+        /// Report how many paths the builds substituted from a cache and
+        /// how many they built locally, from Nix's structured log
+        #[arg(long)]
+        report_cache_hits: bool,
+
+        /// NAR compression: xz, zstd, gzip, or none (default: xz, or the
+        /// first of zstd and gzip that is installed)
+        #[arg(long, value_name = "FORMAT")]
+        compression: Option<Compression>,
+
+        /// Compression level: 0-9, fast, default, or max
+        #[arg(long, value_name = "LEVEL", default_value_t = CompressLevel::Default)]
+        compress_level: CompressLevel,
+
+        /// Keep compressed NARs under the cache directory and reuse them
+        /// for paths compressed earlier with the same settings
         #[arg(long)]
-        parallelism: Option<usize>,
+        reuse_compressed: bool,
+
+        /// Size cap for --reuse-compressed, evicting the least recently
+        /// used NARs first (e.g. 20G) [default: 10G]
+        #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, requires = "reuse_compressed")]
+        reuse_compressed_max_size: Option<u64>,
+
+        /// Maximum parallel uploads, or `auto` to derive it from the CPU
+        /// count and --max-bandwidth
+        #[arg(long, value_name = "N|auto")]
+        parallelism: Option<Parallelism>,
     },
 
     /// Show cache statistics and usage
     ///
     /// Examples:
     ///   flakecache stats --cache my-cache
+    ///   flakecache stats --cache my-cache --since 30d
     #[command(display_order = 7)]
     Stats {
         /// Name of the cache
         #[arg(long, required = true)]
         cache: String,
+
+        /// Start of the reporting window (e.g. 30d, 2024-05-01)
+        #[arg(long, value_parser = parse_time_bound)]
+        since: Option<DateTime<Utc>>,
+
+        /// End of the reporting window (e.g. 1d, 2024-06-01)
+        #[arg(long, value_parser = parse_time_bound)]
+        until: Option<DateTime<Utc>>,
+    },
+
+    /// Show recent changes to a cache: pushes, deletions and GC runs
+    ///
+    /// Lists who changed what and when, newest first, to answer "why is
+    /// this path gone".
+    ///
+    /// Examples:
+    ///   flakecache log --cache my-cache
+    ///   flakecache log --cache my-cache --since 7d --output json
+    #[command(display_order = 19)]
+    Log {
+        /// Name of the cache
+        #[arg(long, required = true)]
+        cache: String,
+
+        /// Maximum number of events
+        #[arg(long, default_value = "50")]
+        limit: usize,
+
+        /// Pagination cursor (from previous result)
+        #[arg(long)]
+        after: Option<String>,
+
+        /// Only events since this time (e.g. 7d, 2024-05-14)
+        #[arg(long, value_parser = parse_time_bound)]
+        since: Option<DateTime<Utc>>,
+
+        /// Only events before this time (e.g. 1d, 2024-06-01)
+        #[arg(long, value_parser = parse_time_bound)]
+        until: Option<DateTime<Utc>>,
+    },
+
+    /// Show or configure server-side garbage collection
+    ///
+    /// --keep-latest runs a client-side retention pass instead: paths are
+    /// grouped by package name (the store path name without its version)
+    /// and every path of all but the N most recently uploaded versions of
+    /// each is deleted. The outputs of a version (-dev, -bin, ...) are
+    /// kept or deleted together.
+    ///
+    /// Examples:
+    ///   flakecache gc --cache my-cache --status
+    ///   flakecache gc --cache my-cache --set-policy --older-than 30d
+    ///   flakecache gc --cache my-cache --keep-latest 3 --dry-run
+    #[command(display_order = 15)]
+    Gc {
+        /// Name of the cache
+        #[arg(long, required = true)]
+        cache: String,
+
+        /// Show the GC policy and the last run
+        #[arg(long, conflicts_with = "set_policy")]
+        status: bool,
+
+        /// Enable automatic GC with the given --older-than
+        #[arg(long, requires = "older_than")]
+        set_policy: bool,
+
+        /// Delete paths unused for this long (e.g. 30d, 12h)
+        #[arg(long, value_parser = parse_duration, requires = "set_policy")]
+        older_than: Option<Duration>,
+
+        /// Keep the N most recently uploaded versions of each package, with
+        /// all their outputs, and delete the rest; paths without an upload
+        /// time are kept
+        #[arg(long, value_name = "N", conflicts_with_all = ["status", "set_policy"])]
+        keep_latest: Option<usize>,
+
+        /// With --keep-latest, show what would be kept and deleted, grouped
+        /// by package, without deleting
+        #[arg(long, requires = "keep_latest")]
+        dry_run: bool,
+
+        /// With --keep-latest, delete without asking for confirmation
+        #[arg(long, requires = "keep_latest")]
+        force: bool,
     },
 
-    /// Check CLI version
+    /// Delete store paths from a cache
+    ///
+    /// Paths come from the command line, a file, or stdin (newline or
+    /// whitespace separated). Asks for confirmation unless --force is given.
+    ///
+    /// Examples:
+    ///   flakecache delete --cache my-cache /nix/store/abc...-hello-2.12.1
+    ///   flakecache delete --cache my-cache --from-file broken-paths.txt
+    ///   nix path-info ./result | flakecache delete --cache my-cache --stdin --force
+    #[command(display_order = 16)]
+    Delete {
+        /// Name of the cache
+        #[arg(long, required = true)]
+        cache: String,
+
+        /// Store paths to delete
+        store_paths: Vec<String>,
+
+        /// Read store paths from FILE
+        #[arg(long, value_name = "FILE")]
+        from_file: Option<PathBuf>,
+
+        /// Read store paths from stdin (requires --force)
+        #[arg(long, requires = "force")]
+        stdin: bool,
+
+        /// Delete without asking for confirmation
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Install a Nix post-build-hook that pushes every local build
+    ///
+    /// Writes a wrapper script that runs `flakecache push --stdin` with the
+    /// built `$OUT_PATHS`, and prints the line to add to nix.conf.
+    ///
+    /// Examples:
+    ///   flakecache install-hook --cache my-cache
+    ///   sudo flakecache install-hook --cache my-cache --write
+    #[command(display_order = 9)]
+    InstallHook {
+        /// Name of the cache to push to
+        #[arg(long, required = true)]
+        cache: String,
+
+        /// Where to write the wrapper script (default: next to the config file)
+        #[arg(long)]
+        path: Option<PathBuf>,
+
+        /// Append the post-build-hook line to nix.conf instead of printing it
+        #[arg(long)]
+        write: bool,
+
+        /// nix.conf to update with --write
+        #[arg(long, default_value = crate::commands::hook::DEFAULT_NIX_CONF)]
+        nix_conf: PathBuf,
+    },
+
+    /// Diagnose the local FlakeCache setup
+    ///
+    /// Checks Nix, credentials, server connectivity, and nix.conf substituter
+    /// and trusted key settings. Exits non-zero if Nix, the credentials or
+    /// the connection, which push and pull need, are not working.
+    ///
+    /// Examples:
+    ///   flakecache doctor
+    ///   flakecache doctor --output json
+    ///   sudo flakecache doctor --fix --cache my-cache --priority before
+    ///   flakecache doctor --pin-key --cache my-cache
+    #[command(display_order = 10)]
+    Doctor {
+        /// Add the cache's substituter to nix.conf before running the checks
+        #[arg(long)]
+        fix: bool,
+
+        /// Cache for --fix, --show-key and --pin-key (default: the
+        /// configured default cache)
+        #[arg(long)]
+        cache: Option<String>,
+
+        /// Show the public keys the cache advertises in its nix-cache-info,
+        /// with their fingerprints
+        #[arg(long)]
+        show_key: bool,
+
+        /// Trust the cache's advertised public key for `import` after
+        /// confirming its fingerprint on the terminal. Compare the
+        /// fingerprint with one from the cache owner: the server decides
+        /// what it advertises.
+        #[arg(long)]
+        pin_key: bool,
+
+        /// Substituter priority for --fix: a number, or 'before'/'after'
+        /// cache.nixos.org (priority 40; lower is tried first)
+        #[arg(long, requires = "fix")]
+        priority: Option<SubstituterPriority>,
+
+        /// nix.conf to update with --fix
+        #[arg(long, default_value = crate::commands::hook::DEFAULT_NIX_CONF)]
+        nix_conf: PathBuf,
+    },
+
+    /// Check CLI version and build information
+    ///
+    /// Reports the git commit, rustc version, target, and enabled features
+    /// the binary was built with.
     ///
     /// Examples:
     ///   flakecache version
+    ///   flakecache version --output json
     #[command(display_order = 8)]
     Version,
 }
 
+/// `flakecache key` subcommands
+#[derive(Subcommand, Debug)]
+pub enum KeyCommand {
+    /// Generate an Ed25519 key pair (`<name>.sec` and `<name>.pub`)
+    Generate {
+        /// Key name, conventionally `<cache>-<n>` (e.g. my-cache-1)
+        #[arg(long, required = true)]
+        name: String,
+
+        /// Directory to write the key files to
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+
+    /// Print the public key line for a secret key file
+    ShowPublic {
+        /// Secret key file written by `key generate` or `nix-store`
+        secret_file: PathBuf,
+    },
+}
+
+/// `flakecache nar` subcommands
+#[derive(Subcommand, Debug)]
+pub enum NarCommand {
+    /// Unpack a NAR into a plain directory tree
+    Extract {
+        /// NAR file, optionally compressed
+        file: PathBuf,
+
+        /// Path to create (must not exist)
+        #[arg(long, required = true)]
+        to: PathBuf,
+    },
+
+    /// Print one file from inside a NAR
+    Cat {
+        /// NAR file, optionally compressed
+        file: PathBuf,
+
+        /// Path of the file inside the archive (e.g. bin/hello)
+        path: String,
+    },
+}
+
+impl Commands {
+    /// Whether the command cannot do anything useful without the server
+    ///
+    /// `pull` is excluded: offline it degrades to local Nix substitution.
+    #[must_use]
+    pub const fn requires_network(&self) -> bool {
+        matches!(
+            self,
+            Self::Login { .. }
+                | Self::Push { .. }
+                | Self::List { .. }
+                | Self::Search { .. }
+                | Self::Caches
+                | Self::Gc { .. }
+                | Self::Delete { .. }
+                | Self::Warm { .. }
+                | Self::Stats { .. }
+                | Self::Log { .. }
+                | Self::Download { .. }
+        )
+    }
+}
+
 impl Cli {
     /// Parse command-line arguments
     ///
@@ -2,3 +2,816 @@
 //!
 //! Implements CBOR (Concise Binary Object Representation) encoding/decoding
 //! for efficient binary protocol communication with the FlakeCache server.
+
+use super::layout::narinfo_url_base;
+use super::request::{
+    check_transport, extra_headers, new_request_id, user_agent, ExtraHeader, REQUEST_ID_HEADER,
+};
+use super::response::{status_error, transport_error};
+use crate::config::default_timeout;
+use crate::error::{CliError, Result};
+use crate::utils::output;
+use crate::utils::throttle::{self, RateLimiter};
+use reqwest::header::{ACCEPT, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Body, Client, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio_util::io::ReaderStream;
+
+/// Media type used by the CBOR API
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// First pause between [`CborClient::await_persisted`] polls; doubles up to
+/// [`MAX_POLL_INTERVAL`]
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Longest pause between [`CborClient::await_persisted`] polls
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Times [`CborClient::get_to_writer`] resumes a broken download with a
+/// `Range` request before giving up
+pub const MAX_RESUMES: u32 = 3;
+
+/// Encode a value as CBOR
+///
+/// # Errors
+///
+/// Returns [`CliError::SerializationError`] if the value cannot be encoded.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    ciborium::into_writer(value, &mut buf)?;
+    Ok(buf)
+}
+
+/// Decode a CBOR value
+///
+/// # Errors
+///
+/// Returns [`CliError::DeserializationError`] if the bytes are not valid CBOR
+/// for `T`.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(ciborium::from_reader(bytes)?)
+}
+
+/// HTTP client speaking the FlakeCache CBOR API
+#[derive(Debug, Clone)]
+pub struct CborClient {
+    http: Client,
+    base_url: String,
+    token: Option<String>,
+    limiter: Option<RateLimiter>,
+    headers: Vec<ExtraHeader>,
+}
+
+impl CborClient {
+    /// Create a client for `base_url` (e.g. `https://c.flakecache.com`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Http`] if the HTTP client cannot be built, or
+    /// [`CliError::InvalidArgument`] for a plain `http` URL that is not
+    /// allowed (see [`check_transport`]).
+    pub fn new(base_url: impl Into<String>, token: Option<String>) -> Result<Self> {
+        Self::with_timeout(base_url, token, Duration::from_secs(default_timeout()))
+    }
+
+    /// Create a client with an explicit request timeout
+    ///
+    /// Uploads and downloads are paced by the process-wide `--max-bandwidth`
+    /// limiter, if one is set, and every request carries the process-wide
+    /// `--header`s.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Http`] if the HTTP client cannot be built, or
+    /// [`CliError::InvalidArgument`] for a plain `http` URL that is not
+    /// allowed (see [`check_transport`]).
+    pub fn with_timeout(
+        base_url: impl Into<String>,
+        token: Option<String>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        check_transport(&base_url)?;
+        let http = Client::builder()
+            .timeout(timeout)
+            .user_agent(user_agent())
+            .build()
+            .map_err(|e| CliError::Http(format!("failed to build HTTP client: {e}")))?;
+
+        Ok(Self {
+            http,
+            base_url,
+            token: token.filter(|t| !t.is_empty()),
+            limiter: throttle::global(),
+            headers: extra_headers().to_vec(),
+        })
+    }
+
+    /// Wait until `bytes` may be sent under the bandwidth limit
+    async fn throttle(&self, bytes: usize) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(bytes).await;
+        }
+    }
+
+    /// Base URL requests are made against
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Absolute URL for an API path
+    #[must_use]
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    /// Start a request with authentication, CBOR accept, request id, and
+    /// extra headers
+    ///
+    /// An extra `Authorization` header takes the place of the bearer token.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = self.url(path);
+        let request_id = new_request_id();
+        output::debug(format_args!(
+            "{method} {url} ({REQUEST_ID_HEADER}: {request_id})"
+        ));
+
+        let mut builder = self
+            .http
+            .request(method, url)
+            .header(ACCEPT, CBOR_CONTENT_TYPE)
+            .header(REQUEST_ID_HEADER, request_id);
+        for header in &self.headers {
+            builder = builder.header(header.name(), header.value());
+        }
+        if let Some(token) = &self.token {
+            if !self.headers.iter().any(ExtraHeader::is_authorization) {
+                builder = builder.bearer_auth(token);
+            }
+        }
+        builder
+    }
+
+    /// Send a request, mapping transport errors and non-2xx statuses
+    async fn send(&self, builder: RequestBuilder) -> Result<Response> {
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| transport_error(&self.base_url, &e))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            Err(status_error(status.as_u16(), &body))
+        }
+    }
+
+    /// Read and decode a CBOR response body
+    async fn read_cbor<T: DeserializeOwned>(&self, response: Response) -> Result<T> {
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| transport_error(&self.base_url, &e))?;
+        decode(&bytes)
+    }
+
+    /// GET a CBOR resource
+    ///
+    /// # Errors
+    ///
+    /// Returns a network, HTTP status, or decode error.
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let response = self.send(self.request(Method::GET, path)).await?;
+        self.read_cbor(response).await
+    }
+
+    /// GET a large CBOR resource (e.g. a listing page), decoding it from
+    /// the body as it arrives
+    ///
+    /// [`get`](Self::get) holds the whole body before decoding it, so a big
+    /// response peaks at twice its size: once as bytes and once as values.
+    /// This reads the body a chunk at a time instead. Decoding is
+    /// synchronous, so it blocks this worker thread through
+    /// [`tokio::task::block_in_place`]; on a current-thread runtime, where
+    /// that is not possible, the body is buffered as with `get`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a network, HTTP status, or decode error.
+    pub async fn get_streamed<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let mut response = self.send(self.request(Method::GET, path)).await?;
+        let handle = Handle::current();
+        if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+            return self.read_cbor(response).await;
+        }
+        let mut body = BlockingBody {
+            response: &mut response,
+            handle,
+            chunk: Vec::new(),
+            pos: 0,
+            error: None,
+        };
+        let decoded = tokio::task::block_in_place(|| ciborium::from_reader(&mut body));
+        match (decoded, body.error) {
+            (Ok(value), _) => Ok(value),
+            // A broken connection surfaces as an I/O error from the decoder
+            (Err(_), Some(e)) => Err(transport_error(&self.base_url, &e)),
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+
+    /// GET a text document (e.g. a `.narinfo`)
+    ///
+    /// # Errors
+    ///
+    /// Returns a network or HTTP status error.
+    pub async fn get_text(&self, path: &str) -> Result<String> {
+        let response = self.send(self.request(Method::GET, path)).await?;
+        response
+            .text()
+            .await
+            .map_err(|e| transport_error(&self.base_url, &e))
+    }
+
+    /// GET a binary resource (e.g. a compressed NAR), streaming it into
+    /// `writer` instead of buffering it in memory
+    ///
+    /// If the connection breaks mid-body and the server advertised
+    /// `Accept-Ranges: bytes`, the download resumes where it stopped with a
+    /// `Range` request, up to [`MAX_RESUMES`] times. The rest of the body is
+    /// appended to `writer`, so nothing already written is fetched twice.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::TransferInterrupted`] if the body is shorter or
+    /// longer than its `Content-Length` or a resume is refused, a network or
+    /// HTTP status error, or an I/O error from `writer`.
+    pub async fn get_to_writer<W: Write + Send>(&self, path: &str, writer: &mut W) -> Result<u64> {
+        let mut response = self.send(self.request(Method::GET, path)).await?;
+        let expected = response.content_length();
+        let resumable = accepts_ranges(&response);
+        let mut written = 0;
+        let mut resumes = 0;
+        loop {
+            let broken = match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    self.throttle(chunk.len()).await;
+                    writer.write_all(&chunk)?;
+                    written += chunk.len() as u64;
+                    continue;
+                }
+                Ok(None) if expected.is_some_and(|expected| written < expected) => {
+                    CliError::TransferInterrupted(format!(
+                        "{}: connection closed after {written} bytes",
+                        self.url(path)
+                    ))
+                }
+                Ok(None) => break,
+                Err(e) => transport_error(&self.base_url, &e),
+            };
+            if !resumable || resumes == MAX_RESUMES {
+                return Err(broken);
+            }
+            resumes += 1;
+            output::debug(format_args!(
+                "{}: {broken}; resuming at byte {written} ({resumes}/{MAX_RESUMES})",
+                self.url(path)
+            ));
+            response = self.resume(path, written).await?;
+        }
+        match expected {
+            Some(expected) if expected != written => Err(CliError::TransferInterrupted(format!(
+                "{}: received {written} of {expected} bytes",
+                self.url(path)
+            ))),
+            _ => Ok(written),
+        }
+    }
+
+    /// GET the rest of `path` from byte `offset` on
+    ///
+    /// Fails unless the server answers `206 Partial Content` starting
+    /// exactly at `offset`; anything else would corrupt the appended file.
+    async fn resume(&self, path: &str, offset: u64) -> Result<Response> {
+        let builder = self
+            .request(Method::GET, path)
+            .header(RANGE, format!("bytes={offset}-"));
+        let response = self.send(builder).await?;
+        let start = response
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(content_range_start);
+        if response.status() != StatusCode::PARTIAL_CONTENT || start != Some(offset) {
+            return Err(CliError::TransferInterrupted(format!(
+                "{}: server did not resume the download at byte {offset}",
+                self.url(path)
+            )));
+        }
+        Ok(response)
+    }
+
+    /// Whether a resource exists (`HEAD`; 404 means absent)
+    ///
+    /// # Errors
+    ///
+    /// Returns a network error, or an HTTP status error other than 404.
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        match self.send(self.request(Method::HEAD, path)).await {
+            Ok(_) => Ok(true),
+            Err(CliError::ApiError { status: 404, .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Wait until an asynchronously processed upload is persisted
+    ///
+    /// Polls the NARInfo of store path `hash` (`HEAD /{cache}/{hash}.narinfo`)
+    /// with backoff until the server serves it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Timeout`] if it is not served within `timeout`,
+    /// or a network or HTTP status error other than 404.
+    pub async fn await_persisted(&self, cache: &str, hash: &str, timeout: Duration) -> Result<()> {
+        let path = narinfo_url_base().narinfo_path(cache, hash);
+        let deadline = std::time::Instant::now() + timeout;
+        let mut interval = MIN_POLL_INTERVAL;
+        loop {
+            if self.exists(&path).await? {
+                return Ok(());
+            }
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(CliError::Timeout(format!(
+                    "{hash} was not persisted in {cache} within {}s",
+                    timeout.as_secs()
+                )));
+            }
+            tokio::time::sleep(interval.min(remaining)).await;
+            interval = (interval * 2).min(MAX_POLL_INTERVAL);
+        }
+    }
+
+    /// DELETE a resource
+    ///
+    /// # Errors
+    ///
+    /// Returns a network or HTTP status error.
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        let _ = self.send(self.request(Method::DELETE, path)).await?;
+        Ok(())
+    }
+
+    /// POST a CBOR body and decode the CBOR response
+    ///
+    /// # Errors
+    ///
+    /// Returns an encode, network, HTTP status, or decode error.
+    pub async fn post<B, T>(&self, path: &str, body: &B) -> Result<T>
+    where
+        B: Serialize + Sync + ?Sized,
+        T: DeserializeOwned,
+    {
+        let builder = self
+            .request(Method::POST, path)
+            .header(CONTENT_TYPE, CBOR_CONTENT_TYPE)
+            .body(encode(body)?);
+        let response = self.send(builder).await?;
+        self.read_cbor(response).await
+    }
+
+    /// Start an asynchronously processed PUT (`X-Async: true`)
+    fn put(&self, path: &str, content_type: &str) -> RequestBuilder {
+        self.request(Method::PUT, path)
+            .header(CONTENT_TYPE, content_type)
+            .header("X-Async", "true")
+    }
+
+    /// PUT raw bytes (e.g. a compressed NAR)
+    ///
+    /// The server processes uploads asynchronously (`X-Async: true`), so a
+    /// successful return means the upload was accepted, not yet persisted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a network or HTTP status error.
+    pub async fn put_binary(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        self.throttle(data.len()).await;
+        let builder = self.put(path, "application/octet-stream").body(data);
+        let _ = self.send(builder).await?;
+        Ok(())
+    }
+
+    /// PUT a file, streaming it from disk instead of buffering it in memory
+    ///
+    /// Behaves like [`Self::put_binary`] but keeps memory use flat for
+    /// multi-GB NARs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if the file cannot be opened, or a
+    /// network or HTTP status error.
+    pub async fn put_file(&self, path: &str, file: &Path) -> Result<()> {
+        let file_error = |e: std::io::Error| CliError::FileError {
+            path: file.to_path_buf(),
+            reason: e.to_string(),
+        };
+        let handle = tokio::fs::File::open(file).await.map_err(file_error)?;
+        let len = handle.metadata().await.map_err(file_error)?.len();
+
+        let stream = ReaderStream::new(handle);
+        let body = match self.limiter.clone() {
+            Some(limiter) => Body::wrap_stream(throttle::throttle_stream(stream, limiter)),
+            None => Body::wrap_stream(stream),
+        };
+        let builder = self
+            .put(path, "application/octet-stream")
+            .header(CONTENT_LENGTH, len.to_string())
+            .body(body);
+        let _ = self.send(builder).await?;
+        Ok(())
+    }
+
+    /// PUT a text document (e.g. a `.narinfo`) with the given content type
+    ///
+    /// # Errors
+    ///
+    /// Returns a network or HTTP status error.
+    pub async fn put_text(&self, path: &str, content_type: &str, body: String) -> Result<()> {
+        self.throttle(body.len()).await;
+        let builder = self.put(path, content_type).body(body);
+        let _ = self.send(builder).await?;
+        Ok(())
+    }
+
+    /// PUT a CBOR-encoded body
+    ///
+    /// Like [`Self::put_binary`], the server may still be processing the
+    /// request when this returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns an encode, network, or HTTP status error.
+    pub async fn put_cbor<B: Serialize + Sync + ?Sized>(&self, path: &str, body: &B) -> Result<()> {
+        let body = encode(body)?;
+        self.throttle(body.len()).await;
+        let builder = self.put(path, CBOR_CONTENT_TYPE).body(body);
+        let _ = self.send(builder).await?;
+        Ok(())
+    }
+}
+
+/// A response body read with blocking calls, one chunk at a time, from
+/// inside [`tokio::task::block_in_place`]
+struct BlockingBody<'a> {
+    response: &'a mut Response,
+    handle: Handle,
+    chunk: Vec<u8>,
+    pos: usize,
+    /// Transport error that ended the body early
+    error: Option<reqwest::Error>,
+}
+
+impl Read for BlockingBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.handle.block_on(self.response.chunk()) {
+                Ok(Some(chunk)) => {
+                    self.chunk = chunk.into();
+                    self.pos = 0;
+                }
+                Ok(None) => return Ok(0),
+                Err(e) => {
+                    let io = std::io::Error::other(e.to_string());
+                    self.error = Some(e);
+                    return Err(io);
+                }
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Whether the server supports byte ranges for this resource
+fn accepts_ranges(response: &Response) -> bool {
+    response
+        .headers()
+        .get(ACCEPT_RANGES)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("bytes"))
+}
+
+/// First byte of a `Content-Range: bytes <first>-<last>/<total>` header
+fn content_range_start(value: &str) -> Option<u64> {
+    let (unit, range) = value.trim().split_once(' ')?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    range.split_once('-')?.0.parse().ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    type TestResult = std::result::Result<(), Box<dyn std::error::Error>>;
+
+    /// One-shot HTTP server that answers a single request with a canned response
+    pub struct MockServer {
+        pub base_url: String,
+        handle: JoinHandle<std::io::Result<String>>,
+    }
+
+    impl MockServer {
+        /// Serve `body` with `status` to the next incoming request
+        pub fn respond(status: u16, body: Vec<u8>) -> std::io::Result<Self> {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let base_url = format!("http://{}", listener.local_addr()?);
+
+            let handle = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept()?;
+                let request = read_request(&mut stream)?;
+                let head = format!(
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: {CBOR_CONTENT_TYPE}\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(head.as_bytes())?;
+                stream.write_all(&body)?;
+                Ok(request)
+            });
+
+            Ok(Self { base_url, handle })
+        }
+
+        /// Answer one connection after another with the raw `responses`,
+        /// closing each connection after writing its response
+        pub fn respond_raw(responses: Vec<Vec<u8>>) -> std::io::Result<Self> {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let base_url = format!("http://{}", listener.local_addr()?);
+
+            let handle = std::thread::spawn(move || {
+                let mut requests = String::new();
+                for response in responses {
+                    let (mut stream, _) = listener.accept()?;
+                    requests.push_str(&read_request(&mut stream)?);
+                    stream.write_all(&response)?;
+                }
+                Ok(requests)
+            });
+
+            Ok(Self { base_url, handle })
+        }
+
+        /// Wait for the server and return the request line and headers it saw
+        pub fn request(self) -> String {
+            self.handle
+                .join()
+                .ok()
+                .and_then(std::result::Result::ok)
+                .unwrap_or_default()
+        }
+    }
+
+    /// Read one HTTP/1.1 request (head and `Content-Length` body)
+    fn read_request(stream: &mut impl Read) -> std::io::Result<String> {
+        let mut data = Vec::new();
+        let mut buf = [0_u8; 4096];
+        let head_end = loop {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+            data.extend_from_slice(&buf[..n]);
+            if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+        let content_length = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+
+        let mut remaining = content_length.saturating_sub(data.len() - head_end);
+        while remaining > 0 {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            remaining = remaining.saturating_sub(n);
+        }
+
+        Ok(head)
+    }
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Ping {
+        ok: bool,
+        count: u32,
+    }
+
+    fn client(server: &MockServer) -> Result<CborClient> {
+        CborClient::new(server.base_url.clone(), Some("secret".to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_get_decodes_cbor() -> TestResult {
+        let expected = Ping { ok: true, count: 3 };
+        let server = MockServer::respond(200, encode(&expected)?)?;
+
+        let got: Ping = client(&server)?.get("/api/v2/cbor/ping").await?;
+        assert_eq!(got, expected);
+
+        let request = server.request();
+        assert!(request.starts_with("GET /api/v2/cbor/ping "));
+        assert!(request
+            .to_ascii_lowercase()
+            .contains("authorization: bearer secret"));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_streamed_decodes_from_the_body() -> TestResult {
+        // Large enough to arrive in several chunks
+        let expected: Vec<Ping> = (0..20_000).map(|count| Ping { ok: true, count }).collect();
+        let server = MockServer::respond(200, encode(&expected)?)?;
+
+        let got: Vec<Ping> = client(&server)?.get_streamed("/api/v2/cbor/ping").await?;
+        assert_eq!(got, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_streamed_buffers_on_current_thread() -> TestResult {
+        let server = MockServer::respond(200, b"not cbor".to_vec())?;
+        let result: Result<Ping> = client(&server)?.get_streamed("/api/v2/cbor/ping").await;
+        assert!(matches!(result, Err(CliError::DeserializationError(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_round_trip() -> TestResult {
+        let server = MockServer::respond(200, encode(&Ping { ok: true, count: 1 })?)?;
+
+        let got: Ping = client(&server)?
+            .post(
+                "upload",
+                &Ping {
+                    ok: false,
+                    count: 0,
+                },
+            )
+            .await?;
+        assert!(got.ok);
+        assert!(server.request().starts_with("POST /upload "));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_binary_is_async() -> TestResult {
+        let server = MockServer::respond(202, Vec::new())?;
+
+        client(&server)?
+            .put_binary("nar/abc", vec![1, 2, 3])
+            .await?;
+
+        let request = server.request().to_ascii_lowercase();
+        assert!(request.starts_with("put /nar/abc "));
+        assert!(request.contains("x-async: true"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_put_file_streams_with_length() -> TestResult {
+        let server = MockServer::respond(202, Vec::new())?;
+        let file = std::env::temp_dir().join(format!("flakecache-put-{}", std::process::id()));
+        std::fs::write(&file, b"compressed nar")?;
+
+        let result = client(&server)?.put_file("nar/abc/xz", &file).await;
+        std::fs::remove_file(&file)?;
+        result?;
+
+        let request = server.request().to_ascii_lowercase();
+        assert!(request.starts_with("put /nar/abc/xz "));
+        assert!(request.contains("content-length: 14"));
+        assert!(request.contains("x-async: true"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_to_writer_resumes_with_range() -> TestResult {
+        let server = MockServer::respond_raw(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nAccept-Ranges: bytes\r\n\
+              Connection: close\r\n\r\n0123"
+                .to_vec(),
+            b"HTTP/1.1 206 Partial Content\r\nContent-Length: 6\r\n\
+              Content-Range: bytes 4-9/10\r\nConnection: close\r\n\r\n456789"
+                .to_vec(),
+        ])?;
+
+        let mut body = Vec::new();
+        let written = client(&server)?.get_to_writer("nar/abc", &mut body).await?;
+        assert_eq!(written, 10);
+        assert_eq!(body, b"0123456789");
+        assert!(server
+            .request()
+            .to_ascii_lowercase()
+            .contains("range: bytes=4-"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_range_start() {
+        assert_eq!(content_range_start("bytes 4-9/10"), Some(4));
+        assert_eq!(content_range_start("bytes 0-0/*"), Some(0));
+        assert_eq!(content_range_start("bytes */10"), None);
+        assert_eq!(content_range_start("items 4-9/10"), None);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_is_api_error() -> TestResult {
+        let server = MockServer::respond(404, b"no such cache".to_vec())?;
+
+        let result: Result<Ping> = client(&server)?.get("missing").await;
+        assert!(matches!(
+            result,
+            Err(CliError::ApiError { status: 404, .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exists_maps_not_found_to_false() -> TestResult {
+        let server = MockServer::respond(404, Vec::new())?;
+        assert!(!client(&server)?.exists("nar/abc/xz").await?);
+        assert!(server.request().starts_with("HEAD /nar/abc/xz "));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_await_persisted() -> TestResult {
+        let server = MockServer::respond(200, Vec::new())?;
+        client(&server)?
+            .await_persisted("my-cache", "abc", Duration::from_secs(5))
+            .await?;
+        assert!(server.request().starts_with("HEAD /my-cache/abc.narinfo "));
+
+        let server = MockServer::respond(404, Vec::new())?;
+        let result = client(&server)?
+            .await_persisted("my-cache", "abc", Duration::ZERO)
+            .await;
+        assert!(matches!(result, Err(CliError::Timeout(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forbidden_is_auth_error() -> TestResult {
+        let server = MockServer::respond(403, Vec::new())?;
+
+        let result = client(&server)?
+            .put_cbor("narinfo", &Ping { ok: true, count: 0 })
+            .await;
+        assert!(matches!(result, Err(CliError::AuthFailed(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_retryable() -> TestResult {
+        let server = MockServer::respond(503, b"overloaded".to_vec())?;
+
+        let result: Result<Ping> = client(&server)?.get("busy").await;
+        assert!(result.is_err_and(|e| e.is_retryable()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_cbor_is_decode_error() -> TestResult {
+        let server = MockServer::respond(200, vec![0xff, 0x00, 0x13])?;
+
+        let result: Result<Ping> = client(&server)?.get("garbage").await;
+        assert!(matches!(result, Err(CliError::DeserializationError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Fast connectivity probing
+//!
+//! Checks that the FlakeCache server is reachable with a short TCP connect
+//! before commands start long-running requests, so offline machines fail
+//! immediately with a clear error instead of waiting for HTTP timeouts.
+
+use crate::error::{CliError, Result};
+use reqwest::Url;
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long the probe waits for a TCP connection
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Result of a connectivity check against the API server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Connectivity {
+    /// The server accepted a TCP connection
+    Online,
+    /// Offline mode was requested with `--offline`
+    OfflineMode,
+    /// The server could not be reached
+    Unreachable(String),
+}
+
+impl Connectivity {
+    /// Probe `api_url` unless offline mode is enabled
+    #[must_use]
+    pub fn check(api_url: &str, offline: bool) -> Self {
+        if offline {
+            return Self::OfflineMode;
+        }
+        match probe(api_url, PROBE_TIMEOUT) {
+            Ok(()) => Self::Online,
+            Err(reason) => Self::Unreachable(reason),
+        }
+    }
+
+    /// `true` if the server can be used
+    #[must_use]
+    pub const fn is_online(&self) -> bool {
+        matches!(self, Self::Online)
+    }
+
+    /// Convert to a command result for operations that need the server
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::ConnectionError`] (exit code 4) when offline.
+    pub fn require(self, api_url: &str) -> Result<()> {
+        let reason = match self {
+            Self::Online => return Ok(()),
+            Self::OfflineMode => "offline mode is enabled (--offline)".to_string(),
+            Self::Unreachable(reason) => format!("server unreachable ({reason})"),
+        };
+        Err(CliError::ConnectionError {
+            host: host_of(api_url),
+            reason,
+        })
+    }
+}
+
+impl fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Online => write!(f, "online"),
+            Self::OfflineMode => write!(f, "offline (--offline)"),
+            Self::Unreachable(reason) => write!(f, "offline ({reason})"),
+        }
+    }
+}
+
+/// Host part of a URL, falling back to the raw string
+fn host_of(api_url: &str) -> String {
+    Url::parse(api_url)
+        .ok()
+        .and_then(|url| url.host_str().map(ToString::to_string))
+        .unwrap_or_else(|| api_url.to_string())
+}
+
+/// Attempt a TCP connection to the host and port of `api_url`
+fn probe(api_url: &str, timeout: Duration) -> std::result::Result<(), String> {
+    let url = Url::parse(api_url).map_err(|e| format!("invalid URL: {e}"))?;
+    let host = url.host_str().ok_or("URL has no host")?;
+    let port = url.port_or_known_default().ok_or("URL has no port")?;
+
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("DNS lookup failed: {e}"))?;
+
+    let mut last_error = format!("no addresses found for {host}");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_mode_skips_probe() {
+        let status = Connectivity::check("https://c.flakecache.com", true);
+        assert_eq!(status, Connectivity::OfflineMode);
+        assert!(matches!(
+            status.require("https://c.flakecache.com"),
+            Err(CliError::ConnectionError { ref host, .. }) if host == "c.flakecache.com"
+        ));
+    }
+
+    #[test]
+    fn test_unreachable_port_is_offline() {
+        // Port 1 on localhost is essentially never listening
+        let status = Connectivity::check("http://127.0.0.1:1", false);
+        assert!(!status.is_online());
+    }
+}
@@ -0,0 +1,163 @@
+//! Where the server serves each cache's binary cache
+//!
+//! NARInfos and NARs are read with the Nix binary cache protocol from one
+//! root per cache: `<root>/<hash>.narinfo`, and each NAR at
+//! `<root>/<URL>` where `URL` comes from its NARInfo. The same root is the
+//! substituter URL written to nix.conf. FlakeCache serves it at
+//! `<api url>/<cache>` (see `API_MAPPING.md`); self-hosted deployments
+//! with other routing override the root with `--narinfo-url-base` or
+//! `narinfo_url_base` in the config file. Uploads use the separate
+//! `api/v1` endpoints and are not affected.
+
+use crate::error::{CliError, Result};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Placeholder replaced with the cache name
+pub const CACHE_PLACEHOLDER: &str = "{cache}";
+
+/// Binary cache root on the FlakeCache server
+pub const DEFAULT_NARINFO_URL_BASE: &str = "{cache}";
+
+/// Process-wide `--narinfo-url-base`
+static NARINFO_URL_BASE: OnceLock<NarInfoUrlBase> = OnceLock::new();
+
+/// Binary cache root relative to the API URL, e.g. `{cache}` or
+/// `nix/{cache}`
+///
+/// Relative so that the token and extra headers only ever go to the API
+/// server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NarInfoUrlBase(String);
+
+impl NarInfoUrlBase {
+    /// Root of `cache`, relative to the API URL
+    #[must_use]
+    pub fn root(&self, cache: &str) -> String {
+        self.0.replace(CACHE_PLACEHOLDER, cache)
+    }
+
+    /// Path of the NARInfo of the store path with hash part `hash`
+    #[must_use]
+    pub fn narinfo_path(&self, cache: &str, hash: &str) -> String {
+        format!("{}/{hash}.narinfo", self.root(cache))
+    }
+
+    /// Path of a NAR, from the `URL` of its NARInfo
+    #[must_use]
+    pub fn nar_path(&self, cache: &str, url: &str) -> String {
+        format!("{}/{url}", self.root(cache))
+    }
+
+    /// Substituter URL of `cache` for nix.conf
+    #[must_use]
+    pub fn substituter_url(&self, api_url: &str, cache: &str) -> String {
+        format!("{}/{}", api_url.trim_end_matches('/'), self.root(cache))
+    }
+}
+
+impl Default for NarInfoUrlBase {
+    fn default() -> Self {
+        Self(DEFAULT_NARINFO_URL_BASE.to_string())
+    }
+}
+
+impl FromStr for NarInfoUrlBase {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            CliError::InvalidArgument(format!("invalid NARInfo URL base '{s}': {reason}"))
+        };
+        let base = s.trim().trim_matches('/');
+        if base.is_empty() {
+            return Err(invalid("it is empty"));
+        }
+        if base.contains("://") {
+            return Err(invalid(
+                "it must be relative to --api-url; point --api-url at another server instead",
+            ));
+        }
+        if base
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "..")
+        {
+            return Err(invalid("it has an empty or '..' path segment"));
+        }
+        if base.contains(['?', '#']) {
+            return Err(invalid("it has a query or fragment"));
+        }
+        Ok(Self(base.to_string()))
+    }
+}
+
+/// Use `base` for the rest of the process
+///
+/// Called once at startup; later calls are ignored.
+pub fn set_narinfo_url_base(base: NarInfoUrlBase) {
+    let _ = NARINFO_URL_BASE.set(base);
+}
+
+/// `--narinfo-url-base`, or [`DEFAULT_NARINFO_URL_BASE`] when not set
+#[must_use]
+pub fn narinfo_url_base() -> &'static NarInfoUrlBase {
+    NARINFO_URL_BASE.get_or_init(NarInfoUrlBase::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_layout_urls() {
+        let base = NarInfoUrlBase::default();
+        assert_eq!(
+            base.narinfo_path("team", "0c7k7ysmldzsb6vyaqx4rj8gd5m44j3a"),
+            "team/0c7k7ysmldzsb6vyaqx4rj8gd5m44j3a.narinfo"
+        );
+        assert_eq!(
+            base.nar_path("team", "nar/1abc.nar.xz"),
+            "team/nar/1abc.nar.xz"
+        );
+        assert_eq!(
+            base.substituter_url("https://c.flakecache.com/", "team"),
+            "https://c.flakecache.com/team"
+        );
+    }
+
+    #[test]
+    fn test_custom_base() -> Result<()> {
+        let base: NarInfoUrlBase = "/nix/{cache}/".parse()?;
+        assert_eq!(base.narinfo_path("team", "abc"), "nix/team/abc.narinfo");
+        assert_eq!(
+            base.nar_path("team", "nar/1abc.nar.xz"),
+            "nix/team/nar/1abc.nar.xz"
+        );
+        assert_eq!(
+            base.substituter_url("https://cache.internal", "team"),
+            "https://cache.internal/nix/team"
+        );
+
+        // A single-cache deployment needs no placeholder
+        let fixed: NarInfoUrlBase = "binary-cache".parse()?;
+        assert_eq!(
+            fixed.narinfo_path("team", "abc"),
+            "binary-cache/abc.narinfo"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_bases_that_leave_the_api_server() {
+        for base in [
+            "",
+            "/",
+            "https://cdn.example/{cache}",
+            "a/../b",
+            "a//b",
+            "x?y",
+        ] {
+            assert!(base.parse::<NarInfoUrlBase>().is_err(), "{base}");
+        }
+    }
+}
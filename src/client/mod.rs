@@ -4,5 +4,7 @@
 //! including CBOR serialization/deserialization for efficient binary protocol.
 
 pub mod cbor;
+pub mod connectivity;
+pub mod layout;
 pub mod request;
 pub mod response;
@@ -1,3 +1,254 @@
 //! HTTP request building and formatting
 //!
 //! Provides utilities for constructing HTTP requests to the FlakeCache API.
+
+use crate::error::{CliError, Result};
+use crate::utils::platform;
+use reqwest::Url;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+/// Header carrying the per-request correlation id
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Environment variable with extra request headers, one `Name: Value` per
+/// line
+pub const EXTRA_HEADERS_ENV: &str = "FLAKECACHE_EXTRA_HEADERS";
+
+/// Process-wide `--allow-insecure-http`
+static ALLOW_INSECURE_HTTP: AtomicBool = AtomicBool::new(false);
+
+/// Process-wide `--header`s and [`EXTRA_HEADERS_ENV`]
+static EXTRA_HEADERS: OnceLock<Vec<ExtraHeader>> = OnceLock::new();
+
+/// Allow plain `http://` API URLs for the rest of the process
+///
+/// Called once at startup with the `--allow-insecure-http` flag.
+pub fn allow_insecure_http() {
+    ALLOW_INSECURE_HTTP.store(true, Ordering::Relaxed);
+}
+
+/// Check that requests to `base_url` are safe to send a bearer token over
+///
+/// `https` is always accepted; `http` only for a loopback host or with
+/// [`allow_insecure_http`].
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] for a plain `http` URL to another
+/// host, any other scheme, or an unparseable URL.
+pub fn check_transport(base_url: &str) -> Result<()> {
+    check_transport_with(base_url, ALLOW_INSECURE_HTTP.load(Ordering::Relaxed))
+}
+
+fn check_transport_with(base_url: &str, allow_insecure: bool) -> Result<()> {
+    let url = Url::parse(base_url)
+        .map_err(|e| CliError::InvalidArgument(format!("invalid API URL '{base_url}': {e}")))?;
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if allow_insecure || url.host_str().is_some_and(is_loopback) => Ok(()),
+        "http" => Err(CliError::InvalidArgument(format!(
+            "refusing to send credentials over plain HTTP to {base_url}; \
+             use https:// or pass --allow-insecure-http"
+        ))),
+        scheme => Err(CliError::InvalidArgument(format!(
+            "unsupported API URL scheme '{scheme}' in {base_url}"
+        ))),
+    }
+}
+
+/// Whether `host` names this machine
+fn is_loopback(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// A header added to every API request (`--header "Name: Value"`), e.g.
+/// for an authenticating proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraHeader {
+    name: String,
+    value: String,
+}
+
+impl ExtraHeader {
+    /// Header name as given
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Header value
+    #[must_use]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Whether this header replaces the bearer token
+    #[must_use]
+    pub fn is_authorization(&self) -> bool {
+        self.name.eq_ignore_ascii_case("authorization")
+    }
+}
+
+impl FromStr for ExtraHeader {
+    type Err = CliError;
+
+    /// Parse `Name: Value`; errors name the header but never echo the
+    /// value, which is often a secret
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, value) = s.split_once(':').ok_or_else(|| {
+            CliError::InvalidArgument("header must look like 'Name: Value'".to_string())
+        })?;
+        let (name, value) = (name.trim(), value.trim());
+        let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if name.is_empty() || !name.chars().all(is_token_char) {
+            return Err(CliError::InvalidArgument(format!(
+                "invalid header name '{name}'"
+            )));
+        }
+        if value.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(CliError::InvalidArgument(format!(
+                "header {name} has control characters in its value"
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Extra headers from [`EXTRA_HEADERS_ENV`] followed by `flags`
+///
+/// An `Authorization` header would replace the FlakeCache token, so it is
+/// only accepted with `allow_authorization` (`--allow-authorization-header`).
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] for a malformed header or an
+/// `Authorization` header that is not allowed.
+pub fn resolve_extra_headers(
+    flags: Vec<ExtraHeader>,
+    allow_authorization: bool,
+) -> Result<Vec<ExtraHeader>> {
+    let mut headers = match std::env::var(EXTRA_HEADERS_ENV) {
+        Ok(value) => parse_header_lines(&value)
+            .map_err(|e| CliError::InvalidArgument(format!("{EXTRA_HEADERS_ENV}: {e}")))?,
+        Err(_) => Vec::new(),
+    };
+    headers.extend(flags);
+    check_authorization(&headers, allow_authorization)?;
+    Ok(headers)
+}
+
+/// One header per non-empty line
+fn parse_header_lines(value: &str) -> Result<Vec<ExtraHeader>> {
+    value
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+fn check_authorization(headers: &[ExtraHeader], allow_authorization: bool) -> Result<()> {
+    match headers.iter().find(|header| header.is_authorization()) {
+        Some(header) if !allow_authorization => Err(CliError::InvalidArgument(format!(
+            "extra header {} would replace the FlakeCache token; \
+             pass --allow-authorization-header to send it anyway",
+            header.name()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Send `headers` with every API request for the rest of the process
+///
+/// Called once at startup; later calls are ignored.
+pub fn set_extra_headers(headers: Vec<ExtraHeader>) {
+    let _ = EXTRA_HEADERS.set(headers);
+}
+
+/// The process-wide extra headers
+#[must_use]
+pub fn extra_headers() -> &'static [ExtraHeader] {
+    EXTRA_HEADERS.get().map_or(&[], Vec::as_slice)
+}
+
+/// `User-Agent` sent with every request
+/// (`flakecache-cli/<version> (<target triple>)`)
+#[must_use]
+pub fn user_agent() -> String {
+    format!(
+        "flakecache-cli/{} ({})",
+        crate::VERSION,
+        platform::target_triple()
+    )
+}
+
+/// Fresh correlation id for one request
+///
+/// UUIDv7 ids sort by creation time, so server logs for one CLI run stay
+/// together.
+#[must_use]
+pub fn new_request_id() -> String {
+    Uuid::now_v7().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_agent_format() {
+        let agent = user_agent();
+        assert!(agent.starts_with(&format!("flakecache-cli/{} (", crate::VERSION)));
+        assert!(agent.ends_with(&format!("({})", platform::target_triple())));
+    }
+
+    #[test]
+    fn test_insecure_http_guard() {
+        assert!(check_transport_with("https://c.flakecache.com", false).is_ok());
+        assert!(check_transport_with("http://127.0.0.1:8080", false).is_ok());
+        assert!(check_transport_with("http://localhost", false).is_ok());
+        assert!(check_transport_with("http://[::1]:8080", false).is_ok());
+        assert!(matches!(
+            check_transport_with("http://c.flakecache.com", false),
+            Err(CliError::InvalidArgument(_))
+        ));
+        assert!(check_transport_with("http://c.flakecache.com", true).is_ok());
+        assert!(check_transport_with("ftp://c.flakecache.com", true).is_err());
+    }
+
+    #[test]
+    fn test_parse_extra_headers() -> Result<()> {
+        let headers = parse_header_lines("CF-Access-Client-Id: abc.access\n\nX-Team:  a: b \n")?;
+        assert_eq!(headers[0].name(), "CF-Access-Client-Id");
+        assert_eq!(headers[0].value(), "abc.access");
+        assert_eq!(headers[1].value(), "a: b");
+
+        assert!("no colon".parse::<ExtraHeader>().is_err());
+        assert!("Bad Name: x".parse::<ExtraHeader>().is_err());
+        assert!("X-Ok: line\rbreak".parse::<ExtraHeader>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_authorization_needs_opt_in() -> Result<()> {
+        let headers = vec!["authorization: Basic abc".parse::<ExtraHeader>()?];
+        assert!(headers[0].is_authorization());
+        assert!(check_authorization(&headers, false).is_err());
+        assert!(check_authorization(&headers, true).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_ids_are_unique() {
+        assert_ne!(new_request_id(), new_request_id());
+    }
+}
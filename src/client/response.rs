@@ -1,3 +1,58 @@
 //! HTTP response parsing and validation
 //!
 //! Handles parsing and validation of responses from the FlakeCache API.
+
+use crate::error::CliError;
+
+/// Map a non-success HTTP status to the matching [`CliError`]
+///
+/// - 401/403 are authentication failures
+/// - 429 and 5xx are transient and map to retryable [`CliError::Http`]
+/// - everything else is reported as [`CliError::ApiError`]
+#[must_use]
+pub fn status_error(status: u16, body: &str) -> CliError {
+    let message = if body.trim().is_empty() {
+        "(empty response body)".to_string()
+    } else {
+        body.trim().to_string()
+    };
+
+    match status {
+        401 => CliError::AuthFailed(format!("401 Unauthorized: {message}")),
+        403 => CliError::AuthFailed(format!("403 Forbidden: {message}")),
+        429 | 500..=599 => CliError::Http(format!("server returned {status}: {message}")),
+        _ => CliError::ApiError { status, message },
+    }
+}
+
+/// Map a transport-level `reqwest` error to the matching [`CliError`]
+#[must_use]
+pub fn transport_error(host: &str, err: &reqwest::Error) -> CliError {
+    if err.is_timeout() {
+        CliError::Timeout(format!("request to {host} timed out"))
+    } else if err.is_connect() {
+        CliError::ConnectionError {
+            host: host.to_string(),
+            reason: err.to_string(),
+        }
+    } else {
+        CliError::Http(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_mapping() {
+        assert!(matches!(status_error(401, ""), CliError::AuthFailed(_)));
+        assert!(matches!(status_error(403, "no"), CliError::AuthFailed(_)));
+        assert!(matches!(
+            status_error(404, "missing"),
+            CliError::ApiError { status: 404, .. }
+        ));
+        assert!(status_error(503, "busy").is_retryable());
+        assert!(!status_error(400, "bad").is_retryable());
+    }
+}
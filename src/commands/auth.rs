@@ -1,3 +1,270 @@
 //! Authentication commands (login, logout, etc.)
 //!
 //! Implements authentication flows including OAuth and token management.
+//! `login --device-code` uses the OAuth device authorization grant
+//! (RFC 8628): the CLI asks for a device code, the user approves it in a
+//! browser on any machine, and the CLI polls the token endpoint until the
+//! approval arrives. No local browser or callback server is needed.
+
+use crate::client::request::{check_transport, extra_headers, user_agent};
+use crate::client::response::{status_error, transport_error};
+use crate::config::auth::AuthConfig;
+use crate::config::default_timeout;
+use crate::error::{CliError, Result};
+use crate::utils::output;
+use reqwest::Client;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// OAuth client id of the CLI
+pub const CLIENT_ID: &str = "flakecache-cli";
+
+/// Device authorization endpoint, relative to the API URL
+pub const DEVICE_CODE_PATH: &str = "oauth/device/code";
+
+/// Token endpoint, relative to the API URL
+pub const TOKEN_PATH: &str = "oauth/token";
+
+/// `grant_type` for polling with a device code
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Polling interval when the server does not send one (RFC 8628 §3.2)
+const DEFAULT_POLL_INTERVAL: u64 = 5;
+
+/// Seconds added to the interval on every `slow_down` (RFC 8628 §3.5)
+const SLOW_DOWN_STEP: u64 = 5;
+
+/// A pending device authorization: the code to show and how to poll
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DeviceAuthorization {
+    /// Code the CLI polls with; never shown to the user
+    pub device_code: String,
+    /// Code the user enters at `verification_uri`
+    pub user_code: String,
+    /// Page where the user approves the login
+    pub verification_uri: String,
+    /// `verification_uri` with the user code filled in, if the server
+    /// offers one
+    #[serde(default)]
+    pub verification_uri_complete: Option<String>,
+    /// Seconds until the device code expires
+    pub expires_in: u64,
+    /// Minimum seconds between polls
+    #[serde(default)]
+    pub interval: Option<u64>,
+}
+
+/// Tokens issued once the user approves
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TokenResponse {
+    /// API access token
+    pub access_token: String,
+    /// Token for renewing the access token
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Seconds until the access token expires
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+impl TokenResponse {
+    /// Credentials to save, with expiry relative to `now` (Unix seconds)
+    #[must_use]
+    pub fn into_auth(self, now: u64) -> AuthConfig {
+        AuthConfig {
+            token: self.access_token,
+            refresh_token: self.refresh_token.unwrap_or_default(),
+            expires_at: self.expires_in.map(|secs| now.saturating_add(secs)),
+            ..AuthConfig::default()
+        }
+    }
+}
+
+/// OAuth error body (RFC 6749 §5.2)
+#[derive(Debug, Deserialize)]
+struct TokenError {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// What one token poll means for the flow
+#[derive(Debug, PartialEq, Eq)]
+enum Poll {
+    /// The user has not approved yet
+    Pending,
+    /// Polling too fast; wait longer between polls
+    SlowDown,
+    /// Approved
+    Granted(TokenResponse),
+}
+
+/// Interpret a token endpoint response
+fn classify_poll(status: u16, body: &str) -> Result<Poll> {
+    if (200..300).contains(&status) {
+        return serde_json::from_str(body)
+            .map(Poll::Granted)
+            .map_err(|e| CliError::OAuthError(format!("invalid token response: {e}")));
+    }
+    let Ok(error) = serde_json::from_str::<TokenError>(body) else {
+        return Err(status_error(status, body));
+    };
+    let reason = || match &error.error_description {
+        Some(description) => format!("{}: {description}", error.error),
+        None => error.error.clone(),
+    };
+    match error.error.as_str() {
+        "authorization_pending" => Ok(Poll::Pending),
+        "slow_down" => Ok(Poll::SlowDown),
+        "access_denied" => Err(CliError::AuthFailed("login was denied".to_string())),
+        "expired_token" => Err(expired()),
+        _ => Err(CliError::OAuthError(reason())),
+    }
+}
+
+fn expired() -> CliError {
+    CliError::OAuthError(
+        "the device code expired before it was approved; run login again".to_string(),
+    )
+}
+
+/// Device authorization grant against a FlakeCache server
+#[derive(Debug, Clone)]
+pub struct DeviceFlow {
+    http: Client,
+    base_url: String,
+}
+
+impl DeviceFlow {
+    /// Flow against the server at `api_url`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Http`] if the HTTP client cannot be built, or
+    /// [`CliError::InvalidArgument`] for a plain `http` URL that is not
+    /// allowed.
+    pub fn new(api_url: &str) -> Result<Self> {
+        let base_url = api_url.trim_end_matches('/').to_string();
+        check_transport(&base_url)?;
+        let http = Client::builder()
+            .timeout(Duration::from_secs(default_timeout()))
+            .user_agent(user_agent())
+            .build()
+            .map_err(|e| CliError::Http(format!("failed to build HTTP client: {e}")))?;
+        Ok(Self { http, base_url })
+    }
+
+    /// POST a form to `path`, returning the status and body
+    async fn post_form(&self, path: &str, form: &[(&str, &str)]) -> Result<(u16, String)> {
+        let url = format!("{}/{path}", self.base_url);
+        output::debug(format_args!("POST {url}"));
+        let mut builder = self.http.post(&url).form(form);
+        for header in extra_headers() {
+            builder = builder.header(header.name(), header.value());
+        }
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| transport_error(&self.base_url, &e))?;
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| transport_error(&self.base_url, &e))?;
+        Ok((status, body))
+    }
+
+    /// Request a device code and the user code to show
+    ///
+    /// # Errors
+    ///
+    /// Returns a network or HTTP status error, or [`CliError::OAuthError`]
+    /// for a malformed response.
+    pub async fn start(&self) -> Result<DeviceAuthorization> {
+        let (status, body) = self
+            .post_form(DEVICE_CODE_PATH, &[("client_id", CLIENT_ID)])
+            .await?;
+        if !(200..300).contains(&status) {
+            return Err(status_error(status, &body));
+        }
+        serde_json::from_str(&body)
+            .map_err(|e| CliError::OAuthError(format!("invalid device authorization: {e}")))
+    }
+
+    /// Poll until the user approves `authorization` or it expires
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::AuthFailed`] if the user denies the login,
+    /// [`CliError::OAuthError`] if the code expires or the server reports
+    /// another OAuth error, or a network error.
+    pub async fn wait_for_token(
+        &self,
+        authorization: &DeviceAuthorization,
+    ) -> Result<TokenResponse> {
+        let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = authorization
+            .interval
+            .unwrap_or(DEFAULT_POLL_INTERVAL)
+            .max(1);
+        let form = [
+            ("grant_type", DEVICE_CODE_GRANT),
+            ("device_code", authorization.device_code.as_str()),
+            ("client_id", CLIENT_ID),
+        ];
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+            if Instant::now() >= deadline {
+                return Err(expired());
+            }
+            let (status, body) = self.post_form(TOKEN_PATH, &form).await?;
+            match classify_poll(status, &body)? {
+                Poll::Pending => {}
+                Poll::SlowDown => interval += SLOW_DOWN_STEP,
+                Poll::Granted(token) => return Ok(token),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_poll() {
+        assert!(matches!(
+            classify_poll(400, r#"{"error":"authorization_pending"}"#),
+            Ok(Poll::Pending)
+        ));
+        assert!(matches!(
+            classify_poll(400, r#"{"error":"slow_down"}"#),
+            Ok(Poll::SlowDown)
+        ));
+        assert!(matches!(
+            classify_poll(400, r#"{"error":"access_denied"}"#),
+            Err(CliError::AuthFailed(_))
+        ));
+        assert!(matches!(
+            classify_poll(400, r#"{"error":"expired_token"}"#),
+            Err(CliError::OAuthError(_))
+        ));
+        assert!(matches!(
+            classify_poll(502, "bad gateway"),
+            Err(CliError::Http(_))
+        ));
+    }
+
+    #[test]
+    fn test_token_into_auth() {
+        let token = TokenResponse {
+            access_token: "abc".to_string(),
+            refresh_token: None,
+            expires_in: Some(3600),
+        };
+        let auth = token.into_auth(1_000);
+        assert!(auth.is_authenticated());
+        assert_eq!(auth.expires_at, Some(4_600));
+        assert!(auth.refresh_token.is_empty());
+    }
+}
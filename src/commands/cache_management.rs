@@ -0,0 +1,1245 @@
+//! Cache management commands (list, search, caches, stats, log, delete, gc)
+//!
+//! Response types for the read-only cache API endpoints and the requests
+//! that fetch them.
+
+use crate::client::cbor::CborClient;
+use crate::error::{CliError, Result};
+use crate::nix::store::{parse_store_path, store_path_basename};
+use crate::utils::progress::format_bytes;
+use crate::utils::table::{Align, Table, MISSING};
+use crate::utils::time::{format_duration, format_timestamp, parse_timestamp};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::time::Duration;
+
+/// A store path held by a cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorePath {
+    /// Full store path (`/nix/store/<hash>-<name>`)
+    pub store_path: String,
+    /// Compressed NAR size in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Upload time as reported by the server
+    #[serde(default)]
+    pub uploaded_at: Option<String>,
+    /// Who uploaded the path
+    #[serde(default)]
+    pub uploaded_by: Option<String>,
+}
+
+impl StorePath {
+    /// Name part of the store path (`hello-2.12` for
+    /// `/nix/store/<hash>-hello-2.12`)
+    #[must_use]
+    pub fn name(&self) -> &str {
+        let basename = store_path_basename(&self.store_path);
+        basename.split_once('-').map_or(basename, |(_, name)| name)
+    }
+}
+
+/// `paths` as a table: path, size, upload time, and uploader
+#[must_use]
+pub fn path_table(paths: &[StorePath]) -> Table {
+    let mut table = Table::new(&[
+        ("PATH", Align::Left),
+        ("SIZE", Align::Right),
+        ("UPLOADED AT", Align::Left),
+        ("UPLOADED BY", Align::Left),
+    ]);
+    for path in paths {
+        table.push(vec![
+            path.store_path.clone(),
+            path.size.map_or_else(|| MISSING.to_string(), format_bytes),
+            path.uploaded_at
+                .as_deref()
+                .map_or_else(|| MISSING.to_string(), format_timestamp),
+            path.uploaded_by
+                .clone()
+                .unwrap_or_else(|| MISSING.to_string()),
+        ]);
+    }
+    table
+}
+
+/// Order of the paths printed by `list` (`--sort`)
+///
+/// Servers do not guarantee an order, so anything but [`ListSort::Server`]
+/// makes repeated listings diff cleanly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListSort {
+    /// By name, then by full store path
+    #[default]
+    Name,
+    /// By full store path, i.e. by hash
+    Path,
+    /// Largest first, then by name
+    Size,
+    /// Most recently uploaded first, then by name
+    Uploaded,
+    /// As returned by the server
+    Server,
+}
+
+impl ListSort {
+    /// Sort `paths` in place; the sort is stable
+    pub fn sort(self, paths: &mut [StorePath]) {
+        let by_name = |a: &StorePath, b: &StorePath| {
+            a.name()
+                .cmp(b.name())
+                .then_with(|| a.store_path.cmp(&b.store_path))
+        };
+        match self {
+            Self::Name => paths.sort_by(by_name),
+            Self::Path => paths.sort_by(|a, b| a.store_path.cmp(&b.store_path)),
+            Self::Size => paths.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| by_name(a, b))),
+            Self::Uploaded => {
+                let uploaded = |p: &StorePath| p.uploaded_at.as_deref().and_then(parse_timestamp);
+                paths.sort_by(|a, b| uploaded(b).cmp(&uploaded(a)).then_with(|| by_name(a, b)));
+            }
+            Self::Server => {}
+        }
+    }
+}
+
+/// One page of `GET /api/v2/cbor/cache/{cache}/paths`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListResponse {
+    /// Store paths on this page
+    #[serde(default)]
+    pub paths: Vec<StorePath>,
+    /// Cursor for the next page, if there is one
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+    /// Total number of matching paths, if the server reports it
+    #[serde(default)]
+    pub total: Option<u64>,
+}
+
+/// A cache visible to the authenticated user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheInfo {
+    /// Cache name, as passed to `--cache`
+    pub name: String,
+    /// Whether the cache can be read without a token
+    #[serde(default)]
+    pub public: bool,
+    /// Free-form description
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Total stored bytes, if the server reports it
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Number of store paths, if the server reports it
+    #[serde(default)]
+    pub count: Option<u64>,
+}
+
+impl fmt::Display for CacheInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.name,
+            if self.public { "public" } else { "private" }
+        )?;
+        match (self.size, self.count) {
+            (Some(size), Some(count)) => write!(f, " - {count} paths, {}", format_bytes(size))?,
+            (Some(size), None) => write!(f, " - {}", format_bytes(size))?,
+            (None, Some(count)) => write!(f, " - {count} paths")?,
+            (None, None) => {}
+        }
+        if let Some(description) = self.description.as_deref().filter(|d| !d.is_empty()) {
+            write!(f, "\n    {description}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Upload-time window (`--since` / `--until`), applied by the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    /// Only paths uploaded at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only paths uploaded before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// Window between two optional bounds
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidArgument`] if `since` is not before
+    /// `until`.
+    pub fn new(since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> Result<Self> {
+        if let (Some(since), Some(until)) = (since, until) {
+            if since >= until {
+                return Err(CliError::InvalidArgument(
+                    "--since must be earlier than --until".to_string(),
+                ));
+            }
+        }
+        Ok(Self { since, until })
+    }
+
+    /// Append the bounds to `path` as `since` / `until` query parameters
+    fn write_params(&self, path: &mut String) {
+        for (name, bound) in [("since", self.since), ("until", self.until)] {
+            if let Some(time) = bound {
+                let separator = if path.contains('?') { '&' } else { '?' };
+                let time = time.to_rfc3339_opts(SecondsFormat::Secs, true);
+                let _ = write!(path, "{separator}{name}={}", urlencoding::encode(&time));
+            }
+        }
+    }
+}
+
+/// Parameters for listing a cache
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListQuery {
+    /// Maximum number of results
+    pub limit: usize,
+    /// Pagination cursor from a previous page
+    pub after: Option<String>,
+    /// Substring filter applied by the server
+    pub query: Option<String>,
+    /// Upload-time window
+    pub range: TimeRange,
+}
+
+impl ListQuery {
+    /// API path for listing `cache` with these parameters
+    #[must_use]
+    pub fn path(&self, cache: &str) -> String {
+        let mut path = format!(
+            "api/v2/cbor/cache/{}/paths?limit={}",
+            urlencoding::encode(cache),
+            self.limit
+        );
+        if let Some(after) = &self.after {
+            let _ = write!(path, "&after={}", urlencoding::encode(after));
+        }
+        if let Some(query) = &self.query {
+            let _ = write!(path, "&query={}", urlencoding::encode(query));
+        }
+        self.range.write_params(&mut path);
+        path
+    }
+}
+
+/// Fetch one page of a cache listing
+///
+/// The page is decoded as it downloads (see [`CborClient::get_streamed`]).
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error.
+pub async fn list_paths(
+    client: &CborClient,
+    cache: &str,
+    query: &ListQuery,
+) -> Result<ListResponse> {
+    client.get_streamed(&query.path(cache)).await
+}
+
+/// Walk a cache listing page by page, calling `visit` for each path as its
+/// page arrives
+///
+/// Starts at `query.after` and follows `next_cursor` until the server
+/// reports no more results; `query.limit` is the page size.
+///
+/// # Returns
+///
+/// The number of paths visited
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error, or the first error
+/// returned by `visit`.
+pub async fn stream_paths<F>(
+    client: &CborClient,
+    cache: &str,
+    query: &ListQuery,
+    mut visit: F,
+) -> Result<usize>
+where
+    F: FnMut(&StorePath) -> Result<()>,
+{
+    let mut query = query.clone();
+    let mut visited = 0;
+    loop {
+        let page = list_paths(client, cache, &query).await?;
+        for path in &page.paths {
+            visit(path)?;
+            visited += 1;
+        }
+        match page.next_cursor {
+            Some(cursor) if !page.paths.is_empty() => query.after = Some(cursor),
+            _ => return Ok(visited),
+        }
+    }
+}
+
+/// Caches the token can access (`GET /api/v2/cbor/caches`)
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error.
+pub async fn list_caches(client: &CborClient) -> Result<Vec<CacheInfo>> {
+    client.get("api/v2/cbor/caches").await
+}
+
+/// Matches for a search pattern in one cache
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchResult {
+    /// Cache that was searched
+    pub cache: String,
+    /// Matching store paths
+    pub paths: Vec<StorePath>,
+}
+
+impl fmt::Display for SearchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.paths.is_empty() {
+            return write!(f, "{}: no matches", self.cache);
+        }
+        write!(f, "{} ({} matches)", self.cache, self.paths.len())?;
+        for path in &self.paths {
+            write!(f, "\n  {}", path.store_path)?;
+            if let Some(size) = path.size {
+                write!(f, " ({})", format_bytes(size))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Search `caches` for paths matching `pattern`
+///
+/// With no caches given, every cache from [`list_caches`] is searched.
+/// A cache that cannot be queried does not stop the search; its error is
+/// returned alongside the results for the caller to report.
+///
+/// # Errors
+///
+/// Returns an error only if the cache listing itself fails.
+pub async fn search(
+    client: &CborClient,
+    caches: &[String],
+    pattern: &str,
+    limit: usize,
+) -> Result<(Vec<SearchResult>, Vec<(String, CliError)>)> {
+    let caches = if caches.is_empty() {
+        list_caches(client)
+            .await?
+            .into_iter()
+            .map(|info| info.name)
+            .collect()
+    } else {
+        caches.to_vec()
+    };
+
+    let query = ListQuery {
+        limit,
+        after: None,
+        query: Some(pattern.to_string()),
+        range: TimeRange::default(),
+    };
+    let mut results = Vec::new();
+    let mut errors = Vec::new();
+    for cache in caches {
+        match list_paths(client, &cache, &query).await {
+            Ok(page) => results.push(SearchResult {
+                cache,
+                paths: page.paths,
+            }),
+            Err(err) => errors.push((cache, err)),
+        }
+    }
+    Ok((results, errors))
+}
+
+/// Response of `GET /api/v2/cbor/cache/{cache}/stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Store paths uploaded in the window
+    #[serde(default)]
+    pub paths: u64,
+    /// Compressed bytes uploaded in the window
+    #[serde(default)]
+    pub size: u64,
+    /// NAR downloads served in the window
+    #[serde(default)]
+    pub downloads: u64,
+    /// Bytes served in the window
+    #[serde(default)]
+    pub bytes_served: u64,
+    /// Start of the window, as reported by the server
+    #[serde(default)]
+    pub since: Option<String>,
+    /// End of the window, as reported by the server
+    #[serde(default)]
+    pub until: Option<String>,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self.since.as_deref().map(format_timestamp);
+        let until = self.until.as_deref().map(format_timestamp);
+        match (since, until) {
+            (Some(since), Some(until)) => writeln!(f, "Window: {since} to {until}")?,
+            (Some(since), None) => writeln!(f, "Window: since {since}")?,
+            (None, Some(until)) => writeln!(f, "Window: until {until}")?,
+            (None, None) => writeln!(f, "Window: all time")?,
+        }
+        let mut table = Table::new(&[
+            ("", Align::Left),
+            ("COUNT", Align::Right),
+            ("SIZE", Align::Right),
+        ]);
+        table.push(vec![
+            "Uploaded".to_string(),
+            self.paths.to_string(),
+            format_bytes(self.size),
+        ]);
+        table.push(vec![
+            "Served".to_string(),
+            self.downloads.to_string(),
+            format_bytes(self.bytes_served),
+        ]);
+        write!(f, "{table}")
+    }
+}
+
+/// Fetch usage statistics for `cache`, optionally limited to `range`
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error.
+pub async fn cache_stats(
+    client: &CborClient,
+    cache: &str,
+    range: &TimeRange,
+) -> Result<CacheStats> {
+    let mut path = format!("api/v2/cbor/cache/{}/stats", urlencoding::encode(cache));
+    range.write_params(&mut path);
+    client.get(&path).await
+}
+
+/// One change to a cache, as recorded in its audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// When the change happened, as reported by the server
+    pub at: String,
+    /// What happened: `push`, `delete`, `gc`, or another server-defined
+    /// action
+    pub action: String,
+    /// User or token that made the change; `None` for changes the server
+    /// made itself, such as automatic GC
+    #[serde(default)]
+    pub actor: Option<String>,
+    /// Store paths the change affected
+    #[serde(default)]
+    pub store_paths: Vec<String>,
+    /// Free-form detail, such as the policy a GC run applied
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<6}  {}",
+            format_timestamp(&self.at),
+            self.action,
+            self.actor.as_deref().unwrap_or("server")
+        )?;
+        match self.store_paths.as_slice() {
+            [] => {}
+            [path] => write!(f, "  {path}")?,
+            paths => write!(f, "  {} paths", paths.len())?,
+        }
+        if let Some(detail) = self.detail.as_deref().filter(|d| !d.is_empty()) {
+            write!(f, " ({detail})")?;
+        }
+        Ok(())
+    }
+}
+
+/// One page of `GET /api/v2/cbor/cache/{cache}/audit`, newest first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLog {
+    /// Events on this page
+    #[serde(default)]
+    pub events: Vec<AuditEvent>,
+    /// Cursor for the next (older) page, if there is one
+    #[serde(default)]
+    pub next_cursor: Option<String>,
+}
+
+/// API path for one page of the audit log of `cache`
+fn audit_log_path(cache: &str, limit: usize, after: Option<&str>, range: &TimeRange) -> String {
+    let mut path = format!(
+        "api/v2/cbor/cache/{}/audit?limit={limit}",
+        urlencoding::encode(cache)
+    );
+    if let Some(after) = after {
+        let _ = write!(path, "&after={}", urlencoding::encode(after));
+    }
+    range.write_params(&mut path);
+    path
+}
+
+/// Fetch recent pushes, deletions and GC runs of `cache`, optionally
+/// limited to `range`
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error. Servers without an
+/// audit log answer 404, reported as [`CliError::CacheError`].
+pub async fn audit_log(
+    client: &CborClient,
+    cache: &str,
+    limit: usize,
+    after: Option<&str>,
+    range: &TimeRange,
+) -> Result<AuditLog> {
+    match client
+        .get(&audit_log_path(cache, limit, after, range))
+        .await
+    {
+        Err(CliError::ApiError { status: 404, .. }) => Err(CliError::CacheError(format!(
+            "the server keeps no audit log for {cache}, or the cache does not exist"
+        ))),
+        result => result,
+    }
+}
+
+/// Body of `POST /api/v2/cbor/cache/{cache}/paths/delete`
+#[derive(Debug, Serialize)]
+struct DeleteRequest<'a> {
+    paths: &'a [String],
+}
+
+/// A path the server refused to delete
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteFailure {
+    /// Store path that was not deleted
+    pub store_path: String,
+    /// Reason given by the server
+    pub reason: String,
+}
+
+/// Outcome of a (batched) delete, per path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteReport {
+    /// Store paths removed from the cache
+    #[serde(default)]
+    pub deleted: Vec<String>,
+    /// Store paths that could not be removed
+    #[serde(default)]
+    pub failed: Vec<DeleteFailure>,
+}
+
+impl DeleteReport {
+    fn fail(&mut self, store_path: &str, reason: String) {
+        self.failed.push(DeleteFailure {
+            store_path: store_path.to_string(),
+            reason,
+        });
+    }
+
+    /// Turn failed deletions into an error
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::CacheError`] if any path was not deleted.
+    pub fn check(&self) -> Result<()> {
+        if self.failed.is_empty() {
+            return Ok(());
+        }
+        Err(CliError::CacheError(format!(
+            "{} of {} deletions failed",
+            self.failed.len(),
+            self.failed.len() + self.deleted.len()
+        )))
+    }
+}
+
+impl fmt::Display for DeleteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Deleted {} paths, {} failed",
+            self.deleted.len(),
+            self.failed.len()
+        )
+    }
+}
+
+/// API path of one store path in `cache`, by hash
+fn store_path_api_path(cache: &str, hash: &str) -> String {
+    format!(
+        "api/v2/cbor/cache/{}/paths/{hash}",
+        urlencoding::encode(cache)
+    )
+}
+
+/// Delete `paths` from `cache`
+///
+/// Sends one batched request; servers without the batch endpoint (404 or
+/// 405) get one `DELETE` per path instead. Malformed store paths are
+/// reported as failures without contacting the server.
+///
+/// # Errors
+///
+/// Returns a network error, or an HTTP status error from the batch
+/// request. Per-path failures are recorded in the report instead.
+pub async fn delete_paths(
+    client: &CborClient,
+    cache: &str,
+    paths: &[String],
+) -> Result<DeleteReport> {
+    let mut report = DeleteReport::default();
+    let mut valid = Vec::new();
+    for path in paths {
+        match parse_store_path(path) {
+            Ok(hash) => valid.push((path.clone(), hash.to_string())),
+            Err(e) => report.fail(path, e.to_string()),
+        }
+    }
+    if valid.is_empty() {
+        return Ok(report);
+    }
+
+    let batch: Vec<String> = valid.iter().map(|(path, _)| path.clone()).collect();
+    let batch_path = format!(
+        "api/v2/cbor/cache/{}/paths/delete",
+        urlencoding::encode(cache)
+    );
+    match client
+        .post::<_, DeleteReport>(&batch_path, &DeleteRequest { paths: &batch })
+        .await
+    {
+        Ok(batched) => {
+            report.deleted = batched.deleted;
+            report.failed.extend(batched.failed);
+            return Ok(report);
+        }
+        Err(CliError::ApiError {
+            status: 404 | 405, ..
+        }) => {}
+        Err(e) => return Err(e),
+    }
+
+    for (path, hash) in valid {
+        match client.delete(&store_path_api_path(cache, &hash)).await {
+            Ok(()) => report.deleted.push(path),
+            Err(e) => report.fail(&path, e.to_string()),
+        }
+    }
+    Ok(report)
+}
+
+/// Server-side automatic garbage collection policy for a cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcPolicy {
+    /// Whether automatic GC runs at all
+    pub enabled: bool,
+    /// Delete paths not uploaded or fetched for this many seconds
+    pub older_than_secs: u64,
+}
+
+impl GcPolicy {
+    /// An enabled policy deleting paths unused for `older_than`
+    #[must_use]
+    pub const fn older_than(older_than: Duration) -> Self {
+        Self {
+            enabled: true,
+            older_than_secs: older_than.as_secs(),
+        }
+    }
+}
+
+impl fmt::Display for GcPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.enabled {
+            write!(
+                f,
+                "delete paths unused for {}",
+                format_duration(Duration::from_secs(self.older_than_secs))
+            )
+        } else {
+            f.write_str("disabled")
+        }
+    }
+}
+
+/// Response of `GET /api/v2/cbor/cache/{cache}/gc`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcStatus {
+    /// Configured policy; `None` means GC only runs when requested
+    #[serde(default)]
+    pub policy: Option<GcPolicy>,
+    /// When GC last ran, as reported by the server
+    #[serde(default)]
+    pub last_run_at: Option<String>,
+    /// Bytes freed by the last run
+    #[serde(default)]
+    pub last_freed_bytes: Option<u64>,
+    /// Store paths deleted by the last run
+    #[serde(default)]
+    pub last_deleted_paths: Option<u64>,
+    /// When the next automatic run is scheduled
+    #[serde(default)]
+    pub next_run_at: Option<String>,
+}
+
+impl fmt::Display for GcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.policy {
+            Some(policy) => write!(f, "Policy: {policy}")?,
+            None => f.write_str("Policy: none (manual GC only)")?,
+        }
+        match &self.last_run_at {
+            Some(at) => {
+                write!(f, "\nLast run: {}", format_timestamp(at))?;
+                if let Some(paths) = self.last_deleted_paths {
+                    write!(f, " ({paths} paths")?;
+                    if let Some(bytes) = self.last_freed_bytes {
+                        write!(f, ", {} freed", format_bytes(bytes))?;
+                    }
+                    f.write_str(")")?;
+                }
+            }
+            None => f.write_str("\nLast run: never")?,
+        }
+        if let Some(at) = &self.next_run_at {
+            write!(f, "\nNext run: {}", format_timestamp(at))?;
+        }
+        Ok(())
+    }
+}
+
+/// Page size when listing a whole cache for [`plan_keep_latest`]
+const RETENTION_PAGE_SIZE: usize = 1000;
+
+/// Package name of a store path name: everything before the version,
+/// which starts at the first `-` not followed by a letter (Nix's
+/// `parseDrvName` rule), so `hello-2.12.1` and `hello-2.12.2` share
+/// `hello`
+#[must_use]
+pub fn package_name(name: &str) -> &str {
+    name.match_indices('-')
+        .find(|&(at, _)| {
+            !name[at + 1..]
+                .chars()
+                .next()
+                .is_some_and(char::is_alphabetic)
+        })
+        .map_or(name, |(at, _)| &name[..at])
+}
+
+/// Version of a store path name, without the package name and without an
+/// output suffix, so `openssl-3.0.13`, `openssl-3.0.13-dev` and
+/// `openssl-3.0.13-bin` share `3.0.13`
+///
+/// The output suffix is whatever `-`-separated part at the end starts with
+/// a letter. A name without a version gives `""`.
+#[must_use]
+pub fn package_version(name: &str) -> &str {
+    let mut version = name[package_name(name).len()..]
+        .strip_prefix('-')
+        .unwrap_or_default();
+    while let Some((rest, suffix)) = version.rsplit_once('-') {
+        if !suffix.chars().next().is_some_and(char::is_alphabetic) {
+            break;
+        }
+        version = rest;
+    }
+    version
+}
+
+/// The paths of one package that `gc --keep-latest` keeps and deletes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RetentionGroup {
+    /// Package name (see [`package_name`])
+    pub name: String,
+    /// Paths kept: every output of the newest versions, and any path
+    /// without an upload time
+    pub keep: Vec<StorePath>,
+    /// Paths of older versions to delete
+    pub delete: Vec<StorePath>,
+}
+
+/// What `gc --keep-latest N` keeps and deletes, per package
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RetentionPlan {
+    /// Versions kept per package
+    pub keep_latest: usize,
+    /// One group per package name, sorted by name
+    pub groups: Vec<RetentionGroup>,
+}
+
+impl RetentionPlan {
+    /// Keep every path of the `keep_latest` most recently uploaded versions
+    /// of each package in `paths`
+    ///
+    /// A version is as new as its newest upload, and all its outputs (see
+    /// [`package_version`]) are kept or deleted together. Paths whose
+    /// upload time is missing or unreadable are never deleted: their age
+    /// is unknown.
+    #[must_use]
+    pub fn keep_latest(paths: Vec<StorePath>, keep_latest: usize) -> Self {
+        type Versions = BTreeMap<String, Vec<(Option<DateTime<Utc>>, StorePath)>>;
+        let mut by_name: BTreeMap<String, Versions> = BTreeMap::new();
+        for path in paths {
+            let uploaded = path.uploaded_at.as_deref().and_then(parse_timestamp);
+            let name = path.name();
+            by_name
+                .entry(package_name(name).to_string())
+                .or_default()
+                .entry(package_version(name).to_string())
+                .or_default()
+                .push((uploaded, path));
+        }
+
+        let groups = by_name
+            .into_iter()
+            .map(|(name, versions)| {
+                let mut versions: Vec<_> = versions
+                    .into_values()
+                    .map(|mut paths| {
+                        paths.sort_by_key(|(uploaded, _)| Reverse(*uploaded));
+                        (
+                            paths.iter().filter_map(|(uploaded, _)| *uploaded).max(),
+                            paths,
+                        )
+                    })
+                    .collect();
+                // Newest first; versions without any upload time sort last
+                versions.sort_by_key(|(uploaded, _)| Reverse(*uploaded));
+                let mut group = RetentionGroup {
+                    name,
+                    ..RetentionGroup::default()
+                };
+                for (index, (_, paths)) in versions.into_iter().enumerate() {
+                    for (uploaded, path) in paths {
+                        if index < keep_latest || uploaded.is_none() {
+                            group.keep.push(path);
+                        } else {
+                            group.delete.push(path);
+                        }
+                    }
+                }
+                group
+            })
+            .collect();
+        Self {
+            keep_latest,
+            groups,
+        }
+    }
+
+    /// Store paths to delete, in package order
+    #[must_use]
+    pub fn deletions(&self) -> Vec<String> {
+        self.groups
+            .iter()
+            .flat_map(|group| &group.delete)
+            .map(|path| path.store_path.clone())
+            .collect()
+    }
+}
+
+impl fmt::Display for RetentionPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut deleted = 0;
+        for group in self.groups.iter().filter(|group| !group.delete.is_empty()) {
+            writeln!(
+                f,
+                "{}: keep {}, delete {}",
+                group.name,
+                group.keep.len(),
+                group.delete.len()
+            )?;
+            for (action, paths) in [("keep", &group.keep), ("delete", &group.delete)] {
+                for path in paths {
+                    let uploaded = path
+                        .uploaded_at
+                        .as_deref()
+                        .map_or_else(|| "upload time unknown".to_string(), format_timestamp);
+                    writeln!(f, "  {action:<6}  {}  ({uploaded})", path.store_path)?;
+                }
+            }
+            deleted += group.delete.len();
+        }
+        let total: usize = self
+            .groups
+            .iter()
+            .map(|group| group.keep.len() + group.delete.len())
+            .sum();
+        write!(
+            f,
+            "Keeping the latest {} versions of each package: delete {deleted} of {total} paths",
+            self.keep_latest
+        )
+    }
+}
+
+/// List every path in `cache` and plan `gc --keep-latest`
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error from the listing.
+pub async fn plan_keep_latest(
+    client: &CborClient,
+    cache: &str,
+    keep_latest: usize,
+) -> Result<RetentionPlan> {
+    let query = ListQuery {
+        limit: RETENTION_PAGE_SIZE,
+        ..ListQuery::default()
+    };
+    let mut paths = Vec::new();
+    let _ = stream_paths(client, cache, &query, |path| {
+        paths.push(path.clone());
+        Ok(())
+    })
+    .await?;
+    Ok(RetentionPlan::keep_latest(paths, keep_latest))
+}
+
+/// API path for a cache's GC endpoints
+fn gc_path(cache: &str) -> String {
+    format!("api/v2/cbor/cache/{}/gc", urlencoding::encode(cache))
+}
+
+/// Fetch a cache's GC policy and last run
+///
+/// # Errors
+///
+/// Returns a network, HTTP status, or decode error.
+pub async fn gc_status(client: &CborClient, cache: &str) -> Result<GcStatus> {
+    client.get(&gc_path(cache)).await
+}
+
+/// Configure server-side automatic GC (`PUT .../gc/policy`)
+///
+/// # Errors
+///
+/// Returns a network or HTTP status error.
+pub async fn set_gc_policy(client: &CborClient, cache: &str, policy: &GcPolicy) -> Result<()> {
+    client
+        .put_cbor(&format!("{}/policy", gc_path(cache)), policy)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::time::{parse_duration, parse_time_bound};
+
+    #[test]
+    fn test_list_query_path_is_encoded() {
+        let query = ListQuery {
+            limit: 50,
+            after: Some("abc".to_string()),
+            query: Some("hello world".to_string()),
+            range: TimeRange::default(),
+        };
+        assert_eq!(
+            query.path("team"),
+            "api/v2/cbor/cache/team/paths?limit=50&after=abc&query=hello%20world"
+        );
+    }
+
+    #[test]
+    fn test_list_sort() {
+        let path = |store_path: &str, size| StorePath {
+            store_path: store_path.to_string(),
+            size: Some(size),
+            uploaded_at: None,
+            uploaded_by: None,
+        };
+        let mut paths = vec![
+            path("/nix/store/ccc-openssl-3.0", 10),
+            path("/nix/store/aaa-zlib-1.3", 30),
+            path("/nix/store/bbb-hello-2.12", 20),
+        ];
+        let order = |paths: &[StorePath]| -> Vec<String> {
+            paths.iter().map(|p| p.name().to_string()).collect()
+        };
+
+        ListSort::Name.sort(&mut paths);
+        assert_eq!(order(&paths), ["hello-2.12", "openssl-3.0", "zlib-1.3"]);
+        ListSort::Path.sort(&mut paths);
+        assert_eq!(order(&paths), ["zlib-1.3", "hello-2.12", "openssl-3.0"]);
+        ListSort::Size.sort(&mut paths);
+        assert_eq!(order(&paths), ["zlib-1.3", "hello-2.12", "openssl-3.0"]);
+
+        // By instant, not by string: 10:00+02:00 is earlier than 09:00Z
+        paths[0].uploaded_at = Some("2024-05-14T10:00:00+02:00".to_string());
+        paths[1].uploaded_at = Some("2024-05-14T09:00:00Z".to_string());
+        ListSort::Uploaded.sort(&mut paths);
+        assert_eq!(order(&paths), ["hello-2.12", "zlib-1.3", "openssl-3.0"]);
+    }
+
+    #[test]
+    fn test_path_table() {
+        let paths = [
+            StorePath {
+                store_path: "/nix/store/aaa-hello".to_string(),
+                size: Some(2048),
+                uploaded_at: Some("last tuesday".to_string()),
+                uploaded_by: Some("ci".to_string()),
+            },
+            StorePath {
+                store_path: "/nix/store/bbb-zlib".to_string(),
+                size: None,
+                uploaded_at: None,
+                uploaded_by: None,
+            },
+        ];
+        assert_eq!(
+            path_table(&paths).render(false),
+            "PATH                    SIZE  UPLOADED AT   UPLOADED BY\n\
+             /nix/store/aaa-hello  2.0 KB  last tuesday  ci\n\
+             /nix/store/bbb-zlib        -  -             -"
+        );
+    }
+
+    #[test]
+    fn test_time_range_params() -> Result<()> {
+        let since = parse_time_bound("2024-05-14")?;
+        let until = parse_time_bound("2024-05-15")?;
+        let mut path = "api/v2/cbor/cache/team/stats".to_string();
+        TimeRange::new(Some(since), Some(until))?.write_params(&mut path);
+        assert_eq!(
+            path,
+            "api/v2/cbor/cache/team/stats?since=2024-05-14T00%3A00%3A00Z&until=2024-05-15T00%3A00%3A00Z"
+        );
+        assert!(TimeRange::new(Some(until), Some(since)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_info_display() {
+        let info = CacheInfo {
+            name: "team".to_string(),
+            public: false,
+            description: Some("CI builds".to_string()),
+            size: Some(3 * 1024 * 1024),
+            count: Some(12),
+        };
+        assert_eq!(
+            info.to_string(),
+            "team (private) - 12 paths, 3.0 MB\n    CI builds"
+        );
+
+        let bare = CacheInfo {
+            name: "oss".to_string(),
+            public: true,
+            description: None,
+            size: None,
+            count: None,
+        };
+        assert_eq!(bare.to_string(), "oss (public)");
+    }
+
+    #[test]
+    fn test_search_result_display() {
+        let result = SearchResult {
+            cache: "team".to_string(),
+            paths: vec![StorePath {
+                store_path: "/nix/store/aaa-hello".to_string(),
+                size: Some(2048),
+                uploaded_at: None,
+                uploaded_by: None,
+            }],
+        };
+        assert_eq!(
+            result.to_string(),
+            "team (1 matches)\n  /nix/store/aaa-hello (2.0 KB)"
+        );
+
+        let empty = SearchResult {
+            cache: "other".to_string(),
+            paths: Vec::new(),
+        };
+        assert_eq!(empty.to_string(), "other: no matches");
+    }
+
+    #[test]
+    fn test_audit_event_display() {
+        let event = |action: &str, actor: Option<&str>, paths: &[&str]| AuditEvent {
+            at: "2024-05-14T09:00:00Z".to_string(),
+            action: action.to_string(),
+            actor: actor.map(ToString::to_string),
+            store_paths: paths.iter().map(ToString::to_string).collect(),
+            detail: None,
+        };
+        assert!(event("delete", Some("alice"), &["/nix/store/aaa-hello"])
+            .to_string()
+            .starts_with("2024-05-14 09:00 UTC ("));
+        let event = |action: &str, actor: Option<&str>, paths: &[&str]| AuditEvent {
+            at: "yesterday".to_string(),
+            ..event(action, actor, paths)
+        };
+        assert_eq!(
+            event("delete", Some("alice"), &["/nix/store/aaa-hello"]).to_string(),
+            "yesterday  delete  alice  /nix/store/aaa-hello"
+        );
+        let gc = AuditEvent {
+            detail: Some("unused for 30d".to_string()),
+            ..event("gc", None, &["/nix/store/aaa-a", "/nix/store/bbb-b"])
+        };
+        assert_eq!(
+            gc.to_string(),
+            "yesterday  gc      server  2 paths (unused for 30d)"
+        );
+        assert_eq!(
+            audit_log_path("my team", 20, Some("c1"), &TimeRange::default()),
+            "api/v2/cbor/cache/my%20team/audit?limit=20&after=c1"
+        );
+    }
+
+    #[test]
+    fn test_delete_report() {
+        let mut report = DeleteReport {
+            deleted: vec!["/nix/store/aaa-hello".to_string()],
+            failed: Vec::new(),
+        };
+        assert!(report.check().is_ok());
+
+        report.fail("/nix/store/bbb-world", "not found".to_string());
+        assert_eq!(report.to_string(), "Deleted 1 paths, 1 failed");
+        assert!(matches!(report.check(), Err(CliError::CacheError(_))));
+    }
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("hello-2.12.1"), "hello");
+        assert_eq!(package_name("python3-3.11.4-env"), "python3");
+        assert_eq!(
+            package_name("rust-analyzer-unwrapped-2024-05-06"),
+            "rust-analyzer-unwrapped"
+        );
+        assert_eq!(package_name("source"), "source");
+
+        assert_eq!(package_version("openssl-3.0.13"), "3.0.13");
+        assert_eq!(package_version("openssl-3.0.13-dev"), "3.0.13");
+        assert_eq!(package_version("python3-3.11.4-env"), "3.11.4");
+        assert_eq!(
+            package_version("rust-analyzer-unwrapped-2024-05-06"),
+            "2024-05-06"
+        );
+        assert_eq!(package_version("source"), "");
+    }
+
+    #[test]
+    fn test_keep_latest_per_package() {
+        let path = |store_path: &str, uploaded_at: Option<&str>| StorePath {
+            store_path: store_path.to_string(),
+            size: None,
+            uploaded_at: uploaded_at.map(ToString::to_string),
+            uploaded_by: None,
+        };
+        let plan = RetentionPlan::keep_latest(
+            vec![
+                path("/nix/store/aaa-hello-2.10", Some("2024-05-01T00:00:00Z")),
+                path("/nix/store/bbb-hello-2.12", Some("2024-05-03T00:00:00Z")),
+                path("/nix/store/ccc-hello-2.11", Some("2024-05-02T00:00:00Z")),
+                path("/nix/store/ddd-hello-2.9", None),
+                path("/nix/store/eee-zlib-1.3", Some("2024-04-01T00:00:00Z")),
+            ],
+            2,
+        );
+        assert_eq!(plan.deletions(), ["/nix/store/aaa-hello-2.10"]);
+        let hello = &plan.groups[0];
+        assert_eq!(hello.name, "hello");
+        let kept: Vec<&str> = hello.keep.iter().map(|p| p.store_path.as_str()).collect();
+        assert_eq!(
+            kept,
+            [
+                "/nix/store/bbb-hello-2.12",
+                "/nix/store/ccc-hello-2.11",
+                "/nix/store/ddd-hello-2.9"
+            ]
+        );
+        assert_eq!(plan.groups[1].keep.len(), 1);
+        assert!(plan
+            .to_string()
+            .ends_with("Keeping the latest 2 versions of each package: delete 1 of 5 paths"));
+    }
+
+    #[test]
+    fn test_keep_latest_keeps_all_outputs_of_a_version() {
+        let path = |store_path: &str, uploaded_at: &str| StorePath {
+            store_path: store_path.to_string(),
+            size: None,
+            uploaded_at: Some(uploaded_at.to_string()),
+            uploaded_by: None,
+        };
+        let plan = RetentionPlan::keep_latest(
+            vec![
+                path("/nix/store/aaa-openssl-3.0.12", "2024-04-01T00:00:00Z"),
+                path("/nix/store/bbb-openssl-3.0.12-dev", "2024-04-01T00:00:00Z"),
+                path("/nix/store/ccc-openssl-3.0.13-bin", "2024-05-01T00:00:00Z"),
+                path("/nix/store/ddd-openssl-3.0.13", "2024-05-01T00:01:00Z"),
+                path("/nix/store/eee-openssl-3.0.13-dev", "2024-05-01T00:02:00Z"),
+            ],
+            1,
+        );
+        assert_eq!(plan.groups.len(), 1);
+        let mut kept: Vec<&str> = plan.groups[0]
+            .keep
+            .iter()
+            .map(|p| p.store_path.as_str())
+            .collect();
+        kept.sort_unstable();
+        assert_eq!(
+            kept,
+            [
+                "/nix/store/ccc-openssl-3.0.13-bin",
+                "/nix/store/ddd-openssl-3.0.13",
+                "/nix/store/eee-openssl-3.0.13-dev"
+            ]
+        );
+        let mut deleted = plan.deletions();
+        deleted.sort_unstable();
+        assert_eq!(
+            deleted,
+            [
+                "/nix/store/aaa-openssl-3.0.12",
+                "/nix/store/bbb-openssl-3.0.12-dev"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gc_status_display() -> Result<()> {
+        let status = GcStatus {
+            policy: Some(GcPolicy::older_than(parse_duration("30d")?)),
+            last_run_at: Some("2024-05-01T03:00:00Z".to_string()),
+            last_freed_bytes: Some(2 * 1024 * 1024),
+            last_deleted_paths: Some(40),
+            next_run_at: None,
+        };
+        let shown = status.to_string();
+        assert!(shown
+            .starts_with("Policy: delete paths unused for 30d\nLast run: 2024-05-01 03:00 UTC ("));
+        assert!(shown.ends_with(" (40 paths, 2.0 MB freed)"));
+        assert_eq!(
+            GcStatus::default().to_string(),
+            "Policy: none (manual GC only)\nLast run: never"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+//! Self-diagnostics (`flakecache doctor`)
+//!
+//! Collects everything that commonly breaks a FlakeCache setup into one
+//! [`DoctorReport`], which renders either as a human checklist or as JSON for
+//! monitoring and CI health checks.
+
+use crate::cache::compress::{self, Compression};
+use crate::client::connectivity::Connectivity;
+use crate::config::Config;
+use crate::error::{CliError, Result};
+use crate::nix::Nix;
+use crate::utils::output::OutputFormat;
+use crate::utils::platform;
+use reqwest::Url;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Default Nix store directory when `NIX_STORE_DIR` is not set
+const DEFAULT_STORE_DIR: &str = "/nix/store";
+
+/// Priority Nix gives cache.nixos.org; substituters with a lower number
+/// are tried first
+pub const NIXOS_CACHE_PRIORITY: u32 = 40;
+
+/// Result of every doctor check
+// One flag per check keeps the JSON flat for monitoring
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DoctorReport {
+    /// `nix-store` could be run
+    pub nix_ok: bool,
+    /// Compressor programs that could be run (`xz`, `zstd`, `gzip`)
+    pub compressors: Vec<String>,
+    /// An access token is saved
+    pub token_present: bool,
+    /// Token expiry (Unix seconds), if known
+    pub token_expires_at: Option<u64>,
+    /// Connectivity to the API server (`online`, `offline (...)`)
+    pub connectivity: String,
+    /// nix.conf lists the FlakeCache server as a substituter
+    pub substituter_configured: bool,
+    /// nix.conf trusts a FlakeCache signing key
+    pub trusted_key_present: bool,
+    /// Duplicate or conflicting substituter entries in nix.conf
+    pub substituter_warnings: Vec<String>,
+    /// Nix store directory
+    pub store_path: String,
+    /// Platform this binary was built for
+    pub target_triple: String,
+}
+
+impl DoctorReport {
+    /// Run all checks against `api_url`
+    #[must_use]
+    pub fn collect(api_url: &str, offline: bool) -> Self {
+        let auth = Config::load().map(|config| config.auth).unwrap_or_default();
+        let api_host = host_of(api_url);
+        let nix_conf = read_nix_conf();
+
+        Self {
+            nix_ok: Nix::new().version().is_ok(),
+            compressors: compress::available_compressors()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            token_present: auth.is_authenticated(),
+            token_expires_at: auth.expires_at,
+            connectivity: Connectivity::check(api_url, offline).to_string(),
+            substituter_configured: has_substituter(&nix_conf, api_host.as_deref()),
+            trusted_key_present: has_trusted_key(&nix_conf, api_host.as_deref()),
+            substituter_warnings: substituter_warnings(&nix_conf),
+            store_path: std::env::var("NIX_STORE_DIR")
+                .unwrap_or_else(|_| DEFAULT_STORE_DIR.to_string()),
+            target_triple: platform::target_triple(),
+        }
+    }
+
+    /// `true` if every check needed for push/pull passed
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        self.nix_ok && self.token_present && self.connectivity == "online"
+    }
+
+    /// Fail unless [`Self::is_healthy`], naming the checks that did not pass
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidConfig`] listing the failed checks.
+    pub fn check(&self) -> Result<()> {
+        if self.is_healthy() {
+            return Ok(());
+        }
+        let mut failed = Vec::new();
+        if !self.nix_ok {
+            failed.push("Nix cannot be run".to_string());
+        }
+        if !self.token_present {
+            failed.push("no access token".to_string());
+        }
+        if self.connectivity != "online" {
+            failed.push(format!("server is {}", self.connectivity));
+        }
+        Err(CliError::InvalidConfig(format!(
+            "doctor checks failed: {}",
+            failed.join(", ")
+        )))
+    }
+
+    /// Render the report in the requested output format
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::CliError::SerializationError`] if JSON encoding fails.
+    pub fn render(&self, format: OutputFormat) -> Result<String> {
+        match format {
+            OutputFormat::Text => Ok(self.to_string()),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            OutputFormat::Jsonl => Ok(serde_json::to_string(self)?),
+        }
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = |ok: bool| if ok { "✓" } else { "✗" };
+
+        writeln!(f, "FlakeCache doctor")?;
+        writeln!(
+            f,
+            "  {} Nix: {}",
+            mark(self.nix_ok),
+            if self.nix_ok {
+                "available"
+            } else {
+                "nix-store not found"
+            }
+        )?;
+        let default = Compression::default().as_str();
+        match self.compressors.first() {
+            None => writeln!(
+                f,
+                "  ✗ Compressors: none found (install {default} to push)"
+            )?,
+            Some(_) if self.compressors.iter().any(|name| name == default) => {
+                writeln!(f, "  ✓ Compressors: {}", self.compressors.join(", "))?;
+            }
+            Some(fallback) => writeln!(
+                f,
+                "  ⚠ Compressors: {} ({default} not found; push uses {fallback}, install {default} or use --compression {fallback})",
+                self.compressors.join(", ")
+            )?,
+        }
+        match (self.token_present, self.token_expires_at) {
+            (false, _) => writeln!(f, "  ✗ Token: missing (run 'flakecache login')")?,
+            (true, Some(expires_at)) => {
+                writeln!(
+                    f,
+                    "  ✓ Token: present (expires {})",
+                    format_unix(expires_at)
+                )?;
+            }
+            (true, None) => writeln!(f, "  ✓ Token: present")?,
+        }
+        writeln!(
+            f,
+            "  {} Server: {}",
+            mark(self.connectivity == "online"),
+            self.connectivity
+        )?;
+        writeln!(
+            f,
+            "  {} Substituter: {}",
+            mark(self.substituter_configured),
+            if self.substituter_configured {
+                "configured in nix.conf"
+            } else {
+                "not in nix.conf"
+            }
+        )?;
+        writeln!(
+            f,
+            "  {} Trusted key: {}",
+            mark(self.trusted_key_present),
+            if self.trusted_key_present {
+                "present"
+            } else {
+                "not in trusted-public-keys"
+            }
+        )?;
+        for warning in &self.substituter_warnings {
+            writeln!(f, "  ⚠ {warning}")?;
+        }
+        writeln!(f, "  Store: {}", self.store_path)?;
+        write!(f, "  Platform: {}", self.target_triple)
+    }
+}
+
+/// Format Unix seconds as RFC 3339, falling back to the raw number
+fn format_unix(secs: u64) -> String {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map_or_else(|| secs.to_string(), |time| time.to_rfc3339())
+}
+
+/// Host part of a URL
+fn host_of(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(ToString::to_string))
+}
+
+/// Contents of the system and user nix.conf files that exist
+#[must_use]
+pub fn read_nix_conf() -> String {
+    let user_conf = dirs::config_dir().map(|dir| dir.join("nix").join("nix.conf"));
+    [
+        Some(PathBuf::from(super::hook::DEFAULT_NIX_CONF)),
+        user_conf,
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(|path| std::fs::read_to_string(path).ok())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// All values of a nix.conf setting, including its `extra-` variant
+#[must_use]
+pub fn nix_conf_values<'a>(conf: &'a str, key: &str) -> Vec<&'a str> {
+    let extra = format!("extra-{key}");
+    conf.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| {
+            let name = name.trim();
+            name == key || name == extra
+        })
+        .flat_map(|(_, value)| value.split_whitespace())
+        .collect()
+}
+
+/// Whether any substituter points at the FlakeCache server
+fn has_substituter(conf: &str, api_host: Option<&str>) -> bool {
+    api_host.is_some_and(|api_host| {
+        nix_conf_values(conf, "substituters")
+            .into_iter()
+            .any(|url| host_of(url).as_deref() == Some(api_host))
+    })
+}
+
+/// Whether a trusted public key looks like a FlakeCache key, i.e. its name
+/// mentions the server host or `flakecache`
+fn has_trusted_key(conf: &str, api_host: Option<&str>) -> bool {
+    nix_conf_values(conf, "trusted-public-keys")
+        .into_iter()
+        .filter_map(|key| key.split_once(':').map(|(name, _)| name))
+        .any(|name| name.contains("flakecache") || api_host.is_some_and(|host| name.contains(host)))
+}
+
+/// A `--priority` value: a number, or `before`/`after` cache.nixos.org
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubstituterPriority(pub u32);
+
+impl FromStr for SubstituterPriority {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "before" => Ok(Self(NIXOS_CACHE_PRIORITY - 1)),
+            "after" => Ok(Self(NIXOS_CACHE_PRIORITY + 1)),
+            _ => s.parse().map(Self).map_err(|_| {
+                CliError::InvalidArgument(format!(
+                    "--priority must be a number, 'before' or 'after', got '{s}'"
+                ))
+            }),
+        }
+    }
+}
+
+/// A substituter as written to nix.conf: `url` with an optional
+/// `?priority=` query
+#[must_use]
+pub fn substituter_entry(url: &str, priority: Option<SubstituterPriority>) -> String {
+    match priority {
+        Some(SubstituterPriority(priority)) => format!("{url}?priority={priority}"),
+        None => url.to_string(),
+    }
+}
+
+/// A substituter URL without its query and trailing slash, so entries for
+/// the same cache compare equal
+fn substituter_base(entry: &str) -> &str {
+    entry
+        .split_once('?')
+        .map_or(entry, |(base, _)| base)
+        .trim_end_matches('/')
+}
+
+/// Query part of a substituter entry, such as `priority=30`
+fn substituter_query(entry: &str) -> Option<&str> {
+    entry.split_once('?').map(|(_, query)| query)
+}
+
+/// Warnings for substituters listed more than once, with or without
+/// conflicting settings
+#[must_use]
+pub fn substituter_warnings(conf: &str) -> Vec<String> {
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    for entry in nix_conf_values(conf, "substituters") {
+        let base = substituter_base(entry);
+        match groups.iter_mut().find(|(seen, _)| *seen == base) {
+            Some((_, entries)) => entries.push(entry),
+            None => groups.push((base, vec![entry])),
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|(_, entries)| entries.len() > 1)
+        .map(|(base, entries)| {
+            if entries
+                .iter()
+                .all(|entry| substituter_query(entry) == substituter_query(entries[0]))
+            {
+                format!("Substituter {base} is listed more than once")
+            } else {
+                format!(
+                    "Substituter {base} is listed with conflicting settings: {}",
+                    entries.join(", ")
+                )
+            }
+        })
+        .collect()
+}
+
+/// Add `url` to `nix_conf` as an `extra-substituters` entry
+///
+/// # Returns
+///
+/// `false` if the substituter was already listed, `true` if it was added
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidConfig`] if the substituter is listed with a
+/// different priority, or a file error if the config cannot be updated.
+pub fn fix_substituter(
+    nix_conf: &Path,
+    url: &str,
+    priority: Option<SubstituterPriority>,
+) -> Result<bool> {
+    let existing = super::hook::read_conf_file(nix_conf)?;
+    let entry = substituter_entry(url, priority);
+    let base = substituter_base(url);
+
+    for current in nix_conf_values(&existing, "substituters") {
+        if substituter_base(current) != base {
+            continue;
+        }
+        if priority.is_none() || current == entry {
+            return Ok(false);
+        }
+        return Err(CliError::InvalidConfig(format!(
+            "{} already lists '{current}'; remove it to change the priority",
+            nix_conf.display()
+        )));
+    }
+
+    super::hook::append_line(nix_conf, existing, &format!("extra-substituters = {entry}"))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONF: &str = "\
+substituters = https://cache.nixos.org/
+extra-substituters = https://c.flakecache.com/my-cache # team cache
+trusted-public-keys = cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=
+";
+
+    #[test]
+    fn test_nix_conf_values_include_extra() {
+        assert_eq!(
+            nix_conf_values(CONF, "substituters"),
+            [
+                "https://cache.nixos.org/",
+                "https://c.flakecache.com/my-cache"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_substituter_and_key_detection() {
+        assert!(has_substituter(CONF, Some("c.flakecache.com")));
+        assert!(!has_substituter(CONF, Some("other.example")));
+        assert!(!has_trusted_key(CONF, Some("c.flakecache.com")));
+
+        let with_key = format!("{CONF}extra-trusted-public-keys = c.flakecache.com-1:abc=\n");
+        assert!(has_trusted_key(&with_key, Some("c.flakecache.com")));
+    }
+
+    #[test]
+    fn test_json_has_all_fields() -> Result<()> {
+        let report = DoctorReport {
+            nix_ok: true,
+            compressors: vec!["zstd".to_string()],
+            token_present: false,
+            token_expires_at: None,
+            connectivity: "offline (--offline)".to_string(),
+            substituter_configured: false,
+            trusted_key_present: false,
+            substituter_warnings: Vec::new(),
+            store_path: DEFAULT_STORE_DIR.to_string(),
+            target_triple: platform::target_triple(),
+        };
+        assert!(!report.is_healthy());
+        assert_eq!(
+            report.check().map_err(|e| e.to_string()),
+            Err(
+                "Invalid configuration: doctor checks failed: no access token, \
+                 server is offline (--offline)"
+                    .to_string()
+            )
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.render(OutputFormat::Json)?)?;
+        assert_eq!(json["nix_ok"], true);
+        assert_eq!(json["connectivity"], "offline (--offline)");
+        assert!(json["token_expires_at"].is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn test_substituter_warnings() {
+        assert!(substituter_warnings(CONF).is_empty());
+
+        let duplicated = format!("{CONF}extra-substituters = https://cache.nixos.org\n");
+        assert_eq!(
+            substituter_warnings(&duplicated),
+            ["Substituter https://cache.nixos.org is listed more than once"]
+        );
+
+        let conflicting =
+            format!("{CONF}extra-substituters = https://c.flakecache.com/my-cache?priority=30\n");
+        assert_eq!(
+            substituter_warnings(&conflicting),
+            ["Substituter https://c.flakecache.com/my-cache is listed with conflicting settings: \
+              https://c.flakecache.com/my-cache, https://c.flakecache.com/my-cache?priority=30"]
+        );
+    }
+
+    #[test]
+    fn test_fix_substituter_with_priority() -> Result<()> {
+        let conf =
+            std::env::temp_dir().join(format!("flakecache-doctor-{}.conf", std::process::id()));
+        std::fs::write(&conf, CONF)?;
+        let url = "https://c.flakecache.com/other";
+        let priority = "before".parse::<SubstituterPriority>().ok();
+        assert_eq!(priority, Some(SubstituterPriority(39)));
+
+        assert!(fix_substituter(&conf, url, priority)?);
+        assert!(!fix_substituter(&conf, url, priority)?);
+        assert!(!fix_substituter(&conf, url, None)?);
+        assert!(fix_substituter(&conf, url, Some(SubstituterPriority(50))).is_err());
+
+        let written = std::fs::read_to_string(&conf)?;
+        assert!(
+            written.ends_with("extra-substituters = https://c.flakecache.com/other?priority=39\n")
+        );
+        let _ = std::fs::remove_file(&conf);
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Nix `post-build-hook` installation
+//!
+//! Writes a small shell wrapper that Nix runs after every local build, pushing
+//! the freshly built outputs (`$OUT_PATHS`) to a FlakeCache cache.
+
+use crate::error::{CliError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default location of the system-wide Nix configuration
+pub const DEFAULT_NIX_CONF: &str = "/etc/nix/nix.conf";
+
+/// Setting name used by Nix for the hook
+const HOOK_SETTING: &str = "post-build-hook";
+
+/// Check that a cache name is safe to embed in the generated script
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidCacheName`] for empty names or names containing
+/// anything other than ASCII alphanumerics, `-`, `_`, `.` and `/`.
+pub fn validate_cache_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if valid {
+        Ok(())
+    } else {
+        Err(CliError::InvalidCacheName {
+            name: name.to_string(),
+        })
+    }
+}
+
+/// Quote a value for a POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Render the wrapper script invoked by Nix
+///
+/// `--keep-going` is used so that a failed upload never blocks the Nix build
+/// queue; failures are still reported in the daemon log.
+#[must_use]
+pub fn render_wrapper(flakecache_bin: &Path, cache: &str) -> String {
+    format!(
+        "#!/bin/sh\n\
+         # Generated by `flakecache install-hook`. Nix runs this after every build.\n\
+         set -eu\n\
+         set -f\n\
+         [ -n \"${{OUT_PATHS:-}}\" ] || exit 0\n\
+         echo \"$OUT_PATHS\" | exec {} push --cache {} --stdin --keep-going\n",
+        shell_quote(&flakecache_bin.to_string_lossy()),
+        shell_quote(cache)
+    )
+}
+
+/// The `nix.conf` line that enables the wrapper
+#[must_use]
+pub fn nix_conf_line(wrapper: &Path) -> String {
+    format!("{HOOK_SETTING} = {}", wrapper.display())
+}
+
+/// Default path for the wrapper script (next to the CLI config)
+///
+/// # Errors
+///
+/// Returns an error if the config directory cannot be determined.
+pub fn default_wrapper_path() -> Result<PathBuf> {
+    let config_path = crate::config::Config::config_path()?;
+    let dir = config_path
+        .parent()
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    Ok(dir.join("post-build-hook.sh"))
+}
+
+/// Write the wrapper script to `path` and make it executable
+///
+/// # Errors
+///
+/// Returns [`CliError::DirError`] or [`CliError::FileError`] if the script
+/// cannot be written.
+pub fn write_wrapper(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| CliError::DirError {
+            path: parent.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+    }
+
+    fs::write(path, contents).map_err(|e| CliError::FileError {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+
+    #[cfg(unix)]
+    {
+        use std::fs::Permissions;
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, Permissions::from_mode(0o755)).map_err(|e| {
+            CliError::FileError {
+                path: path.to_path_buf(),
+                reason: format!("Failed to set permissions: {e}"),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Append the hook line to a `nix.conf`
+///
+/// # Returns
+///
+/// `false` if the exact line was already present, `true` if it was appended
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidConfig`] if a different `post-build-hook` is
+/// already configured, or a file error if the config cannot be updated.
+pub fn append_to_nix_conf(nix_conf: &Path, line: &str) -> Result<bool> {
+    let existing = read_conf_file(nix_conf)?;
+
+    for current in existing.lines().map(str::trim) {
+        if current == line {
+            return Ok(false);
+        }
+        if current.split('=').next().map(str::trim) == Some(HOOK_SETTING) {
+            return Err(CliError::InvalidConfig(format!(
+                "{} already sets a different hook: '{current}'",
+                nix_conf.display()
+            )));
+        }
+    }
+
+    append_line(nix_conf, existing, line)?;
+    Ok(true)
+}
+
+/// Contents of a `nix.conf`, empty if it does not exist yet
+pub(crate) fn read_conf_file(nix_conf: &Path) -> Result<String> {
+    match fs::read_to_string(nix_conf) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(CliError::ConfigRead {
+            path: nix_conf.to_path_buf(),
+            reason: e.to_string(),
+        }),
+    }
+}
+
+/// Write `existing` back to `nix_conf` with `line` appended
+pub(crate) fn append_line(nix_conf: &Path, existing: String, line: &str) -> Result<()> {
+    let mut contents = existing;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(line);
+    contents.push('\n');
+
+    fs::write(nix_conf, contents).map_err(|e| CliError::ConfigWrite {
+        path: nix_conf.to_path_buf(),
+        reason: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapper_invokes_push_from_stdin() {
+        let script = render_wrapper(Path::new("/usr/bin/flakecache"), "my-org/cache");
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(
+            "exec '/usr/bin/flakecache' push --cache 'my-org/cache' --stdin --keep-going"
+        ));
+    }
+
+    #[test]
+    fn test_rejects_unsafe_cache_names() {
+        assert!(validate_cache_name("main").is_ok());
+        assert!(validate_cache_name("my-org/cache").is_ok());
+        assert!(validate_cache_name("").is_err());
+        assert!(validate_cache_name("x'; rm -rf /").is_err());
+    }
+
+    #[test]
+    fn test_nix_conf_line() {
+        assert_eq!(
+            nix_conf_line(Path::new("/etc/flakecache/hook.sh")),
+            "post-build-hook = /etc/flakecache/hook.sh"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Signing key management (`flakecache key`)
+//!
+//! Generates and reads Ed25519 binary cache keys in the same format as
+//! `nix-store --generate-binary-cache-key`: `<name>:<base64>`, where the
+//! secret key is the 32-byte seed followed by the 32-byte public key.
+
+use crate::error::{CliError, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use ed25519_dalek::SigningKey;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A named Ed25519 signing key
+#[derive(Debug, Clone)]
+pub struct SecretKey {
+    name: String,
+    key: SigningKey,
+}
+
+impl SecretKey {
+    /// Generate a new key from OS randomness
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidArgument`] for an invalid name, or
+    /// [`CliError::Internal`] if no randomness source is available.
+    pub fn generate(name: &str) -> Result<Self> {
+        validate_key_name(name)?;
+        let mut seed = [0_u8; 32];
+        fs::File::open("/dev/urandom")
+            .and_then(|mut urandom| urandom.read_exact(&mut seed))
+            .map_err(|e| CliError::Internal(format!("cannot read /dev/urandom: {e}")))?;
+        Ok(Self {
+            name: name.to_string(),
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// Key name (the part before `:`)
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The Ed25519 signing key
+    #[must_use]
+    pub const fn signing_key(&self) -> &SigningKey {
+        &self.key
+    }
+
+    /// Secret key in Nix format (`<name>:<base64 seed+public>`)
+    #[must_use]
+    pub fn to_nix_string(&self) -> String {
+        format!(
+            "{}:{}",
+            self.name,
+            STANDARD.encode(self.key.to_keypair_bytes())
+        )
+    }
+
+    /// Public key in the format Nix expects in `trusted-public-keys`
+    #[must_use]
+    pub fn public_key(&self) -> String {
+        format!(
+            "{}:{}",
+            self.name,
+            STANDARD.encode(self.key.verifying_key().to_bytes())
+        )
+    }
+
+    /// Read a secret key file
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if the file cannot be read, or
+    /// [`CliError::SignatureError`] if it is not a valid Nix secret key.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| CliError::FileError {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        contents.parse()
+    }
+
+    /// Read a secret key from environment variable `var` (`--signing-key-env`)
+    ///
+    /// The value is either the key in Nix format or the base64 encoding of
+    /// a key file, which survives CI secret stores that mangle newlines.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidArgument`] if `var` is unset or empty, or
+    /// [`CliError::SignatureError`] if it holds no valid Nix secret key.
+    pub fn from_env(var: &str) -> Result<Self> {
+        let value = std::env::var(var)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .ok_or_else(|| CliError::InvalidArgument(format!("{var} is not set")))?;
+        value.parse().or_else(|err| {
+            STANDARD
+                .decode(value.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or(err)?
+                .parse()
+        })
+    }
+}
+
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.key.to_bytes() == other.key.to_bytes()
+    }
+}
+
+impl Eq for SecretKey {}
+
+impl std::str::FromStr for SecretKey {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| CliError::SignatureError(format!("invalid secret key: {reason}"));
+
+        let (name, encoded) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| invalid("expected <name>:<base64>"))?;
+        let bytes = STANDARD
+            .decode(encoded)
+            .map_err(|_| invalid("key is not valid base64"))?;
+        let keypair: [u8; 64] = bytes.try_into().map_err(|_| invalid("expected 64 bytes"))?;
+        let key = SigningKey::from_keypair_bytes(&keypair)
+            .map_err(|_| invalid("public half does not match the secret key"))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            key,
+        })
+    }
+}
+
+/// Reject names Nix could not round-trip (`:` separates name and key)
+fn validate_key_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(':') || name.chars().any(char::is_whitespace) {
+        return Err(CliError::InvalidArgument(format!(
+            "invalid key name '{name}' (use e.g. my-cache-1)"
+        )));
+    }
+    Ok(())
+}
+
+/// Paths of a generated key pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFiles {
+    /// Secret key file (`<name>.sec`, mode 0600)
+    pub secret: PathBuf,
+    /// Public key file (`<name>.pub`)
+    pub public: PathBuf,
+}
+
+/// Write `key` as `<name>.sec` and `<name>.pub` in `dir`
+///
+/// Existing files are never overwritten, so a key in use cannot be lost by
+/// re-running the command.
+///
+/// # Errors
+///
+/// Returns [`CliError::DirError`] or [`CliError::FileError`] if the files
+/// cannot be created.
+pub fn write_key_files(key: &SecretKey, dir: &Path) -> Result<KeyFiles> {
+    fs::create_dir_all(dir).map_err(|e| CliError::DirError {
+        path: dir.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+
+    let files = KeyFiles {
+        secret: dir.join(format!("{}.sec", key.name())),
+        public: dir.join(format!("{}.pub", key.name())),
+    };
+    write_new(&files.secret, &key.to_nix_string(), 0o600)?;
+    write_new(&files.public, &key.public_key(), 0o644)?;
+    Ok(files)
+}
+
+/// Create `path` (failing if it exists) with `contents` and Unix `mode`
+fn write_new(path: &Path, contents: &str, mode: u32) -> Result<()> {
+    let file_error = |e: std::io::Error| CliError::FileError {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    };
+
+    let mut options = fs::OpenOptions::new();
+    let _ = options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let _ = options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let mut file = options.open(path).map_err(file_error)?;
+    file.write_all(contents.as_bytes()).map_err(file_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nix_format_round_trip() -> Result<()> {
+        let key = SecretKey::generate("my-cache-1")?;
+        let secret = key.to_nix_string();
+        assert!(secret.starts_with("my-cache-1:"));
+
+        let parsed: SecretKey = secret.parse()?;
+        assert_eq!(parsed.public_key(), key.public_key());
+
+        let public = key.public_key();
+        let encoded = public.strip_prefix("my-cache-1:").unwrap_or_default();
+        assert_eq!(STANDARD.decode(encoded).map(|b| b.len()).ok(), Some(32));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_env_accepts_base64_key_file() -> Result<()> {
+        let key = SecretKey::generate("env-1")?;
+        let var = format!("FLAKECACHE_TEST_SIGNING_KEY_{}", std::process::id());
+        std::env::set_var(&var, STANDARD.encode(format!("{}\n", key.to_nix_string())));
+        let parsed = SecretKey::from_env(&var);
+        std::env::remove_var(&var);
+
+        assert_eq!(parsed?, key);
+        assert!(matches!(
+            SecretKey::from_env(&var),
+            Err(CliError::InvalidArgument(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_bad_keys() {
+        assert!("no-colon".parse::<SecretKey>().is_err());
+        assert!("name:not base64!".parse::<SecretKey>().is_err());
+        assert!("name:AAAA".parse::<SecretKey>().is_err());
+        assert!(SecretKey::generate("bad:name").is_err());
+    }
+
+    #[test]
+    fn test_write_key_files_never_overwrites() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flakecache-key-{}", std::process::id()));
+        let key = SecretKey::generate("test-1")?;
+
+        let files = write_key_files(&key, &dir)?;
+        let public = fs::read_to_string(&files.public).unwrap_or_default();
+        let again = write_key_files(&key, &dir);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(public, key.public_key());
+        assert!(matches!(again, Err(CliError::FileError { .. })));
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+//! Push manifests (`flakecache push --manifest`)
+//!
+//! A manifest records exactly what one push uploaded, so a release can later
+//! be diffed against another or checked against the cache's contents.
+
+use crate::client::cbor;
+use crate::error::{CliError, Result};
+use crate::nix::flake::FlakeSource;
+use crate::nix::NarInfo;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Manifest format version written by this CLI
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// One uploaded store path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Full store path
+    pub store_path: String,
+    /// NAR location relative to the cache root
+    pub url: String,
+    /// Compression of the uploaded file
+    pub compression: String,
+    /// Hash of the compressed file (`sha256:<base32>`)
+    pub file_hash: Option<String>,
+    /// Size of the compressed file in bytes
+    pub file_size: Option<u64>,
+    /// Hash of the uncompressed NAR (`sha256:<base32>`)
+    pub nar_hash: String,
+    /// Size of the uncompressed NAR in bytes
+    pub nar_size: u64,
+    /// References as store path basenames
+    pub references: Vec<String>,
+    /// When the upload finished (RFC 3339)
+    pub uploaded_at: String,
+}
+
+impl ManifestEntry {
+    /// Record the NARInfo of a path uploaded just now
+    #[must_use]
+    pub fn uploaded_now(narinfo: &NarInfo) -> Self {
+        Self {
+            store_path: narinfo.store_path.clone(),
+            url: narinfo.url.clone(),
+            compression: narinfo.compression.clone(),
+            file_hash: narinfo.file_hash.clone(),
+            file_size: narinfo.file_size,
+            nar_hash: narinfo.nar_hash.clone(),
+            nar_size: narinfo.nar_size,
+            references: narinfo.references.clone(),
+            uploaded_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Everything uploaded by one push
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// [`MANIFEST_VERSION`] at the time of writing
+    pub version: u32,
+    /// Cache the paths were pushed to
+    pub cache: String,
+    /// When the push started (RFC 3339)
+    pub created_at: String,
+    /// Flake the paths were pushed from, with its git revision
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<FlakeSource>,
+    /// Uploaded paths, in upload order
+    pub paths: Vec<ManifestEntry>,
+}
+
+/// On-disk encoding of a manifest, chosen by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// Pretty-printed JSON (the default)
+    Json,
+    /// CBOR, for files ending in `.cbor`
+    Cbor,
+}
+
+impl ManifestFormat {
+    /// Format for `path`: CBOR for `.cbor`, JSON otherwise
+    #[must_use]
+    pub fn for_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext == "cbor") {
+            Self::Cbor
+        } else {
+            Self::Json
+        }
+    }
+}
+
+impl Manifest {
+    /// Start an empty manifest for a push to `cache`
+    #[must_use]
+    pub fn new(cache: &str) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            cache: cache.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            source: None,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Record the flake the push came from
+    #[must_use]
+    pub fn with_source(mut self, source: Option<FlakeSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Record an uploaded path
+    pub fn record(&mut self, narinfo: &NarInfo) {
+        self.paths.push(ManifestEntry::uploaded_now(narinfo));
+    }
+
+    /// Write the manifest to `path` in the format its extension selects
+    ///
+    /// # Errors
+    ///
+    /// Returns a serialization error, or [`CliError::FileError`] if the
+    /// file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let bytes = match ManifestFormat::for_path(path) {
+            ManifestFormat::Json => serde_json::to_vec_pretty(self)?,
+            ManifestFormat::Cbor => cbor::encode(self)?,
+        };
+        fs::write(path, bytes).map_err(|e| CliError::FileError {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Read a manifest written by [`Self::write`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if the file cannot be read, or a
+    /// deserialization error if it is not a manifest.
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).map_err(|e| CliError::FileError {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        match ManifestFormat::for_path(path) {
+            ManifestFormat::Json => Ok(serde_json::from_slice(&bytes)?),
+            ManifestFormat::Cbor => cbor::decode(&bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(
+            ManifestFormat::for_path(Path::new("release.cbor")),
+            ManifestFormat::Cbor
+        );
+        assert_eq!(
+            ManifestFormat::for_path(Path::new("release.json")),
+            ManifestFormat::Json
+        );
+        assert_eq!(
+            ManifestFormat::for_path(Path::new("manifest")),
+            ManifestFormat::Json
+        );
+    }
+
+    #[test]
+    fn test_manifest_round_trip() -> Result<()> {
+        let info = NarInfo {
+            store_path: "/nix/store/0c75sid0a2r1dpmnwnbnp8ingjbmr3pl-hello-2.12.1".to_string(),
+            url: "nar/abc.nar.xz".to_string(),
+            compression: "xz".to_string(),
+            file_hash: Some("sha256:abc".to_string()),
+            file_size: Some(10),
+            nar_hash: "sha256:def".to_string(),
+            nar_size: 20,
+            references: Vec::new(),
+            deriver: None,
+            system: None,
+            sigs: Vec::new(),
+            ca: None,
+        };
+        let mut manifest = Manifest::new("my-cache");
+        manifest.record(&info);
+        assert_eq!(manifest.paths[0].nar_size, 20);
+        assert_eq!(manifest.paths[0].compression, "xz");
+
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("flakecache-manifest-{}.json", std::process::id()));
+        manifest.write(&path)?;
+        let read = Manifest::read(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(read?, manifest);
+        Ok(())
+    }
+}
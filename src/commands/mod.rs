@@ -3,3 +3,10 @@
 pub mod push;
 pub mod pull;
 pub mod auth;
+pub mod hook;
+pub mod doctor;
+pub mod cache_management;
+pub mod key;
+pub mod manifest;
+pub mod watch;
+pub mod version;
@@ -1,3 +1,721 @@
 //! Pull/resolve command implementation
 //!
 //! Handles downloading and resolving dependencies from the FlakeCache service.
+
+use crate::client::cbor::CborClient;
+use crate::client::layout::narinfo_url_base;
+use crate::error::{CliError, Result};
+use crate::nix::flake;
+use crate::nix::resolve::{resolve_narinfo, RetryOptions};
+use crate::nix::store::{is_derivation, STORE_DIR};
+use crate::nix::{ClosureKind, Nix};
+use crate::utils::output;
+use crate::utils::progress::format_bytes;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// NARInfo requests in flight at once while estimating a download or
+/// walking a closure
+const ESTIMATE_CONCURRENCY: usize = 32;
+
+/// How a single store path was satisfied during a resolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathOutcome {
+    /// Downloaded from the cache (`file_size` is the NARInfo `FileSize`)
+    Restored {
+        /// Compressed bytes served by the cache
+        file_size: u64,
+    },
+    /// Substituted by Nix from the cache after the CLI download failed
+    Substituted,
+    /// Already valid in the local Nix store
+    AlreadyPresent,
+    /// Not available from the cache; left for Nix to build
+    Missing,
+}
+
+/// Tally of per-path outcomes, printed as the savings summary after a resolve
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolveSummary {
+    /// Paths restored from the cache
+    pub restored: usize,
+    /// Total compressed bytes downloaded from the cache
+    pub restored_bytes: u64,
+    /// Of the restored paths, those Nix substituted after the CLI download
+    /// failed (their bytes are not counted)
+    pub substituted: usize,
+    /// Paths that were already in the local store
+    pub already_present: usize,
+    /// Paths the cache could not serve
+    pub missing: usize,
+}
+
+impl ResolveSummary {
+    /// Record the outcome of a single store path
+    pub const fn record(&mut self, outcome: PathOutcome) {
+        match outcome {
+            PathOutcome::Restored { file_size } => {
+                self.restored += 1;
+                self.restored_bytes += file_size;
+            }
+            PathOutcome::Substituted => {
+                self.restored += 1;
+                self.substituted += 1;
+            }
+            PathOutcome::AlreadyPresent => self.already_present += 1,
+            PathOutcome::Missing => self.missing += 1,
+        }
+    }
+
+    /// Total number of paths recorded
+    #[must_use]
+    pub const fn total(&self) -> usize {
+        self.restored + self.already_present + self.missing
+    }
+
+    /// Share of the paths that had to be fetched which the cache served
+    /// (1.0 when nothing needed fetching)
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> f64 {
+        let wanted = self.restored + self.missing;
+        if wanted == 0 {
+            1.0
+        } else {
+            self.restored as f64 / wanted as f64
+        }
+    }
+}
+
+impl fmt::Display for ResolveSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Restored {} paths ({}) from cache, {} already present",
+            self.restored,
+            format_bytes(self.restored_bytes),
+            self.already_present
+        )?;
+        if self.substituted > 0 {
+            write!(f, ", {} via Nix substitution", self.substituted)?;
+        }
+        if self.missing > 0 {
+            write!(f, ", {} not in cache", self.missing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Size of a pull, worked out from NARInfos without downloading (`--estimate`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadEstimate {
+    /// Paths the cache would serve
+    pub paths: usize,
+    /// Compressed bytes to download (sum of `FileSize`)
+    pub file_bytes: u64,
+    /// Unpacked size of those paths (sum of `NarSize`)
+    pub nar_bytes: u64,
+    /// Paths whose NARInfo has no `FileSize`, counted at `NarSize`
+    pub unknown_size: usize,
+    /// Paths already valid in the local store
+    pub present: usize,
+    /// Paths the cache does not have
+    pub missing: Vec<String>,
+}
+
+impl DownloadEstimate {
+    /// Whether the download is larger than `limit` bytes
+    #[must_use]
+    pub const fn exceeds(&self, limit: u64) -> bool {
+        self.file_bytes > limit
+    }
+}
+
+impl fmt::Display for DownloadEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} paths to download, {} ({} unpacked), {} already present",
+            self.paths,
+            format_bytes(self.file_bytes),
+            format_bytes(self.nar_bytes),
+            self.present
+        )?;
+        if !self.missing.is_empty() {
+            write!(f, ", {} not in cache", self.missing.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimate the download needed to realise the closure of `roots`
+///
+/// Walks the closure through NARInfo `References`, so paths that were
+/// never built locally are included. `missing_locally` filters a batch of
+/// paths down to those not valid in the local store (see
+/// [`Nix::query_invalid`]); valid paths are not expanded, because their
+/// closure is valid too. A 404 counts the path as missing; other failures
+/// are retried per `retry` (see [`resolve_narinfo`]).
+///
+/// # Errors
+///
+/// Returns the first NARInfo fetch or `missing_locally` error.
+pub async fn estimate_download<F>(
+    client: &CborClient,
+    cache: &str,
+    roots: &[String],
+    retry: &RetryOptions,
+    mut missing_locally: F,
+) -> Result<DownloadEstimate>
+where
+    F: FnMut(&[String]) -> Result<Vec<String>>,
+{
+    let mut estimate = DownloadEstimate::default();
+    let mut seen: HashSet<String> = roots.iter().cloned().collect();
+    let mut frontier: Vec<String> = seen.iter().cloned().collect();
+
+    while !frontier.is_empty() {
+        let wanted = missing_locally(&frontier)?;
+        estimate.present += frontier.len() - wanted.len();
+
+        let mut next = Vec::new();
+        for batch in wanted.chunks(ESTIMATE_CONCURRENCY) {
+            let fetches = batch
+                .iter()
+                .map(|path| resolve_narinfo(client, cache, path, retry));
+            for (path, narinfo) in batch.iter().zip(futures::future::join_all(fetches).await) {
+                let Some(narinfo) = narinfo? else {
+                    estimate.missing.push(path.clone());
+                    continue;
+                };
+                estimate.paths += 1;
+                estimate.nar_bytes += narinfo.nar_size;
+                estimate.file_bytes += narinfo.file_size.unwrap_or_else(|| {
+                    estimate.unknown_size += 1;
+                    narinfo.nar_size
+                });
+                for reference in &narinfo.references {
+                    let reference = format!("{STORE_DIR}/{reference}");
+                    if seen.insert(reference.clone()) {
+                        next.push(reference);
+                    }
+                }
+            }
+        }
+        frontier = next;
+    }
+    Ok(estimate)
+}
+
+/// Binary cache URL Nix substitutes `cache` from (`<api url>/<cache>`, or
+/// under `--narinfo-url-base`)
+#[must_use]
+pub fn substituter_url(api_url: &str, cache: &str) -> String {
+    narinfo_url_base().substituter_url(api_url, cache)
+}
+
+/// Fetch one store path with `download`, falling back to `substitute`
+///
+/// `download` is the CLI's own NAR download and import, returning the
+/// compressed bytes fetched. If it fails, `substitute` hands the path to
+/// Nix's substituter instead (see [`Nix::substitute_from`]); the outcome
+/// says which one succeeded.
+///
+/// # Errors
+///
+/// Returns [`CliError::DownloadFailed`] with both reasons if the download
+/// and the substitution fail.
+pub fn fetch_with_fallback<D, S>(path: &str, download: D, substitute: S) -> Result<PathOutcome>
+where
+    D: FnOnce(&str) -> Result<u64>,
+    S: FnOnce(&str) -> Result<()>,
+{
+    let download_err = match download(path) {
+        Ok(file_size) => return Ok(PathOutcome::Restored { file_size }),
+        Err(e) => e,
+    };
+    output::debug(format_args!(
+        "download of {path} failed ({download_err}); trying Nix substitution"
+    ));
+    match substitute(path) {
+        Ok(()) => Ok(PathOutcome::Substituted),
+        Err(e) => Err(CliError::DownloadFailed(format!(
+            "{path}: {download_err}; Nix substitution also failed: {e}"
+        ))),
+    }
+}
+
+/// Whether a resolve may fall back to building what the cache lacks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BuildMode {
+    /// Substitute what the cache has and build the rest locally
+    #[default]
+    Build,
+    /// Only substitute; report paths that would need a build (`--no-build`)
+    SubstituteOnly,
+}
+
+impl BuildMode {
+    /// Mode selected by the `--no-build` flag
+    #[must_use]
+    pub const fn from_flag(no_build: bool) -> Self {
+        if no_build {
+            Self::SubstituteOnly
+        } else {
+            Self::Build
+        }
+    }
+}
+
+/// Result of a substitute-only resolve
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubstituteOutcome {
+    /// Paths fetched from a substituter
+    pub fetched: Vec<String>,
+    /// Derivations no substituter could provide, left unbuilt
+    pub unbuilt: Vec<String>,
+}
+
+/// Fetch whatever the substituters have for `installable` and build
+/// nothing, recording each derivation that would need a build as
+/// [`PathOutcome::Missing`]
+///
+/// With [`ClosureKind::Build`] the outputs of the installable's input
+/// derivations are fetched too, so the installable itself can be rebuilt
+/// without further downloads. Other kinds fetch what running it needs.
+///
+/// # Errors
+///
+/// Returns [`crate::CliError::StoreError`] if evaluation or substitution
+/// fails.
+pub fn substitute_only(
+    nix: &Nix,
+    installable: &str,
+    closure: ClosureKind,
+    summary: &mut ResolveSummary,
+) -> Result<SubstituteOutcome> {
+    let mut plan = nix.dry_run(installable)?;
+    if closure == ClosureKind::Build {
+        plan.merge(nix.dry_run_paths(&build_inputs(nix, installable)?)?);
+    }
+    for _ in &plan.will_build {
+        summary.record(PathOutcome::Missing);
+    }
+    let _ = nix.substitute(&plan.will_fetch)?;
+    Ok(SubstituteOutcome {
+        fetched: plan.will_fetch,
+        unbuilt: plan.will_build,
+    })
+}
+
+/// Input derivations of `installable`: realising them yields everything
+/// its build reads
+fn build_inputs(nix: &Nix, installable: &str) -> Result<Vec<String>> {
+    let mut inputs: Vec<String> = Vec::new();
+    for drv in nix.derivation_paths(installable)? {
+        for input in nix.query_references(&drv)? {
+            if is_derivation(&input) && !inputs.contains(&input) {
+                inputs.push(input);
+            }
+        }
+    }
+    Ok(inputs)
+}
+
+/// Output paths of `installable`, evaluated without building anything
+///
+/// With [`ClosureKind::Build`] the substitutable outputs of its input
+/// derivations are included, as in [`substitute_only`].
+///
+/// # Errors
+///
+/// Returns [`crate::CliError::StoreError`] if evaluation fails, or a
+/// [`crate::CliError::FlakeResolutionError`] if Nix reports no outputs.
+pub fn requested_paths(nix: &Nix, installable: &str, closure: ClosureKind) -> Result<Vec<String>> {
+    let planned = nix.build_json(installable, &["--dry-run".to_string()])?;
+    let mut paths = flake::extract_store_paths(installable, &planned)?;
+    if closure == ClosureKind::Build {
+        let inputs = nix.dry_run_paths(&build_inputs(nix, installable)?)?;
+        paths.extend(inputs.will_fetch);
+    }
+    Ok(paths)
+}
+
+/// The closure a pull has to realise
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PullClosure {
+    /// Paths of the closure already valid in the local store, without
+    /// derivations
+    pub local: Vec<String>,
+    /// Paths to fetch from the cache, dependencies before the paths that
+    /// reference them
+    pub remote: Vec<String>,
+    /// Paths neither valid locally nor in the cache, left for Nix to build
+    pub uncached: Vec<String>,
+}
+
+impl PullClosure {
+    /// The closure split into what is present and what has to be fetched,
+    /// for the `--only-missing` report
+    #[must_use]
+    pub fn delta(&self) -> ClosureDelta {
+        ClosureDelta {
+            missing: self.remote.clone(),
+            present: self.local.len(),
+        }
+    }
+}
+
+/// Work out the closure of `roots` from the local store and the cache
+///
+/// Paths the local store has are expanded there with `local_closure`
+/// (a batch of valid paths to their closure of the pull's [`ClosureKind`]);
+/// the others through the references the cache's NARInfos list, which
+/// `references` looks up for a batch of paths (`None` for a path the cache
+/// lacks). `invalid` filters a
+/// batch down to the paths not valid locally, as [`Nix::query_invalid`]
+/// does, so no NARInfo is fetched for a path the store already has.
+///
+/// # Errors
+///
+/// Returns the first error of `invalid`, `local_closure` or `references`.
+pub fn pull_closure<V, L, R>(
+    roots: &[String],
+    mut invalid: V,
+    mut local_closure: L,
+    mut references: R,
+) -> Result<PullClosure>
+where
+    V: FnMut(&[String]) -> Result<Vec<String>>,
+    L: FnMut(&[String]) -> Result<Vec<String>>,
+    R: FnMut(&[String]) -> Result<Vec<Option<Vec<String>>>>,
+{
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut frontier: Vec<String> = roots
+        .iter()
+        .filter(|root| seen.insert((*root).clone()))
+        .cloned()
+        .collect();
+    let mut local: Vec<String> = Vec::new();
+    let mut local_seen: HashSet<String> = HashSet::new();
+    let mut remote: Vec<(String, Vec<String>)> = Vec::new();
+    let mut uncached = Vec::new();
+
+    while !frontier.is_empty() {
+        let missing = invalid(&frontier)?;
+        let missing_set: HashSet<&str> = missing.iter().map(String::as_str).collect();
+        let valid: Vec<String> = frontier
+            .iter()
+            .filter(|path| !missing_set.contains(path.as_str()))
+            .cloned()
+            .collect();
+        if !valid.is_empty() {
+            for path in local_closure(&valid)? {
+                if !is_derivation(&path) && local_seen.insert(path.clone()) {
+                    local.push(path);
+                }
+            }
+        }
+
+        let mut next = Vec::new();
+        for (path, found) in missing.iter().zip(references(&missing)?) {
+            let Some(refs) = found else {
+                uncached.push(path.clone());
+                continue;
+            };
+            let refs: Vec<String> = refs.into_iter().filter(|r| r != path).collect();
+            for reference in &refs {
+                if seen.insert(reference.clone()) {
+                    next.push(reference.clone());
+                }
+            }
+            remote.push((path.clone(), refs));
+        }
+        frontier = next;
+    }
+
+    Ok(PullClosure {
+        local,
+        remote: dependencies_first(&remote),
+        uncached,
+    })
+}
+
+/// Import the remote paths of `closure` in order, recording every path's
+/// outcome in `summary`, and return the fetched paths
+///
+/// `downloads` yields one download result per remote path, in the same
+/// order, so the downloads can run ahead concurrently while `import` takes
+/// them one at a time, dependencies first, as `nix-store --import` needs.
+/// Local paths count as [`PathOutcome::AlreadyPresent`] and are never
+/// fetched again; uncached ones as [`PathOutcome::Missing`].
+///
+/// # Errors
+///
+/// Returns the first error of `import`, or [`CliError::Internal`] if
+/// `downloads` ends early.
+pub fn fetch_closure<T, D, I>(
+    closure: &PullClosure,
+    downloads: D,
+    mut import: I,
+    summary: &mut ResolveSummary,
+) -> Result<Vec<String>>
+where
+    D: IntoIterator<Item = Result<T>>,
+    I: FnMut(&str, Result<T>) -> Result<PathOutcome>,
+{
+    for _ in &closure.local {
+        summary.record(PathOutcome::AlreadyPresent);
+    }
+    let mut downloads = downloads.into_iter();
+    for path in &closure.remote {
+        let downloaded = downloads
+            .next()
+            .ok_or_else(|| CliError::Internal(format!("no download result for {path}")))?;
+        summary.record(import(path, downloaded)?);
+    }
+    for _ in &closure.uncached {
+        summary.record(PathOutcome::Missing);
+    }
+    Ok(closure.remote.clone())
+}
+
+/// References of each of `paths` from its NARInfo in `cache` (`None` if
+/// the cache does not have it), for [`pull_closure`]
+///
+/// # Errors
+///
+/// Returns the first NARInfo fetch error other than a 404 (see
+/// [`resolve_narinfo`]).
+pub async fn cache_references(
+    client: &CborClient,
+    cache: &str,
+    paths: &[String],
+    retry: &RetryOptions,
+) -> Result<Vec<Option<Vec<String>>>> {
+    let mut found = Vec::with_capacity(paths.len());
+    for batch in paths.chunks(ESTIMATE_CONCURRENCY) {
+        let fetches = batch
+            .iter()
+            .map(|path| resolve_narinfo(client, cache, path, retry));
+        for narinfo in futures::future::join_all(fetches).await {
+            found.push(narinfo?.map(|narinfo| {
+                narinfo
+                    .references
+                    .iter()
+                    .map(|reference| format!("{STORE_DIR}/{reference}"))
+                    .collect()
+            }));
+        }
+    }
+    Ok(found)
+}
+
+/// Paths of `graph` (path and references) ordered so that every path comes
+/// after the references it has in the graph, as `nix-store --import` needs
+fn dependencies_first(graph: &[(String, Vec<String>)]) -> Vec<String> {
+    let refs: HashMap<&str, &[String]> = graph
+        .iter()
+        .map(|(path, refs)| (path.as_str(), refs.as_slice()))
+        .collect();
+    let mut done: HashSet<&str> = HashSet::new();
+    let mut order = Vec::with_capacity(graph.len());
+    for (root, _) in graph {
+        // Iterative post-order walk; a reference cycle cannot occur in a
+        // valid store, and `done` stops one from looping forever
+        let mut stack: Vec<(&str, usize)> = vec![(root.as_str(), 0)];
+        while let Some((path, next)) = stack.pop() {
+            if next == 0 && !done.insert(path) {
+                continue;
+            }
+            let children = refs.get(path).copied().unwrap_or_default();
+            match children.get(next) {
+                Some(child) => {
+                    stack.push((path, next + 1));
+                    if refs.contains_key(child.as_str()) && !done.contains(child.as_str()) {
+                        stack.push((child.as_str(), 0));
+                    }
+                }
+                None => order.push(path.to_string()),
+            }
+        }
+    }
+    order
+}
+
+/// A closure split into paths to download and paths already present
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClosureDelta {
+    /// Paths not valid in the local store, in closure order
+    pub missing: Vec<String>,
+    /// Number of closure paths already valid locally
+    pub present: usize,
+}
+
+impl ClosureDelta {
+    /// Number of paths in the closure
+    #[must_use]
+    pub const fn total(&self) -> usize {
+        self.present + self.missing.len()
+    }
+}
+
+impl fmt::Display for ClosureDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} already present, {} to fetch",
+            self.present,
+            self.total(),
+            self.missing.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_tally() {
+        let mut summary = ResolveSummary::default();
+        summary.record(PathOutcome::Restored { file_size: 1024 });
+        summary.record(PathOutcome::Restored { file_size: 512 });
+        summary.record(PathOutcome::AlreadyPresent);
+
+        assert_eq!(summary.restored, 2);
+        assert_eq!(summary.restored_bytes, 1536);
+        assert_eq!(summary.total(), 3);
+        assert_eq!(
+            summary.to_string(),
+            "Restored 2 paths (1.5 KB) from cache, 1 already present"
+        );
+    }
+
+    #[test]
+    fn test_summary_reports_missing() {
+        let mut summary = ResolveSummary::default();
+        summary.record(PathOutcome::Missing);
+        assert!(summary.to_string().ends_with(", 1 not in cache"));
+
+        summary.record(PathOutcome::Restored { file_size: 1 });
+        summary.record(PathOutcome::AlreadyPresent);
+        assert!((summary.hit_ratio() - 0.5).abs() < f64::EPSILON);
+        assert!((ResolveSummary::default().hit_ratio() - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_download_estimate_display() {
+        let mut estimate = DownloadEstimate {
+            paths: 3,
+            file_bytes: 2048,
+            nar_bytes: 8192,
+            present: 5,
+            ..DownloadEstimate::default()
+        };
+        assert_eq!(
+            estimate.to_string(),
+            "3 paths to download, 2.0 KB (8.0 KB unpacked), 5 already present"
+        );
+        assert!(estimate.exceeds(1024));
+        assert!(!estimate.exceeds(2048));
+
+        estimate.missing.push("/nix/store/aaa-gone".to_string());
+        assert!(estimate.to_string().ends_with(", 1 not in cache"));
+    }
+
+    #[test]
+    fn test_build_mode_from_flag() {
+        assert_eq!(BuildMode::from_flag(false), BuildMode::Build);
+        assert_eq!(BuildMode::from_flag(true), BuildMode::SubstituteOnly);
+        assert_eq!(BuildMode::default(), BuildMode::Build);
+    }
+
+    #[test]
+    fn test_resolve_fetches_only_remote_paths() -> Result<()> {
+        let path = |name: &str| format!("{STORE_DIR}/{name}");
+        // app -> lib -> glibc; glibc is already in the local store, zlib
+        // is in neither the store nor the cache
+        let local = [path("glibc")];
+        let cache: HashMap<String, Vec<String>> = [
+            (path("app"), vec![path("app"), path("lib"), path("zlib")]),
+            (path("lib"), vec![path("glibc")]),
+        ]
+        .into_iter()
+        .collect();
+        let closure = pull_closure(
+            &[path("app")],
+            |paths| {
+                Ok(paths
+                    .iter()
+                    .filter(|p| !local.contains(p))
+                    .cloned()
+                    .collect())
+            },
+            |paths| Ok(paths.to_vec()),
+            |paths| Ok(paths.iter().map(|p| cache.get(p).cloned()).collect()),
+        )?;
+        assert_eq!(closure.local, [path("glibc")]);
+        assert_eq!(closure.remote, [path("lib"), path("app")]);
+        assert_eq!(closure.uncached, [path("zlib")]);
+        assert_eq!(
+            closure.delta().to_string(),
+            "1 of 3 already present, 2 to fetch"
+        );
+
+        let mut imported = Vec::new();
+        let mut summary = ResolveSummary::default();
+        let fetched = fetch_closure(
+            &closure,
+            closure.remote.iter().map(|path| Ok(format!("{path}.nar"))),
+            |path, nar: Result<String>| {
+                imported.push((path.to_string(), nar?));
+                Ok(PathOutcome::Restored { file_size: 10 })
+            },
+            &mut summary,
+        )?;
+        assert_eq!(fetched, [path("lib"), path("app")]);
+        assert_eq!(
+            imported,
+            [
+                (path("lib"), path("lib.nar")),
+                (path("app"), path("app.nar"))
+            ]
+        );
+        assert_eq!(summary.already_present, 1);
+        assert_eq!(summary.restored, 2);
+        assert_eq!(summary.missing, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_falls_back_to_nix_substitution() -> Result<()> {
+        let failed = |_: &str| Err(CliError::DownloadFailed("truncated".to_string()));
+        let path = "/nix/store/aaa-hello";
+
+        assert_eq!(
+            fetch_with_fallback(path, |_| Ok(64), |_| Ok(()))?,
+            PathOutcome::Restored { file_size: 64 }
+        );
+        assert_eq!(
+            fetch_with_fallback(path, failed, |_| Ok(()))?,
+            PathOutcome::Substituted
+        );
+        let err = fetch_with_fallback(path, failed, |_| {
+            Err(CliError::StoreError("no substituter".to_string()))
+        });
+        assert!(matches!(err, Err(CliError::DownloadFailed(m)) if m.contains("truncated")));
+
+        let mut summary = ResolveSummary::default();
+        summary.record(PathOutcome::Substituted);
+        assert_eq!(
+            summary.to_string(),
+            "Restored 1 paths (0 B) from cache, 0 already present, 1 via Nix substitution"
+        );
+        assert_eq!(
+            substituter_url("https://c.flakecache.com/", "team"),
+            "https://c.flakecache.com/team"
+        );
+        Ok(())
+    }
+}
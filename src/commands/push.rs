@@ -1,3 +1,701 @@
 //! Push/upload command implementation
 //!
 //! Handles uploading build artifacts (store paths) to the FlakeCache service.
+
+use crate::cache::compress::CompressOptions;
+use crate::cache::compressed::CompressedCache;
+use crate::cache::transfer::{NarHashMismatch, UploadMode};
+use crate::commands::key::SecretKey;
+use crate::error::{CliError, Result};
+use crate::nix::flake::FlakeSource;
+use crate::nix::store::store_path_hash;
+use crate::nix::NarInfo;
+use crate::utils::ci_summary::SummaryTarget;
+use crate::utils::progress::format_bytes;
+use futures::stream::{self, StreamExt};
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// What to do when an individual store path fails to upload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Upload every path, then fail if any of them failed
+    #[default]
+    FailAtEnd,
+    /// Abort on the first failed path
+    FailFast,
+    /// Best-effort: report failures but still succeed
+    KeepGoing,
+}
+
+impl FailurePolicy {
+    /// Build the policy from the `--fail-fast` / `--keep-going` flags
+    #[must_use]
+    pub const fn from_flags(fail_fast: bool, keep_going: bool) -> Self {
+        if fail_fast {
+            Self::FailFast
+        } else if keep_going {
+            Self::KeepGoing
+        } else {
+            Self::FailAtEnd
+        }
+    }
+}
+
+/// Settings shared by every store path uploaded in one push
+// One field per command-line flag
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PushOptions {
+    /// Failure handling (`--fail-fast` / `--keep-going`)
+    pub policy: FailurePolicy,
+    /// NAR compression settings (`--compression-threads`)
+    pub compress: CompressOptions,
+    /// Scratch directory for compressed NARs (`--temp-dir`)
+    pub temp_dir: PathBuf,
+    /// Reuse NARs compressed by earlier runs (`--reuse-compressed`)
+    pub reuse_compressed: Option<CompressedCache>,
+    /// Refuse pushes larger than this many uncompressed bytes
+    /// (`--max-closure-size`, unless `--force`)
+    pub max_closure_size: Option<u64>,
+    /// Maximum parallel uploads
+    pub parallelism: Option<usize>,
+    /// Whether to upload NARs or only their NARInfo (`--narinfo-only`)
+    pub upload_mode: UploadMode,
+    /// Skip signature verification
+    pub skip_verification: bool,
+    /// Sign each NARInfo with this key (`--signing-key`, `--signing-key-env`)
+    pub signing_key: Option<SecretKey>,
+    /// Re-fetch each uploaded path to confirm the server serves it
+    /// (`--verify-after-push`)
+    pub verify_after_push: bool,
+    /// Compare each path with the cache's copy and leave differing ones
+    /// alone (`--check-reproducible`)
+    pub check_reproducible: bool,
+    /// Fail the push if any path is not reproducible (`--fail-on-mismatch`)
+    pub fail_on_mismatch: bool,
+    /// Wait up to this long for each upload to be persisted (`--wait`)
+    pub wait: Option<Duration>,
+    /// Print a timing breakdown at the end
+    pub time: bool,
+    /// Write a manifest of the uploaded paths here (`--manifest`)
+    pub manifest: Option<PathBuf>,
+    /// Flake the paths come from, recorded in the manifest
+    pub source: Option<FlakeSource>,
+    /// Append CI step metrics to these files (`--summary-file`)
+    pub summary: Vec<SummaryTarget>,
+    /// Write Prometheus metrics of the run here (`--metrics-file`)
+    pub metrics_file: Option<PathBuf>,
+}
+
+/// Paths listed by [`PushSize`]
+const LARGEST_SHOWN: usize = 5;
+
+/// Uncompressed size of the paths a push would upload
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PushSize {
+    /// Number of paths
+    pub paths: usize,
+    /// Sum of their NAR sizes
+    pub total: u64,
+    /// The largest paths with their NAR sizes, largest first
+    pub largest: Vec<(String, u64)>,
+}
+
+impl PushSize {
+    /// Size of `paths`, whose NAR sizes are `sizes` (see
+    /// [`Nix::query_sizes`](crate::nix::Nix::query_sizes))
+    #[must_use]
+    pub fn of(paths: &[String], sizes: &[u64]) -> Self {
+        let mut largest: Vec<(String, u64)> =
+            paths.iter().cloned().zip(sizes.iter().copied()).collect();
+        largest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        largest.truncate(LARGEST_SHOWN);
+        Self {
+            paths: paths.len(),
+            total: sizes.iter().sum(),
+            largest,
+        }
+    }
+
+    /// Whether the push is larger than `limit` bytes
+    #[must_use]
+    pub const fn exceeds(&self, limit: u64) -> bool {
+        self.total > limit
+    }
+
+    /// Fail if the push is larger than `limit` bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::CacheError`] naming the largest paths.
+    pub fn check(&self, limit: u64) -> Result<()> {
+        if !self.exceeds(limit) {
+            return Ok(());
+        }
+        Err(CliError::CacheError(format!(
+            "push of {self} exceeds --max-closure-size {} (pass --force to push anyway)",
+            format_bytes(limit)
+        )))
+    }
+}
+
+impl fmt::Display for PushSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in {} paths; largest:",
+            format_bytes(self.total),
+            self.paths
+        )?;
+        for (path, size) in &self.largest {
+            write!(f, "\n  {:>10}  {path}", format_bytes(*size))?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a push across all requested store paths
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PushSummary {
+    /// Number of paths uploaded successfully
+    pub succeeded: usize,
+    /// Paths that failed, with the reason
+    pub failed: Vec<(String, String)>,
+    /// Paths not attempted because of `--fail-fast`
+    pub skipped: usize,
+    /// Duplicate paths dropped because they were already handled this run
+    pub deduplicated: usize,
+    /// Paths not uploaded because their NAR differs from the cache's copy
+    /// (`--check-reproducible`)
+    pub not_reproducible: Vec<NarHashMismatch>,
+}
+
+impl PushSummary {
+    /// Turn the summary into the command result according to `policy`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::UploadFailed`] listing the failed paths unless the
+    /// policy is [`FailurePolicy::KeepGoing`].
+    pub fn check(&self, policy: FailurePolicy) -> Result<()> {
+        if self.failed.is_empty() || policy == FailurePolicy::KeepGoing {
+            return Ok(());
+        }
+
+        let paths: Vec<&str> = self.failed.iter().map(|(path, _)| path.as_str()).collect();
+        Err(CliError::UploadFailed(format!(
+            "{} of {} paths failed: {}",
+            self.failed.len(),
+            self.succeeded + self.failed.len() + self.skipped,
+            paths.join(", ")
+        )))
+    }
+
+    /// Record paths the upload stage left alone as not reproducible
+    ///
+    /// The pipeline counts them as succeeded, since leaving them alone is
+    /// not a failure.
+    pub fn record_not_reproducible(&mut self, mismatches: Vec<NarHashMismatch>) {
+        self.succeeded = self.succeeded.saturating_sub(mismatches.len());
+        self.not_reproducible = mismatches;
+    }
+
+    /// Turn non-reproducible paths into an error (`--fail-on-mismatch`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::CacheError`] listing the paths whose NAR
+    /// differs from the cache's copy.
+    pub fn check_reproducible(&self) -> Result<()> {
+        if self.not_reproducible.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<&str> = self
+            .not_reproducible
+            .iter()
+            .map(|mismatch| mismatch.store_path.as_str())
+            .collect();
+        Err(CliError::CacheError(format!(
+            "{} paths are not reproducible: {}",
+            paths.len(),
+            paths.join(", ")
+        )))
+    }
+}
+
+impl fmt::Display for PushSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Pushed {} paths, {} failed",
+            self.succeeded,
+            self.failed.len()
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        if self.deduplicated > 0 {
+            write!(f, ", {} duplicates", self.deduplicated)?;
+        }
+        if !self.not_reproducible.is_empty() {
+            write!(f, ", {} not reproducible", self.not_reproducible.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Uncompressed versus compressed size of one or more uploaded NARs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Uncompressed NAR bytes (`NarSize`)
+    pub nar_bytes: u64,
+    /// Compressed bytes uploaded (`FileSize`)
+    pub file_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Sizes recorded in an uploaded path's NARInfo
+    #[must_use]
+    pub fn of(narinfo: &NarInfo) -> Self {
+        Self {
+            nar_bytes: narinfo.nar_size,
+            file_bytes: narinfo.file_size.unwrap_or(narinfo.nar_size),
+        }
+    }
+
+    /// Add another path's sizes to this total
+    pub const fn add(&mut self, other: Self) {
+        self.nar_bytes += other.nar_bytes;
+        self.file_bytes += other.file_bytes;
+    }
+
+    /// Compression ratio (uncompressed / compressed); 1.0 when empty
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> f64 {
+        if self.file_bytes == 0 {
+            1.0
+        } else {
+            self.nar_bytes as f64 / self.file_bytes as f64
+        }
+    }
+}
+
+impl fmt::Display for CompressionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} → {}, {:.1}x",
+            format_bytes(self.nar_bytes),
+            format_bytes(self.file_bytes),
+            self.ratio()
+        )
+    }
+}
+
+/// Read whitespace-separated store paths (e.g. Nix's `$OUT_PATHS`) from a reader
+///
+/// # Errors
+///
+/// Returns an error if reading from `reader` fails.
+pub fn read_paths<R: BufRead>(reader: R) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        paths.extend(line?.split_whitespace().map(ToString::to_string));
+    }
+    Ok(paths)
+}
+
+/// Store paths already handed to the uploader during this run
+///
+/// Keyed by store path hash, so overlapping closures from several flake
+/// outputs are only dumped, compressed, and uploaded once per invocation.
+#[derive(Debug, Default)]
+pub struct UploadedSet {
+    hashes: Mutex<HashSet<String>>,
+}
+
+impl UploadedSet {
+    /// Create an empty set
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `store_path`, returning `false` if it was already recorded
+    pub fn insert(&self, store_path: &str) -> bool {
+        let hash = store_path_hash(store_path).to_string();
+        self.hashes
+            .lock()
+            .map_or(true, |mut hashes| hashes.insert(hash))
+    }
+
+    /// Number of distinct paths recorded
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.hashes.lock().map_or(0, |hashes| hashes.len())
+    }
+
+    /// `true` if nothing has been recorded yet
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Sizes of the two worker pools in [`push_pipelined`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineWorkers {
+    /// Paths dumped and compressed at once (CPU-bound)
+    pub compress: usize,
+    /// Paths uploaded at once (network-bound)
+    pub upload: usize,
+}
+
+impl PipelineWorkers {
+    /// Enough compressors to keep `cpus` cores busy when each runs
+    /// `compress_threads` threads, feeding `upload` uploads
+    #[must_use]
+    pub fn new(cpus: usize, compress_threads: usize, upload: usize) -> Self {
+        Self {
+            compress: (cpus / compress_threads.max(1)).max(1),
+            upload: upload.max(1),
+        }
+    }
+}
+
+/// Compress and upload paths in two overlapping stages
+///
+/// Up to [`PipelineWorkers::compress`] paths are passed to `prepare` at
+/// once, and each result is queued for up to [`PipelineWorkers::upload`]
+/// concurrent `upload`s. The queue holds at most one prepared path per
+/// upload worker; when it is full, compression waits, so only a bounded
+/// number of compressed NARs are on disk at a time.
+///
+/// Paths already in `seen` (duplicates in `paths`, or paths pushed earlier
+/// in the same run) are counted in [`PushSummary::deduplicated`] and never
+/// prepared, and a failed `prepare` counts as a failed path. With [`FailurePolicy::FailFast`] no new path
+/// is started after the first failure; paths never uploaded are counted
+/// in [`PushSummary::skipped`], and their prepared values dropped.
+///
+/// Both stages run on the calling task, so `prepare` and `upload` need not
+/// be `Send`.
+#[allow(clippy::future_not_send)]
+pub async fn push_pipelined<T, P, PF, U, UF>(
+    paths: &[String],
+    policy: FailurePolicy,
+    seen: &UploadedSet,
+    workers: PipelineWorkers,
+    prepare: P,
+    upload: U,
+) -> PushSummary
+where
+    P: Fn(String) -> PF,
+    PF: Future<Output = Result<T>>,
+    U: Fn(T) -> UF,
+    UF: Future<Output = Result<()>>,
+{
+    let mut summary = PushSummary::default();
+    let mut queued = Vec::new();
+    for path in paths {
+        if seen.insert(path) {
+            queued.push(path.clone());
+        } else {
+            summary.deduplicated += 1;
+        }
+    }
+    let total = queued.len();
+
+    let stop = AtomicBool::new(false);
+    let (stop, prepare, upload) = (&stop, &prepare, &upload);
+    let (tx, mut rx) = mpsc::channel::<(String, Result<T>)>(workers.upload.max(1));
+
+    let compress = async move {
+        let mut prepared = stream::iter(queued)
+            .map(|path| async move {
+                if stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let result = prepare(path.clone()).await;
+                Some((path, result))
+            })
+            .buffer_unordered(workers.compress.max(1));
+        while let Some(item) = prepared.next().await {
+            if let Some(item) = item {
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+            }
+        }
+    };
+
+    let queue = stream::poll_fn(move |cx| rx.poll_recv(cx));
+    let mut uploads = queue
+        .map(|(path, prepared)| async move {
+            if stop.load(Ordering::Relaxed) {
+                return (path, None);
+            }
+            let result = match prepared {
+                Ok(prepared) => upload(prepared).await,
+                Err(err) => Err(err),
+            };
+            (path, Some(result))
+        })
+        .buffer_unordered(workers.upload.max(1));
+    let collect = async {
+        while let Some((path, result)) = uploads.next().await {
+            match result {
+                None => {}
+                Some(Ok(())) => summary.succeeded += 1,
+                Some(Err(err)) => {
+                    summary.failed.push((path, err.to_string()));
+                    if policy == FailurePolicy::FailFast {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    };
+
+    let ((), ()) = futures::future::join(compress, collect).await;
+    summary.skipped = total - summary.succeeded - summary.failed.len();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_stats() {
+        let mut total = CompressionStats {
+            nar_bytes: 120 * 1024 * 1024,
+            file_bytes: 34 * 1024 * 1024,
+        };
+        assert_eq!(total.to_string(), "120.0 MB → 34.0 MB, 3.5x");
+
+        total.add(CompressionStats {
+            nar_bytes: 8 * 1024 * 1024,
+            file_bytes: 30 * 1024 * 1024,
+        });
+        assert_eq!(total.to_string(), "128.0 MB → 64.0 MB, 2.0x");
+        assert!((CompressionStats::default().ratio() - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_push_size_lists_largest_paths() {
+        let names: Vec<String> = (0..7).map(|i| format!("/nix/store/p{i}")).collect();
+        let sizes: Vec<u64> = (0..7).map(|i| (i + 1) * 1024 * 1024).collect();
+        let size = PushSize::of(&names, &sizes);
+
+        assert_eq!(size.total, 28 * 1024 * 1024);
+        assert_eq!(size.largest.len(), LARGEST_SHOWN);
+        assert_eq!(
+            size.largest[0],
+            ("/nix/store/p6".to_string(), 7 * 1024 * 1024)
+        );
+        assert!(size
+            .to_string()
+            .starts_with("28.0 MB in 7 paths; largest:\n"));
+
+        assert!(size.check(28 * 1024 * 1024).is_ok());
+        assert!(matches!(size.check(1024), Err(CliError::CacheError(_))));
+    }
+
+    fn paths() -> Vec<String> {
+        ["a", "b", "c"].iter().map(ToString::to_string).collect()
+    }
+
+    fn fail_b(path: &str) -> Result<()> {
+        if path == "b" {
+            Err(CliError::UploadFailed("boom".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Push `paths` one at a time, uploading with `upload`
+    async fn push<U>(
+        paths: &[String],
+        policy: FailurePolicy,
+        seen: &UploadedSet,
+        upload: U,
+    ) -> PushSummary
+    where
+        U: Fn(&str) -> Result<()>,
+    {
+        let upload = &upload;
+        push_pipelined(
+            paths,
+            policy,
+            seen,
+            PipelineWorkers::new(1, 1, 1),
+            |path| async move { Ok(path) },
+            |path| async move { upload(&path) },
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_fail_at_end_attempts_everything() {
+        let summary = push(
+            &paths(),
+            FailurePolicy::FailAtEnd,
+            &UploadedSet::new(),
+            fail_b,
+        )
+        .await;
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(summary.failed.len(), 1);
+        assert!(summary.check(FailurePolicy::FailAtEnd).is_err());
+    }
+
+    #[test]
+    fn test_read_paths_splits_whitespace() {
+        let input = "/nix/store/a-x /nix/store/b-y\n/nix/store/c-z\n";
+        let paths = read_paths(input.as_bytes()).unwrap_or_default();
+        assert_eq!(
+            paths,
+            ["/nix/store/a-x", "/nix/store/b-y", "/nix/store/c-z"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keep_going_succeeds() {
+        let summary = push(
+            &paths(),
+            FailurePolicy::KeepGoing,
+            &UploadedSet::new(),
+            fail_b,
+        )
+        .await;
+        assert!(summary.check(FailurePolicy::KeepGoing).is_ok());
+    }
+
+    #[test]
+    fn test_not_reproducible_paths() {
+        let mut summary = PushSummary {
+            succeeded: 1,
+            ..PushSummary::default()
+        };
+        assert!(summary.check_reproducible().is_ok());
+
+        summary.record_not_reproducible(vec![NarHashMismatch {
+            store_path: "/nix/store/aaa-x".to_string(),
+            local: "sha256:111".to_string(),
+            cached: "sha256:222".to_string(),
+        }]);
+        assert_eq!(summary.succeeded, 0);
+        assert_eq!(
+            summary.to_string(),
+            "Pushed 0 paths, 0 failed, 1 not reproducible"
+        );
+        assert!(summary.check(FailurePolicy::FailAtEnd).is_ok());
+        assert!(summary.check_reproducible().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_uploaded_once() {
+        let seen = UploadedSet::new();
+        let batch =
+            ["/nix/store/aaa-x", "/nix/store/bbb-y", "/nix/store/aaa-x"].map(ToString::to_string);
+        let uploaded = Mutex::new(Vec::new());
+
+        let summary = push(&batch, FailurePolicy::FailAtEnd, &seen, |path| {
+            if let Ok(mut uploaded) = uploaded.lock() {
+                uploaded.push(path.to_string());
+            }
+            Ok(())
+        })
+        .await;
+        let uploaded = uploaded.into_inner().unwrap_or_default();
+        assert_eq!(uploaded, ["/nix/store/aaa-x", "/nix/store/bbb-y"]);
+        assert_eq!(summary.deduplicated, 1);
+
+        // A second batch in the same run skips what was already pushed
+        let again = push(&batch[1..], FailurePolicy::FailAtEnd, &seen, |_| Ok(())).await;
+        assert_eq!((again.succeeded, again.deduplicated), (0, 2));
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn test_pipeline_workers() {
+        assert_eq!(
+            PipelineWorkers::new(16, 4, 8),
+            PipelineWorkers {
+                compress: 4,
+                upload: 8
+            }
+        );
+        assert_eq!(PipelineWorkers::new(4, 8, 0).compress, 1);
+        assert_eq!(PipelineWorkers::new(4, 8, 0).upload, 1);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_uploads_prepared_paths() {
+        let batch = [
+            "/nix/store/aaa-x",
+            "/nix/store/bbb-y",
+            "/nix/store/aaa-x",
+            "/nix/store/ccc-z",
+        ]
+        .map(ToString::to_string);
+        let uploaded = Mutex::new(Vec::new());
+        let workers = PipelineWorkers::new(2, 1, 2);
+
+        let summary = push_pipelined(
+            &batch,
+            FailurePolicy::FailAtEnd,
+            &UploadedSet::new(),
+            workers,
+            |path| async move {
+                if path.ends_with("-y") {
+                    Err(CliError::StoreError("dump failed".to_string()))
+                } else {
+                    Ok(path)
+                }
+            },
+            |path| {
+                let uploaded = &uploaded;
+                async move {
+                    if let Ok(mut uploaded) = uploaded.lock() {
+                        uploaded.push(path);
+                    }
+                    Ok(())
+                }
+            },
+        )
+        .await;
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(summary.failed[0].0, "/nix/store/bbb-y");
+        assert_eq!((summary.deduplicated, summary.skipped), (1, 0));
+        let mut uploaded = uploaded.into_inner().unwrap_or_default();
+        uploaded.sort();
+        assert_eq!(uploaded, ["/nix/store/aaa-x", "/nix/store/ccc-z"]);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_fail_fast_skips_remaining() {
+        let workers = PipelineWorkers::new(1, 1, 1);
+        let summary = push_pipelined(
+            &paths(),
+            FailurePolicy::FailFast,
+            &UploadedSet::new(),
+            workers,
+            |path| async move { Ok(path) },
+            |path| async move { fail_b(&path) },
+        )
+        .await;
+        assert_eq!(summary.succeeded, 1);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.skipped, 1);
+    }
+}
@@ -0,0 +1,108 @@
+//! Build information (`flakecache version`)
+//!
+//! Reports which build is running: crate version plus the metadata
+//! `build.rs` embeds (git commit, rustc version, target, features). With
+//! `--output json` the report is machine-readable for support and
+//! compatibility checks, including after a self-update.
+
+use crate::error::Result;
+use crate::utils::output::OutputFormat;
+use crate::utils::platform;
+use serde::Serialize;
+use std::fmt;
+
+/// Shown for metadata the build could not determine
+const UNKNOWN: &str = "unknown";
+
+/// What this binary is and how it was built
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    /// Crate version
+    pub version: String,
+    /// Commit the binary was built from (`-dirty` with local changes)
+    pub git_commit: Option<String>,
+    /// `rustc --version` of the compiler
+    pub rustc_version: Option<String>,
+    /// Target triple the binary was built for
+    pub target: String,
+    /// Enabled Cargo features
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    /// Build information of the running binary
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: option_env!("FLAKECACHE_GIT_COMMIT").map(ToString::to_string),
+            rustc_version: option_env!("FLAKECACHE_RUSTC_VERSION").map(ToString::to_string),
+            target: option_env!("FLAKECACHE_TARGET")
+                .map_or_else(platform::target_triple, ToString::to_string),
+            features: parse_features(option_env!("FLAKECACHE_FEATURES").unwrap_or_default()),
+        }
+    }
+
+    /// Render the build information in the requested output format
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::CliError::SerializationError`] if JSON encoding fails.
+    pub fn render(&self, format: OutputFormat) -> Result<String> {
+        match format {
+            OutputFormat::Text => Ok(self.to_string()),
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            OutputFormat::Jsonl => Ok(serde_json::to_string(self)?),
+        }
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "FlakeCache CLI v{}", self.version)?;
+        writeln!(
+            f,
+            "  Commit: {}",
+            self.git_commit.as_deref().unwrap_or(UNKNOWN)
+        )?;
+        writeln!(
+            f,
+            "  Rustc: {}",
+            self.rustc_version.as_deref().unwrap_or(UNKNOWN)
+        )?;
+        writeln!(f, "  Target: {}", self.target)?;
+        if self.features.is_empty() {
+            write!(f, "  Features: none")
+        } else {
+            write!(f, "  Features: {}", self.features.join(", "))
+        }
+    }
+}
+
+/// Features from the comma-separated list `build.rs` embeds
+fn parse_features(list: &str) -> Vec<String> {
+    list.split(',')
+        .filter(|feature| !feature.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_features() {
+        assert!(parse_features("").is_empty());
+        assert_eq!(parse_features("default,graph"), ["default", "graph"]);
+    }
+
+    #[test]
+    fn test_text_starts_with_version() {
+        let info = BuildInfo::current();
+        assert!(info
+            .to_string()
+            .starts_with(&format!("FlakeCache CLI v{}", env!("CARGO_PKG_VERSION"))));
+        assert!(!info.target.is_empty());
+    }
+}
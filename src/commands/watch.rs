@@ -0,0 +1,259 @@
+//! Continuous resolve (`flakecache pull --watch`)
+//!
+//! Watches a flake's `flake.nix` and `flake.lock` and re-runs resolution
+//! whenever either changes, so a dev shell's dependencies stay warm while
+//! the lock file evolves.
+
+use crate::error::{CliError, Result};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Files whose changes trigger a new resolve
+pub const WATCHED_FILES: [&str; 2] = ["flake.nix", "flake.lock"];
+
+/// Quiet period after the last change before resolving again
+///
+/// Editors and `nix flake update` often write several times in a row.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// What changed between two resolve cycles
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CycleDiff {
+    /// Cycle number, starting at 1
+    pub cycle: usize,
+    /// Paths fetched this cycle that no earlier cycle fetched
+    pub added: Vec<String>,
+    /// Paths already fetched by an earlier cycle
+    pub unchanged: usize,
+}
+
+impl CycleDiff {
+    /// Compare this cycle's paths with everything seen so far, recording
+    /// the new ones in `seen`
+    pub fn record(cycle: usize, seen: &mut HashSet<String>, paths: Vec<String>) -> Self {
+        let total = paths.len();
+        let added: Vec<String> = paths
+            .into_iter()
+            .filter(|path| seen.insert(path.clone()))
+            .collect();
+        Self {
+            cycle,
+            unchanged: total - added.len(),
+            added,
+        }
+    }
+}
+
+impl fmt::Display for CycleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cycle {}: {} new paths, {} unchanged",
+            self.cycle,
+            self.added.len(),
+            self.unchanged
+        )?;
+        for path in &self.added {
+            write!(f, "\n  + {path}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory of the local flake named by a flake output (`.#dev` -> `.`)
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] for a remote flake such as
+/// `nixpkgs#hello`, which has no files to watch.
+pub fn flake_dir(flake_output: Option<&str>) -> Result<PathBuf> {
+    let flake_ref = flake_output
+        .and_then(|output| output.split('#').next())
+        .unwrap_or_default();
+    let flake_ref = flake_ref.strip_prefix("path:").unwrap_or(flake_ref);
+
+    if flake_ref.is_empty() {
+        Ok(PathBuf::from("."))
+    } else if flake_ref.starts_with('.') || flake_ref.starts_with('/') {
+        Ok(PathBuf::from(flake_ref))
+    } else {
+        Err(CliError::InvalidArgument(format!(
+            "--watch needs a local flake, but '{flake_ref}' is remote"
+        )))
+    }
+}
+
+/// Whether a filesystem event touches one of [`WATCHED_FILES`]
+fn is_flake_change(event: &Event) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|path| {
+            path.file_name()
+                .is_some_and(|name| WATCHED_FILES.iter().any(|watched| name == *watched))
+        })
+}
+
+/// Block until a flake file changes and then stays unchanged for `debounce`
+fn wait_for_change(events: &Receiver<notify::Result<Event>>, debounce: Duration) -> Result<()> {
+    let closed = || CliError::Internal("file watcher stopped".to_string());
+
+    // Transient watcher errors (e.g. a file replaced mid-scan) are skipped
+    // like unrelated events
+    loop {
+        if let Ok(event) = events.recv().map_err(|_| closed())? {
+            if is_flake_change(&event) {
+                break;
+            }
+        }
+    }
+    loop {
+        match events.recv_timeout(debounce) {
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return Ok(()),
+            Err(RecvTimeoutError::Disconnected) => return Err(closed()),
+        }
+    }
+}
+
+/// Run `resolve` now and again after every change to the flake in
+/// `flake_dir`, until the process is interrupted
+///
+/// `resolve` returns the store paths it fetched; each cycle's result is
+/// passed to `report`. A failing cycle is reported and watching continues,
+/// since the next edit may well fix it.
+///
+/// # Errors
+///
+/// Returns [`CliError::FileError`] if `flake_dir` cannot be watched, or
+/// [`CliError::Internal`] if the watcher stops.
+pub fn watch<F, R>(
+    flake_dir: &Path,
+    debounce: Duration,
+    mut resolve: F,
+    mut report: R,
+) -> Result<()>
+where
+    F: FnMut() -> Result<Vec<String>>,
+    R: FnMut(Result<CycleDiff>),
+{
+    let watch_error = |e: notify::Error| CliError::FileError {
+        path: flake_dir.to_path_buf(),
+        reason: format!("cannot watch for changes: {e}"),
+    };
+    let (tx, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+    // Watch the directory rather than the files: editors and `nix flake
+    // update` replace files by renaming, which would orphan a file watch
+    watcher
+        .watch(flake_dir, RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+
+    let mut seen = HashSet::new();
+    let mut cycle = 0;
+    loop {
+        cycle += 1;
+        report(resolve().map(|paths| CycleDiff::record(cycle, &mut seen, paths)));
+        wait_for_change(&events, debounce)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_diff_reports_only_new_paths() {
+        let mut seen = HashSet::new();
+        let first = CycleDiff::record(1, &mut seen, vec!["/nix/store/a".to_string()]);
+        assert_eq!(first.added, ["/nix/store/a"]);
+
+        let second = CycleDiff::record(
+            2,
+            &mut seen,
+            vec!["/nix/store/a".to_string(), "/nix/store/b".to_string()],
+        );
+        assert_eq!(second.added, ["/nix/store/b"]);
+        assert_eq!(second.unchanged, 1);
+        assert_eq!(
+            second.to_string(),
+            "Cycle 2: 1 new paths, 1 unchanged\n  + /nix/store/b"
+        );
+    }
+
+    #[test]
+    fn test_lock_bump_resolves_new_paths() -> Result<()> {
+        use crate::commands::pull::{fetch_closure, pull_closure, PathOutcome, ResolveSummary};
+        use crate::nix::store::STORE_DIR;
+        use std::collections::HashMap;
+
+        let path = |name: &str| format!("{STORE_DIR}/{name}");
+        // The lock bump moves app from lib-1 to lib-2; glibc is shared
+        let cache: HashMap<String, Vec<String>> = [
+            (path("app-1"), vec![path("lib-1"), path("glibc")]),
+            (path("app-2"), vec![path("lib-2"), path("glibc")]),
+            (path("lib-1"), vec![path("glibc")]),
+            (path("lib-2"), vec![path("glibc")]),
+            (path("glibc"), vec![]),
+        ]
+        .into_iter()
+        .collect();
+        let mut store: HashSet<String> = HashSet::new();
+        // As `pull` resolves: walk the closure, then fetch what is remote
+        let mut resolve = |root: &str| -> Result<Vec<String>> {
+            let closure = pull_closure(
+                &[path(root)],
+                |paths| {
+                    Ok(paths
+                        .iter()
+                        .filter(|p| !store.contains(*p))
+                        .cloned()
+                        .collect())
+                },
+                |paths| Ok(paths.to_vec()),
+                |paths| Ok(paths.iter().map(|p| cache.get(p).cloned()).collect()),
+            )?;
+            let import = |path: &str, _: Result<()>| {
+                let _ = store.insert(path.to_string());
+                Ok(PathOutcome::Restored { file_size: 1 })
+            };
+            let downloads = closure.remote.iter().map(|_| Ok(()));
+            fetch_closure(&closure, downloads, import, &mut ResolveSummary::default())
+        };
+
+        let mut seen = HashSet::new();
+        let first = CycleDiff::record(1, &mut seen, resolve("app-1")?);
+        assert_eq!(first.added, [path("glibc"), path("lib-1"), path("app-1")]);
+        let second = CycleDiff::record(2, &mut seen, resolve("app-2")?);
+        assert_eq!(second.added, [path("lib-2"), path("app-2")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_flake_files_trigger() {
+        let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+        let modify = EventKind::Modify(notify::event::ModifyKind::Any);
+        let access = EventKind::Access(notify::event::AccessKind::Any);
+
+        assert!(is_flake_change(&event(modify, "/src/app/flake.lock")));
+        assert!(!is_flake_change(&event(modify, "/src/app/main.rs")));
+        assert!(!is_flake_change(&event(access, "/src/app/flake.nix")));
+    }
+
+    #[test]
+    fn test_flake_dir_from_output() -> Result<()> {
+        assert_eq!(flake_dir(None)?, PathBuf::from("."));
+        assert_eq!(
+            flake_dir(Some(".#devShells.x86_64-linux.default"))?,
+            PathBuf::from(".")
+        );
+        assert_eq!(
+            flake_dir(Some("path:/src/app#dev"))?,
+            PathBuf::from("/src/app")
+        );
+        assert!(flake_dir(Some("nixpkgs#hello")).is_err());
+        Ok(())
+    }
+}
@@ -4,9 +4,12 @@
 //! credentials, cache settings, and user preferences.
 
 use crate::error::{CliError, Result};
+use crate::utils::output;
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 pub mod auth;
 pub mod defaults;
@@ -14,6 +17,86 @@ pub mod defaults;
 pub use auth::AuthConfig;
 pub use defaults::*;
 
+/// Environment variable overriding the cache directory
+pub const CACHE_DIR_ENV: &str = "FLAKECACHE_CACHE_DIR";
+
+/// Environment variable overriding the scratch directory for compression
+pub const TEMP_DIR_ENV: &str = "FLAKECACHE_TMPDIR";
+
+/// Process-wide cache directory set from `--cache-dir`
+static CACHE_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Override the cache directory for the rest of the process
+///
+/// Called once at startup with the `--cache-dir` flag; later calls are ignored.
+pub fn set_cache_dir_override(path: PathBuf) {
+    let _ = CACHE_DIR_OVERRIDE.set(path);
+}
+
+/// Treat unset and empty environment variables the same
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Resolve the cache directory from its sources, highest priority first:
+/// `--cache-dir`, `FLAKECACHE_CACHE_DIR`, `$XDG_CACHE_HOME/flakecache`,
+/// then `~/.cache/flakecache`
+fn resolve_cache_dir(
+    flag: Option<&Path>,
+    env_dir: Option<String>,
+    xdg_cache_home: Option<String>,
+    home: Option<PathBuf>,
+) -> Option<PathBuf> {
+    flag.map(Path::to_path_buf)
+        .or_else(|| env_dir.map(PathBuf::from))
+        .or_else(|| xdg_cache_home.map(|xdg| PathBuf::from(xdg).join("flakecache")))
+        .or_else(|| home.map(|home| home.join(".cache").join("flakecache")))
+}
+
+/// Resolve the scratch directory from its sources, highest priority first:
+/// `--temp-dir`, `FLAKECACHE_TMPDIR`, `TMPDIR`, then the system default
+fn resolve_temp_dir(
+    flag: Option<&Path>,
+    env_dir: Option<String>,
+    tmpdir: Option<String>,
+) -> PathBuf {
+    flag.map(Path::to_path_buf)
+        .or_else(|| env_dir.map(PathBuf::from))
+        .or_else(|| tmpdir.map(PathBuf::from))
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Scratch directory for compressed NARs, uploaded or downloaded
+///
+/// Multi-GB NARs can exhaust a `tmpfs` `/tmp`, so the directory is
+/// overridable and checked up front rather than failing mid-upload.
+///
+/// # Errors
+///
+/// Returns [`CliError::DirError`] if the directory does not exist or is not
+/// writable.
+pub fn temp_dir(flag: Option<&Path>) -> Result<PathBuf> {
+    let dir = resolve_temp_dir(flag, non_empty_env(TEMP_DIR_ENV), non_empty_env("TMPDIR"));
+    let dir_error = |reason: String| CliError::DirError {
+        path: dir.clone(),
+        reason,
+    };
+
+    if !dir.is_dir() {
+        return Err(dir_error("temporary directory does not exist".to_string()));
+    }
+    let probe = dir.join(format!(".flakecache-write-test-{}", std::process::id()));
+    let _ = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)
+        .map_err(|e| dir_error(format!("temporary directory is not writable: {e}")))?;
+    let _ = fs::remove_file(&probe);
+
+    Ok(dir)
+}
+
 /// Main CLI configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -38,6 +121,16 @@ pub struct Config {
     /// Maximum parallel uploads/downloads
     #[serde(default = "defaults::default_parallelism")]
     pub parallelism: usize,
+
+    /// Binary cache root relative to the API URL, for servers that do not
+    /// serve caches at `<api url>/<cache>` (see `--narinfo-url-base`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub narinfo_url_base: Option<String>,
+
+    /// Public keys (`<name>:<base64>`) trusted in addition to nix.conf's
+    /// `trusted-public-keys`, added by `doctor --pin-key`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<String>,
 }
 
 impl Config {
@@ -64,6 +157,29 @@ impl Config {
         toml::from_str(&contents).map_err(|e| CliError::InvalidConfig(e.to_string()))
     }
 
+    /// The saved API token, if there is one
+    ///
+    /// A missing, unreadable, or corrupt config counts as "not logged in"
+    /// rather than an error, so a damaged file never blocks `login`.
+    #[must_use]
+    pub fn saved_token() -> Option<String> {
+        Self::token_from(&Self::config_path().ok()?)
+    }
+
+    /// The API token saved in the config at `path`
+    #[must_use]
+    pub fn token_from(path: &Path) -> Option<String> {
+        match Self::load_from(path) {
+            Ok(config) => Some(config.auth.token).filter(|token| !token.is_empty()),
+            Err(e) => {
+                if path.exists() {
+                    output::debug(format_args!("Ignoring unusable {}: {e}", path.display()));
+                }
+                None
+            }
+        }
+    }
+
     /// Save configuration to default location
     pub fn save(&self) -> Result<()> {
         let path = Self::config_path()?;
@@ -83,22 +199,17 @@ impl Config {
         let contents =
             toml::to_string_pretty(self).map_err(|e| CliError::SerializationError(e.to_string()))?;
 
-        fs::write(path, contents).map_err(|e| CliError::ConfigWrite {
-            path: path.to_path_buf(),
-            reason: e.to_string(),
-        })?;
-
-        // Set restrictive permissions on config file (contains credentials)
-        #[cfg(unix)]
-        {
-            use std::fs::Permissions;
-            use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(path, Permissions::from_mode(0o600)).map_err(|e| {
-                CliError::ConfigWrite {
-                    path: path.to_path_buf(),
-                    reason: format!("Failed to set permissions: {e}"),
-                }
-            })?;
+        // Write a sibling temp file and rename it into place, so a crash or
+        // full disk never leaves a truncated config behind
+        let tmp = path.with_extension(format!("toml.tmp-{}", std::process::id()));
+        let written =
+            write_private(&tmp, contents.as_bytes()).and_then(|()| fs::rename(&tmp, path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(CliError::ConfigWrite {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            });
         }
 
         Ok(())
@@ -126,24 +237,23 @@ impl Config {
     }
 
     /// Get cache directory path
+    ///
+    /// Every piece of on-disk cache state (credentials cache, dependency
+    /// cache, temporary data) lives under this one directory. See
+    /// [`set_cache_dir_override`] and [`CACHE_DIR_ENV`] for overrides.
     pub fn cache_dir() -> Result<PathBuf> {
-        let cache_home = std::env::var("XDG_CACHE_HOME")
-            .ok()
-            .and_then(|path| if path.is_empty() { None } else { Some(path) })
-            .or_else(|| {
-                dirs::home_dir().map(|home| {
-                    home.join(".cache").to_string_lossy().to_string()
-                })
-            });
-
-        cache_home
-            .ok_or_else(|| {
-                CliError::Internal(
-                    "Could not determine cache directory: XDG_CACHE_HOME not set and no home directory found"
-                        .to_string(),
-                )
-            })
-            .map(|path| PathBuf::from(path).join("flakecache"))
+        resolve_cache_dir(
+            CACHE_DIR_OVERRIDE.get().map(PathBuf::as_path),
+            non_empty_env(CACHE_DIR_ENV),
+            non_empty_env("XDG_CACHE_HOME"),
+            dirs::home_dir(),
+        )
+        .ok_or_else(|| {
+            CliError::Internal(
+                "Could not determine cache directory: XDG_CACHE_HOME not set and no home directory found"
+                    .to_string(),
+            )
+        })
     }
 
     /// Merge another config into this one, with other taking precedence
@@ -205,10 +315,27 @@ impl Default for Config {
             verbose: false,
             timeout_secs: default_timeout(),
             parallelism: default_parallelism(),
+            narinfo_url_base: None,
+            trusted_keys: Vec::new(),
         }
     }
 }
 
+/// Write `contents` to `path` readable only by the owner (0600 on Unix),
+/// syncing it to disk before returning
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    let _ = options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let _ = options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -221,9 +348,86 @@ mod tests {
         assert!(config.timeout_secs > 0);
     }
 
+    #[test]
+    fn test_cache_dir_precedence() {
+        let home = Some(PathBuf::from("/home/u"));
+        let xdg = || Some("/xdg".to_string());
+        let env = || Some("/env".to_string());
+
+        assert_eq!(
+            resolve_cache_dir(Some(Path::new("/flag")), env(), xdg(), home.clone()),
+            Some(PathBuf::from("/flag"))
+        );
+        assert_eq!(
+            resolve_cache_dir(None, env(), xdg(), home.clone()),
+            Some(PathBuf::from("/env"))
+        );
+        assert_eq!(
+            resolve_cache_dir(None, None, xdg(), home.clone()),
+            Some(PathBuf::from("/xdg/flakecache"))
+        );
+        assert_eq!(
+            resolve_cache_dir(None, None, None, home),
+            Some(PathBuf::from("/home/u/.cache/flakecache"))
+        );
+    }
+
+    #[test]
+    fn test_temp_dir_precedence() {
+        let tmpdir = || Some("/tmpdir".to_string());
+        assert_eq!(
+            resolve_temp_dir(Some(Path::new("/flag")), Some("/env".to_string()), tmpdir()),
+            PathBuf::from("/flag")
+        );
+        assert_eq!(
+            resolve_temp_dir(None, Some("/env".to_string()), tmpdir()),
+            PathBuf::from("/env")
+        );
+        assert_eq!(resolve_temp_dir(None, None, tmpdir()), PathBuf::from("/tmpdir"));
+        assert_eq!(resolve_temp_dir(None, None, None), std::env::temp_dir());
+    }
+
+    #[test]
+    fn test_temp_dir_must_exist() {
+        let missing = Path::new("/nonexistent/flakecache-scratch");
+        assert!(matches!(temp_dir(Some(missing)), Err(CliError::DirError { .. })));
+        assert!(temp_dir(Some(&std::env::temp_dir())).is_ok());
+    }
+
     #[test]
     fn test_config_validation() {
         let config = Config::default();
         assert!(config.validate().is_ok());
     }
+
+    #[test]
+    fn test_save_replaces_file_atomically() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flakecache-config-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        Config::default().save_to(&path)?;
+        Config::default().save_to(&path)?;
+
+        let entries = fs::read_dir(&dir).map(Iterator::count).unwrap_or_default();
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(&path).map(|m| m.permissions().mode() & 0o777).unwrap_or_default()
+        };
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(entries, 1, "temp file left behind");
+        #[cfg(unix)]
+        assert_eq!(mode, 0o600);
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_config_means_no_token() {
+        let path = std::env::temp_dir()
+            .join(format!("flakecache-corrupt-{}.toml", std::process::id()));
+        let _ = fs::write(&path, "[auth]\ntoken = \"trunc");
+        let token = Config::token_from(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(token, None);
+        assert_eq!(Config::token_from(Path::new("/nonexistent/config.toml")), None);
+    }
 }
@@ -231,6 +231,67 @@ impl CliError {
                 | Self::Timeout(_)
         )
     }
+
+    /// Stable `snake_case` name of the error variant, for machine-readable
+    /// output
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Http(_) => "http",
+            Self::ConnectionError { .. } => "connection_error",
+            Self::ApiError { .. } => "api_error",
+            Self::InvalidResponse(_) => "invalid_response",
+            Self::AuthFailed(_) => "auth_failed",
+            Self::MissingToken => "missing_token",
+            Self::OAuthError(_) => "oauth_error",
+            Self::TokenExpired(_) => "token_expired",
+            Self::ConfigRead { .. } => "config_read",
+            Self::InvalidConfig(_) => "invalid_config",
+            Self::NoConfig => "no_config",
+            Self::ConfigWrite { .. } => "config_write",
+            Self::StoreError(_) => "store_error",
+            Self::FlakeResolutionError { .. } => "flake_resolution_error",
+            Self::InvalidStorePath { .. } => "invalid_store_path",
+            Self::FlakeNotFound { .. } => "flake_not_found",
+            Self::StorePathNotFound { .. } => "store_path_not_found",
+            Self::CacheError(_) => "cache_error",
+            Self::SignatureError(_) => "signature_error",
+            Self::CacheNotFound { .. } => "cache_not_found",
+            Self::InvalidCacheName { .. } => "invalid_cache_name",
+            Self::UploadFailed(_) => "upload_failed",
+            Self::DownloadFailed(_) => "download_failed",
+            Self::TransferInterrupted(_) => "transfer_interrupted",
+            Self::ChecksumMismatch { .. } => "checksum_mismatch",
+            Self::SerializationError(_) => "serialization_error",
+            Self::DeserializationError(_) => "deserialization_error",
+            Self::EncodingError(_) => "encoding_error",
+            Self::FileError { .. } => "file_error",
+            Self::DirError { .. } => "dir_error",
+            Self::PermissionDenied { .. } => "permission_denied",
+            Self::InvalidArgument(_) => "invalid_argument",
+            Self::MissingArgument(_) => "missing_argument",
+            Self::Internal(_) => "internal",
+            Self::Timeout(_) => "timeout",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    /// The error as printed on stderr with `--output json`
+    ///
+    /// `{"error": {"kind", "message", "retryable", "exit_code"}}`, so
+    /// automation can tell e.g. an auth failure from a network failure
+    /// without matching on the message.
+    #[must_use]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "kind": self.kind(),
+                "message": self.to_string(),
+                "retryable": self.is_retryable(),
+                "exit_code": self.exit_code(),
+            }
+        })
+    }
 }
 
 impl From<std::io::Error> for CliError {
@@ -277,3 +338,28 @@ impl From<ciborium::ser::Error<std::io::Error>> for CliError {
         Self::SerializationError(format!("CBOR encode error: {err}"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let json = CliError::ConnectionError {
+            host: "api.flakecache.com".to_string(),
+            reason: "refused".to_string(),
+        }
+        .to_json();
+        assert_eq!(json["error"]["kind"], "connection_error");
+        assert_eq!(json["error"]["retryable"], true);
+        assert_eq!(json["error"]["exit_code"], 4);
+        assert_eq!(
+            json["error"]["message"],
+            "Failed to connect to api.flakecache.com: refused"
+        );
+        assert_eq!(
+            CliError::MissingToken.to_json()["error"]["retryable"],
+            false
+        );
+    }
+}
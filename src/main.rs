@@ -2,8 +2,65 @@
 //!
 //! Fast, reliable, and feature-complete CLI for managing a shared Nix binary cache.
 
-use flakecache_cli::cli::{Cli, Commands};
-use flakecache_cli::Result;
+use flakecache_cli::cache::closure::ClosureCache;
+use flakecache_cli::cache::compress::{self, CompressOptions, Compression, DecompressedNar};
+use flakecache_cli::cache::compressed::{self, CompressedCache};
+use flakecache_cli::cache::download::{self, DownloadTarget, DownloadedPair};
+use flakecache_cli::cache::import;
+use flakecache_cli::cache::narinfo_cache::{self, NarInfoCache};
+use flakecache_cli::cache::signing::{self, PublicKey};
+use flakecache_cli::cache::transfer::{self, UploadMode};
+use flakecache_cli::cache::warm::{self, WarmEntry};
+use flakecache_cli::cli::{Cli, Commands, KeyCommand, NarCommand};
+use flakecache_cli::client::cbor::CborClient;
+use flakecache_cli::client::connectivity::Connectivity;
+use flakecache_cli::client::layout::{self, NarInfoUrlBase};
+use flakecache_cli::client::request;
+use flakecache_cli::commands::cache_management::{
+    self, GcPolicy, ListQuery, ListSort, TimeRange,
+};
+use flakecache_cli::commands::auth::DeviceFlow;
+use flakecache_cli::commands::doctor::{self, DoctorReport, SubstituterPriority};
+use flakecache_cli::commands::hook;
+use flakecache_cli::commands::key::{self, SecretKey};
+use flakecache_cli::commands::manifest::Manifest;
+use flakecache_cli::commands::pull::{
+    self, BuildMode, DownloadEstimate, ResolveSummary,
+};
+use flakecache_cli::commands::push::{
+    self, CompressionStats, FailurePolicy, PipelineWorkers, PushOptions, PushSize, UploadedSet,
+};
+use flakecache_cli::commands::version::BuildInfo;
+use flakecache_cli::commands::watch;
+use flakecache_cli::config::{self, default_parallelism, Config};
+use flakecache_cli::nix::flake::FlakeSource;
+use flakecache_cli::nix::{flake, nar};
+use flakecache_cli::nix::resolve::{resolve_narinfo, RetryOptions};
+use flakecache_cli::nix::{BuildActivity, ClosureKind, Nix};
+use flakecache_cli::utils::ci_summary::{self, StepSummary, SummaryTarget};
+use flakecache_cli::utils::metrics::{self, MetricsFile};
+use flakecache_cli::utils::lock::InstanceLock;
+use flakecache_cli::utils::output::{self, OutputFormat, Verbosity};
+use flakecache_cli::utils::parallel::Parallelism;
+use flakecache_cli::utils::progress::{format_bytes, Phase, TransferTimings};
+use flakecache_cli::utils::throttle::{self, RateLimiter};
+use flakecache_cli::utils::time::{self, DisplayZone};
+use flakecache_cli::utils::trace;
+use flakecache_cli::{CliError, Result};
+use futures::stream::{self, StreamExt};
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Print an informational line unless `--quiet` is set
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
 
 fn main() {
     let exit_code = run();
@@ -13,11 +70,22 @@ fn main() {
 /// Main application entry point
 fn run() -> i32 {
     let cli = Cli::parse_args();
+    let json_errors = cli.output.is_json();
 
-    match execute(cli) {
+    let result = execute(cli);
+    match trace::finish() {
+        Ok(Some(path)) => info!("Wrote trace to {}", path.display()),
+        Ok(None) => {}
+        Err(e) => eprintln!("⚠ Could not write trace: {e}"),
+    }
+    match result {
         Ok(()) => 0,
         Err(err) => {
-            eprintln!("Error: {err}");
+            if json_errors {
+                eprintln!("{}", err.to_json());
+            } else {
+                eprintln!("Error: {err}");
+            }
             err.exit_code()
         }
     }
@@ -25,44 +93,373 @@ fn run() -> i32 {
 
 /// Execute the requested command
 fn execute(cli: Cli) -> Result<()> {
+    output::set_verbosity(Verbosity::from_flags(cli.quiet, cli.verbose));
+    output::set_styled(output::resolve_styled(cli.no_color));
+    time::set_display_zone(DisplayZone::from_flags(cli.local));
     if cli.verbose {
         println!("FlakeCache CLI v{}", env!("CARGO_PKG_VERSION"));
         println!("Verbose output enabled");
     }
 
+    if cli.allow_insecure_http {
+        request::allow_insecure_http();
+    }
+    request::set_extra_headers(request::resolve_extra_headers(
+        cli.headers,
+        cli.allow_authorization_header,
+    )?);
+    if let Some(dir) = &cli.cache_dir {
+        config::set_cache_dir_override(dir.clone());
+    }
+    let narinfo_url_base = match cli.narinfo_url_base.clone() {
+        Some(base) => Some(base),
+        None => Config::load()
+            .ok()
+            .and_then(|config| config.narinfo_url_base)
+            .map(|base| base.parse::<NarInfoUrlBase>())
+            .transpose()?,
+    };
+    if let Some(base) = narinfo_url_base {
+        layout::set_narinfo_url_base(base);
+    }
+    if let Some(mbps) = throttle::resolve_mbps(cli.max_bandwidth)? {
+        throttle::set_global(RateLimiter::from_mbps(mbps)?);
+    }
+    if let Some(ttl) = narinfo_cache::resolve_ttl(cli.narinfo_cache_ttl)? {
+        narinfo_cache::set_global(NarInfoCache::new(ttl, narinfo_cache::DEFAULT_CAPACITY));
+    }
+    if let Some(path) = trace::resolve_output(cli.profile_output.as_deref())? {
+        trace::enable(path);
+    }
+
+    if cli.command.requires_network() {
+        Connectivity::check(&cli.api_url, cli.offline).require(&cli.api_url)?;
+    }
+
     match cli.command {
-        Commands::Login { cache } => handle_login(cache, cli.verbose),
+        Commands::Login { cache, device_code } => {
+            if device_code {
+                handle_device_login(&cli.api_url, cache)
+            } else {
+                handle_login(cache, cli.verbose)
+            }
+        }
         Commands::Logout => handle_logout(cli.verbose),
         Commands::Pull {
             flake_output,
             cache,
             parallelism,
-        } => handle_pull(flake_output, cache, parallelism, cli.verbose),
+            retries,
+            timeout,
+            retry_delay,
+            only_missing,
+            no_build,
+            closure,
+            watch,
+            time,
+            summary_file,
+            metrics_file,
+            estimate,
+            max_download_size,
+            to_store,
+            report_missing,
+            out,
+        } => handle_pull(
+            &cli.api_url,
+            flake_output,
+            cache,
+            capped_parallelism(parallelism),
+            RetryOptions::resolve(retries, timeout, retry_delay)?,
+            only_missing,
+            BuildMode::from_flag(no_build),
+            closure.unwrap_or_default(),
+            watch,
+            time,
+            &ci_summary::targets(summary_file.as_deref()),
+            metrics_file.as_deref(),
+            DownloadLimit {
+                estimate_only: estimate,
+                max_bytes: max_download_size,
+            },
+            report_missing.then_some(MissingReport { out }),
+            to_store.as_deref(),
+            &Connectivity::check(&cli.api_url, cli.offline),
+            &config::temp_dir(cli.temp_dir.as_deref())?,
+            cli.verbose,
+        ),
         Commands::Push {
             cache,
             flake_output,
             store_path,
+            stdin,
+            closure,
             parallelism,
+            compression_threads,
+            max_closure_size,
+            force,
+            compression,
+            compress_level,
+            reuse_compressed,
+            reuse_compressed_max_size,
             skip_verification,
-        } => handle_push(
+            signing_key,
+            signing_key_env,
+            fail_fast,
+            keep_going,
+            narinfo_only,
+            verify_nar,
+            verify_after_push,
+            check_reproducible,
+            fail_on_mismatch,
+            wait,
+            time,
+            manifest,
+            require_clean,
+            summary_file,
+            metrics_file,
+        } => {
+            // Only the manifest records the source, and only --require-clean
+            // needs it checked; otherwise skip the `nix flake metadata` call
+            let source = match flake_output.as_deref() {
+                Some(output) if manifest.is_some() || require_clean => {
+                    flake_source(output, require_clean)?
+                }
+                _ => None,
+            };
+            handle_push(
+                &cli.api_url,
+                cache,
+                flake_output,
+                store_path,
+                stdin,
+                closure.unwrap_or_default(),
+                PushOptions {
+                    policy: FailurePolicy::from_flags(fail_fast, keep_going),
+                    compress: CompressOptions::with_threads(compression_threads)
+                        .with_compression(push_compression(compression)?)
+                        .with_level(compress_level),
+                    temp_dir: config::temp_dir(cli.temp_dir.as_deref())?,
+                    reuse_compressed: reuse_cache(reuse_compressed, reuse_compressed_max_size)?,
+                    max_closure_size: max_closure_size.filter(|_| !force),
+                    parallelism: capped_parallelism(parallelism),
+                    upload_mode: if narinfo_only {
+                        UploadMode::NarInfoOnly { verify_nar }
+                    } else {
+                        UploadMode::Full
+                    },
+                    skip_verification,
+                    signing_key: match (signing_key, signing_key_env) {
+                        (Some(file), _) => Some(SecretKey::from_file(&file)?),
+                        (None, Some(var)) => Some(SecretKey::from_env(&var)?),
+                        (None, None) => None,
+                    },
+                    verify_after_push,
+                    check_reproducible,
+                    fail_on_mismatch,
+                    wait,
+                    time,
+                    manifest,
+                    source,
+                    summary: ci_summary::targets(summary_file.as_deref()),
+                    metrics_file,
+                },
+                cli.verbose,
+            )
+        }
+        Commands::List {
             cache,
-            flake_output,
+            limit,
+            after,
+            since,
+            until,
+            sort,
+        } => {
+            let range = TimeRange::new(since, until)?;
+            handle_list(&cli.api_url, cache, limit, after, range, sort, cli.output, cli.verbose)
+        }
+        Commands::Search {
+            pattern,
+            caches,
+            limit,
+        } => handle_search(&cli.api_url, &pattern, &caches, limit, cli.output),
+        Commands::Caches => handle_caches(&cli.api_url, cli.output),
+        Commands::Gc {
+            cache,
+            status,
+            set_policy,
+            older_than,
+            keep_latest,
+            dry_run,
+            force,
+        } => match keep_latest {
+            Some(keep) => handle_gc_keep_latest(&cli.api_url, &cache, keep, dry_run, force, cli.output),
+            None => handle_gc(&cli.api_url, &cache, status, set_policy, older_than, cli.output),
+        },
+        Commands::Delete {
+            cache,
+            store_paths,
+            from_file,
+            stdin,
+            force,
+        } => handle_delete(
+            &cli.api_url,
+            &cache,
+            store_paths,
+            from_file.as_deref(),
+            stdin,
+            force,
+            cli.output,
+        ),
+        Commands::Key { command } => handle_key(command),
+        Commands::Nar { command } => handle_nar(command, cli.verbose),
+        Commands::Import {
+            dir,
+            trusted_keys,
+            no_check_sigs,
+        } => handle_import(&dir, trusted_keys, no_check_sigs, cli.verbose),
+        Commands::Download {
+            cache,
+            hash,
             store_path,
+            output_dir,
+        } => {
+            let target = match (hash, store_path) {
+                (Some(hash), _) => DownloadTarget::Hash(hash),
+                (None, Some(path)) => DownloadTarget::StorePath(path),
+                (None, None) => {
+                    return Err(CliError::MissingArgument("--hash or --store-path".to_string()))
+                }
+            };
+            handle_download(&cli.api_url, &cache, &target, &output_dir, cli.output)
+        }
+        Commands::Warm {
+            cache,
+            installables,
+            from_file,
+            system,
+            all_systems,
+            nix_args,
+            nix_args_tail,
+            report_cache_hits,
+            compression,
+            compress_level,
+            reuse_compressed,
+            reuse_compressed_max_size,
             parallelism,
-            skip_verification,
+        } => handle_warm(
+            &cli.api_url,
+            cache,
+            &installables,
+            from_file.as_deref(),
+            system,
+            all_systems,
+            &[nix_args, nix_args_tail].concat(),
+            report_cache_hits,
+            PushOptions {
+                compress: CompressOptions::default()
+                    .with_compression(push_compression(compression)?)
+                    .with_level(compress_level),
+                temp_dir: config::temp_dir(cli.temp_dir.as_deref())?,
+                reuse_compressed: reuse_cache(reuse_compressed, reuse_compressed_max_size)?,
+                parallelism: capped_parallelism(parallelism),
+                ..PushOptions::default()
+            },
             cli.verbose,
         ),
-        Commands::List {
+        Commands::Stats {
+            cache,
+            since,
+            until,
+        } => {
+            let range = TimeRange::new(since, until)?;
+            handle_stats(&cli.api_url, &cache, &range, cli.output, cli.verbose)
+        }
+        Commands::Log {
             cache,
             limit,
             after,
-        } => handle_list(cache, limit, after, cli.verbose),
-        Commands::Warm {
+            since,
+            until,
+        } => {
+            let range = TimeRange::new(since, until)?;
+            handle_log(&cli.api_url, &cache, limit, after.as_deref(), &range, cli.output)
+        }
+        Commands::InstallHook {
             cache,
-            parallelism,
-        } => handle_warm(cache, parallelism, cli.verbose),
-        Commands::Stats { cache } => handle_stats(cache, cli.verbose),
-        Commands::Version => handle_version(),
+            path,
+            write,
+            nix_conf,
+        } => handle_install_hook(&cache, path, write, &nix_conf, cli.verbose),
+        Commands::Doctor {
+            fix,
+            cache,
+            show_key,
+            pin_key,
+            priority,
+            nix_conf,
+        } => {
+            if fix || show_key || pin_key {
+                let cache = match cache {
+                    Some(cache) => cache,
+                    None => Config::load()?.default_cache.ok_or_else(|| {
+                        CliError::MissingArgument(
+                            "--cache (needed for --fix, --show-key and --pin-key)".to_string(),
+                        )
+                    })?,
+                };
+                if fix {
+                    handle_doctor_fix(&cli.api_url, &cache, priority, &nix_conf)?;
+                }
+                if show_key || pin_key {
+                    handle_doctor_key(&cli.api_url, &cache, pin_key)?;
+                }
+            }
+            handle_doctor(&cli.api_url, cli.offline, cli.output)
+        }
+        Commands::Version => handle_version(cli.output),
+    }
+}
+
+/// Authenticated API client using the saved token
+fn api_client(api_url: &str) -> Result<CborClient> {
+    let token = Config::saved_token().ok_or(CliError::MissingToken)?;
+    CborClient::new(api_url, Some(token))
+}
+
+/// Compression for a push: `--compression` if its program is installed,
+/// else xz, falling back with a warning to another installed compressor
+fn push_compression(requested: Option<Compression>) -> Result<Compression> {
+    let compression = compress::choose_compression(requested, &compress::available_compressors())?;
+    let wanted = requested.unwrap_or_default();
+    if compression != wanted {
+        eprintln!("⚠ {wanted} not found; compressing NARs with {compression} (install {wanted} to use it)");
+    }
+    Ok(compression)
+}
+
+/// Compressed NAR cache for `--reuse-compressed`, if enabled
+fn reuse_cache(enabled: bool, max_size: Option<u64>) -> Result<Option<CompressedCache>> {
+    enabled
+        .then(|| CompressedCache::open(max_size.unwrap_or(compressed::DEFAULT_MAX_BYTES)))
+        .transpose()
+}
+
+/// Lock out other flakecache processes using the cache directory,
+/// waiting for one that already holds it
+fn instance_lock() -> Result<InstanceLock> {
+    InstanceLock::acquire(&InstanceLock::default_path()?, |holder| match holder {
+        Some(pid) => eprintln!("⚠ Another flakecache (PID {pid}) is pushing; waiting for it to finish"),
+        None => eprintln!("⚠ Another flakecache is pushing; waiting for it to finish"),
+    })
+}
+
+/// Parallelism resolved from `--parallelism` (including `auto`) and
+/// reduced to what `--max-bandwidth` can usefully feed
+fn capped_parallelism(requested: Option<Parallelism>) -> Option<usize> {
+    let limiter = throttle::global();
+    match requested {
+        Some(parallelism) => Some(parallelism.resolve(limiter.as_ref())),
+        None => limiter.map(|limiter| limiter.cap_parallelism(default_parallelism())),
     }
 }
 
@@ -75,7 +472,40 @@ fn handle_login(cache: Option<String>, verbose: bool) -> Result<()> {
         }
     }
 
-    println!("✓ Login successful");
+    info!("✓ Login successful");
+    Ok(())
+}
+
+/// Handle `login --device-code`
+fn handle_device_login(api_url: &str, cache: Option<String>) -> Result<()> {
+    let flow = DeviceFlow::new(api_url)?;
+    let token = tokio::runtime::Runtime::new()?.block_on(async {
+        let authorization = flow.start().await?;
+        match &authorization.verification_uri_complete {
+            Some(uri) => println!("Open {uri} and confirm the code {}", authorization.user_code),
+            None => println!(
+                "Open {} and enter the code {}",
+                authorization.verification_uri, authorization.user_code
+            ),
+        }
+        info!("Waiting for approval...");
+        flow.wait_for_token(&authorization).await
+    })?;
+
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(CliError::NoConfig) => Config::default(),
+        Err(e) => return Err(e),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    config.auth = token.into_auth(now);
+    if cache.is_some() {
+        config.default_cache = cache;
+    }
+    config.save()?;
+    info!("✓ Login successful");
     Ok(())
 }
 
@@ -85,17 +515,85 @@ fn handle_logout(verbose: bool) -> Result<()> {
         println!("Clearing credentials...");
     }
 
-    println!("✓ Logged out");
+    info!("✓ Logged out");
     Ok(())
 }
 
 /// Handle pull command
 fn handle_pull(
+    api_url: &str,
     flake_output: Option<String>,
     cache: Option<String>,
     parallelism: Option<usize>,
+    retry: RetryOptions,
+    only_missing: bool,
+    build_mode: BuildMode,
+    closure: ClosureKind,
+    watch: bool,
+    time: bool,
+    summary_targets: &[SummaryTarget],
+    metrics_file: Option<&Path>,
+    limit: DownloadLimit,
+    missing_report: Option<MissingReport>,
+    to_store: Option<&str>,
+    connectivity: &Connectivity,
+    temp_dir: &Path,
     verbose: bool,
 ) -> Result<()> {
+    let timings = TransferTimings::start();
+
+    if limit.is_set() || missing_report.is_some() {
+        let cache = match cache.clone() {
+            Some(cache) => cache,
+            None => Config::load()?.default_cache.ok_or_else(|| {
+                CliError::MissingArgument(
+                    "--cache (needed for --estimate and --report-missing)".to_string(),
+                )
+            })?,
+        };
+        let installable = flake_output.as_deref().unwrap_or(".");
+        let estimate = estimate_pull(api_url, &cache, installable, &retry)?;
+        if limit.estimate_only {
+            println!("{estimate}");
+        } else {
+            info!("{estimate}");
+        }
+        if let Some(report) = &missing_report {
+            report.write(&estimate.missing)?;
+        }
+        if let Some(max) = limit.max_bytes.filter(|max| estimate.exceeds(*max)) {
+            return Err(CliError::CacheError(format!(
+                "estimated download of {} exceeds --max-download-size {}",
+                format_bytes(estimate.file_bytes),
+                format_bytes(max)
+            )));
+        }
+        if limit.estimate_only {
+            return Ok(());
+        }
+    }
+
+    let source_cache = if connectivity.is_online() {
+        match cache.clone() {
+            Some(cache) => Some(cache),
+            None => Config::load()?.default_cache,
+        }
+    } else {
+        eprintln!("⚠ FlakeCache is {connectivity}; relying on local Nix substitution only");
+        None
+    };
+    let source = source_cache.map(|cache| PullSource {
+        api_url,
+        cache,
+        retry: &retry,
+        temp_dir,
+        trusted_keys: local_trusted_keys(),
+        parallelism: parallelism.unwrap_or_else(default_parallelism),
+    });
+    if source.as_ref().is_some_and(|source| source.trusted_keys.is_empty()) {
+        info!("No trusted-public-keys in nix.conf or the config file; Nix will substitute every path");
+    }
+
     if verbose {
         println!("Pulling dependencies...");
         if let Some(output) = &flake_output {
@@ -107,19 +605,299 @@ fn handle_pull(
         if let Some(n) = parallelism {
             println!("Parallelism: {n}");
         }
+        println!(
+            "Retries: {} (timeout {}s, delay {}s)",
+            retry.retries,
+            retry.timeout.as_secs(),
+            retry.retry_delay.as_secs()
+        );
+        if build_mode == BuildMode::SubstituteOnly {
+            println!("Not building paths missing from the cache");
+        }
+        println!("Closure: {}", closure.name());
+        if let Some(store) = to_store {
+            println!("Copying fetched paths to: {store}");
+        }
+    }
+
+    if watch {
+        let flake_dir = watch::flake_dir(flake_output.as_deref())?;
+        info!("Watching {} for flake changes (Ctrl-C to stop)", flake_dir.display());
+        return watch::watch(
+            &flake_dir,
+            watch::DEFAULT_DEBOUNCE,
+            || {
+                let (_, fetched) = resolve_once(
+                    flake_output.as_deref(),
+                    source.as_ref(),
+                    only_missing,
+                    build_mode,
+                    closure,
+                    verbose,
+                )?;
+                copy_to_store(to_store, &fetched)?;
+                Ok(fetched)
+            },
+            |cycle| match cycle {
+                Ok(diff) => info!("{diff}"),
+                Err(e) => eprintln!("✗ Resolve failed: {e}"),
+            },
+        );
+    }
+
+    let (summary, fetched) = resolve_once(
+        flake_output.as_deref(),
+        source.as_ref(),
+        only_missing,
+        build_mode,
+        closure,
+        verbose,
+    )?;
+    copy_to_store(to_store, &fetched)?;
+    info!("✓ Pull complete");
+    info!("{summary}");
+    if time {
+        println!("{}", timings.report());
+    }
+    if !summary_targets.is_empty() {
+        StepSummary::new("pull")
+            .with("paths_restored", summary.restored)
+            .with("paths_substituted", summary.substituted)
+            .with("paths_present", summary.already_present)
+            .with("paths_missing", summary.missing)
+            .with("downloaded_bytes", summary.restored_bytes)
+            .with("cache_hit_ratio", format!("{:.2}", summary.hit_ratio()))
+            .with("duration_secs", format!("{:.1}", timings.report().total.as_secs_f64()))
+            .write(summary_targets)?;
+    }
+    if let Some(path) = metrics_file {
+        MetricsFile::new("pull", cache.as_deref())
+            .counter(
+                metrics::CACHE_HITS,
+                "Store paths fetched from the cache",
+                summary.restored as u64,
+            )
+            .gauge(metrics::DURATION, "Duration of the run", timings.report().total.as_secs_f64())
+            .write(path)?;
+    }
+    Ok(())
+}
+
+/// The cache a pull resolves against
+#[derive(Debug, Clone)]
+struct PullSource<'a> {
+    api_url: &'a str,
+    cache: String,
+    retry: &'a RetryOptions,
+    /// Where NARs are downloaded to before they are imported
+    temp_dir: &'a Path,
+    /// Keys a NARInfo must be signed by for the CLI to import its NAR
+    trusted_keys: Vec<PublicKey>,
+    /// NARs downloaded at once
+    parallelism: usize,
+}
+
+/// `pull --estimate` / `--max-download-size`
+#[derive(Debug, Clone, Copy)]
+struct DownloadLimit {
+    estimate_only: bool,
+    max_bytes: Option<u64>,
+}
+
+impl DownloadLimit {
+    const fn is_set(&self) -> bool {
+        self.estimate_only || self.max_bytes.is_some()
+    }
+}
+
+/// `pull --report-missing` / `--out`
+#[derive(Debug, Clone)]
+struct MissingReport {
+    out: Option<PathBuf>,
+}
+
+impl MissingReport {
+    /// Write `missing` one path per line, to `--out` or stdout
+    fn write(&self, missing: &[String]) -> Result<()> {
+        let list: String = missing.iter().map(|path| format!("{path}\n")).collect();
+        let Some(out) = &self.out else {
+            print!("{list}");
+            return Ok(());
+        };
+        std::fs::write(out, list).map_err(|e| CliError::FileError {
+            path: out.clone(),
+            reason: e.to_string(),
+        })?;
+        info!("Wrote {} missing paths to {}", missing.len(), out.display());
+        Ok(())
+    }
+}
+
+/// Size the download for `installable` from the cache's NARInfos
+fn estimate_pull(
+    api_url: &str,
+    cache: &str,
+    installable: &str,
+    retry: &RetryOptions,
+) -> Result<DownloadEstimate> {
+    let nix = Nix::new();
+    let planned = nix.build_json(installable, &["--dry-run".to_string()])?;
+    let roots = flake::extract_store_paths(installable, &planned)?;
+    let client = api_client(api_url)?;
+    tokio::runtime::Runtime::new()?
+        .block_on(pull::estimate_download(&client, cache, &roots, retry, |paths| {
+            nix.query_invalid(paths)
+        }))
+}
+
+/// Resolve the requested closure once, returning the tally and the paths
+/// that had to be fetched
+///
+/// With a `source`, the closure is walked through the local store and the
+/// cache's NARInfos and the paths the cache has are fetched from it.
+/// Without one (offline, or no cache configured) Nix does all the work.
+fn resolve_once(
+    flake_output: Option<&str>,
+    source: Option<&PullSource>,
+    only_missing: bool,
+    build_mode: BuildMode,
+    closure_kind: ClosureKind,
+    verbose: bool,
+) -> Result<(ResolveSummary, Vec<String>)> {
+    // Filled in per path as each store path is classified during resolution
+    let mut summary = ResolveSummary::default();
+    if build_mode == BuildMode::SubstituteOnly {
+        let outcome = pull::substitute_only(
+            &Nix::new(),
+            flake_output.unwrap_or("."),
+            closure_kind,
+            &mut summary,
+        )?;
+        if !outcome.unbuilt.is_empty() {
+            eprintln!(
+                "⚠ {} derivations are not in the cache and were not built (--no-build):",
+                outcome.unbuilt.len()
+            );
+            for drv in &outcome.unbuilt {
+                eprintln!("  {drv}");
+            }
+        }
+        return Ok((summary, outcome.fetched));
     }
+    let nix = Nix::new();
+    let installable = flake_output.unwrap_or(".");
+    let Some(source) = source else {
+        // No cache to resolve against: Nix substitutes from its own
+        // substituters and builds the rest
+        let _ = nix.build_json(installable, &[])?;
+        return Ok((summary, Vec::new()));
+    };
+    let client = api_client(source.api_url)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let closures = ClosureCache::open()?;
+    let roots = pull::requested_paths(&nix, installable, closure_kind)?;
+    let closure = pull::pull_closure(
+        &roots,
+        |paths| nix.query_invalid(paths),
+        |valid| closures.query(&nix, valid, closure_kind),
+        |paths| {
+            runtime.block_on(pull::cache_references(&client, &source.cache, paths, source.retry))
+        },
+    )?;
+    if only_missing {
+        info!("{}", closure.delta());
+    }
+    if verbose {
+        println!("Fetching {} paths", closure.remote.len());
+    }
+    let substituter = pull::substituter_url(source.api_url, &source.cache);
+    // Downloads run up to `parallelism` ahead; `buffered` hands them back in
+    // closure order so each import finds its references already present
+    let mut downloads = stream::iter(&closure.remote)
+        .map(|path| download_verified(&client, source, path))
+        .buffered(source.parallelism.max(1));
+    let fetched = pull::fetch_closure(
+        &closure,
+        std::iter::from_fn(|| runtime.block_on(downloads.next())),
+        |path, downloaded| {
+            pull::fetch_with_fallback(
+                path,
+                |path| import_downloaded(&nix, source, path, downloaded?),
+                |path| nix.substitute_from(&[path.to_string()], &substituter).map(|_| ()),
+            )
+        },
+        &mut summary,
+    )?;
+    if !closure.uncached.is_empty() {
+        info!("Building {} paths the cache does not have", closure.uncached.len());
+        let _ = nix.build_json(installable, &[])?;
+    }
+    Ok((summary, fetched))
+}
 
-    println!("✓ Pull complete");
+/// Download `path`'s NAR and NARInfo from the cache once the NARInfo is
+/// signed by a trusted key
+///
+/// An unsigned or untrusted path is refused before anything is
+/// downloaded; the caller then leaves it to Nix's substituter, which
+/// checks signatures itself.
+async fn download_verified(
+    client: &CborClient,
+    source: &PullSource<'_>,
+    path: &str,
+) -> Result<DownloadedPair> {
+    let narinfo = resolve_narinfo(client, &source.cache, path, source.retry).await?;
+    if !narinfo.is_some_and(|info| signing::verify_narinfo(&info, &source.trusted_keys)) {
+        return Err(CliError::SignatureError(format!(
+            "{path} has no valid signature by a trusted key"
+        )));
+    }
+    let target = DownloadTarget::StorePath(path.to_string());
+    download::download_pair(client, &source.cache, &target, source.temp_dir).await
+}
+
+/// Import a NAR fetched by [`download_verified`] once it matches its
+/// NARInfo, removing the downloaded files and returning the compressed
+/// bytes fetched
+fn import_downloaded(
+    nix: &Nix,
+    source: &PullSource,
+    path: &str,
+    pair: DownloadedPair,
+) -> Result<u64> {
+    let imported = transfer::import_verified(
+        nix,
+        path,
+        &pair.narinfo,
+        &pair.nar_file,
+        &source.trusted_keys,
+    );
+    let _ = std::fs::remove_file(&pair.nar_file);
+    let _ = std::fs::remove_file(&pair.narinfo_file);
+    imported?;
+    Ok(pair.narinfo.file_size.unwrap_or(0))
+}
+
+/// `pull --to-store`: copy what the resolve fetched into `dest`
+fn copy_to_store(dest: Option<&str>, paths: &[String]) -> Result<()> {
+    let Some(dest) = dest else {
+        return Ok(());
+    };
+    info!("Copying {} paths to {dest}", paths.len());
+    Nix::new().copy_to(paths, dest)?;
+    info!("✓ Copied to {dest}");
     Ok(())
 }
 
 /// Handle push command
 fn handle_push(
+    api_url: &str,
     cache: String,
     flake_output: Option<String>,
     store_path: Option<String>,
-    parallelism: Option<usize>,
-    skip_verification: bool,
+    stdin: bool,
+    closure: ClosureKind,
+    options: PushOptions,
     verbose: bool,
 ) -> Result<()> {
     if verbose {
@@ -131,20 +909,240 @@ fn handle_push(
         if let Some(path) = &store_path {
             println!("Store path: {path}");
         }
-        if let Some(n) = parallelism {
+        if let Some(n) = options.parallelism {
             println!("Parallelism: {n}");
         }
-        if skip_verification {
+        println!(
+            "Compression: {} level {} ({} threads)",
+            options.compress.compression, options.compress.level, options.compress.threads
+        );
+        println!("Temp dir: {}", options.temp_dir.display());
+        if options.skip_verification {
             println!("Signature verification: SKIPPED");
         }
+        if let Some(key) = &options.signing_key {
+            println!("Signing with key: {}", key.name());
+        }
+        if let UploadMode::NarInfoOnly { verify_nar } = options.upload_mode {
+            println!("Uploading NARInfo only (verify NAR exists: {verify_nar})");
+        }
     }
 
-    println!("✓ Push complete");
+    let nix = Nix::new();
+    let mut paths: Vec<String> = store_path.into_iter().collect();
+    if stdin {
+        paths.extend(push::read_paths(std::io::stdin().lock())?);
+    }
+    if let Some(output) = &flake_output {
+        info!("Building {output}");
+        let built = nix.build_json(output, &[])?;
+        paths.extend(flake::extract_store_paths(output, &built)?);
+    }
+    if paths.is_empty() {
+        return Err(CliError::MissingArgument(
+            "a flake output, --store-path, or paths on --stdin".to_string(),
+        ));
+    }
+    // The cache must hold everything the NARInfos reference, so even the
+    // default pushes the runtime closure rather than only the named paths
+    let paths = ClosureCache::open()?.query(&nix, &paths, closure)?;
+    info!("Pushing {} paths ({} closure)", paths.len(), closure.name());
+    if let Some(limit) = options.max_closure_size {
+        let size = PushSize::of(&paths, &nix.query_sizes(&paths)?);
+        if verbose {
+            println!("Push size: {size}");
+        }
+        size.check(limit)?;
+    }
+    upload_paths(api_url, &cache, &paths, &options, verbose)?;
+
+    info!("✓ Push complete");
+    Ok(())
+}
+
+/// Where the flake of `flake_output` came from, for the push manifest
+///
+/// A git tree with uncommitted changes is refused with `--require-clean`
+/// and warned about otherwise: its paths may match no commit. If the
+/// metadata cannot be read the push goes on without a source, unless
+/// `--require-clean` needs it.
+fn flake_source(flake_output: &str, require_clean: bool) -> Result<Option<FlakeSource>> {
+    let flake = flake::flake_ref(flake_output);
+    let metadata = match Nix::new().flake_metadata(flake) {
+        Ok(metadata) => metadata,
+        Err(e) if require_clean => return Err(e),
+        Err(e) => {
+            eprintln!("⚠ Could not read the metadata of {flake} ({e}); recording no source");
+            return Ok(None);
+        }
+    };
+    let source = FlakeSource::from_metadata(&metadata);
+    if source.dirty {
+        if require_clean {
+            return Err(CliError::FlakeResolutionError {
+                flake: flake.to_string(),
+                reason: "the git tree has uncommitted changes (--require-clean)".to_string(),
+            });
+        }
+        eprintln!("⚠ {flake} has uncommitted changes; the pushed paths may not match any commit");
+    }
+    Ok(Some(source))
+}
+
+/// Upload store paths to `cache`, printing the push summary
+fn upload_paths(
+    api_url: &str,
+    cache: &str,
+    paths: &[String],
+    options: &PushOptions,
+    verbose: bool,
+) -> Result<()> {
+    // Compression temp files and the compressed NAR cache are shared with
+    // any other push or warm on this machine
+    let _lock = instance_lock()?;
+    let timings = RefCell::new(TransferTimings::start());
+    let client = api_client(api_url)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let nix = Nix::new();
+    let workers = PipelineWorkers::new(
+        num_cpus::get(),
+        options.compress.threads,
+        options.parallelism.unwrap_or_else(default_parallelism),
+    );
+    if verbose {
+        println!(
+            "Pipeline: {} compression workers, {} upload workers",
+            workers.compress, workers.upload
+        );
+    }
+
+    let seen = UploadedSet::new();
+    let sizes = RefCell::new(CompressionStats::default());
+    let manifest = RefCell::new(
+        options
+            .manifest
+            .as_ref()
+            .map(|_| Manifest::new(cache).with_source(options.source.clone())),
+    );
+    let mismatches = RefCell::new(Vec::new());
+    let prepare = |path: String| {
+        let nix = &nix;
+        async move {
+            transfer::prepare_store_path(
+                nix,
+                &path,
+                options.compress,
+                &options.temp_dir,
+                options.reuse_compressed.as_ref(),
+                options.signing_key.as_ref(),
+            )
+            .await
+        }
+    };
+    let upload = |prepared: transfer::PreparedPath| {
+        let (client, timings, sizes, manifest) = (&client, &timings, &sizes, &manifest);
+        let mismatches = &mismatches;
+        async move {
+            if options.check_reproducible {
+                if let Some(mismatch) =
+                    transfer::check_reproducible(client, cache, &prepared.narinfo).await?
+                {
+                    eprintln!("⚠ Not reproducible, keeping the cached copy: {mismatch}");
+                    mismatches.borrow_mut().push(mismatch);
+                    return Ok(());
+                }
+            }
+            let uploaded =
+                transfer::publish_prepared(client, cache, prepared, options.upload_mode).await?;
+            if let Some(timeout) = options.wait {
+                let hash = uploaded.narinfo.store_hash();
+                client.await_persisted(cache, hash, timeout).await?;
+            }
+            if options.verify_after_push {
+                transfer::verify_served(client, cache, &uploaded.narinfo).await?;
+            }
+            let mut timings = timings.borrow_mut();
+            timings.add(Phase::Compress, uploaded.compress_time);
+            timings.add(Phase::Network, uploaded.upload_time);
+            timings.add_bytes(uploaded.narinfo.file_size.unwrap_or_default());
+            drop(timings);
+            let stats = CompressionStats::of(&uploaded.narinfo);
+            sizes.borrow_mut().add(stats);
+            if let Some(manifest) = manifest.borrow_mut().as_mut() {
+                manifest.record(&uploaded.narinfo);
+            }
+            if verbose {
+                println!(
+                    "  ✓ {} ({stats}, {})",
+                    uploaded.narinfo.store_path, uploaded.narinfo.compression
+                );
+            }
+            Ok(())
+        }
+    };
+    let mut summary = runtime.block_on(push::push_pipelined(
+        paths,
+        options.policy,
+        &seen,
+        workers,
+        prepare,
+        upload,
+    ));
+    summary.record_not_reproducible(mismatches.into_inner());
+    let (timings, sizes) = (timings.into_inner(), sizes.into_inner());
+    let manifest = manifest.into_inner();
+
+    info!("{summary}");
+    if summary.succeeded > 0 {
+        info!("Total: {sizes}");
+    }
+    for (path, reason) in &summary.failed {
+        eprintln!("  ✗ {path}: {reason}");
+    }
+    if options.time {
+        println!("{}", timings.report());
+    }
+    if let (Some(manifest), Some(path)) = (&manifest, &options.manifest) {
+        manifest.write(path)?;
+        info!("Wrote manifest of {} paths to {}", manifest.paths.len(), path.display());
+    }
+    if !options.summary.is_empty() {
+        StepSummary::new("push")
+            .with("paths_pushed", summary.succeeded)
+            .with("paths_failed", summary.failed.len())
+            .with("paths_skipped", summary.skipped)
+            .with("paths_not_reproducible", summary.not_reproducible.len())
+            .with("nar_bytes", sizes.nar_bytes)
+            .with("uploaded_bytes", sizes.file_bytes)
+            .with("compression_ratio", format!("{:.2}", sizes.ratio()))
+            .with("duration_secs", format!("{:.1}", timings.report().total.as_secs_f64()))
+            .write(&options.summary)?;
+    }
+    if let Some(path) = &options.metrics_file {
+        MetricsFile::new("push", Some(cache))
+            .counter(metrics::PATHS_UPLOADED, "Store paths uploaded", summary.succeeded as u64)
+            .counter(metrics::BYTES_UPLOADED, "Compressed bytes uploaded", sizes.file_bytes)
+            .gauge(metrics::DURATION, "Duration of the run", timings.report().total.as_secs_f64())
+            .write(path)?;
+    }
+    summary.check(options.policy)?;
+    if options.fail_on_mismatch {
+        summary.check_reproducible()?;
+    }
     Ok(())
 }
 
 /// Handle list command
-fn handle_list(cache: String, limit: usize, after: Option<String>, verbose: bool) -> Result<()> {
+fn handle_list(
+    api_url: &str,
+    cache: String,
+    limit: usize,
+    after: Option<String>,
+    range: TimeRange,
+    sort: ListSort,
+    output: OutputFormat,
+    verbose: bool,
+) -> Result<()> {
     if verbose {
         println!("Listing cache contents...");
         println!("Cache: {cache}");
@@ -154,37 +1152,661 @@ fn handle_list(cache: String, limit: usize, after: Option<String>, verbose: bool
         }
     }
 
-    println!("✓ Cache contents:");
+    let client = api_client(api_url)?;
+    let query = ListQuery {
+        limit,
+        after,
+        query: None,
+        range,
+    };
+    if output.is_json_lines() {
+        // Every page, one path per line, written as each page arrives
+        let mut stdout = std::io::stdout().lock();
+        let visit = |path: &cache_management::StorePath| -> Result<()> {
+            serde_json::to_writer(&mut stdout, path)?;
+            writeln!(stdout)?;
+            Ok(stdout.flush()?)
+        };
+        let _ = tokio::runtime::Runtime::new()?
+            .block_on(cache_management::stream_paths(&client, &cache, &query, visit))?;
+        return Ok(());
+    }
+    let mut page = tokio::runtime::Runtime::new()?
+        .block_on(cache_management::list_paths(&client, &cache, &query))?;
+    sort.sort(&mut page.paths);
+
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&page)?);
+        return Ok(());
+    }
+    if page.paths.is_empty() {
+        info!("Cache {cache} has no matching paths");
+    } else {
+        info!("✓ Cache contents:");
+        println!("{}", cache_management::path_table(&page.paths));
+    }
+    if let Some(cursor) = &page.next_cursor {
+        println!("More results: --after {cursor}");
+    }
     Ok(())
 }
 
+/// Handle search command
+fn handle_search(
+    api_url: &str,
+    pattern: &str,
+    caches: &[String],
+    limit: usize,
+    output: OutputFormat,
+) -> Result<()> {
+    let client = api_client(api_url)?;
+    let (results, errors) = tokio::runtime::Runtime::new()?
+        .block_on(cache_management::search(&client, caches, pattern, limit))?;
+
+    for (cache, err) in &errors {
+        eprintln!("⚠ Could not search {cache}: {err}");
+    }
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for result in &results {
+            println!("{result}");
+        }
+    }
+    Ok(())
+}
+
+/// Handle caches command
+fn handle_caches(api_url: &str, output: OutputFormat) -> Result<()> {
+    let client = api_client(api_url)?;
+    let caches =
+        tokio::runtime::Runtime::new()?.block_on(cache_management::list_caches(&client))?;
+
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&caches)?);
+    } else if caches.is_empty() {
+        info!("No caches found for this account");
+    } else {
+        for cache in &caches {
+            println!("{cache}");
+        }
+    }
+    Ok(())
+}
+
+/// Handle delete command
+fn handle_delete(
+    api_url: &str,
+    cache: &str,
+    mut paths: Vec<String>,
+    from_file: Option<&Path>,
+    stdin: bool,
+    force: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    if let Some(file) = from_file {
+        let reader = std::fs::File::open(file).map_err(|e| CliError::FileError {
+            path: file.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        paths.extend(push::read_paths(std::io::BufReader::new(reader))?);
+    }
+    if stdin {
+        paths.extend(push::read_paths(std::io::stdin().lock())?);
+    }
+    let mut seen = std::collections::HashSet::new();
+    paths.retain(|path| seen.insert(path.clone()));
+    if paths.is_empty() {
+        return Err(CliError::MissingArgument(
+            "store paths to delete (positional, --from-file, or --stdin)".to_string(),
+        ));
+    }
+
+    let question = format!("Delete {} store paths from {cache}?", paths.len());
+    if !force && !confirm(&question, "refusing to delete without confirmation; pass --force")? {
+        return Err(CliError::Cancelled);
+    }
+
+    delete_and_report(&api_client(api_url)?, cache, &paths, output)
+}
+
+/// Delete `paths` from `cache` and print the outcome of each
+fn delete_and_report(
+    client: &CborClient,
+    cache: &str,
+    paths: &[String],
+    output: OutputFormat,
+) -> Result<()> {
+    let report = tokio::runtime::Runtime::new()?
+        .block_on(cache_management::delete_paths(client, cache, paths))?;
+
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for path in &report.deleted {
+            info!("  ✓ {path}");
+        }
+        for failure in &report.failed {
+            eprintln!("  ✗ {}: {}", failure.store_path, failure.reason);
+        }
+        info!("{report}");
+    }
+    report.check()
+}
+
+/// Ask a yes/no question on the terminal; anything but `y` is no
+///
+/// Fails with `refusal` when stdin is not a terminal, so scripts cannot
+/// answer by accident.
+fn confirm(question: &str, refusal: &str) -> Result<bool> {
+    use std::io::{BufRead, IsTerminal};
+
+    if !std::io::stdin().is_terminal() {
+        return Err(CliError::InvalidArgument(refusal.to_string()));
+    }
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    let _ = std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Handle gc command
+fn handle_gc(
+    api_url: &str,
+    cache: &str,
+    status: bool,
+    set_policy: bool,
+    older_than: Option<Duration>,
+    output: OutputFormat,
+) -> Result<()> {
+    let client = api_client(api_url)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    if let (true, Some(older_than)) = (set_policy, older_than) {
+        let policy = GcPolicy::older_than(older_than);
+        runtime.block_on(cache_management::set_gc_policy(&client, cache, &policy))?;
+        info!("✓ GC policy for {cache}: {policy}");
+        return Ok(());
+    }
+    if !status {
+        return Err(CliError::MissingArgument(
+            "one of --status or --set-policy --older-than".to_string(),
+        ));
+    }
+
+    let gc_status = runtime.block_on(cache_management::gc_status(&client, cache))?;
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&gc_status)?);
+    } else {
+        println!("{gc_status}");
+    }
+    Ok(())
+}
+
+/// `gc --keep-latest`: delete all but the newest uploads of each package
+fn handle_gc_keep_latest(
+    api_url: &str,
+    cache: &str,
+    keep: usize,
+    dry_run: bool,
+    force: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    if keep == 0 {
+        return Err(CliError::InvalidArgument(
+            "--keep-latest must be at least 1; use delete to empty a cache".to_string(),
+        ));
+    }
+    let client = api_client(api_url)?;
+    let plan = tokio::runtime::Runtime::new()?
+        .block_on(cache_management::plan_keep_latest(&client, cache, keep))?;
+    let deletions = plan.deletions();
+
+    if dry_run {
+        if output.is_json() {
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        } else {
+            println!("{plan}");
+        }
+        return Ok(());
+    }
+    if deletions.is_empty() {
+        info!("✓ Nothing to delete: no package in {cache} has more than {keep} paths");
+        return Ok(());
+    }
+    if !output.is_json() {
+        info!("{plan}");
+    }
+    let question = format!("Delete {} store paths from {cache}?", deletions.len());
+    if !force && !confirm(&question, "refusing to delete without confirmation; pass --force")? {
+        return Err(CliError::Cancelled);
+    }
+    delete_and_report(&client, cache, &deletions, output)
+}
+
 /// Handle warm command
-fn handle_warm(cache: String, parallelism: Option<usize>, verbose: bool) -> Result<()> {
+fn handle_warm(
+    api_url: &str,
+    cache: String,
+    installables: &[String],
+    from_file: Option<&Path>,
+    system: Option<String>,
+    all_systems: bool,
+    nix_args: &[String],
+    report_cache_hits: bool,
+    options: PushOptions,
+    verbose: bool,
+) -> Result<()> {
+    warm::check_nix_args(nix_args)?;
+    let mut entries: Vec<WarmEntry> = installables.iter().map(|i| WarmEntry::argument(i)).collect();
+    if let Some(file) = from_file {
+        let reader = std::fs::File::open(file).map_err(|e| CliError::FileError {
+            path: file.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        entries.extend(warm::read_warm_file(std::io::BufReader::new(reader))?);
+    }
+
+    let nix = Nix::new();
+    let systems = if all_systems {
+        let installables: Vec<String> = entries.iter().map(|e| e.installable.clone()).collect();
+        warm::flake_systems(&nix, &installables)?
+    } else {
+        vec![warm::target_system(&nix, system)]
+    };
     if verbose {
         println!("Warming cache...");
         println!("Cache: {cache}");
-        if let Some(n) = parallelism {
+        println!("Systems: {}", systems.join(", "));
+        if !nix_args.is_empty() {
+            println!("Nix arguments: {}", nix_args.join(" "));
+        }
+        if let Some(n) = options.parallelism {
             println!("Parallelism: {n}");
         }
     }
 
-    println!("✓ Cache warming complete");
+    let mut paths = Vec::new();
+    let mut failed = 0;
+    let mut activity = report_cache_hits.then(BuildActivity::default);
+    for system in &systems {
+        info!("Building for {system}");
+        for entry in &entries {
+            let installable = std::slice::from_ref(&entry.installable);
+            match warm::build_for_system(&nix, installable, system, nix_args, activity.as_mut()) {
+                Ok(built) => {
+                    if from_file.is_some() {
+                        info!("  ✓ {entry} ({} paths)", built.len());
+                    }
+                    paths.extend(built);
+                }
+                // With --all-systems, one architecture without a builder must
+                // not stop the others from being warmed
+                Err(e) if all_systems => eprintln!("⚠ Skipping {entry} on {system}: {e}"),
+                // A warm file keeps going so every broken line is reported
+                Err(e) if from_file.is_some() => {
+                    eprintln!("  ✗ {entry}: {e}");
+                    failed += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    if let Some(activity) = &activity {
+        println!("Cache hits: {activity}");
+    }
+    if !paths.is_empty() {
+        let paths = ClosureCache::open()?.query(&nix, &paths, ClosureKind::Runtime)?;
+        upload_paths(api_url, &cache, &paths, &options, verbose)?;
+    }
+    if failed > 0 {
+        return Err(CliError::StoreError(format!(
+            "{failed} of {} warm entries failed to build",
+            entries.len() * systems.len()
+        )));
+    }
+
+    info!("✓ Cache warming complete");
     Ok(())
 }
 
 /// Handle stats command
-fn handle_stats(cache: String, verbose: bool) -> Result<()> {
+fn handle_stats(
+    api_url: &str,
+    cache: &str,
+    range: &TimeRange,
+    output: OutputFormat,
+    verbose: bool,
+) -> Result<()> {
     if verbose {
         println!("Fetching cache statistics...");
         println!("Cache: {cache}");
     }
 
-    println!("✓ Cache statistics:");
+    let client = api_client(api_url)?;
+    let stats = tokio::runtime::Runtime::new()?
+        .block_on(cache_management::cache_stats(&client, cache, range))?;
+
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    info!("✓ Cache statistics:");
+    println!("{stats}");
+    Ok(())
+}
+
+/// Handle log command
+fn handle_log(
+    api_url: &str,
+    cache: &str,
+    limit: usize,
+    after: Option<&str>,
+    range: &TimeRange,
+    output: OutputFormat,
+) -> Result<()> {
+    let client = api_client(api_url)?;
+    let log = tokio::runtime::Runtime::new()?
+        .block_on(cache_management::audit_log(&client, cache, limit, after, range))?;
+
+    if output.is_json_lines() {
+        for event in &log.events {
+            println!("{}", serde_json::to_string(event)?);
+        }
+        return Ok(());
+    }
+    if output.is_json() {
+        println!("{}", serde_json::to_string_pretty(&log)?);
+        return Ok(());
+    }
+    if log.events.is_empty() {
+        info!("No changes to {cache} in this window");
+        return Ok(());
+    }
+    info!("✓ Changes to {cache}, newest first:");
+    for event in &log.events {
+        println!("{event}");
+        if event.store_paths.len() > 1 {
+            for path in &event.store_paths {
+                println!("    {path}");
+            }
+        }
+    }
+    if let Some(cursor) = &log.next_cursor {
+        println!("More events: --after {cursor}");
+    }
+    Ok(())
+}
+
+/// Handle key subcommands
+fn handle_key(command: KeyCommand) -> Result<()> {
+    match command {
+        KeyCommand::Generate { name, out } => {
+            let secret = SecretKey::generate(&name)?;
+            let files = key::write_key_files(&secret, &out)?;
+            info!("✓ Wrote secret key to {}", files.secret.display());
+            info!("✓ Wrote public key to {}", files.public.display());
+            info!("Add the public key to trusted-public-keys in nix.conf:");
+            println!("{}", secret.public_key());
+        }
+        KeyCommand::ShowPublic { secret_file } => {
+            println!("{}", SecretKey::from_file(&secret_file)?.public_key());
+        }
+    }
+    Ok(())
+}
+
+/// Handle nar subcommands
+fn handle_nar(command: NarCommand, verbose: bool) -> Result<()> {
+    match command {
+        NarCommand::Extract { file, to } => {
+            let mut archive = DecompressedNar::open(&file)?;
+            if verbose {
+                println!("Compression: {}", archive.compression());
+            }
+            nar::extract(&mut archive, &to)?;
+            archive.finish()?;
+            info!("✓ Extracted {} to {}", file.display(), to.display());
+        }
+        NarCommand::Cat { file, path } => {
+            let mut archive = DecompressedNar::open(&file)?;
+            nar::cat(&mut archive, &path, &mut std::io::stdout().lock())?;
+            archive.finish()?;
+        }
+    }
+    Ok(())
+}
+
+/// Handle download command
+fn handle_download(
+    api_url: &str,
+    cache: &str,
+    target: &DownloadTarget,
+    output_dir: &Path,
+    output: OutputFormat,
+) -> Result<()> {
+    let client = api_client(api_url)?;
+    let pair = tokio::runtime::Runtime::new()?
+        .block_on(download::download_pair(&client, cache, target, output_dir))?;
+
+    if output.is_json() {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "store_path": pair.narinfo.store_path,
+                "narinfo": pair.narinfo_file,
+                "nar": pair.nar_file,
+            }))?
+        );
+        return Ok(());
+    }
+    info!("✓ Downloaded {}", pair.narinfo.store_path);
+    println!("{}", pair.narinfo_file.display());
+    println!("{}", pair.nar_file.display());
+    Ok(())
+}
+
+/// Keys trusted for imports: nix.conf `trusted-public-keys` and the keys
+/// pinned in the config file with `doctor --pin-key`
+fn local_trusted_keys() -> Vec<PublicKey> {
+    let conf = doctor::read_nix_conf();
+    let pinned = Config::load().map(|config| config.trusted_keys).unwrap_or_default();
+    doctor::nix_conf_values(&conf, "trusted-public-keys")
+        .into_iter()
+        .chain(pinned.iter().map(String::as_str))
+        .filter_map(|key| key.parse().ok())
+        .collect()
+}
+
+/// Handle import command
+fn handle_import(
+    dir: &Path,
+    trusted_keys: Vec<PublicKey>,
+    no_check_sigs: bool,
+    verbose: bool,
+) -> Result<()> {
+    let trusted = if no_check_sigs {
+        None
+    } else if trusted_keys.is_empty() {
+        let keys = local_trusted_keys();
+        if keys.is_empty() {
+            return Err(CliError::SignatureError(
+                "no trusted-public-keys in nix.conf or the config file; pass --trusted-key, \
+                 pin one with `doctor --pin-key`, or pass --no-check-sigs"
+                    .to_string(),
+            ));
+        }
+        Some(keys)
+    } else {
+        Some(trusted_keys)
+    };
+    if verbose {
+        match &trusted {
+            Some(keys) => {
+                let names: Vec<&str> = keys.iter().map(PublicKey::name).collect();
+                println!("Trusted keys: {}", names.join(", "));
+            }
+            None => println!("Signature verification: SKIPPED"),
+        }
+    }
+
+    let nix = Nix::new();
+    let pairs = import::find_pairs(dir)?;
+    let paths: Vec<String> = pairs.iter().map(|pair| pair.narinfo.store_path.clone()).collect();
+    let invalid: std::collections::HashSet<String> =
+        nix.query_invalid(&paths)?.into_iter().collect();
+
+    let (mut imported, mut present, mut failed) = (0, 0, 0);
+    for pair in &pairs {
+        let path = &pair.narinfo.store_path;
+        if !invalid.contains(path) {
+            present += 1;
+            if verbose {
+                println!("  - {path} (already present)");
+            }
+            continue;
+        }
+        match import::import_pair(&nix, pair, trusted.as_deref()) {
+            Ok(()) => {
+                imported += 1;
+                info!("  ✓ {path}");
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("  ✗ {path}: {e}");
+            }
+        }
+    }
+
+    info!("Imported {imported} paths, {present} already present");
+    if failed > 0 {
+        return Err(CliError::StoreError(format!(
+            "{failed} of {} paths failed to import",
+            imported + failed
+        )));
+    }
+    Ok(())
+}
+
+/// Handle doctor command
+fn handle_doctor(api_url: &str, offline: bool, output: OutputFormat) -> Result<()> {
+    let report = DoctorReport::collect(api_url, offline);
+    println!("{}", report.render(output)?);
+    report.check()
+}
+
+/// Handle install-hook command
+fn handle_install_hook(
+    cache: &str,
+    path: Option<PathBuf>,
+    write: bool,
+    nix_conf: &Path,
+    verbose: bool,
+) -> Result<()> {
+    hook::validate_cache_name(cache)?;
+
+    let wrapper = match path {
+        Some(path) => path,
+        None => hook::default_wrapper_path()?,
+    };
+    let flakecache_bin = std::env::current_exe()?;
+
+    if verbose {
+        println!("Writing post-build-hook wrapper to {}", wrapper.display());
+    }
+    hook::write_wrapper(&wrapper, &hook::render_wrapper(&flakecache_bin, cache))?;
+    info!("✓ Wrote {}", wrapper.display());
+
+    let line = hook::nix_conf_line(&wrapper);
+    if write {
+        if hook::append_to_nix_conf(nix_conf, &line)? {
+            info!("✓ Added to {}: {line}", nix_conf.display());
+            info!("Restart the Nix daemon for the hook to take effect");
+        } else {
+            info!("✓ {} already contains the hook", nix_conf.display());
+        }
+    } else {
+        println!("Add this line to {}:", nix_conf.display());
+        println!("  {line}");
+    }
+
+    Ok(())
+}
+
+/// Add the cache's substituter to nix.conf for `doctor --fix`
+fn handle_doctor_fix(
+    api_url: &str,
+    cache: &str,
+    priority: Option<SubstituterPriority>,
+    nix_conf: &Path,
+) -> Result<()> {
+    let url = pull::substituter_url(api_url, cache);
+    if doctor::fix_substituter(nix_conf, &url, priority)? {
+        info!(
+            "✓ Added to {}: extra-substituters = {}",
+            nix_conf.display(),
+            doctor::substituter_entry(&url, priority)
+        );
+        info!("Restart the Nix daemon for the substituter to take effect");
+    } else {
+        info!("✓ {} already lists {url}", nix_conf.display());
+    }
+    Ok(())
+}
+
+/// Show the public keys `cache` advertises and, with `pin`, trust the
+/// ones the user confirms by fingerprint
+///
+/// Never trusts a key without an answer on the terminal: the server
+/// decides what it advertises.
+fn handle_doctor_key(api_url: &str, cache: &str, pin: bool) -> Result<()> {
+    let client = api_client(api_url)?;
+    let keys = tokio::runtime::Runtime::new()?
+        .block_on(transfer::fetch_cache_public_keys(&client, cache))?;
+    if keys.is_empty() {
+        return Err(CliError::CacheError(format!(
+            "{cache} does not advertise a public key in its nix-cache-info"
+        )));
+    }
+    for key in &keys {
+        println!("{key}");
+        println!("  Fingerprint: {}", key.fingerprint());
+    }
+    if !pin {
+        return Ok(());
+    }
+
+    eprintln!("⚠ These keys were sent by the server; anyone controlling it or the connection can substitute their own.");
+    eprintln!("⚠ Only trust a key whose fingerprint matches one the cache owner gave you another way.");
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(CliError::NoConfig) => Config::default(),
+        Err(e) => return Err(e),
+    };
+    let mut pinned = false;
+    for key in keys {
+        let entry = key.to_string();
+        if config.trusted_keys.contains(&entry) {
+            info!("✓ {} is already trusted", key.name());
+            continue;
+        }
+        let question = format!("Trust {} with fingerprint {}?", key.name(), key.fingerprint());
+        if !confirm(&question, "refusing to trust a key without confirmation on a terminal")? {
+            info!("Not trusting {}", key.name());
+            continue;
+        }
+        config.trusted_keys.push(entry);
+        pinned = true;
+        info!("✓ Trusting {} for import", key.name());
+    }
+    if pinned {
+        config.save()?;
+    }
     Ok(())
 }
 
 /// Handle version command
-fn handle_version() -> Result<()> {
-    println!("FlakeCache CLI v{}", env!("CARGO_PKG_VERSION"));
+fn handle_version(output: OutputFormat) -> Result<()> {
+    println!("{}", BuildInfo::current().render(output)?);
     Ok(())
 }
@@ -0,0 +1,207 @@
+//! What a Nix build substituted and what it built
+//!
+//! With `--log-format internal-json`, Nix reports every build and
+//! substitution as a structured `@nix {...}` activity on stderr, so the
+//! counts do not depend on the wording of a Nix version or on the locale.
+//! A log without such lines (an old Nix, or a wrapper that rewrites the
+//! format) falls back to matching Nix's English progress messages.
+
+use serde::Deserialize;
+use std::fmt;
+
+/// Arguments that make Nix log structured activities
+pub const LOG_FORMAT_ARGS: [&str; 2] = ["--log-format", "internal-json"];
+
+/// Prefix of each structured log line
+const JSON_PREFIX: &str = "@nix ";
+
+/// Activity type of a local build (`actBuild`)
+const ACT_BUILD: u64 = 105;
+
+/// Activity type of a substitution from a binary cache (`actSubstitute`)
+const ACT_SUBSTITUTE: u64 = 108;
+
+/// One structured log line
+#[derive(Debug, Deserialize)]
+struct LogEvent {
+    action: String,
+    #[serde(default, rename = "type")]
+    kind: Option<u64>,
+    #[serde(default)]
+    fields: Vec<serde_json::Value>,
+    #[serde(default)]
+    msg: Option<String>,
+}
+
+impl LogEvent {
+    /// Parse a `@nix {...}` line
+    fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str(line.strip_prefix(JSON_PREFIX)?).ok()
+    }
+}
+
+/// Store paths a build substituted and derivations it built locally
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildActivity {
+    /// Derivations built locally (cache misses)
+    pub built: Vec<String>,
+    /// Paths substituted from a binary cache (cache hits)
+    pub substituted: Vec<String>,
+    /// Whether the log had structured activities; `false` means the
+    /// counts come from the progress messages
+    pub structured: bool,
+}
+
+impl BuildActivity {
+    /// Read the activities from a build's stderr
+    #[must_use]
+    pub fn parse(stderr: &str) -> Self {
+        let events: Vec<LogEvent> = stderr.lines().filter_map(LogEvent::parse).collect();
+        if events.is_empty() {
+            return Self::parse_messages(stderr);
+        }
+        let mut activity = Self {
+            structured: true,
+            ..Self::default()
+        };
+        for event in events.iter().filter(|event| event.action == "start") {
+            let Some(path) = event.fields.first().and_then(serde_json::Value::as_str) else {
+                continue;
+            };
+            match event.kind {
+                Some(ACT_BUILD) => activity.built.push(path.to_string()),
+                Some(ACT_SUBSTITUTE) => activity.substituted.push(path.to_string()),
+                _ => {}
+            }
+        }
+        activity
+    }
+
+    /// Fallback for plain logs: `building '<drv>'...` and `copying path
+    /// '<path>' from '<substituter>'...`
+    fn parse_messages(stderr: &str) -> Self {
+        let quoted = |rest: &str| rest.split_once('\'').map(|(path, _)| path.to_string());
+        let mut activity = Self::default();
+        for line in stderr.lines().map(str::trim) {
+            if let Some(drv) = line.strip_prefix("building '").and_then(quoted) {
+                activity.built.push(drv);
+            } else if let Some(rest) = line.strip_prefix("copying path '") {
+                if rest.contains("' from '") {
+                    activity.substituted.extend(quoted(rest));
+                }
+            }
+        }
+        activity
+    }
+
+    /// Add the activities of another build
+    pub fn merge(&mut self, other: Self) {
+        self.built.extend(other.built);
+        self.substituted.extend(other.substituted);
+        self.structured |= other.structured;
+    }
+
+    /// Share of realised paths that came from a cache (0.0 when nothing
+    /// was realised)
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.built.len() + self.substituted.len();
+        if total == 0 {
+            0.0
+        } else {
+            self.substituted.len() as f64 / total as f64
+        }
+    }
+}
+
+impl fmt::Display for BuildActivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} substituted, {} built locally ({:.0}% cache hits)",
+            self.substituted.len(),
+            self.built.len(),
+            self.hit_ratio() * 100.0
+        )?;
+        let realised = !self.built.is_empty() || !self.substituted.is_empty();
+        if realised && !self.structured {
+            write!(f, " [from progress messages]")?;
+        }
+        Ok(())
+    }
+}
+
+/// A structured log as plain text: the message of each `msg` event, other
+/// lines as they are, and no activity events
+///
+/// Keeps error reports of a build run with [`LOG_FORMAT_ARGS`] readable.
+/// Text before a structured event on the same line (such as the prefix
+/// of a [`CliError::StoreError`](crate::CliError::StoreError)) is kept.
+#[must_use]
+pub fn plain_log(log: &str) -> String {
+    log.lines()
+        .filter_map(|line| {
+            let Some(at) = line.find(JSON_PREFIX) else {
+                return Some(line.to_string());
+            };
+            let (before, json) = line.split_at(at);
+            let Some(event) = LogEvent::parse(json) else {
+                return Some(line.to_string());
+            };
+            let before = before.trim_end();
+            match event.msg {
+                Some(msg) if before.is_empty() => Some(msg),
+                Some(msg) => Some(format!("{before} {msg}")),
+                None => (!before.is_empty()).then(|| before.to_string()),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"@nix {"action":"start","id":1,"level":4,"parent":0,"text":"copying path '/nix/store/aaa-glibc' from 'https://cache.nixos.org'","type":108,"fields":["/nix/store/aaa-glibc","https://cache.nixos.org"]}
+@nix {"action":"start","id":2,"level":3,"parent":0,"text":"building '/nix/store/bbb-hello.drv'","type":105,"fields":["/nix/store/bbb-hello.drv","",1,1]}
+@nix {"action":"result","id":2,"type":101,"fields":["hello"]}
+@nix {"action":"msg","level":0,"msg":"error: builder for '/nix/store/bbb-hello.drv' failed"}
+"#;
+
+    #[test]
+    fn test_structured_activities() {
+        let activity = BuildActivity::parse(LOG);
+        assert!(activity.structured);
+        assert_eq!(activity.substituted, ["/nix/store/aaa-glibc"]);
+        assert_eq!(activity.built, ["/nix/store/bbb-hello.drv"]);
+        assert_eq!(
+            activity.to_string(),
+            "1 substituted, 1 built locally (50% cache hits)"
+        );
+        assert_eq!(
+            plain_log(&format!("nix build failed: {LOG}")),
+            "nix build failed:\nerror: builder for '/nix/store/bbb-hello.drv' failed"
+        );
+    }
+
+    #[test]
+    fn test_falls_back_to_progress_messages() {
+        let log = "these 2 paths will be fetched (1.2 MiB download):\n\
+                   copying path '/nix/store/aaa-glibc' from 'https://cache.nixos.org'...\n\
+                   building '/nix/store/bbb-hello.drv'...\n\
+                   copying path '/nix/store/ccc-out' to 'ssh://builder'...\n";
+        let mut activity = BuildActivity::parse(log);
+        assert!(!activity.structured);
+        assert_eq!(activity.substituted, ["/nix/store/aaa-glibc"]);
+        assert_eq!(activity.built, ["/nix/store/bbb-hello.drv"]);
+
+        activity.merge(BuildActivity {
+            substituted: vec!["/nix/store/ddd-zlib".to_string()],
+            ..BuildActivity::default()
+        });
+        assert!((activity.hit_ratio() - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert!(BuildActivity::default().hit_ratio().abs() < f64::EPSILON);
+    }
+}
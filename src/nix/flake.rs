@@ -1,3 +1,380 @@
 //! Flake utilities and helpers
 //!
 //! Utilities for working with Nix flakes and their outputs.
+
+use crate::error::{CliError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Placeholder replaced by the target system in flake expressions
+/// (`.#packages.{system}.default`)
+pub const SYSTEM_PLACEHOLDER: &str = "{system}";
+
+/// Nix system double (`x86_64-linux`, `aarch64-darwin`) for a Rust
+/// architecture and OS as reported by [`std::env::consts`]
+#[must_use]
+pub fn nix_system(arch: &str, os: &str) -> String {
+    let os = match os {
+        "macos" => "darwin",
+        other => other,
+    };
+    format!("{arch}-{os}")
+}
+
+/// Nix system this binary was built for
+///
+/// Used when `nix config show system` is unavailable.
+#[must_use]
+pub fn host_system() -> String {
+    nix_system(std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// Flake outputs keyed by system (`packages.<system>.<name>`)
+pub const PER_SYSTEM_OUTPUTS: [&str; 6] = [
+    "packages",
+    "devShells",
+    "checks",
+    "apps",
+    "legacyPackages",
+    "formatter",
+];
+
+/// Flake reference part of an installable (`.#hello` -> `.`)
+#[must_use]
+pub fn flake_ref(installable: &str) -> &str {
+    match installable.split_once('#') {
+        Some(("", _)) => ".",
+        Some((flake, _)) => flake,
+        None => installable,
+    }
+}
+
+/// Where a flake's source came from, for push provenance
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlakeSource {
+    /// Locked flake reference (`nix flake metadata` `url`)
+    pub url: Option<String>,
+    /// Commit of a clean git tree, or the commit a dirty tree is based on
+    /// with `-dirty` appended when Nix reports it
+    pub revision: Option<String>,
+    /// Whether a git flake had uncommitted changes
+    pub dirty: bool,
+}
+
+impl FlakeSource {
+    /// Read the source from `nix flake metadata --json`
+    ///
+    /// A git tree with uncommitted changes has no `revision`; newer Nix
+    /// versions report a `dirtyRevision` instead. Flakes that are not git
+    /// trees (`path:`, tarballs) are never dirty.
+    #[must_use]
+    pub fn from_metadata(metadata: &serde_json::Value) -> Self {
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(serde_json::Value::as_str)
+                .map(ToString::to_string)
+        };
+        let is_git = metadata
+            .get("locked")
+            .and_then(|locked| locked.get("type"))
+            .and_then(serde_json::Value::as_str)
+            == Some("git");
+        let revision = field("revision");
+        let dirty_revision = field("dirtyRevision");
+        Self {
+            url: field("url"),
+            dirty: revision.is_none() && (dirty_revision.is_some() || is_git),
+            revision: revision.or(dirty_revision),
+        }
+    }
+}
+
+impl fmt::Display for FlakeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.url.as_deref().unwrap_or("flake"))?;
+        if let Some(revision) = &self.revision {
+            write!(f, " at {revision}")?;
+        }
+        if self.dirty {
+            write!(f, " (uncommitted changes)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Systems a flake provides outputs for, from `nix flake show --json`
+///
+/// Returns the sorted union of the system keys under each of
+/// [`PER_SYSTEM_OUTPUTS`].
+#[must_use]
+pub fn systems_in(show: &serde_json::Value) -> Vec<String> {
+    let mut systems: Vec<String> = PER_SYSTEM_OUTPUTS
+        .iter()
+        .filter_map(|output| show.get(output)?.as_object())
+        .flat_map(serde_json::Map::keys)
+        .cloned()
+        .collect();
+    systems.sort();
+    systems.dedup();
+    systems
+}
+
+/// Substitute `system` for every [`SYSTEM_PLACEHOLDER`] in `installable`
+#[must_use]
+pub fn with_system(installable: &str, system: &str) -> String {
+    installable.replace(SYSTEM_PLACEHOLDER, system)
+}
+
+/// Output store paths from `nix build --json` output
+///
+/// Nix prints one object per installable, each with an `outputs` map from
+/// output name to store path; newer Nix versions add `startTime` and
+/// `stopTime`. An opaque store path installable prints `{"path": ...}`
+/// instead. Also accepted are a single object rather than a list, plain
+/// path strings, and outputs given as `{"path": ...}` objects. Outputs
+/// without a known path (`null`, as for unbuilt content-addressed
+/// derivations) are skipped. Paths are returned in order, without
+/// duplicates.
+///
+/// # Errors
+///
+/// Returns [`CliError::FlakeResolutionError`] naming the JSON shape if no
+/// output paths are found.
+pub fn extract_store_paths(installable: &str, json: &serde_json::Value) -> Result<Vec<String>> {
+    let builds = match json {
+        serde_json::Value::Array(builds) => builds.iter().collect(),
+        other => vec![other],
+    };
+    let mut paths: Vec<String> = Vec::new();
+    for path in builds.into_iter().flat_map(build_paths) {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(CliError::FlakeResolutionError {
+            flake: installable.to_string(),
+            reason: format!(
+                "nix build reported no output paths (got {})",
+                describe_shape(json)
+            ),
+        });
+    }
+    Ok(paths)
+}
+
+/// Output paths of one `nix build --json` entry
+fn build_paths(build: &serde_json::Value) -> Vec<String> {
+    use serde_json::Value;
+    match build {
+        Value::String(path) => vec![path.clone()],
+        Value::Object(entry) => match entry.get("outputs") {
+            Some(Value::Object(outputs)) => outputs.values().filter_map(output_path).collect(),
+            Some(Value::Array(outputs)) => outputs.iter().filter_map(output_path).collect(),
+            _ => entry
+                .get("path")
+                .and_then(Value::as_str)
+                .map(ToString::to_string)
+                .into_iter()
+                .collect(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Store path of one output: a path string or a `{"path": ...}` object
+fn output_path(output: &serde_json::Value) -> Option<String> {
+    let path = match output {
+        serde_json::Value::Object(output) => output.get("path")?,
+        other => other,
+    };
+    path.as_str().map(ToString::to_string)
+}
+
+/// Short description of unexpected `nix build --json` output for errors
+fn describe_shape(json: &serde_json::Value) -> String {
+    match json {
+        serde_json::Value::Array(builds) if builds.is_empty() => "an empty list".to_string(),
+        serde_json::Value::Array(builds) => {
+            let mut entries: Vec<String> = builds.iter().map(describe_entry).collect();
+            entries.dedup();
+            format!("a list of {}", entries.join("; "))
+        }
+        other => describe_entry(other),
+    }
+}
+
+fn describe_entry(entry: &serde_json::Value) -> String {
+    use serde_json::Value;
+    match entry {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "a boolean".to_string(),
+        Value::Number(_) => "a number".to_string(),
+        Value::String(_) => "a string".to_string(),
+        Value::Array(_) => "a nested list".to_string(),
+        Value::Object(entry) if entry.contains_key("drvPath") => {
+            "derivations without output paths (drvPath only)".to_string()
+        }
+        Value::Object(entry) => {
+            let keys: Vec<&str> = entry.keys().map(String::as_str).collect();
+            if keys.is_empty() {
+                "an empty object".to_string()
+            } else {
+                format!("objects with keys {}", keys.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nix_system_mapping() {
+        assert_eq!(nix_system("x86_64", "linux"), "x86_64-linux");
+        assert_eq!(nix_system("aarch64", "macos"), "aarch64-darwin");
+    }
+
+    #[test]
+    fn test_with_system() {
+        assert_eq!(
+            with_system(".#packages.{system}.default", "aarch64-darwin"),
+            ".#packages.aarch64-darwin.default"
+        );
+        assert_eq!(
+            with_system("nixpkgs#hello", "x86_64-linux"),
+            "nixpkgs#hello"
+        );
+    }
+
+    #[test]
+    fn test_flake_ref() {
+        assert_eq!(flake_ref(".#hello"), ".");
+        assert_eq!(flake_ref("#hello"), ".");
+        assert_eq!(flake_ref("github:owner/repo#app"), "github:owner/repo");
+        assert_eq!(flake_ref("nixpkgs"), "nixpkgs");
+    }
+
+    #[test]
+    fn test_flake_source_from_metadata() {
+        let clean = FlakeSource::from_metadata(&serde_json::json!({
+            "url": "git+file:///src/app?rev=abc123",
+            "revision": "abc123",
+            "locked": { "type": "git" }
+        }));
+        assert!(!clean.dirty);
+        assert_eq!(
+            clean.to_string(),
+            "git+file:///src/app?rev=abc123 at abc123"
+        );
+
+        let dirty = FlakeSource::from_metadata(&serde_json::json!({
+            "url": "git+file:///src/app",
+            "dirtyRevision": "abc123-dirty",
+            "locked": { "type": "git" }
+        }));
+        assert!(dirty.dirty);
+        assert_eq!(dirty.revision.as_deref(), Some("abc123-dirty"));
+
+        // Older Nix: a dirty git tree just has no revision
+        let old = FlakeSource::from_metadata(&serde_json::json!({ "locked": { "type": "git" } }));
+        assert!(old.dirty);
+        let path = FlakeSource::from_metadata(&serde_json::json!({ "locked": { "type": "path" } }));
+        assert!(!path.dirty);
+    }
+
+    #[test]
+    fn test_systems_in_flake_show() {
+        let show = serde_json::json!({
+            "packages": {
+                "x86_64-linux": { "default": { "type": "derivation" } },
+                "aarch64-darwin": { "default": { "type": "derivation" } }
+            },
+            "devShells": { "x86_64-linux": {} },
+            "nixosConfigurations": { "host": {} }
+        });
+        assert_eq!(systems_in(&show), ["aarch64-darwin", "x86_64-linux"]);
+    }
+
+    /// `nix build --json` from older Nix versions
+    const BUILD_JSON: &str = r#"[{
+        "drvPath": "/nix/store/aaa-hello.drv",
+        "outputs": {
+            "man": "/nix/store/ccc-hello-man",
+            "out": "/nix/store/bbb-hello"
+        }
+    }]"#;
+
+    /// `nix build --json` from newer Nix versions, with build times
+    const BUILD_JSON_TIMED: &str = r#"[{
+        "drvPath": "/nix/store/aaa-hello.drv",
+        "outputs": { "out": "/nix/store/bbb-hello" },
+        "startTime": 1700000000,
+        "stopTime": 1700000042
+    }]"#;
+
+    /// `nix build --json /nix/store/...` (an opaque store path)
+    const BUILD_JSON_OPAQUE: &str = r#"[{ "path": "/nix/store/ddd-source" }]"#;
+
+    fn parse(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_extract_store_paths() -> Result<()> {
+        assert_eq!(
+            extract_store_paths(".#hello", &parse(BUILD_JSON))?,
+            ["/nix/store/ccc-hello-man", "/nix/store/bbb-hello"]
+        );
+        assert_eq!(
+            extract_store_paths(".#hello", &parse(BUILD_JSON_TIMED))?,
+            ["/nix/store/bbb-hello"]
+        );
+        assert_eq!(
+            extract_store_paths("/nix/store/ddd-source", &parse(BUILD_JSON_OPAQUE))?,
+            ["/nix/store/ddd-source"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_store_paths_variants() -> Result<()> {
+        // A single object, outputs as objects, unknown outputs, and the same
+        // installable built twice
+        let json = parse(
+            r#"{ "drvPath": "/nix/store/aaa-x.drv",
+                 "outputs": { "out": { "path": "/nix/store/bbb-x" }, "dev": null } }"#,
+        );
+        assert_eq!(extract_store_paths(".#x", &json)?, ["/nix/store/bbb-x"]);
+
+        let twice = format!(
+            "[{0}, {0}]",
+            &BUILD_JSON_TIMED[1..BUILD_JSON_TIMED.len() - 1]
+        );
+        assert_eq!(
+            extract_store_paths(".#hello", &parse(&twice))?,
+            ["/nix/store/bbb-hello"]
+        );
+        assert_eq!(
+            extract_store_paths(".#x", &parse(r#"["/nix/store/bbb-x"]"#))?,
+            ["/nix/store/bbb-x"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_store_paths_names_the_shape() {
+        let reason = |json: &str| match extract_store_paths(".#x", &parse(json)) {
+            Err(CliError::FlakeResolutionError { reason, .. }) => reason,
+            other => format!("unexpected {other:?}"),
+        };
+        assert!(reason("[]").contains("an empty list"));
+        assert!(reason(r#"[{ "drvPath": "/nix/store/aaa-x.drv" }]"#)
+            .contains("derivations without output paths (drvPath only)"));
+        assert!(reason(r#"{ "result": 1 }"#).contains("objects with keys result"));
+        assert!(reason("42").contains("a number"));
+    }
+}
@@ -1,5 +1,877 @@
 //! Nix integration (store operations, flake resolution)
+//!
+//! All subprocess calls to `nix` and `nix-store` go through [`Nix`], which
+//! threads the target store, captures stderr, and maps failures to
+//! [`CliError::StoreError`].
 
+pub mod activity;
+pub mod nar;
+pub mod narinfo;
 pub mod resolve;
 pub mod store;
 pub mod flake;
+
+pub use activity::BuildActivity;
+pub use narinfo::NarInfo;
+
+use crate::error::{CliError, Result};
+use crate::utils::{output, trace};
+use std::io::{Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
+
+/// Store paths per `nix-store --check-validity` call, well under `ARG_MAX`
+const VALIDITY_BATCH_SIZE: usize = 500;
+
+/// Attempts for a Nix command that fails because the daemon is busy
+pub const DAEMON_BUSY_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry of a busy-daemon failure; doubles each time
+const DAEMON_BUSY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Messages Nix prints when it failed only because of contention, matched
+/// on its own `error:` lines only
+const DAEMON_BUSY_MARKERS: [&str; 3] = [
+    "database is locked",
+    "' is busy",
+    "cannot connect to socket at '/nix/var/nix/daemon-socket/",
+];
+
+/// Handle for running Nix commands against a (possibly non-default) store
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nix {
+    /// Value passed as `--store`, if not the default local store
+    store: Option<String>,
+}
+
+impl Nix {
+    /// Use the default store (`/nix/store` or the daemon)
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { store: None }
+    }
+
+    /// Use a specific store URI (e.g. `ssh-ng://builder`, `/mnt/nix`)
+    #[must_use]
+    pub fn with_store(store: impl Into<String>) -> Self {
+        Self {
+            store: Some(store.into()),
+        }
+    }
+
+    /// The configured store URI, if any
+    #[must_use]
+    pub fn store(&self) -> Option<&str> {
+        self.store.as_deref()
+    }
+
+    /// Build a `nix-store` command with the store flag applied
+    fn nix_store(&self) -> Command {
+        let mut cmd = Command::new("nix-store");
+        if let Some(store) = &self.store {
+            let _ = cmd.args(["--store", store]);
+        }
+        cmd
+    }
+
+    /// Build a `nix` command with flakes enabled and the store flag applied
+    fn nix(&self) -> Command {
+        let mut cmd = Command::new("nix");
+        let _ = cmd.args(["--extra-experimental-features", "nix-command flakes"]);
+        if let Some(store) = &self.store {
+            let _ = cmd.args(["--store", store]);
+        }
+        cmd
+    }
+
+    /// Run a command to completion, failing on a non-zero exit status
+    fn run(cmd: Command, what: &str) -> Result<Output> {
+        Self::run_attempts(cmd, what, 1)
+    }
+
+    /// [`Self::run`] for a store query or realisation, retrying failures
+    /// caused by a busy daemon or a locked store database (see
+    /// [`is_daemon_busy`]) up to [`DAEMON_BUSY_ATTEMPTS`] times with
+    /// exponential backoff
+    ///
+    /// Not for `nix build` or evaluation: their output carries build logs,
+    /// and rerunning a failed build costs far more than it could save.
+    fn run_store(cmd: Command, what: &str) -> Result<Output> {
+        Self::run_attempts(cmd, what, DAEMON_BUSY_ATTEMPTS)
+    }
+
+    fn run_attempts(mut cmd: Command, what: &str, attempts: u32) -> Result<Output> {
+        let _span = trace::span("nix", what, "");
+        let mut attempt = 1;
+        loop {
+            let output = cmd
+                .stdin(Stdio::null())
+                .output()
+                .map_err(|e| CliError::StoreError(format!("{what}: failed to spawn: {e}")))?;
+            if output.status.success() {
+                return Ok(output);
+            }
+
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let busy = attempts > 1 && is_daemon_busy(&stderr);
+            if busy && attempt < attempts {
+                let delay = DAEMON_BUSY_BACKOFF * 2_u32.pow(attempt - 1);
+                output::debug(format_args!(
+                    "{what}: Nix daemon busy, retrying in {}ms",
+                    delay.as_millis()
+                ));
+                std::thread::sleep(delay);
+                attempt += 1;
+                continue;
+            }
+
+            let note = if busy {
+                format!(", Nix daemon still busy after {attempt} attempts")
+            } else {
+                String::new()
+            };
+            return Err(CliError::StoreError(format!(
+                "{what} failed ({}{note}): {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+    }
+
+    /// Serialise a store path as a NAR (`nix-store --dump`) into `out`
+    ///
+    /// # Returns
+    ///
+    /// The number of NAR bytes written
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if `nix-store` fails or the output
+    /// cannot be written.
+    pub fn dump_to<W: Write>(&self, path: &str, out: &mut W) -> Result<u64> {
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--dump", path]);
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| CliError::StoreError(format!("nix-store --dump: failed to spawn: {e}")))?;
+
+        let copied = child
+            .stdout
+            .take()
+            .map_or(Ok(0), |mut stdout| std::io::copy(&mut stdout, out));
+        let output = child
+            .wait_with_output()
+            .map_err(|e| CliError::StoreError(format!("nix-store --dump {path}: {e}")))?;
+
+        if !output.status.success() {
+            return Err(CliError::StoreError(format!(
+                "nix-store --dump {path} failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        copied.map_err(|e| CliError::StoreError(format!("nix-store --dump {path}: {e}")))
+    }
+
+    /// Direct references of a store path
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails.
+    pub fn query_references(&self, path: &str) -> Result<Vec<String>> {
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--query", "--references", path]);
+        Self::run_store(cmd, "nix-store --query --references").map(|o| output_lines(&o.stdout))
+    }
+
+    /// NAR hash Nix registered for a store path (`sha256:<base32>`)
+    ///
+    /// Read from the Nix database, so it is much cheaper than dumping the
+    /// path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails or prints no hash.
+    pub fn query_hash(&self, path: &str) -> Result<String> {
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--query", "--hash", path]);
+        let output = Self::run_store(cmd, "nix-store --query --hash")?;
+        output_lines(&output.stdout)
+            .into_iter()
+            .next()
+            .ok_or_else(|| CliError::StoreError(format!("nix-store --query --hash {path}: no hash")))
+    }
+
+    /// Derivation that produced a store path, if Nix knows it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails.
+    pub fn query_deriver(&self, path: &str) -> Result<Option<String>> {
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--query", "--deriver", path]);
+        let output = Self::run_store(cmd, "nix-store --query --deriver")?;
+        Ok(output_lines(&output.stdout)
+            .into_iter()
+            .next()
+            .filter(|deriver| deriver != "unknown-deriver"))
+    }
+
+    /// Runtime closure of the given store paths
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails.
+    pub fn query_requisites(&self, paths: &[String]) -> Result<Vec<String>> {
+        self.query_closure(paths, ClosureKind::Runtime)
+    }
+
+    /// Closure of the given store paths of the requested kind
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails.
+    pub fn query_closure(&self, paths: &[String], kind: ClosureKind) -> Result<Vec<String>> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let roots = if kind == ClosureKind::Build {
+            self.derivations_of(paths)?
+        } else {
+            paths.to_vec()
+        };
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(kind.query_args()).args(&roots);
+        Self::run_store(cmd, &format!("nix-store {}", kind.query_args().join(" ")))
+            .map(|o| output_lines(&o.stdout))
+    }
+
+    /// The `.drv` files that produced `paths`, for [`ClosureKind::Build`]
+    ///
+    /// `.drv` paths are kept as they are. A path whose deriver is unknown
+    /// or no longer in the store stands for itself, so at least its runtime
+    /// closure is included.
+    fn derivations_of(&self, paths: &[String]) -> Result<Vec<String>> {
+        let mut roots: Vec<String> = Vec::with_capacity(paths.len());
+        for path in paths {
+            let root = if store::is_derivation(path) {
+                path.clone()
+            } else {
+                match self.query_deriver(path)? {
+                    Some(drv) if self.path_valid(&drv)? => drv,
+                    _ => {
+                        output::debug(format_args!(
+                            "{path} has no derivation in the store; using its runtime closure"
+                        ));
+                        path.clone()
+                    }
+                }
+            };
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Derivations of an installable (`nix path-info --derivation`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if evaluation fails.
+    pub fn derivation_paths(&self, installable: &str) -> Result<Vec<String>> {
+        let mut cmd = self.nix();
+        let _ = cmd.args(["path-info", "--derivation", installable]);
+        Self::run(cmd, &format!("nix path-info --derivation {installable}"))
+            .map(|o| output_lines(&o.stdout))
+    }
+
+    /// What realising store paths or derivations would do, without doing
+    /// it (`nix-store --realise --dry-run`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails.
+    pub fn dry_run_paths(&self, paths: &[String]) -> Result<RealisePlan> {
+        if paths.is_empty() {
+            return Ok(RealisePlan::default());
+        }
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--realise", "--dry-run"]).args(paths);
+        let output = Self::run_store(cmd, "nix-store --realise --dry-run")?;
+        Ok(RealisePlan::parse(&String::from_utf8_lossy(&output.stderr)))
+    }
+
+    /// Realise (substitute or build) store paths
+    ///
+    /// # Returns
+    ///
+    /// The realised output paths
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if realisation fails.
+    pub fn realise(&self, paths: &[String]) -> Result<Vec<String>> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut cmd = self.nix_store();
+        let _ = cmd.arg("--realise").args(paths);
+        Self::run_store(cmd, "nix-store --realise").map(|o| output_lines(&o.stdout))
+    }
+
+    /// Build an installable with `nix build --json --no-link`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the build fails, or a
+    /// deserialization error if Nix prints invalid JSON.
+    pub fn build_json(
+        &self,
+        installable: &str,
+        extra_args: &[String],
+    ) -> Result<serde_json::Value> {
+        let mut cmd = self.nix();
+        let _ = cmd
+            .args(["build", "--json", "--no-link", installable])
+            .args(extra_args);
+        let output = Self::run(cmd, &format!("nix build {installable}"))?;
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// [`Self::build_json`], also reporting what the build substituted and
+    /// what it built locally, from Nix's structured log
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::build_json`]; the log in a [`CliError::StoreError`]
+    /// is turned back into plain text.
+    pub fn build_json_with_activity(
+        &self,
+        installable: &str,
+        extra_args: &[String],
+    ) -> Result<(serde_json::Value, BuildActivity)> {
+        let mut cmd = self.nix();
+        let _ = cmd
+            .args(["build", "--json", "--no-link", installable])
+            .args(activity::LOG_FORMAT_ARGS)
+            .args(extra_args);
+        let output = Self::run(cmd, &format!("nix build {installable}")).map_err(|e| match e {
+            CliError::StoreError(message) => CliError::StoreError(activity::plain_log(&message)),
+            e => e,
+        })?;
+        let activity = BuildActivity::parse(&String::from_utf8_lossy(&output.stderr));
+        Ok((serde_json::from_slice(&output.stdout)?, activity))
+    }
+
+    /// Output tree of a flake (`nix flake show --json`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if evaluation fails, or a
+    /// deserialization error if Nix prints invalid JSON.
+    pub fn flake_show(&self, flake_ref: &str) -> Result<serde_json::Value> {
+        let mut cmd = self.nix();
+        let _ = cmd.args(["flake", "show", "--json", flake_ref]);
+        let output = Self::run(cmd, &format!("nix flake show {flake_ref}"))?;
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// Source and lock of a flake (`nix flake metadata --json`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the flake cannot be fetched, or
+    /// a deserialization error if Nix prints invalid JSON.
+    pub fn flake_metadata(&self, flake_ref: &str) -> Result<serde_json::Value> {
+        let mut cmd = self.nix();
+        let _ = cmd.args(["flake", "metadata", "--json", flake_ref]);
+        let output = Self::run(cmd, &format!("nix flake metadata {flake_ref}"))?;
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// What realising an installable would do, without doing it
+    /// (`nix build --dry-run`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if evaluation fails.
+    pub fn dry_run(&self, installable: &str) -> Result<RealisePlan> {
+        let mut cmd = self.nix();
+        let _ = cmd.args(["build", "--dry-run", "--no-link", installable]);
+        let output = Self::run(cmd, &format!("nix build --dry-run {installable}"))?;
+        Ok(RealisePlan::parse(&String::from_utf8_lossy(&output.stderr)))
+    }
+
+    /// Substitute store paths from the configured substituters, never
+    /// building locally (`--max-jobs 0`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if a path cannot be substituted.
+    pub fn substitute(&self, paths: &[String]) -> Result<Vec<String>> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--realise", "--max-jobs", "0"]).args(paths);
+        Self::run_store(cmd, "nix-store --realise --max-jobs 0").map(|o| output_lines(&o.stdout))
+    }
+
+    /// Substitute store paths from `substituter` alone, never building
+    /// locally (`--option substituters <url> --max-jobs 0`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if a path cannot be substituted.
+    pub fn substitute_from(&self, paths: &[String], substituter: &str) -> Result<Vec<String>> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut cmd = self.nix_store();
+        let _ = cmd
+            .args(["--realise", "--max-jobs", "0", "--option", "substituters", substituter])
+            .args(paths);
+        Self::run_store(cmd, &format!("nix-store --realise from {substituter}"))
+            .map(|o| output_lines(&o.stdout))
+    }
+
+    /// Copy store paths and their closures from this store to `dest`
+    /// (`nix copy --to <dest>`, e.g. `ssh-ng://builder`)
+    ///
+    /// Paths already valid in `dest` are skipped by Nix.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the copy fails, for example
+    /// because `dest` is unreachable or rejects unsigned paths.
+    pub fn copy_to(&self, paths: &[String], dest: &str) -> Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = self.copy_cmd(dest);
+        let _ = cmd.args(paths);
+        Self::run(cmd, &format!("nix copy --to {dest}")).map(|_| ())
+    }
+
+    /// `nix copy --to <dest>`, reading from this store
+    fn copy_cmd(&self, dest: &str) -> Command {
+        let mut cmd = Command::new("nix");
+        let _ = cmd.args(["--extra-experimental-features", "nix-command flakes"]);
+        let _ = cmd.args(["copy", "--to", dest]);
+        if let Some(store) = &self.store {
+            let _ = cmd.args(["--from", store]);
+        }
+        cmd
+    }
+
+    /// The `system` setting of the local Nix (e.g. `x86_64-linux`)
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if Nix is not installed or prints
+    /// nothing.
+    pub fn current_system(&self) -> Result<String> {
+        let mut cmd = self.nix();
+        let _ = cmd.args(["show-config", "system"]);
+        let output = Self::run(cmd, "nix show-config system")?;
+        output_lines(&output.stdout)
+            .into_iter()
+            .next()
+            .ok_or_else(|| CliError::StoreError("nix show-config printed no system".to_string()))
+    }
+
+    /// Whether a flake output exists, without building it
+    ///
+    /// `extra_args` are passed through to `nix eval` (e.g. `--system`).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if evaluation fails for any reason
+    /// other than the attribute being absent.
+    pub fn has_output(&self, installable: &str, extra_args: &[String]) -> Result<bool> {
+        let mut cmd = self.nix();
+        let _ = cmd
+            .args(["eval", "--json", installable, "--apply", "_: true"])
+            .args(extra_args);
+        match Self::run(cmd, &format!("nix eval {installable}")) {
+            Ok(_) => Ok(true),
+            Err(CliError::StoreError(message))
+                if message.contains("does not provide attribute") =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Version string reported by `nix-store --version`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if Nix is not installed.
+    pub fn version(&self) -> Result<String> {
+        let mut cmd = self.nix_store();
+        let _ = cmd.arg("--version");
+        Self::run(cmd, "nix-store --version")
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+    }
+
+    /// Import a single uncompressed NAR described by `info`
+    ///
+    /// The NAR is framed the way `nix-store --export` would and piped into
+    /// `nix-store --import`, which registers the path with its references
+    /// and deriver. Callers must verify the NAR first; Nix trusts it as-is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if `nix-store` fails or the NAR
+    /// cannot be read.
+    pub fn import_nar<R: Read>(&self, info: &NarInfo, nar: &mut R) -> Result<()> {
+        let store_dir = info
+            .store_path
+            .rsplit_once('/')
+            .map_or("/nix/store", |(dir, _)| dir);
+        let full_path = |basename: &String| format!("{store_dir}/{basename}");
+        let references: Vec<String> = info.references.iter().map(full_path).collect();
+        let deriver = info.deriver.as_ref().map(full_path);
+        let path = &info.store_path;
+
+        let mut cmd = self.nix_store();
+        let _ = cmd.arg("--import");
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                CliError::StoreError(format!("nix-store --import: failed to spawn: {e}"))
+            })?;
+
+        let written = child.stdin.take().map_or(Ok(0), |mut stdin| {
+            store::write_export(path, &references, deriver.as_deref(), nar, &mut stdin)
+        });
+        let output = child
+            .wait_with_output()
+            .map_err(|e| CliError::StoreError(format!("nix-store --import {path}: {e}")))?;
+
+        if !output.status.success() {
+            return Err(CliError::StoreError(format!(
+                "nix-store --import {path} failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        written
+            .map(|_| ())
+            .map_err(|e| CliError::StoreError(format!("nix-store --import {path}: {e}")))
+    }
+
+    /// The subset of `paths` that is not valid in the store
+    ///
+    /// Paths are checked in batches with a single `nix-store --check-validity
+    /// --print-invalid` per batch, so large closures cost a handful of
+    /// processes rather than one per path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if `nix-store` cannot be run.
+    pub fn query_invalid(&self, paths: &[String]) -> Result<Vec<String>> {
+        let mut invalid = Vec::new();
+        for batch in paths.chunks(VALIDITY_BATCH_SIZE) {
+            let mut cmd = self.nix_store();
+            let _ = cmd
+                .args(["--check-validity", "--print-invalid"])
+                .args(batch);
+            invalid.extend(
+                Self::run_store(cmd, "nix-store --check-validity").map(|o| output_lines(&o.stdout))?,
+            );
+        }
+        Ok(invalid)
+    }
+
+    /// NAR size of each of `paths`, in the same order
+    ///
+    /// Queried in batches like [`Self::query_invalid`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] if the query fails or prints an
+    /// unexpected size.
+    pub fn query_sizes(&self, paths: &[String]) -> Result<Vec<u64>> {
+        let mut sizes = Vec::with_capacity(paths.len());
+        for batch in paths.chunks(VALIDITY_BATCH_SIZE) {
+            let mut cmd = self.nix_store();
+            let _ = cmd.args(["--query", "--size"]).args(batch);
+            let lines = Self::run_store(cmd, "nix-store --query --size").map(|o| output_lines(&o.stdout))?;
+            if lines.len() != batch.len() {
+                return Err(CliError::StoreError(format!(
+                    "nix-store --query --size printed {} sizes for {} paths",
+                    lines.len(),
+                    batch.len()
+                )));
+            }
+            for line in lines {
+                sizes.push(line.parse().map_err(|_| {
+                    CliError::StoreError(format!("nix-store --query --size: bad size '{line}'"))
+                })?);
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Whether a store path is valid (present) in the store
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::StoreError`] only if `nix-store` cannot be run;
+    /// an invalid path is `Ok(false)`.
+    pub fn path_valid(&self, path: &str) -> Result<bool> {
+        let mut cmd = self.nix_store();
+        let _ = cmd.args(["--check-validity", path]);
+        let output = cmd
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| CliError::StoreError(format!("nix-store --check-validity: {e}")))?;
+        Ok(output.status.success())
+    }
+}
+
+/// Which closure of a store path to transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClosureKind {
+    /// Everything needed to run the paths (`--requisites`)
+    #[default]
+    Runtime,
+    /// The runtime closure plus the outputs of every derivation in it
+    /// (`--requisites --include-outputs`)
+    ///
+    /// Given `.drv` paths this is the full build closure, which lets a
+    /// remote builder skip rebuilding dependencies, at the cost of a much
+    /// larger upload than the runtime closure.
+    WithOutputs,
+    /// Everything needed to build the paths: the closure of the
+    /// derivations that produced them, with every derivation output in the
+    /// store (`--requisites --include-outputs` on the `.drv` files)
+    ///
+    /// This is what a machine that will rebuild the paths needs, compilers
+    /// and sources included; typically many times the runtime closure.
+    Build,
+}
+
+impl ClosureKind {
+    /// Short name for messages and cache keys
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Runtime => "runtime",
+            Self::WithOutputs => "runtime with outputs",
+            Self::Build => "build",
+        }
+    }
+
+    /// `nix-store` arguments computing this closure
+    ///
+    /// For [`Self::Build`] they are applied to the paths' derivations.
+    #[must_use]
+    pub const fn query_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Runtime => &["--query", "--requisites"],
+            Self::WithOutputs | Self::Build => &["--query", "--requisites", "--include-outputs"],
+        }
+    }
+}
+
+impl std::str::FromStr for ClosureKind {
+    type Err = CliError;
+
+    /// Parse the `--closure` flag: `runtime`, `with-outputs` or `build`
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "runtime" => Ok(Self::Runtime),
+            "with-outputs" => Ok(Self::WithOutputs),
+            "build" => Ok(Self::Build),
+            other => Err(CliError::InvalidArgument(format!(
+                "unknown closure '{other}' (expected runtime, with-outputs or build)"
+            ))),
+        }
+    }
+}
+
+/// Store paths `nix build --dry-run` reports it would build or fetch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RealisePlan {
+    /// Derivations with no substitute, which would be built locally
+    pub will_build: Vec<String>,
+    /// Output paths available from a substituter
+    pub will_fetch: Vec<String>,
+}
+
+impl RealisePlan {
+    /// Parse the dry-run report Nix prints on stderr
+    ///
+    /// Each section starts with a header such as `these 2 derivations will
+    /// be built:` or `this path will be fetched (1.2 MiB download):`,
+    /// followed by one indented store path per line.
+    #[must_use]
+    pub fn parse(stderr: &str) -> Self {
+        let mut plan = Self::default();
+        let mut section: Option<&mut Vec<String>> = None;
+        for line in stderr.lines() {
+            let path = line.trim();
+            if line.starts_with(' ') && path.starts_with('/') {
+                if let Some(paths) = section.as_mut() {
+                    paths.push(path.to_string());
+                }
+            } else if line.contains("will be built") {
+                section = Some(&mut plan.will_build);
+            } else if line.contains("will be fetched") {
+                section = Some(&mut plan.will_fetch);
+            } else {
+                section = None;
+            }
+        }
+        plan
+    }
+
+    /// Add the paths of `other` that this plan does not list yet
+    pub fn merge(&mut self, other: Self) {
+        for (mine, theirs) in [
+            (&mut self.will_build, other.will_build),
+            (&mut self.will_fetch, other.will_fetch),
+        ] {
+            for path in theirs {
+                if !mine.contains(&path) {
+                    mine.push(path);
+                }
+            }
+        }
+    }
+}
+
+/// Whether Nix's stderr shows a transient daemon or store-lock failure
+/// rather than a real error (a missing path, a failed build)
+#[must_use]
+pub fn is_daemon_busy(stderr: &str) -> bool {
+    stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("error: "))
+        .any(|error| DAEMON_BUSY_MARKERS.iter().any(|marker| error.contains(marker)))
+}
+
+/// Split command output into non-empty trimmed lines
+fn output_lines(stdout: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_lines() {
+        let out = b"/nix/store/aaa-foo\n\n  /nix/store/bbb-bar  \n";
+        assert_eq!(
+            output_lines(out),
+            ["/nix/store/aaa-foo", "/nix/store/bbb-bar"]
+        );
+    }
+
+    #[test]
+    fn test_closure_kind_args() -> Result<()> {
+        assert_eq!("runtime".parse::<ClosureKind>()?, ClosureKind::Runtime);
+        assert_eq!(
+            "with-outputs".parse::<ClosureKind>()?.query_args(),
+            ["--query", "--requisites", "--include-outputs"]
+        );
+        assert_eq!("build".parse::<ClosureKind>()?, ClosureKind::Build);
+        assert!("full".parse::<ClosureKind>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_daemon_busy_detection() {
+        assert!(is_daemon_busy(
+            "error: SQLite database '/nix/var/nix/db/db.sqlite' is busy"
+        ));
+        assert!(is_daemon_busy(
+            "error: cannot connect to socket at '/nix/var/nix/daemon-socket/socket': \
+             Resource temporarily unavailable"
+        ));
+        assert!(!is_daemon_busy(
+            "error: path '/nix/store/aaa-x' is not valid"
+        ));
+        assert!(!is_daemon_busy(
+            "error: builder for '/nix/store/aaa-x.drv' failed"
+        ));
+    }
+
+    #[test]
+    fn test_build_log_is_not_daemon_busy() {
+        // A failed build whose own log mentions contention is a real failure
+        let stderr = "\
+hello> curl: (56) Recv failure: Connection reset by peer
+hello> sqlite3.OperationalError: database is locked
+hello> connect: Resource temporarily unavailable
+error: builder for '/nix/store/aaa-hello.drv' failed with exit code 1;
+       last 10 log lines:
+       > error: cannot connect to socket at '/nix/var/nix/daemon-socket/socket'
+";
+        assert!(!is_daemon_busy(stderr));
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        let stderr = "\
+this derivation will be built:
+  /nix/store/aaa-app.drv
+these 2 paths will be fetched (1.20 MiB download, 5.00 MiB unpacked):
+  /nix/store/bbb-glibc
+  /nix/store/ccc-openssl
+warning: Git tree is dirty
+";
+        let plan = RealisePlan::parse(stderr);
+        assert_eq!(plan.will_build, ["/nix/store/aaa-app.drv"]);
+        assert_eq!(
+            plan.will_fetch,
+            ["/nix/store/bbb-glibc", "/nix/store/ccc-openssl"]
+        );
+        assert_eq!(RealisePlan::parse(""), RealisePlan::default());
+
+        let mut merged = plan.clone();
+        merged.merge(RealisePlan {
+            will_build: vec!["/nix/store/ddd-gcc.drv".to_string()],
+            will_fetch: vec!["/nix/store/bbb-glibc".to_string()],
+        });
+        assert_eq!(merged.will_build.len(), 2);
+        assert_eq!(merged.will_fetch, plan.will_fetch);
+    }
+
+    #[test]
+    fn test_store_flag_is_threaded() {
+        let nix = Nix::with_store("ssh-ng://builder");
+        let cmd = nix.nix_store();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["--store", "ssh-ng://builder"]);
+        assert_eq!(Nix::new().store(), None);
+    }
+
+    #[test]
+    fn test_copy_reads_from_own_store() {
+        let local = Nix::new().copy_cmd("ssh-ng://builder");
+        let args: Vec<_> = local.get_args().collect();
+        assert_eq!(args[2..], ["copy", "--to", "ssh-ng://builder"]);
+
+        let mounted = Nix::with_store("/mnt/nix").copy_cmd("ssh-ng://builder");
+        let args: Vec<_> = mounted.get_args().collect();
+        assert_eq!(args[5..], ["--from", "/mnt/nix"]);
+    }
+}
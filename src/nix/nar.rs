@@ -0,0 +1,417 @@
+//! NAR (Nix ARchive) reading
+//!
+//! Streams the serialisation written by `nix-store --dump`, calling a
+//! visitor for every file, symlink, and directory, so archives can be
+//! inspected or unpacked without importing them into a store.
+
+use crate::error::{CliError, Result};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// First string of every NAR
+pub const NAR_VERSION_MAGIC: &str = "nix-archive-1";
+
+/// Longest token accepted outside file contents (names, symlink targets)
+const MAX_TOKEN_LEN: u64 = 64 * 1024;
+
+/// Deepest directory nesting accepted, to bound recursion on crafted input
+const MAX_DEPTH: usize = 256;
+
+/// One node of a NAR, passed to the visitor of [`read_nar`]
+pub enum NarNode<'a> {
+    /// A regular file; `contents` yields exactly `size` bytes
+    Regular {
+        /// Whether the file is executable
+        executable: bool,
+        /// Size of the contents in bytes
+        size: u64,
+        /// The file contents (unread bytes are skipped afterwards)
+        contents: &'a mut dyn Read,
+    },
+    /// A symbolic link
+    Symlink {
+        /// Link target, as stored
+        target: String,
+    },
+    /// A directory; its entries follow as separate nodes
+    Directory,
+}
+
+impl fmt::Debug for NarNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Regular {
+                executable, size, ..
+            } => f
+                .debug_struct("Regular")
+                .field("executable", executable)
+                .field("size", size)
+                .finish_non_exhaustive(),
+            Self::Symlink { target } => f.debug_struct("Symlink").field("target", target).finish(),
+            Self::Directory => f.write_str("Directory"),
+        }
+    }
+}
+
+fn invalid(reason: impl fmt::Display) -> CliError {
+    CliError::DeserializationError(format!("invalid NAR: {reason}"))
+}
+
+/// Streaming NAR tokenizer
+struct NarReader<R> {
+    inner: R,
+}
+
+impl<R: Read> NarReader<R> {
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.inner
+            .read_exact(&mut buf)
+            .map_err(|e| invalid(format!("truncated archive: {e}")))?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn skip_padding(&mut self, len: u64) -> Result<()> {
+        let padding = usize::try_from((8 - len % 8) % 8).unwrap_or_default();
+        let mut buf = [0; 8];
+        let pad = &mut buf[..padding];
+        self.inner
+            .read_exact(pad)
+            .map_err(|e| invalid(format!("truncated archive: {e}")))?;
+        if pad.iter().any(|&b| b != 0) {
+            return Err(invalid("non-zero padding"));
+        }
+        Ok(())
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let len = self.read_u64()?;
+        if len > MAX_TOKEN_LEN {
+            return Err(invalid(format!("string of {len} bytes is too long")));
+        }
+        let mut buf = vec![0; usize::try_from(len).unwrap_or_default()];
+        self.inner
+            .read_exact(&mut buf)
+            .map_err(|e| invalid(format!("truncated archive: {e}")))?;
+        self.skip_padding(len)?;
+        String::from_utf8(buf).map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        let found = self.read_string()?;
+        if found == token {
+            Ok(())
+        } else {
+            Err(invalid(format!("expected '{token}', found '{found}'")))
+        }
+    }
+
+    fn read_node<F>(&mut self, path: &Path, depth: usize, visit: &mut F) -> Result<()>
+    where
+        F: FnMut(&Path, NarNode<'_>) -> Result<()>,
+    {
+        if depth > MAX_DEPTH {
+            return Err(invalid("directories nested too deeply"));
+        }
+        self.expect("(")?;
+        self.expect("type")?;
+        match self.read_string()?.as_str() {
+            "regular" => {
+                let mut field = self.read_string()?;
+                let executable = field == "executable";
+                if executable {
+                    self.expect("")?;
+                    field = self.read_string()?;
+                }
+                if field != "contents" {
+                    return Err(invalid(format!("expected 'contents', found '{field}'")));
+                }
+                let size = self.read_u64()?;
+                let mut contents = (&mut self.inner).take(size);
+                visit(
+                    path,
+                    NarNode::Regular {
+                        executable,
+                        size,
+                        contents: &mut contents,
+                    },
+                )?;
+                // Skip whatever the visitor did not read
+                let _ = io::copy(&mut contents, &mut io::sink())
+                    .map_err(|e| invalid(format!("truncated archive: {e}")))?;
+                if contents.limit() > 0 {
+                    return Err(invalid("truncated file contents"));
+                }
+                self.skip_padding(size)?;
+            }
+            "symlink" => {
+                self.expect("target")?;
+                let target = self.read_string()?;
+                visit(path, NarNode::Symlink { target })?;
+            }
+            "directory" => {
+                visit(path, NarNode::Directory)?;
+                let mut previous: Option<String> = None;
+                loop {
+                    match self.read_string()?.as_str() {
+                        ")" => return Ok(()),
+                        "entry" => {}
+                        other => return Err(invalid(format!("unexpected '{other}'"))),
+                    }
+                    self.expect("(")?;
+                    self.expect("name")?;
+                    let name = self.read_string()?;
+                    validate_entry_name(&name, previous.as_deref())?;
+                    self.expect("node")?;
+                    self.read_node(&path.join(&name), depth + 1, visit)?;
+                    self.expect(")")?;
+                    previous = Some(name);
+                }
+            }
+            other => return Err(invalid(format!("unknown node type '{other}'"))),
+        }
+        self.expect(")")
+    }
+}
+
+/// Reject names that could escape the extraction root; Nix also requires
+/// entries to be sorted and unique
+fn validate_entry_name(name: &str, previous: Option<&str>) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(invalid(format!("illegal entry name '{name}'")));
+    }
+    if previous.is_some_and(|previous| previous >= name) {
+        return Err(invalid(format!("entry '{name}' is out of order")));
+    }
+    Ok(())
+}
+
+/// Walk a NAR, calling `visit` with each node's path relative to the root
+///
+/// The root itself has the empty path.
+///
+/// # Errors
+///
+/// Returns [`CliError::DeserializationError`] for a malformed archive, or
+/// any error returned by `visit`.
+pub fn read_nar<R, F>(nar: R, mut visit: F) -> Result<()>
+where
+    R: Read,
+    F: FnMut(&Path, NarNode<'_>) -> Result<()>,
+{
+    let mut reader = NarReader { inner: nar };
+    reader.expect(NAR_VERSION_MAGIC)?;
+    reader.read_node(Path::new(""), 0, &mut visit)
+}
+
+/// Unpack a NAR into `dest`, which must not exist yet
+///
+/// `dest` becomes the root node: a directory, a file, or a symlink.
+///
+/// # Errors
+///
+/// Returns [`CliError::FileError`] if `dest` exists or a node cannot be
+/// written, or [`CliError::DeserializationError`] for a malformed archive.
+pub fn extract<R: Read>(nar: R, dest: &Path) -> Result<()> {
+    let file_error = |path: &Path, e: &dyn fmt::Display| CliError::FileError {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    };
+    if dest.symlink_metadata().is_ok() {
+        return Err(file_error(dest, &"already exists"));
+    }
+
+    read_nar(nar, |path, node| {
+        // `dest.join("")` would add a trailing slash, breaking a file root
+        let target = if path.as_os_str().is_empty() {
+            dest.to_path_buf()
+        } else {
+            dest.join(path)
+        };
+        match node {
+            NarNode::Directory => fs::create_dir(&target).map_err(|e| file_error(&target, &e)),
+            NarNode::Regular {
+                executable,
+                contents,
+                ..
+            } => {
+                let mut file = fs::File::create(&target).map_err(|e| file_error(&target, &e))?;
+                let _ = io::copy(contents, &mut file).map_err(|e| file_error(&target, &e))?;
+                set_executable(&file, executable).map_err(|e| file_error(&target, &e))
+            }
+            NarNode::Symlink {
+                target: link_target,
+            } => symlink(&link_target, &target).map_err(|e| file_error(&target, &e)),
+        }
+    })
+}
+
+#[cfg(unix)]
+fn set_executable(file: &fs::File, executable: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if executable { 0o755 } else { 0o644 };
+    file.set_permissions(fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_file: &fs::File, _executable: bool) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are only supported on Unix",
+    ))
+}
+
+/// Normalise a path inside a NAR (`/bin/hello`, `./bin/hello` -> `bin/hello`)
+fn inner_path(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+/// Copy the contents of the regular file at `path` inside a NAR to `out`
+///
+/// # Errors
+///
+/// Returns [`CliError::FileError`] if there is no regular file at `path`,
+/// or [`CliError::DeserializationError`] for a malformed archive.
+pub fn cat<R: Read, W: Write>(nar: R, path: &str, out: &mut W) -> Result<()> {
+    let wanted = inner_path(path);
+    let mut found = false;
+    read_nar(nar, |node_path, node| {
+        if node_path != wanted {
+            return Ok(());
+        }
+        match node {
+            NarNode::Regular { contents, .. } => {
+                found = true;
+                let _ = io::copy(contents, out)?;
+                Ok(())
+            }
+            NarNode::Symlink { target } => Err(CliError::FileError {
+                path: wanted.clone(),
+                reason: format!("is a symlink to {target}"),
+            }),
+            NarNode::Directory => Err(CliError::FileError {
+                path: wanted.clone(),
+                reason: "is a directory".to_string(),
+            }),
+        }
+    })?;
+
+    if found {
+        Ok(())
+    } else {
+        Err(CliError::FileError {
+            path: wanted,
+            reason: "not found in archive".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix::store::write_nix_string;
+
+    fn encode(tokens: &[&str]) -> io::Result<Vec<u8>> {
+        let mut nar = Vec::new();
+        for token in tokens {
+            write_nix_string(&mut nar, token)?;
+        }
+        Ok(nar)
+    }
+
+    /// A directory with `README`, an executable `bin/hello`, and `lib -> bin`
+    #[rustfmt::skip]
+    fn sample_nar() -> io::Result<Vec<u8>> {
+        encode(&[
+            NAR_VERSION_MAGIC, "(", "type", "directory",
+            "entry", "(", "name", "README", "node",
+                "(", "type", "regular", "contents", "read me", ")", ")",
+            "entry", "(", "name", "bin", "node",
+                "(", "type", "directory",
+                "entry", "(", "name", "hello", "node",
+                    "(", "type", "regular", "executable", "", "contents", "#!/bin/sh\n", ")", ")",
+                ")", ")",
+            "entry", "(", "name", "lib", "node",
+                "(", "type", "symlink", "target", "bin", ")", ")",
+            ")",
+        ])
+    }
+
+    #[test]
+    fn test_read_nar_visits_every_node() -> Result<()> {
+        let mut seen = Vec::new();
+        read_nar(sample_nar()?.as_slice(), |path, node| {
+            seen.push(format!("{} {node:?}", path.display()));
+            Ok(())
+        })?;
+        assert_eq!(
+            seen,
+            [
+                " Directory",
+                "README Regular { executable: false, size: 7, .. }",
+                "bin Directory",
+                "bin/hello Regular { executable: true, size: 10, .. }",
+                "lib Symlink { target: \"bin\" }",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat_file_inside_nar() -> Result<()> {
+        let mut out = Vec::new();
+        cat(sample_nar()?.as_slice(), "/bin/hello", &mut out)?;
+        assert_eq!(out, b"#!/bin/sh\n");
+
+        assert!(cat(sample_nar()?.as_slice(), "bin", &mut out).is_err());
+        assert!(cat(sample_nar()?.as_slice(), "missing", &mut out).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_directory_tree() -> Result<()> {
+        let dest = std::env::temp_dir().join(format!("flakecache-nar-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dest);
+
+        let result = extract(sample_nar()?.as_slice(), &dest);
+        let readme = fs::read_to_string(dest.join("README")).unwrap_or_default();
+        let link = fs::read_link(dest.join("lib")).unwrap_or_default();
+        let again = extract(sample_nar()?.as_slice(), &dest);
+        let _ = fs::remove_dir_all(&dest);
+
+        result?;
+        assert_eq!(readme, "read me");
+        assert_eq!(link, PathBuf::from("bin"));
+        assert!(again.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_path_traversal() -> io::Result<()> {
+        #[rustfmt::skip]
+        let nar = encode(&[
+            NAR_VERSION_MAGIC, "(", "type", "directory",
+            "entry", "(", "name", "..", "node",
+                "(", "type", "regular", "contents", "x", ")", ")",
+            ")",
+        ])?;
+        assert!(read_nar(nar.as_slice(), |_, _| Ok(())).is_err());
+        assert!(read_nar(&b"garbage!"[..], |_, _| Ok(())).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! NARInfo parsing and serialisation
+//!
+//! A `.narinfo` file describes one store path in a binary cache: where its
+//! compressed NAR lives, its hashes and sizes, and its references.
+
+use super::store::store_path_hash;
+use crate::error::{CliError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Compression Nix assumes when the `Compression` field is absent
+const DEFAULT_COMPRESSION: &str = "bzip2";
+
+/// A parsed `.narinfo` document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NarInfo {
+    /// Full store path (`/nix/store/<hash>-<name>`)
+    pub store_path: String,
+    /// NAR location, relative to the cache root (e.g. `nar/<hash>.nar.xz`)
+    pub url: String,
+    /// Compression of the file at `url` (`xz`, `zstd`, `none`, ...)
+    pub compression: String,
+    /// Hash of the compressed file (`sha256:<base32>`)
+    pub file_hash: Option<String>,
+    /// Size of the compressed file in bytes
+    pub file_size: Option<u64>,
+    /// Hash of the uncompressed NAR (`sha256:<base32>`)
+    pub nar_hash: String,
+    /// Size of the uncompressed NAR in bytes
+    pub nar_size: u64,
+    /// References as store path basenames (`<hash>-<name>`)
+    pub references: Vec<String>,
+    /// Deriver basename (`<hash>-<name>.drv`)
+    pub deriver: Option<String>,
+    /// System the path was built for
+    pub system: Option<String>,
+    /// Signatures (`<key-name>:<base64>`)
+    pub sigs: Vec<String>,
+    /// Content address, for content-addressed paths
+    pub ca: Option<String>,
+}
+
+impl NarInfo {
+    /// The hash part of the store path basename
+    #[must_use]
+    pub fn store_hash(&self) -> &str {
+        store_path_hash(&self.store_path)
+    }
+}
+
+fn invalid(reason: impl fmt::Display) -> CliError {
+    CliError::DeserializationError(format!("invalid NARInfo: {reason}"))
+}
+
+fn parse_size(key: &str, value: &str) -> Result<u64> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{key} is not a number: '{value}'")))
+}
+
+impl FromStr for NarInfo {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut store_path = None;
+        let mut url = None;
+        let mut compression = None;
+        let mut file_hash = None;
+        let mut file_size = None;
+        let mut nar_hash = None;
+        let mut nar_size = None;
+        let mut references = Vec::new();
+        let mut deriver = None;
+        let mut system = None;
+        let mut sigs = Vec::new();
+        let mut ca = None;
+
+        for line in s.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("malformed line '{line}'")))?;
+            let value = value.trim();
+
+            match key.trim() {
+                "StorePath" => store_path = Some(value.to_string()),
+                "URL" => url = Some(value.to_string()),
+                "Compression" => compression = Some(value.to_string()),
+                "FileHash" => file_hash = Some(value.to_string()),
+                "FileSize" => file_size = Some(parse_size(key, value)?),
+                "NarHash" => nar_hash = Some(value.to_string()),
+                "NarSize" => nar_size = Some(parse_size(key, value)?),
+                "References" => {
+                    references = value.split_whitespace().map(ToString::to_string).collect();
+                }
+                "Deriver" if value != "unknown-deriver" => deriver = Some(value.to_string()),
+                "System" => system = Some(value.to_string()),
+                "Sig" => sigs.push(value.to_string()),
+                "CA" => ca = Some(value.to_string()),
+                // Unknown keys are ignored for forward compatibility, as Nix does
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            store_path: store_path.ok_or_else(|| invalid("missing StorePath"))?,
+            url: url.ok_or_else(|| invalid("missing URL"))?,
+            compression: compression.unwrap_or_else(|| DEFAULT_COMPRESSION.to_string()),
+            file_hash,
+            file_size,
+            nar_hash: nar_hash.ok_or_else(|| invalid("missing NarHash"))?,
+            nar_size: nar_size.ok_or_else(|| invalid("missing NarSize"))?,
+            references,
+            deriver,
+            system,
+            sigs,
+            ca,
+        })
+    }
+}
+
+impl fmt::Display for NarInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "StorePath: {}", self.store_path)?;
+        writeln!(f, "URL: {}", self.url)?;
+        writeln!(f, "Compression: {}", self.compression)?;
+        if let Some(file_hash) = &self.file_hash {
+            writeln!(f, "FileHash: {file_hash}")?;
+        }
+        if let Some(file_size) = self.file_size {
+            writeln!(f, "FileSize: {file_size}")?;
+        }
+        writeln!(f, "NarHash: {}", self.nar_hash)?;
+        writeln!(f, "NarSize: {}", self.nar_size)?;
+        writeln!(f, "References: {}", self.references.join(" "))?;
+        if let Some(deriver) = &self.deriver {
+            writeln!(f, "Deriver: {deriver}")?;
+        }
+        if let Some(system) = &self.system {
+            writeln!(f, "System: {system}")?;
+        }
+        for sig in &self.sigs {
+            writeln!(f, "Sig: {sig}")?;
+        }
+        if let Some(ca) = &self.ca {
+            writeln!(f, "CA: {ca}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+StorePath: /nix/store/0c75sid0a2r1dpmnwnbnp8ingjbmr3pl-hello-2.12.1
+URL: nar/1mqn5ywqwhbvm9y6gv5k9mdgxzjwlhjr8c8sgqycms1syr0xb9gp.nar.xz
+Compression: xz
+FileHash: sha256:1mqn5ywqwhbvm9y6gv5k9mdgxzjwlhjr8c8sgqycms1syr0xb9gp
+FileSize: 50088
+NarHash: sha256:0n9nigwkfpc4mkhcgrmhf4h3ywxh8gsw1x4cfb7ffmxjzc7ly8rj
+NarSize: 226560
+References: 0c75sid0a2r1dpmnwnbnp8ingjbmr3pl-hello-2.12.1 x3j5d4wrv2s0hs5n1hz1alm5y35xh0vq-glibc-2.38
+Deriver: 7vmbwv8a8hp3z6r9p7k46wqlg0zl0i3d-hello-2.12.1.drv
+System: x86_64-linux
+Sig: cache.nixos.org-1:abc123==
+";
+
+    #[test]
+    fn test_parse_all_fields() -> Result<()> {
+        let info: NarInfo = SAMPLE.parse()?;
+        assert_eq!(info.store_hash(), "0c75sid0a2r1dpmnwnbnp8ingjbmr3pl");
+        assert_eq!(info.compression, "xz");
+        assert_eq!(info.file_size, Some(50088));
+        assert_eq!(info.nar_size, 226_560);
+        assert_eq!(info.references.len(), 2);
+        assert_eq!(info.system.as_deref(), Some("x86_64-linux"));
+        assert_eq!(info.sigs, ["cache.nixos.org-1:abc123=="]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let info: NarInfo = SAMPLE.parse()?;
+        assert_eq!(info.to_string(), SAMPLE);
+        assert_eq!(info.to_string().parse::<NarInfo>()?, info);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_required_field() {
+        let result = "URL: nar/x.nar\nNarHash: sha256:x\nNarSize: 1\n".parse::<NarInfo>();
+        assert!(
+            matches!(result, Err(CliError::DeserializationError(msg)) if msg.contains("StorePath"))
+        );
+    }
+
+    #[test]
+    fn test_compression_defaults_to_bzip2() -> Result<()> {
+        let text = "StorePath: /nix/store/a-b\nURL: nar/x\nNarHash: sha256:x\nNarSize: 1\n";
+        assert_eq!(text.parse::<NarInfo>()?.compression, "bzip2");
+        Ok(())
+    }
+}
@@ -1,3 +1,259 @@
 //! Flake and dependency resolution
 //!
 //! Resolves flake outputs and their dependencies from the Nix store.
+
+use crate::cache::transfer::fetch_narinfo_cached;
+use crate::client::cbor::CborClient;
+use crate::error::{CliError, Result};
+use crate::nix::NarInfo;
+use std::future::Future;
+use std::time::Duration;
+
+/// Default number of attempts per store path
+pub const MAX_RETRIES: u32 = 3;
+
+/// Default per-attempt download timeout in seconds
+pub const DOWNLOAD_TIMEOUT_SECS: u64 = 300;
+
+/// Default delay between attempts in seconds
+pub const RETRY_DELAY_SECS: u64 = 2;
+
+/// Environment variable for `--retries`
+pub const RETRIES_ENV: &str = "FLAKECACHE_RETRIES";
+
+/// Environment variable for `--timeout`
+pub const TIMEOUT_ENV: &str = "FLAKECACHE_TIMEOUT";
+
+/// Environment variable for `--retry-delay`
+pub const RETRY_DELAY_ENV: &str = "FLAKECACHE_RETRY_DELAY";
+
+/// How hard to try fetching each store path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryOptions {
+    /// Attempts per store path (at least 1)
+    pub retries: u32,
+    /// Timeout for a single attempt
+    pub timeout: Duration,
+    /// Pause between attempts
+    pub retry_delay: Duration,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            retries: MAX_RETRIES,
+            timeout: Duration::from_secs(DOWNLOAD_TIMEOUT_SECS),
+            retry_delay: Duration::from_secs(RETRY_DELAY_SECS),
+        }
+    }
+}
+
+impl RetryOptions {
+    /// Build options from command-line flags, falling back to
+    /// `FLAKECACHE_RETRIES`, `FLAKECACHE_TIMEOUT`, `FLAKECACHE_RETRY_DELAY`,
+    /// then the defaults
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidArgument`] if an environment variable is
+    /// not a number, `retries` is 0, or `timeout` is 0.
+    pub fn resolve(
+        retries: Option<u32>,
+        timeout_secs: Option<u64>,
+        retry_delay_secs: Option<u64>,
+    ) -> Result<Self> {
+        Self::from_sources(
+            retries.map_or_else(|| env_number(RETRIES_ENV), |n| Ok(Some(n)))?,
+            timeout_secs.map_or_else(|| env_number(TIMEOUT_ENV), |n| Ok(Some(n)))?,
+            retry_delay_secs.map_or_else(|| env_number(RETRY_DELAY_ENV), |n| Ok(Some(n)))?,
+        )
+    }
+
+    /// Validate already-chosen values, using defaults for the missing ones
+    fn from_sources(
+        retries: Option<u32>,
+        timeout_secs: Option<u64>,
+        retry_delay_secs: Option<u64>,
+    ) -> Result<Self> {
+        let defaults = Self::default();
+        let options = Self {
+            retries: retries.unwrap_or(defaults.retries),
+            timeout: timeout_secs.map_or(defaults.timeout, Duration::from_secs),
+            retry_delay: retry_delay_secs.map_or(defaults.retry_delay, Duration::from_secs),
+        };
+
+        if options.retries == 0 {
+            return Err(CliError::InvalidArgument(
+                "--retries must be at least 1".to_string(),
+            ));
+        }
+        if options.timeout.is_zero() {
+            return Err(CliError::InvalidArgument(
+                "--timeout must be greater than 0".to_string(),
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// Parse a numeric environment variable, treating unset and empty as absent
+fn env_number<T: std::str::FromStr>(name: &str) -> Result<Option<T>> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value.trim().parse().map_err(|_| {
+                CliError::InvalidArgument(format!(
+                    "{name} must be a non-negative number, got '{value}'"
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Fetch one store path, retrying transient failures
+///
+/// Each attempt is bounded by `options.timeout`. Only errors for which
+/// [`CliError::is_retryable`] holds are retried; the last error is returned
+/// once `options.retries` attempts have been made.
+///
+/// # Errors
+///
+/// Returns the error of the final attempt, or [`CliError::Timeout`] if it
+/// timed out.
+pub async fn resolve_single<T, F, Fut>(
+    store_path: &str,
+    options: &RetryOptions,
+    mut fetch: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = tokio::time::timeout(options.timeout, fetch())
+            .await
+            .unwrap_or_else(|_| {
+                Err(CliError::Timeout(format!(
+                    "{store_path} after {}s",
+                    options.timeout.as_secs()
+                )))
+            });
+
+        match result {
+            Err(err) if err.is_retryable() && attempt < options.retries => {
+                attempt += 1;
+                tokio::time::sleep(options.retry_delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Look up the NARInfo of `store_path`, retrying transient failures
+///
+/// Only a 404 is a cache miss (`None`), which lets the caller fall back to
+/// building the path. Every other failure is an error: 401/403 surface as
+/// [`CliError::AuthFailed`] and are not retried, while 429/5xx and network
+/// errors are retried as in [`resolve_single`], so a permission or server
+/// problem is never mistaken for a benign miss.
+///
+/// Answers, misses included, are reused from the NARInfo cache (see
+/// [`fetch_narinfo_cached`]).
+///
+/// # Errors
+///
+/// Returns the error of the final attempt for anything but a 404.
+pub async fn resolve_narinfo(
+    client: &CborClient,
+    cache: &str,
+    store_path: &str,
+    options: &RetryOptions,
+) -> Result<Option<NarInfo>> {
+    resolve_single(store_path, options, || {
+        fetch_narinfo_cached(client, cache, store_path)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_defaults_match_constants() -> Result<()> {
+        let options = RetryOptions::from_sources(None, None, None)?;
+        assert_eq!(options, RetryOptions::default());
+        assert_eq!(options.retries, MAX_RETRIES);
+        assert_eq!(options.timeout, Duration::from_secs(DOWNLOAD_TIMEOUT_SECS));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(RetryOptions::from_sources(Some(0), None, None).is_err());
+        assert!(RetryOptions::from_sources(None, Some(0), None).is_err());
+        assert!(RetryOptions::from_sources(Some(1), Some(1), Some(0)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_single_retries_transient_errors() {
+        let options = RetryOptions {
+            retries: 3,
+            timeout: Duration::from_secs(5),
+            retry_delay: Duration::ZERO,
+        };
+        let calls = AtomicUsize::new(0);
+
+        let result: Result<()> = resolve_single("/nix/store/aaa-hello", &options, || {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(CliError::DownloadFailed("reset".to_string())) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<()> = resolve_single("/nix/store/aaa-hello", &options, || {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async {
+                Err(CliError::InvalidStorePath {
+                    path: "x".to_string(),
+                })
+            }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_narinfo_statuses_are_not_misses() {
+        use crate::client::response::status_error;
+
+        let options = RetryOptions {
+            retries: 2,
+            timeout: Duration::from_secs(5),
+            retry_delay: Duration::ZERO,
+        };
+        let calls = AtomicUsize::new(0);
+        let result: Result<Option<()>> = resolve_single("/nix/store/aaa-hello", &options, || {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(status_error(403, "denied")) }
+        })
+        .await;
+        assert!(matches!(result, Err(CliError::AuthFailed(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<Option<()>> = resolve_single("/nix/store/aaa-hello", &options, || {
+            let _ = calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(status_error(502, "bad gateway")) }
+        })
+        .await;
+        assert!(result.is_err_and(|e| e.is_retryable()));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
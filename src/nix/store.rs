@@ -1,3 +1,235 @@
 //! Nix store operations
 //!
 //! Low-level operations for interacting with the local Nix store.
+
+use crate::error::{CliError, Result};
+use std::io::{self, Read, Write};
+
+/// Marker preceding each path's metadata in `nix-store --export` output
+const EXPORT_MAGIC: u64 = 0x4558_494e;
+
+/// Default Nix store directory
+pub const STORE_DIR: &str = "/nix/store";
+
+/// Length of the hash part of a store path basename (160 bits in base32)
+pub const STORE_HASH_LEN: usize = 32;
+
+/// Alphabet of Nix's base32 encoding (no `e`, `o`, `u`, `t`)
+const NIX_BASE32_CHARS: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Encode bytes in Nix's base32 format, as used in store paths and hashes
+#[must_use]
+pub fn nix_base32_encode(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+    let len = (bytes.len() * 8 - 1) / 5 + 1;
+
+    (0..len)
+        .rev()
+        .map(|n| {
+            let bit = n * 5;
+            let (i, j) = (bit / 8, bit % 8);
+            let low = u16::from(bytes[i]) >> j;
+            let high = bytes.get(i + 1).map_or(0, |&b| u16::from(b) << (8 - j));
+            char::from(NIX_BASE32_CHARS[usize::from((low | high) & 0x1f)])
+        })
+        .collect()
+}
+
+/// Basename of a store path (`/nix/store/<hash>-<name>` -> `<hash>-<name>`),
+/// the form used in NARInfo `References` and `Deriver`
+#[must_use]
+pub fn store_path_basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Hash part of a store path (`/nix/store/<hash>-<name>` -> `<hash>`)
+#[must_use]
+pub fn store_path_hash(path: &str) -> &str {
+    let base = store_path_basename(path);
+    base.split('-').next().unwrap_or(base)
+}
+
+/// Whether a store path is a derivation (`<hash>-<name>.drv`)
+#[must_use]
+pub fn is_derivation(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == "drv")
+}
+
+/// Validate a store path and return its hash part
+///
+/// Unlike [`store_path_hash`], which trusts its input, this checks the path
+/// is `/nix/store/<hash>-<name>` with a 32-character Nix base32 hash, so a
+/// malformed path can never end up in a cache URL.
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidStorePath`] if the path is malformed.
+pub fn parse_store_path(path: &str) -> Result<&str> {
+    let invalid = || CliError::InvalidStorePath {
+        path: path.to_string(),
+    };
+    let base = path
+        .strip_prefix(STORE_DIR)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .filter(|base| !base.contains('/'))
+        .ok_or_else(invalid)?;
+    let (hash, name) = base.split_at_checked(STORE_HASH_LEN).ok_or_else(invalid)?;
+
+    let valid_hash = hash.bytes().all(|b| NIX_BASE32_CHARS.contains(&b));
+    match name.strip_prefix('-') {
+        Some(name) if valid_hash && !name.is_empty() => Ok(hash),
+        _ => Err(invalid()),
+    }
+}
+
+/// Validate the hash part of a store path on its own (e.g. `--hash`)
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidStorePath`] unless `hash` is 32 Nix base32
+/// characters.
+pub fn parse_store_hash(hash: &str) -> Result<&str> {
+    if hash.len() == STORE_HASH_LEN && hash.bytes().all(|b| NIX_BASE32_CHARS.contains(&b)) {
+        Ok(hash)
+    } else {
+        Err(CliError::InvalidStorePath {
+            path: hash.to_string(),
+        })
+    }
+}
+
+/// Format a SHA-256 digest the way NARInfo files do (`sha256:<base32>`)
+#[must_use]
+pub fn sha256_nix(digest: &[u8; 32]) -> String {
+    format!("sha256:{}", nix_base32_encode(digest))
+}
+
+/// Write a string in Nix wire format: length, bytes, zero padding to 8
+pub(crate) fn write_nix_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(&(s.len() as u64).to_le_bytes())?;
+    out.write_all(s.as_bytes())?;
+    out.write_all(&[0; 8][..(8 - s.len() % 8) % 8])
+}
+
+/// Wrap one NAR in the framing `nix-store --import` reads
+///
+/// `references` and `deriver` are full store paths. Returns the number of
+/// NAR bytes copied.
+///
+/// # Errors
+///
+/// Returns any I/O error from reading `nar` or writing `out`.
+pub fn write_export<R: Read, W: Write>(
+    store_path: &str,
+    references: &[String],
+    deriver: Option<&str>,
+    nar: &mut R,
+    out: &mut W,
+) -> io::Result<u64> {
+    out.write_all(&1_u64.to_le_bytes())?;
+    let nar_bytes = io::copy(nar, out)?;
+    out.write_all(&EXPORT_MAGIC.to_le_bytes())?;
+    write_nix_string(out, store_path)?;
+    out.write_all(&(references.len() as u64).to_le_bytes())?;
+    for reference in references {
+        write_nix_string(out, reference)?;
+    }
+    write_nix_string(out, deriver.unwrap_or_default())?;
+    // No legacy signature, then the end-of-export marker
+    out.write_all(&0_u64.to_le_bytes())?;
+    out.write_all(&0_u64.to_le_bytes())?;
+    Ok(nar_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nix_base32_known_digest() {
+        // sha256 of the empty string, as printed by `nix-hash --to-base32`
+        let empty = [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+            0x78, 0x52, 0xb8, 0x55,
+        ];
+        assert_eq!(
+            sha256_nix(&empty),
+            "sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        );
+    }
+
+    #[test]
+    fn test_store_path_hash() {
+        assert_eq!(
+            store_path_hash("/nix/store/0c75sid0a2r1dpmnwnbnp8ingjbmr3pl-hello-2.12.1"),
+            "0c75sid0a2r1dpmnwnbnp8ingjbmr3pl"
+        );
+        assert_eq!(store_path_hash("abc-foo"), "abc");
+        assert!(is_derivation("/nix/store/aaa-hello-2.12.1.drv"));
+        assert!(!is_derivation("/nix/store/aaa-hello-2.12.1"));
+    }
+
+    #[test]
+    fn test_parse_store_path() {
+        let hash = "0c75sid0a2r1dpmnwnbnp8ingjbmr3pl";
+        assert_eq!(
+            parse_store_path(&format!("/nix/store/{hash}-hello-2.12.1")).ok(),
+            Some(hash)
+        );
+        // Names may contain dashes and dots
+        assert!(parse_store_path(&format!("/nix/store/{hash}-foo-bar.drv")).is_ok());
+
+        for bad in [
+            "/nix/store/0c75sid0a2r1-hello",
+            &format!("/nix/store/{}-hello", hash.replace('0', "e")),
+            &format!("/nix/store/{}-hello", hash.to_uppercase()),
+            &format!("/opt/store/{hash}-hello"),
+            &format!("{hash}-hello"),
+            &format!("/nix/store/{hash}"),
+            &format!("/nix/store/{hash}-"),
+            &format!("/nix/store/{hash}-hello/bin/hello"),
+            "/nix/store/ü0c75sid0a2r1dpmnwnbnp8ingjbmr3p-hello",
+        ] {
+            assert!(
+                matches!(
+                    parse_store_path(bad),
+                    Err(CliError::InvalidStorePath { .. })
+                ),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_export_framing() -> io::Result<()> {
+        let mut out = Vec::new();
+        let copied = write_export(
+            "/nix/store/aaa-x",
+            &["/nix/store/bbb-y".to_string()],
+            None,
+            &mut &b"NARBYTES"[..],
+            &mut out,
+        )?;
+        assert_eq!(copied, 8);
+        assert_eq!(&out[..8], 1_u64.to_le_bytes());
+        assert_eq!(&out[8..16], b"NARBYTES");
+        assert_eq!(&out[16..24], EXPORT_MAGIC.to_le_bytes());
+        assert_eq!(&out[24..32], 16_u64.to_le_bytes());
+        assert_eq!(&out[32..48], b"/nix/store/aaa-x");
+        // refs count + one padded ref + empty deriver + two trailing zeros
+        assert_eq!(out.len(), 48 + 8 + 24 + 8 + 16);
+        assert!(out.ends_with(&[0; 16]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nix_base32_length() {
+        assert_eq!(nix_base32_encode(&[]), "");
+        assert_eq!(nix_base32_encode(&[0; 20]).len(), 32);
+    }
+}
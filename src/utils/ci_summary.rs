@@ -0,0 +1,178 @@
+//! CI step summaries and outputs (`--summary-file`)
+//!
+//! Appends the metrics of a push or pull to the files CI systems read after
+//! a step: a Markdown table for `$GITHUB_STEP_SUMMARY`, and `key=value`
+//! lines for `$GITHUB_OUTPUT` (also used for a plain `--summary-file`).
+
+use crate::error::{CliError, Result};
+use std::fmt::{self, Write as _};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// GitHub Actions job summary file (Markdown)
+pub const GITHUB_STEP_SUMMARY_ENV: &str = "GITHUB_STEP_SUMMARY";
+
+/// GitHub Actions step output file (`key=value`)
+pub const GITHUB_OUTPUT_ENV: &str = "GITHUB_OUTPUT";
+
+/// How a summary is written to one file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryFormat {
+    /// A Markdown table, for job summaries
+    Markdown,
+    /// One `key=value` line per metric, for step outputs and dotenv files
+    KeyValue,
+}
+
+impl SummaryFormat {
+    /// Format for a `--summary-file`: Markdown for `.md`, otherwise
+    /// `key=value`
+    #[must_use]
+    pub fn for_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|ext| ext == "md") {
+            Self::Markdown
+        } else {
+            Self::KeyValue
+        }
+    }
+}
+
+/// A file to append a summary to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryTarget {
+    /// File to append to
+    pub path: PathBuf,
+    /// Encoding of the appended summary
+    pub format: SummaryFormat,
+}
+
+/// Files to write: `--summary-file` if given, else the GitHub Actions
+/// files named by the environment
+#[must_use]
+pub fn targets(flag: Option<&Path>) -> Vec<SummaryTarget> {
+    targets_from(flag, |name| std::env::var(name).ok())
+}
+
+fn targets_from(flag: Option<&Path>, env: impl Fn(&str) -> Option<String>) -> Vec<SummaryTarget> {
+    if let Some(path) = flag {
+        return vec![SummaryTarget {
+            path: path.to_path_buf(),
+            format: SummaryFormat::for_path(path),
+        }];
+    }
+    [
+        (GITHUB_STEP_SUMMARY_ENV, SummaryFormat::Markdown),
+        (GITHUB_OUTPUT_ENV, SummaryFormat::KeyValue),
+    ]
+    .into_iter()
+    .filter_map(|(name, format)| {
+        let path = env(name).filter(|path| !path.is_empty())?;
+        Some(SummaryTarget {
+            path: PathBuf::from(path),
+            format,
+        })
+    })
+    .collect()
+}
+
+/// Metrics of one command run, in display order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepSummary {
+    /// Command the metrics describe (`push`, `pull`)
+    pub command: &'static str,
+    /// Metric names (`snake_case`) and values
+    pub metrics: Vec<(&'static str, String)>,
+}
+
+impl StepSummary {
+    /// Empty summary for `command`
+    #[must_use]
+    pub const fn new(command: &'static str) -> Self {
+        Self {
+            command,
+            metrics: Vec::new(),
+        }
+    }
+
+    /// Add a metric
+    #[must_use]
+    pub fn with(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.metrics.push((name, value.to_string()));
+        self
+    }
+
+    /// Render in `format`, ending with a newline
+    #[must_use]
+    pub fn render(&self, format: SummaryFormat) -> String {
+        let mut out = String::new();
+        match format {
+            SummaryFormat::Markdown => {
+                let _ = writeln!(out, "### flakecache {}\n", self.command);
+                out.push_str("| Metric | Value |\n| --- | --- |\n");
+                for (name, value) in &self.metrics {
+                    let _ = writeln!(out, "| {name} | {value} |");
+                }
+            }
+            SummaryFormat::KeyValue => {
+                for (name, value) in &self.metrics {
+                    let _ = writeln!(out, "{name}={value}");
+                }
+            }
+        }
+        out
+    }
+
+    /// Append the summary to every target
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if a file cannot be appended to.
+    pub fn write(&self, targets: &[SummaryTarget]) -> Result<()> {
+        for target in targets {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&target.path)
+                .and_then(|mut file| file.write_all(self.render(target.format).as_bytes()))
+                .map_err(|e| CliError::FileError {
+                    path: target.path.clone(),
+                    reason: e.to_string(),
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_from_env() {
+        let env = |name: &str| (name == GITHUB_OUTPUT_ENV).then(|| "/tmp/out".to_string());
+        assert_eq!(
+            targets_from(None, env),
+            [SummaryTarget {
+                path: PathBuf::from("/tmp/out"),
+                format: SummaryFormat::KeyValue,
+            }]
+        );
+        let flagged = targets_from(Some(Path::new("summary.md")), env);
+        assert_eq!(flagged[0].format, SummaryFormat::Markdown);
+        assert!(targets_from(None, |_| None).is_empty());
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let summary = StepSummary::new("push").with("paths", 3).with("failed", 0);
+        assert_eq!(
+            summary.render(SummaryFormat::KeyValue),
+            "paths=3\nfailed=0\n"
+        );
+        assert_eq!(
+            summary.render(SummaryFormat::Markdown),
+            "### flakecache push\n\n| Metric | Value |\n| --- | --- |\n| paths | 3 |\n| failed | 0 |\n"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Advisory lock against concurrent flakecache processes
+//!
+//! Two pushes in the same CI job, or a push next to a warm, share the
+//! compressed NAR and closure caches and the temp directory. Operations
+//! that touch that state hold an exclusive lock on
+//! `<cache dir>/flakecache.lock`; a second process waits for it. The lock
+//! is an OS file lock (`flock` on Unix), released when its holder exits
+//! for any reason, so the lock file left behind by a killed process never
+//! blocks anyone. The file records the holder's PID for the waiting
+//! message.
+
+use crate::config::Config;
+use crate::error::{CliError, Result};
+use crate::utils::output;
+use std::fs::{File, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file in the cache directory
+pub const LOCK_FILE: &str = "flakecache.lock";
+
+/// Exclusive lock, held until dropped
+#[derive(Debug)]
+pub struct InstanceLock {
+    // Closing the file releases the lock
+    _file: File,
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Lock file in the cache directory (see [`Config::cache_dir`])
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::Internal`] if no cache directory can be
+    /// determined.
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(Config::cache_dir()?.join(LOCK_FILE))
+    }
+
+    /// Take the lock at `path`, blocking while another process holds it
+    ///
+    /// `on_wait` is called once before blocking, with the holder's PID if
+    /// it could be read.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if the lock file cannot be created
+    /// or locked.
+    pub fn acquire(path: &Path, on_wait: impl FnOnce(Option<u32>)) -> Result<Self> {
+        let file_error = |e: std::io::Error| CliError::FileError {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(file_error)?;
+        }
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(file_error)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                on_wait(read_pid(&mut file));
+                file.lock().map_err(file_error)?;
+            }
+            Err(TryLockError::Error(e)) => return Err(file_error(e)),
+        }
+        output::debug(format_args!("locked {}", path.display()));
+
+        // Only the holder writes, so a reader never sees a torn PID
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| write!(file, "{}", std::process::id()))
+            .map_err(file_error)?;
+        Ok(Self {
+            _file: file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// The locked file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// PID recorded in a lock file, if it holds one
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.rewind().ok()?;
+    let _ = file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_sees_holder() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flakecache-lock-{}", std::process::id()));
+        let path = dir.join(LOCK_FILE);
+        let held = InstanceLock::acquire(&path, |_| {})?;
+        assert_eq!(held.path(), path);
+
+        let mut file = File::open(&path)?;
+        assert_eq!(read_pid(&mut file), Some(std::process::id()));
+        assert!(matches!(file.try_lock(), Err(TryLockError::WouldBlock)));
+
+        // Released on drop; the file stays behind without blocking anyone
+        drop(held);
+        let mut waited = false;
+        let _again = InstanceLock::acquire(&path, |_| waited = true)?;
+        assert!(!waited);
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
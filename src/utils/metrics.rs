@@ -0,0 +1,182 @@
+//! Prometheus textfile metrics (`--metrics-file`)
+//!
+//! After a push or pull, the metrics of the run can be written in the
+//! text exposition format for node_exporter's textfile collector. The file
+//! describes the last run only: each write replaces it, through a
+//! temporary file and a rename so the collector never reads half of it.
+
+use crate::error::{CliError, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Store paths the run uploaded
+pub const PATHS_UPLOADED: &str = "flakecache_paths_uploaded_total";
+
+/// Compressed bytes the run uploaded
+pub const BYTES_UPLOADED: &str = "flakecache_bytes_uploaded_total";
+
+/// Store paths the run fetched from the cache
+pub const CACHE_HITS: &str = "flakecache_cache_hits_total";
+
+/// Wall-clock time of the run
+pub const DURATION: &str = "flakecache_duration_seconds";
+
+/// Prometheus metric type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// A count, named `*_total`
+    Counter,
+    /// A measured value
+    Gauge,
+}
+
+impl MetricKind {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+        }
+    }
+}
+
+/// One metric with its help text and value
+#[derive(Debug, Clone, PartialEq)]
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+    value: f64,
+}
+
+/// Metrics of one command run, all with the same labels
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsFile {
+    labels: Vec<(&'static str, String)>,
+    metrics: Vec<Metric>,
+}
+
+impl MetricsFile {
+    /// Metrics of `command` (`push`, `pull`), labelled with `cache` if
+    /// it is known
+    #[must_use]
+    pub fn new(command: &str, cache: Option<&str>) -> Self {
+        let mut labels = vec![("command", command.to_string())];
+        labels.extend(cache.map(|cache| ("cache", cache.to_string())));
+        Self {
+            labels,
+            metrics: Vec::new(),
+        }
+    }
+
+    /// Add a counter
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn counter(self, name: &'static str, help: &'static str, value: u64) -> Self {
+        self.with(name, help, MetricKind::Counter, value as f64)
+    }
+
+    /// Add a gauge
+    #[must_use]
+    pub fn gauge(self, name: &'static str, help: &'static str, value: f64) -> Self {
+        self.with(name, help, MetricKind::Gauge, value)
+    }
+
+    fn with(
+        mut self,
+        name: &'static str,
+        help: &'static str,
+        kind: MetricKind,
+        value: f64,
+    ) -> Self {
+        self.metrics.push(Metric {
+            name,
+            help,
+            kind,
+            value,
+        });
+        self
+    }
+
+    /// The metrics in the text exposition format, ending with a newline
+    #[must_use]
+    pub fn render(&self) -> String {
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{name}=\"{}\"", escape_label(value)))
+            .collect();
+        let labels = labels.join(",");
+        let mut out = String::new();
+        for metric in &self.metrics {
+            let _ = writeln!(out, "# HELP {} {}", metric.name, metric.help);
+            let _ = writeln!(out, "# TYPE {} {}", metric.name, metric.kind.name());
+            let _ = writeln!(out, "{}{{{labels}}} {}", metric.name, metric.value);
+        }
+        out
+    }
+
+    /// Replace `path` with the metrics
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::FileError`] if the file cannot be written.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&tmp, self.render())
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp);
+                CliError::FileError {
+                    path: path.to_path_buf(),
+                    reason: e.to_string(),
+                }
+            })
+    }
+}
+
+/// A label value with `\`, `"` and newlines escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposition_format() {
+        let metrics = MetricsFile::new("push", Some("team \"a\""))
+            .counter(PATHS_UPLOADED, "Store paths uploaded", 3)
+            .gauge(DURATION, "Duration of the run", 1.5);
+        assert_eq!(
+            metrics.render(),
+            "# HELP flakecache_paths_uploaded_total Store paths uploaded\n\
+             # TYPE flakecache_paths_uploaded_total counter\n\
+             flakecache_paths_uploaded_total{command=\"push\",cache=\"team \\\"a\\\"\"} 3\n\
+             # HELP flakecache_duration_seconds Duration of the run\n\
+             # TYPE flakecache_duration_seconds gauge\n\
+             flakecache_duration_seconds{command=\"push\",cache=\"team \\\"a\\\"\"} 1.5\n"
+        );
+    }
+
+    #[test]
+    fn test_write_replaces_file() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("flakecache-metrics-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("flakecache.prom");
+        fs::write(&path, "stale")?;
+
+        MetricsFile::new("pull", None)
+            .counter(CACHE_HITS, "Store paths fetched from the cache", 7)
+            .write(&path)?;
+        let written = fs::read_to_string(&path)?;
+        assert!(written.ends_with("flakecache_cache_hits_total{command=\"pull\"} 7\n"));
+        assert_eq!(fs::read_dir(&dir)?.count(), 1);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
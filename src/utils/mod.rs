@@ -1,6 +1,15 @@
 //! Utilities (progress tracking, parallelization, chunking, etc.)
 
 pub mod chunker;
+pub mod ci_summary;
+pub mod lock;
+pub mod metrics;
+pub mod output;
 pub mod progress;
 pub mod parallel;
+pub mod platform;
 pub mod streaming;
+pub mod table;
+pub mod throttle;
+pub mod time;
+pub mod trace;
@@ -0,0 +1,137 @@
+//! Output formatting
+//!
+//! Selects between human-readable and machine-readable command output, and
+//! how much informational output is printed.
+
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// How command results are printed (`--output`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// A single JSON document on stdout
+    Json,
+    /// One JSON object per line, printed as results arrive
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// `true` for machine-readable formats
+    #[must_use]
+    pub const fn is_json(self) -> bool {
+        matches!(self, Self::Json | Self::Jsonl)
+    }
+
+    /// `true` for JSON Lines, where each result is printed on its own line
+    #[must_use]
+    pub const fn is_json_lines(self) -> bool {
+        matches!(self, Self::Jsonl)
+    }
+}
+
+/// How much diagnostic output to print (`--quiet` / `--verbose`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only warnings, errors, and requested results
+    Quiet,
+    /// Progress and status lines
+    #[default]
+    Normal,
+    /// Everything, including debugging detail
+    Verbose,
+}
+
+impl Verbosity {
+    /// Build the level from the `--quiet` / `--verbose` flags
+    #[must_use]
+    pub const fn from_flags(quiet: bool, verbose: bool) -> Self {
+        if quiet {
+            Self::Quiet
+        } else if verbose {
+            Self::Verbose
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+/// Process-wide verbosity, set once from the command line
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Set the verbosity for the rest of the process
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Current verbosity
+#[must_use]
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+/// `true` if informational output should be suppressed
+#[must_use]
+pub fn is_quiet() -> bool {
+    verbosity() == Verbosity::Quiet
+}
+
+/// Process-wide color and table borders, set once from the command line
+static STYLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether text output should use color and table borders: only on a
+/// terminal, and not with `--no-color` or a non-empty `$NO_COLOR`
+#[must_use]
+pub fn resolve_styled(no_color: bool) -> bool {
+    let no_color = no_color || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && std::io::stdout().is_terminal()
+}
+
+/// Set whether text output is styled for the rest of the process
+pub fn set_styled(styled: bool) {
+    STYLED.store(styled, Ordering::Relaxed);
+}
+
+/// `true` if text output should use color and table borders
+#[must_use]
+pub fn is_styled() -> bool {
+    STYLED.load(Ordering::Relaxed)
+}
+
+/// Print a diagnostic line to stderr when `--verbose` is set
+///
+/// Diagnostics go to stderr so they never mix with results on stdout.
+#[allow(clippy::print_stderr)]
+pub fn debug(message: fmt::Arguments<'_>) {
+    if verbosity() == Verbosity::Verbose {
+        eprintln!("{message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_from_flags() {
+        assert_eq!(Verbosity::from_flags(true, false), Verbosity::Quiet);
+        assert_eq!(Verbosity::from_flags(false, true), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(false, false), Verbosity::Normal);
+        assert!(Verbosity::Quiet < Verbosity::Normal);
+    }
+
+    #[test]
+    fn test_json_lines_is_json() {
+        assert!(OutputFormat::Jsonl.is_json());
+        assert!(OutputFormat::Jsonl.is_json_lines());
+        assert!(!OutputFormat::Json.is_json_lines());
+        assert!(!OutputFormat::Text.is_json());
+    }
+}
@@ -1,3 +1,105 @@
 //! Parallelization utilities
 //!
 //! Utilities for parallel uploads/downloads with adaptive concurrency.
+
+use crate::error::{CliError, Result};
+use crate::utils::throttle::RateLimiter;
+use std::fmt;
+use std::str::FromStr;
+
+/// Transfers per CPU core for `--parallelism auto`
+///
+/// Transfers mostly wait on the network, so more than one per core keeps
+/// the link busy; compression still runs on the cores.
+pub const TRANSFERS_PER_CPU: usize = 2;
+
+/// Upper bound for `--parallelism auto`, to stay polite to the server
+pub const MAX_AUTO_PARALLELISM: usize = 64;
+
+/// A `--parallelism` value: a fixed count or `auto`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parallelism {
+    /// Derived from the CPU count and bandwidth limit
+    Auto,
+    /// Exactly this many transfers
+    Fixed(usize),
+}
+
+impl Parallelism {
+    /// Number of concurrent transfers to run
+    ///
+    /// A fixed count is kept except that, like `auto`, it is capped to
+    /// what `limiter` can usefully feed.
+    #[must_use]
+    pub fn resolve(self, limiter: Option<&RateLimiter>) -> usize {
+        match self {
+            Self::Auto => adaptive_concurrency(num_cpus::get(), limiter),
+            Self::Fixed(n) => limiter.map_or(n, |limiter| limiter.cap_parallelism(n)),
+        }
+    }
+}
+
+impl fmt::Display for Parallelism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::Fixed(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+impl FromStr for Parallelism {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        match s.parse() {
+            Ok(0) | Err(_) => Err(CliError::InvalidArgument(format!(
+                "--parallelism must be a positive number or 'auto', got '{s}'"
+            ))),
+            Ok(n) => Ok(Self::Fixed(n)),
+        }
+    }
+}
+
+/// Concurrent transfers for `cpus` cores under an optional bandwidth limit
+///
+/// [`TRANSFERS_PER_CPU`] per core, at most [`MAX_AUTO_PARALLELISM`], then
+/// reduced so each connection still gets a useful share of `limiter`.
+#[must_use]
+pub fn adaptive_concurrency(cpus: usize, limiter: Option<&RateLimiter>) -> usize {
+    let by_cpu = cpus
+        .max(1)
+        .saturating_mul(TRANSFERS_PER_CPU)
+        .min(MAX_AUTO_PARALLELISM);
+    limiter.map_or(by_cpu, |limiter| limiter.cap_parallelism(by_cpu))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_parallelism() -> Result<()> {
+        assert_eq!("auto".parse::<Parallelism>()?, Parallelism::Auto);
+        assert_eq!("8".parse::<Parallelism>()?, Parallelism::Fixed(8));
+        assert!("0".parse::<Parallelism>().is_err());
+        assert!("many".parse::<Parallelism>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_adaptive_concurrency() -> Result<()> {
+        assert_eq!(adaptive_concurrency(4, None), 8);
+        assert_eq!(adaptive_concurrency(0, None), TRANSFERS_PER_CPU);
+        assert_eq!(adaptive_concurrency(128, None), MAX_AUTO_PARALLELISM);
+
+        // 10 Mbps feeds five 2 Mbps connections
+        let limiter = RateLimiter::from_mbps(10)?;
+        assert_eq!(adaptive_concurrency(16, Some(&limiter)), 5);
+        assert_eq!(Parallelism::Fixed(3).resolve(Some(&limiter)), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+//! Platform detection
+//!
+//! The normalized Rust target triple of this build, shared by `doctor` and
+//! the `User-Agent` header so both report the platform the same way.
+
+/// C library / ABI this binary was built against (`gnu`, `musl`, `msvc`),
+/// or empty when the target has none
+const TARGET_ENV: &str = if cfg!(target_env = "musl") {
+    "musl"
+} else if cfg!(target_env = "gnu") {
+    "gnu"
+} else if cfg!(target_env = "msvc") {
+    "msvc"
+} else {
+    ""
+};
+
+/// Target triple (`x86_64-unknown-linux-musl`, `aarch64-apple-darwin`) for
+/// an architecture, OS, and environment as reported by
+/// [`std::env::consts`] and `cfg(target_env)`
+///
+/// Linux without a known environment is taken to be glibc.
+#[must_use]
+pub fn target_triple_for(arch: &str, os: &str, env: &str) -> String {
+    match os {
+        "linux" => {
+            let env = if env.is_empty() { "gnu" } else { env };
+            format!("{arch}-unknown-linux-{env}")
+        }
+        "macos" => format!("{arch}-apple-darwin"),
+        "ios" => format!("{arch}-apple-ios"),
+        "windows" => {
+            let env = if env.is_empty() { "msvc" } else { env };
+            format!("{arch}-pc-windows-{env}")
+        }
+        "android" => format!("{arch}-linux-android"),
+        other if env.is_empty() => format!("{arch}-unknown-{other}"),
+        other => format!("{arch}-unknown-{other}-{env}"),
+    }
+}
+
+/// Target triple of this binary
+#[must_use]
+pub fn target_triple() -> String {
+    target_triple_for(std::env::consts::ARCH, std::env::consts::OS, TARGET_ENV)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_triple_mapping() {
+        let cases = [
+            (("x86_64", "linux", "gnu"), "x86_64-unknown-linux-gnu"),
+            (("x86_64", "linux", "musl"), "x86_64-unknown-linux-musl"),
+            (("aarch64", "linux", ""), "aarch64-unknown-linux-gnu"),
+            (("aarch64", "macos", ""), "aarch64-apple-darwin"),
+            (("x86_64", "macos", ""), "x86_64-apple-darwin"),
+            (("x86_64", "windows", "msvc"), "x86_64-pc-windows-msvc"),
+            (("x86_64", "freebsd", ""), "x86_64-unknown-freebsd"),
+        ];
+        for ((arch, os, env), triple) in cases {
+            assert_eq!(target_triple_for(arch, os, env), triple);
+        }
+    }
+
+    #[test]
+    fn test_host_triple_matches_build() {
+        let triple = target_triple();
+        assert!(triple.starts_with(std::env::consts::ARCH));
+        if cfg!(target_env = "musl") {
+            assert!(triple.ends_with("-musl"));
+        }
+    }
+}
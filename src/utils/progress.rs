@@ -1,3 +1,223 @@
 //! Progress tracking and reporting
 //!
 //! Provides progress bars and status reporting for long-running operations.
+
+use crate::error::{CliError, Result};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Format a byte count as a human-readable size (e.g. `1.5 GB`)
+///
+/// Uses binary (1024-based) units, printed with one decimal place above bytes.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for &next in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+
+    format!("{value:.1} {unit}")
+}
+
+/// Parse a human-readable size (`500M`, `2G`, `2GB`, `1024`) into bytes
+///
+/// Units are binary (1024-based), matching [`format_bytes`]; a bare number
+/// is bytes.
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if `s` is not a size.
+pub fn parse_byte_size(s: &str) -> Result<u64> {
+    let invalid =
+        || CliError::InvalidArgument(format!("invalid size '{s}' (expected e.g. 500M, 2G)"));
+    let trimmed = s.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (digits, unit) = trimmed.split_at(split);
+    let value: u64 = digits.parse().map_err(|_| invalid())?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let exponent = match unit.strip_suffix('B').unwrap_or(&unit) {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return Err(invalid()),
+    };
+    value
+        .checked_mul(1024_u64.pow(exponent))
+        .ok_or_else(invalid)
+}
+
+/// A timed phase of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Serialising a store path with `nix-store --dump`
+    Dump,
+    /// Compressing (or decompressing) the NAR
+    Compress,
+    /// Sending or receiving bytes over the network
+    Network,
+}
+
+/// Accumulates per-phase timings for the `--time` summary
+///
+/// Phase durations are summed across all paths, so with parallel transfers
+/// they can exceed the wall-clock total.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferTimings {
+    started: Instant,
+    dump: Duration,
+    compress: Duration,
+    network: Duration,
+    bytes: u64,
+}
+
+impl TransferTimings {
+    /// Start timing a transfer command
+    #[must_use]
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            dump: Duration::ZERO,
+            compress: Duration::ZERO,
+            network: Duration::ZERO,
+            bytes: 0,
+        }
+    }
+
+    /// Add `elapsed` to a phase
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        let slot = match phase {
+            Phase::Dump => &mut self.dump,
+            Phase::Compress => &mut self.compress,
+            Phase::Network => &mut self.network,
+        };
+        *slot += elapsed;
+    }
+
+    /// Run `f`, attributing its duration to `phase`
+    pub fn time<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    /// Count bytes transferred over the network
+    pub const fn add_bytes(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+
+    /// Snapshot the timings, using the time elapsed so far as the total
+    #[must_use]
+    pub fn report(&self) -> TimingReport {
+        TimingReport {
+            dump: self.dump,
+            compress: self.compress,
+            network: self.network,
+            total: self.started.elapsed(),
+            bytes: self.bytes,
+        }
+    }
+}
+
+/// Printable timing breakdown produced by [`TransferTimings::report`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingReport {
+    /// Time spent in `nix-store --dump`
+    pub dump: Duration,
+    /// Time spent compressing/decompressing
+    pub compress: Duration,
+    /// Time spent on network transfer
+    pub network: Duration,
+    /// Wall-clock total
+    pub total: Duration,
+    /// Bytes transferred over the network
+    pub bytes: u64,
+}
+
+impl TimingReport {
+    /// Effective throughput in MB/s over the wall-clock total
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn megabytes_per_sec(&self) -> f64 {
+        let secs = self.total.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes as f64 / (1024.0 * 1024.0) / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Timing breakdown:")?;
+        writeln!(f, "  nix-store dump  {:>9.2}s", self.dump.as_secs_f64())?;
+        writeln!(f, "  compression     {:>9.2}s", self.compress.as_secs_f64())?;
+        writeln!(f, "  network         {:>9.2}s", self.network.as_secs_f64())?;
+        writeln!(f, "  total           {:>9.2}s", self.total.as_secs_f64())?;
+        write!(
+            f,
+            "  throughput      {:>9.1} MB/s ({})",
+            self.megabytes_per_sec(),
+            format_bytes(self.bytes)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn test_parse_byte_size() -> Result<()> {
+        assert_eq!(parse_byte_size("1024")?, 1024);
+        assert_eq!(parse_byte_size("500M")?, 500 * 1024 * 1024);
+        assert_eq!(parse_byte_size("2G")?, 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_byte_size("2gb")?, parse_byte_size("2G")?);
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("1.5G").is_err());
+        assert!(parse_byte_size("3Q").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_timing_report() {
+        let mut timings = TransferTimings::start();
+        timings.add(Phase::Compress, Duration::from_millis(1500));
+        timings.add(Phase::Compress, Duration::from_millis(500));
+        timings.add_bytes(10 * 1024 * 1024);
+
+        let report = TimingReport {
+            total: Duration::from_secs(5),
+            ..timings.report()
+        };
+        assert_eq!(report.compress, Duration::from_secs(2));
+        assert!((report.megabytes_per_sec() - 2.0).abs() < f64::EPSILON);
+        assert!(report.to_string().contains("compression          2.00s"));
+    }
+}
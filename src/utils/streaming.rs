@@ -1,3 +1,81 @@
 //! Streaming utilities
 //!
 //! Efficient streaming for large file transfers.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{self, Write};
+
+/// Writer adapter that SHA-256 hashes and counts every byte passed through it
+///
+/// Hashing the stream as it is written guarantees the digest matches exactly
+/// what reached the inner writer, without reading the data back.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Wrap `inner`
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Bytes written so far
+    pub const fn bytes_written(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Finish hashing, returning the inner writer, digest, and byte count
+    pub fn finish(self) -> (W, [u8; 32], u64) {
+        (self.inner, self.hasher.finalize().into(), self.bytes)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> fmt::Debug for HashingWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashingWriter")
+            .field("bytes", &self.bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashing_writer() -> io::Result<()> {
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"hello ")?;
+        writer.write_all(b"world")?;
+        assert_eq!(writer.bytes_written(), 11);
+
+        let (inner, digest, bytes) = writer.finish();
+        assert_eq!(inner, b"hello world");
+        assert_eq!(bytes, 11);
+        assert_eq!(
+            hex::encode(digest),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! Aligned text tables for command results
+//!
+//! Each column is padded to its widest cell, and sizes and counts are
+//! right-aligned so their digits line up. Styled output (see
+//! [`output::is_styled`]) adds a bold header with a rule under it; plain
+//! output, for pipes and `--no-color`, is the padded columns alone.
+
+use crate::utils::output;
+use std::fmt;
+
+/// Space between columns
+const GAP: &str = "  ";
+
+/// Shown for a value the server did not report
+pub const MISSING: &str = "-";
+
+/// How a column's cells are aligned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    /// Names, paths and other text
+    Left,
+    /// Sizes and counts
+    Right,
+}
+
+/// Rows of cells under a header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Table {
+    columns: Vec<(String, Align)>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Empty table with the given column headers
+    #[must_use]
+    pub fn new(columns: &[(&str, Align)]) -> Self {
+        Self {
+            columns: columns
+                .iter()
+                .map(|(header, align)| ((*header).to_string(), *align))
+                .collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row; missing trailing cells are left blank
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// The table as text, with or without color and the header rule
+    #[must_use]
+    pub fn render(&self, styled: bool) -> String {
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, (header, _))| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|cell| cell.chars().count())
+                    .fold(header.chars().count(), usize::max)
+            })
+            .collect();
+
+        let headers: Vec<&str> = self
+            .columns
+            .iter()
+            .map(|(header, _)| header.as_str())
+            .collect();
+        let mut lines = vec![self.line(&headers, &widths)];
+        if styled {
+            lines[0] = format!("\x1b[1m{}\x1b[0m", lines[0]);
+            let rule: Vec<String> = widths.iter().map(|&width| "─".repeat(width)).collect();
+            lines.push(format!("\x1b[2m{}\x1b[0m", rule.join(GAP)));
+        }
+        for row in &self.rows {
+            let cells: Vec<&str> = (0..self.columns.len())
+                .map(|i| row.get(i).map_or("", String::as_str))
+                .collect();
+            lines.push(self.line(&cells, &widths));
+        }
+        lines.join("\n")
+    }
+
+    /// One line of padded cells, without trailing spaces
+    fn line(&self, cells: &[&str], widths: &[usize]) -> String {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .zip(&self.columns)
+            .map(|((cell, &width), (_, align))| match align {
+                Align::Left => format!("{cell:<width$}"),
+                Align::Right => format!("{cell:>width$}"),
+            })
+            .collect();
+        padded.join(GAP).trim_end().to_string()
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(output::is_styled()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_are_aligned() {
+        let mut table = Table::new(&[
+            ("PATH", Align::Left),
+            ("SIZE", Align::Right),
+            ("BY", Align::Left),
+        ]);
+        table.push(vec![
+            "/nix/store/aaa-hello".into(),
+            "1.5 MB".into(),
+            "ci".into(),
+        ]);
+        table.push(vec!["/nix/store/bbb-glibc-2.39".into(), "12 B".into()]);
+        assert_eq!(
+            table.render(false),
+            "PATH                         SIZE  BY\n\
+             /nix/store/aaa-hello       1.5 MB  ci\n\
+             /nix/store/bbb-glibc-2.39    12 B"
+        );
+
+        let styled = table.render(true);
+        let lines: Vec<&str> = styled.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("\x1b[1mPATH"));
+        assert!(lines[1].contains(&"─".repeat(25)));
+        assert_eq!(lines[2], "/nix/store/aaa-hello       1.5 MB  ci");
+    }
+}
@@ -0,0 +1,205 @@
+//! Bandwidth limiting (`--max-bandwidth`)
+//!
+//! A token bucket shared by every transfer in the process, so the cap
+//! applies to aggregate throughput rather than per connection.
+
+use crate::error::{CliError, Result};
+use futures::{Stream, StreamExt};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Environment variable for `--max-bandwidth` (megabits per second)
+pub const MAX_BANDWIDTH_ENV: &str = "FLAKECACHE_MAX_BANDWIDTH";
+
+/// Bytes per second in one megabit per second
+const BYTES_PER_MBPS: u64 = 125_000;
+
+/// Smallest share of the limit worth opening another connection for
+/// (2 Mbps)
+pub const MIN_CONNECTION_RATE: u64 = 2 * BYTES_PER_MBPS;
+
+/// Process-wide limiter set from `--max-bandwidth`
+static GLOBAL: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Token bucket state
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes available without waiting; negative when in debt
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Shared token-bucket rate limiter
+///
+/// Clones share one bucket. The bucket holds at most one second of
+/// transfer, so short idle periods do not allow a long burst afterwards.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    /// Limit throughput to `bytes_per_sec` (at least 1)
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        Self {
+            bytes_per_sec,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    /// Limit throughput to `mbps` megabits per second
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CliError::InvalidArgument`] if `mbps` is 0.
+    pub fn from_mbps(mbps: u64) -> Result<Self> {
+        if mbps == 0 {
+            return Err(CliError::InvalidArgument(
+                "--max-bandwidth must be greater than 0".to_string(),
+            ));
+        }
+        Ok(Self::new(mbps.saturating_mul(BYTES_PER_MBPS)))
+    }
+
+    /// The configured limit in bytes per second
+    #[must_use]
+    pub const fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Most parallel transfers worth running under this limit
+    ///
+    /// Beyond [`MIN_CONNECTION_RATE`] per connection, extra connections
+    /// only contend for the same budget.
+    #[must_use]
+    pub fn cap_parallelism(&self, requested: usize) -> usize {
+        let useful =
+            usize::try_from(self.bytes_per_sec / MIN_CONNECTION_RATE).unwrap_or(usize::MAX);
+        requested.min(useful.max(1))
+    }
+
+    /// Take `bytes` from the bucket, returning how long to wait before
+    /// sending them
+    ///
+    /// Tokens may go negative, so a chunk larger than the bucket is still
+    /// admitted after a proportionally longer wait.
+    #[allow(clippy::cast_precision_loss)]
+    fn reserve(&self, bytes: usize) -> Duration {
+        let rate = self.bytes_per_sec as f64;
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate) - bytes as f64;
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+
+    /// Wait until `bytes` may be transferred
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Pace a byte stream (e.g. an upload body) through `limiter`
+pub fn throttle_stream<S, T, E>(
+    stream: S,
+    limiter: RateLimiter,
+) -> impl Stream<Item = std::result::Result<T, E>>
+where
+    S: Stream<Item = std::result::Result<T, E>>,
+    T: AsRef<[u8]>,
+{
+    stream.then(move |chunk| {
+        let limiter = limiter.clone();
+        async move {
+            if let Ok(bytes) = &chunk {
+                limiter.acquire(bytes.as_ref().len()).await;
+            }
+            chunk
+        }
+    })
+}
+
+/// Megabits per second from `--max-bandwidth`, falling back to
+/// `FLAKECACHE_MAX_BANDWIDTH`
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if the environment variable is
+/// not a whole number.
+pub fn resolve_mbps(flag: Option<u64>) -> Result<Option<u64>> {
+    if flag.is_some() {
+        return Ok(flag);
+    }
+    std::env::var(MAX_BANDWIDTH_ENV)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value.trim().parse().map_err(|_| {
+                CliError::InvalidArgument(format!(
+                    "{MAX_BANDWIDTH_ENV} must be a whole number of Mbps, got '{value}'"
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Apply `limiter` to every transfer for the rest of the process
+///
+/// Called once at startup; later calls are ignored.
+pub fn set_global(limiter: RateLimiter) {
+    let _ = GLOBAL.set(limiter);
+}
+
+/// The process-wide limiter, if `--max-bandwidth` was given
+#[must_use]
+pub fn global() -> Option<RateLimiter> {
+    GLOBAL.get().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_allows_one_second_burst() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.reserve(600), Duration::ZERO);
+
+        // 400 tokens left; 1400 more puts the bucket one second in debt
+        let wait = limiter.reserve(1400);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_from_mbps() -> Result<()> {
+        assert_eq!(RateLimiter::from_mbps(8)?.bytes_per_sec(), 1_000_000);
+        assert!(RateLimiter::from_mbps(0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cap_parallelism() -> Result<()> {
+        // 10 Mbps leaves room for five 2 Mbps connections
+        let limiter = RateLimiter::from_mbps(10)?;
+        assert_eq!(limiter.cap_parallelism(200), 5);
+        assert_eq!(limiter.cap_parallelism(3), 3);
+        assert_eq!(RateLimiter::from_mbps(1)?.cap_parallelism(8), 1);
+        Ok(())
+    }
+}
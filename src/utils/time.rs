@@ -0,0 +1,292 @@
+//! Human-friendly durations and timestamps
+//!
+//! Parses and prints the compact `30d` / `12h` / `90m` form used by
+//! command-line flags such as `--older-than`, the relative-or-absolute
+//! times taken by `--since` / `--until`, and server timestamps as both an
+//! absolute time and an age (`2024-05-14 09:30 UTC (3 days ago)`).
+
+use crate::error::{CliError, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Unit suffixes and their length in seconds, largest first
+const UNITS: [(char, u64); 5] = [
+    ('w', 7 * 24 * 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// Parse `<n><unit>` where unit is `s`, `m`, `h`, `d`, or `w`
+///
+/// A bare number is taken as seconds.
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] for anything else, or a zero
+/// duration.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let invalid = || {
+        CliError::InvalidArgument(format!(
+            "invalid duration '{s}' (expected e.g. 30d, 12h, 90m, 45s)"
+        ))
+    };
+
+    let s = s.trim();
+    let (number, unit_secs) = match s.chars().last() {
+        Some(c) if c.is_ascii_digit() => (s, 1),
+        Some(c) => {
+            let (_, secs) = UNITS
+                .iter()
+                .find(|(unit, _)| *unit == c.to_ascii_lowercase())
+                .ok_or_else(invalid)?;
+            (&s[..s.len() - c.len_utf8()], *secs)
+        }
+        None => return Err(invalid()),
+    };
+    let secs = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit_secs))
+        .filter(|&secs| secs > 0)
+        .ok_or_else(invalid)?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Format a duration in the largest unit that divides it exactly
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    UNITS
+        .iter()
+        .find(|(_, unit_secs)| secs > 0 && secs.is_multiple_of(*unit_secs))
+        .map_or_else(
+            || format!("{secs}s"),
+            |(unit, unit_secs)| format!("{}{unit}", secs / unit_secs),
+        )
+}
+
+/// Parse a `--since` / `--until` bound relative to the current time
+///
+/// See [`parse_time_bound_at`] for the accepted forms.
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if `s` is not a duration or date.
+pub fn parse_time_bound(s: &str) -> Result<DateTime<Utc>> {
+    parse_time_bound_at(s, Utc::now())
+}
+
+/// Parse a point in time: a duration before `now` (`7d`, `12h`), a date
+/// (`2024-05-14`, midnight UTC), or an RFC 3339 timestamp
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if `s` is none of these.
+pub fn parse_time_bound_at(s: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(ago) = parse_duration(s) {
+        return i64::try_from(ago.as_secs())
+            .ok()
+            .and_then(|secs| now.timestamp().checked_sub(secs))
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .ok_or_else(|| CliError::InvalidArgument(format!("'{s}' is too far in the past")));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        if let Some(midnight) = date.and_hms_opt(0, 0, 0) {
+            return Ok(midnight.and_utc());
+        }
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| {
+            CliError::InvalidArgument(format!(
+                "invalid time '{s}' (expected e.g. 7d, 12h, 2024-05-14, \
+                 or 2024-05-14T09:30:00Z)"
+            ))
+        })
+}
+
+/// Units of [`format_age`] and their length in seconds, largest first
+const AGE_UNITS: [(&str, u64); 6] = [
+    ("year", 365 * 24 * 60 * 60),
+    ("month", 30 * 24 * 60 * 60),
+    ("week", 7 * 24 * 60 * 60),
+    ("day", 24 * 60 * 60),
+    ("hour", 60 * 60),
+    ("minute", 60),
+];
+
+/// Time zone that timestamps are printed in (`--utc` / `--local`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisplayZone {
+    /// UTC, as the server reports them
+    #[default]
+    Utc,
+    /// The local time zone of this machine
+    Local,
+}
+
+impl DisplayZone {
+    /// Build the zone from the `--local` flag
+    #[must_use]
+    pub const fn from_flags(local: bool) -> Self {
+        if local {
+            Self::Local
+        } else {
+            Self::Utc
+        }
+    }
+}
+
+/// Process-wide `--local`, set once from the command line
+static LOCAL_TIME: AtomicBool = AtomicBool::new(false);
+
+/// Print timestamps in `zone` for the rest of the process
+pub fn set_display_zone(zone: DisplayZone) {
+    LOCAL_TIME.store(zone == DisplayZone::Local, Ordering::Relaxed);
+}
+
+/// Current display zone
+#[must_use]
+pub fn display_zone() -> DisplayZone {
+    DisplayZone::from_flags(LOCAL_TIME.load(Ordering::Relaxed))
+}
+
+/// Parse a server timestamp: RFC 3339, or ISO 8601 without an offset
+/// (taken as UTC)
+#[must_use]
+pub fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").map(|time| time.and_utc())
+        })
+        .ok()
+}
+
+/// A server timestamp in the [`display_zone`] with its age, such as
+/// `2024-05-14 09:30 UTC (3 days ago)`
+///
+/// A timestamp that cannot be parsed is returned unchanged.
+#[must_use]
+pub fn format_timestamp(s: &str) -> String {
+    format_timestamp_at(s, Utc::now(), display_zone())
+}
+
+/// [`format_timestamp`] with the age relative to `now`, in `zone`
+#[must_use]
+pub fn format_timestamp_at(s: &str, now: DateTime<Utc>, zone: DisplayZone) -> String {
+    let Some(time) = parse_timestamp(s) else {
+        return s.to_string();
+    };
+    let absolute = match zone {
+        DisplayZone::Utc => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        DisplayZone::Local => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M %:z")
+            .to_string(),
+    };
+    format!("{absolute} ({})", format_age(time, now))
+}
+
+/// How long before (or after) `now` a time is, in its largest whole unit:
+/// `3 days ago`, `in 2 hours`, or `just now` under a minute
+#[must_use]
+pub fn format_age(time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let secs = now.timestamp() - time.timestamp();
+    let Some((unit, count)) = AGE_UNITS
+        .iter()
+        .find(|(_, unit_secs)| secs.unsigned_abs() >= *unit_secs)
+        .map(|(unit, unit_secs)| (unit, secs.unsigned_abs() / unit_secs))
+    else {
+        return "just now".to_string();
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    if secs > 0 {
+        format!("{count} {unit}{plural} ago")
+    } else {
+        format!("in {count} {unit}{plural}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() -> Result<()> {
+        assert_eq!(parse_duration("30d")?.as_secs(), 30 * 86_400);
+        assert_eq!(parse_duration("12h")?.as_secs(), 12 * 3_600);
+        assert_eq!(parse_duration("2W")?.as_secs(), 14 * 86_400);
+        assert_eq!(parse_duration("45")?.as_secs(), 45);
+        for bad in ["", "d", "0d", "10y", "-1d", "1.5h"] {
+            assert!(parse_duration(bad).is_err(), "{bad}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_duration_round_trips() -> Result<()> {
+        for text in ["30d", "2w", "36h", "90m", "45s"] {
+            assert_eq!(format_duration(parse_duration(text)?), text);
+        }
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_time_bound_forms() -> Result<()> {
+        let now = parse_time_bound_at("2024-05-14T12:00:00Z", Utc::now())?;
+        assert_eq!(
+            parse_time_bound_at("12h", now)?,
+            parse_time_bound_at("2024-05-14", now)?
+        );
+        assert_eq!(
+            parse_time_bound_at("1w", now)?.timestamp(),
+            now.timestamp() - 7 * 86_400
+        );
+        for bad in ["", "yesterday", "2024-13-01", "0d"] {
+            assert!(parse_time_bound_at(bad, now).is_err(), "{bad}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_timestamp() -> Result<()> {
+        let now = parse_time_bound_at("2024-05-17T12:00:00Z", Utc::now())?;
+        assert_eq!(
+            format_timestamp_at("2024-05-14T09:30:15Z", now, DisplayZone::Utc),
+            "2024-05-14 09:30 UTC (3 days ago)"
+        );
+        assert_eq!(
+            format_timestamp_at("2024-05-17T11:00:00", now, DisplayZone::Utc),
+            "2024-05-17 11:00 UTC (1 hour ago)"
+        );
+        // Unparseable times are shown as the server sent them
+        assert_eq!(
+            format_timestamp_at("yesterday", now, DisplayZone::Local),
+            "yesterday"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_age() -> Result<()> {
+        let now = parse_time_bound_at("2024-05-17T12:00:00Z", Utc::now())?;
+        let age =
+            |ago: &str| -> Result<String> { Ok(format_age(parse_time_bound_at(ago, now)?, now)) };
+        assert_eq!(age("30s")?, "just now");
+        assert_eq!(age("90m")?, "1 hour ago");
+        assert_eq!(age("2w")?, "2 weeks ago");
+        assert_eq!(age("400d")?, "1 year ago");
+        assert_eq!(
+            format_age(now, parse_time_bound_at("2d", now)?),
+            "in 2 days"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Chrome trace output (`--profile-output`, `FLAKECACHE_TRACE=chrome`)
+//!
+//! Records a span for each Nix command, compression, and network phase and
+//! writes them in the Trace Event format, so a slow push can be loaded into
+//! `chrome://tracing` or Perfetto to see where the time went and how much
+//! of it overlapped.
+
+use crate::error::{CliError, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Instant;
+
+/// Environment variable enabling tracing; the only mode is `chrome`
+pub const TRACE_ENV: &str = "FLAKECACHE_TRACE";
+
+/// File written when tracing is enabled through [`TRACE_ENV`] alone
+pub const DEFAULT_TRACE_FILE: &str = "flakecache-trace.json";
+
+/// Process-wide tracer, set when tracing is enabled
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+/// Source of small, stable per-thread track numbers
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: u64 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+}
+
+/// One complete (`"ph": "X"`) event in the Trace Event format
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    /// Start, in microseconds since tracing was enabled
+    ts: u64,
+    /// Duration in microseconds
+    dur: u64,
+    pid: u32,
+    tid: u64,
+    args: BTreeMap<&'static str, String>,
+}
+
+/// Top-level document of a Chrome trace file
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceFile<'a> {
+    trace_events: &'a [TraceEvent],
+    display_time_unit: &'static str,
+}
+
+/// Collected spans and where to write them
+#[derive(Debug)]
+struct Tracer {
+    output: PathBuf,
+    epoch: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+impl Tracer {
+    fn new(output: PathBuf) -> Self {
+        Self {
+            output,
+            epoch: Instant::now(),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record a span that ran from `open.started` until now
+    fn record(&self, open: OpenSpan) {
+        let micros = |d: std::time::Duration| u64::try_from(d.as_micros()).unwrap_or(u64::MAX);
+        let mut args = BTreeMap::new();
+        if !open.detail.is_empty() {
+            let _ = args.insert("detail", open.detail);
+        }
+        let event = TraceEvent {
+            name: open.name,
+            cat: open.cat,
+            ph: "X",
+            ts: micros(open.started.saturating_duration_since(self.epoch)),
+            dur: micros(open.started.elapsed()),
+            pid: std::process::id(),
+            tid: TID.with(|tid| *tid),
+            args,
+        };
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event);
+    }
+
+    /// Serialize the events recorded so far
+    fn to_json(&self) -> Result<Vec<u8>> {
+        let events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(serde_json::to_vec(&TraceFile {
+            trace_events: &events,
+            display_time_unit: "ms",
+        })?)
+    }
+}
+
+/// A span in progress; recorded when dropped
+#[derive(Debug)]
+struct OpenSpan {
+    cat: &'static str,
+    name: String,
+    detail: String,
+    started: Instant,
+}
+
+/// Guard returned by [`span`]
+///
+/// Does nothing unless tracing is enabled.
+#[derive(Debug)]
+#[must_use = "a span is recorded when the guard is dropped"]
+pub struct Span {
+    open: Option<OpenSpan>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let (Some(open), Some(tracer)) = (self.open.take(), TRACER.get()) {
+            tracer.record(open);
+        }
+    }
+}
+
+/// Start a span in category `cat` (`nix`, `compress`, `network`)
+///
+/// `detail`, typically the store path, is shown in the event's arguments.
+pub fn span(cat: &'static str, name: &str, detail: &str) -> Span {
+    Span {
+        open: TRACER.get().map(|_| OpenSpan {
+            cat,
+            name: name.to_string(),
+            detail: detail.to_string(),
+            started: Instant::now(),
+        }),
+    }
+}
+
+/// Where to write the trace: `--profile-output`, else
+/// [`DEFAULT_TRACE_FILE`] when `FLAKECACHE_TRACE=chrome`
+///
+/// # Errors
+///
+/// Returns [`CliError::InvalidArgument`] if `FLAKECACHE_TRACE` is set to
+/// anything other than `chrome`.
+pub fn resolve_output(flag: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(path) = flag {
+        return Ok(Some(path.to_path_buf()));
+    }
+    match std::env::var(TRACE_ENV).ok().as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(mode) if mode.eq_ignore_ascii_case("chrome") => {
+            Ok(Some(PathBuf::from(DEFAULT_TRACE_FILE)))
+        }
+        Some(mode) => Err(CliError::InvalidArgument(format!(
+            "{TRACE_ENV} must be 'chrome', got '{mode}'"
+        ))),
+    }
+}
+
+/// Record spans for the rest of the process, to be written to `output`
+///
+/// Called once at startup; later calls are ignored.
+pub fn enable(output: PathBuf) {
+    let _ = TRACER.set(Tracer::new(output));
+}
+
+/// Write the recorded trace, if tracing is enabled
+///
+/// # Returns
+///
+/// The file written, or `None` when tracing is off
+///
+/// # Errors
+///
+/// Returns a serialization error, or [`CliError::FileError`] if the file
+/// cannot be written.
+pub fn finish() -> Result<Option<PathBuf>> {
+    let Some(tracer) = TRACER.get() else {
+        return Ok(None);
+    };
+    std::fs::write(&tracer.output, tracer.to_json()?).map_err(|e| CliError::FileError {
+        path: tracer.output.clone(),
+        reason: e.to_string(),
+    })?;
+    Ok(Some(tracer.output.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_complete_events() {
+        let tracer = Tracer::new(PathBuf::from("trace.json"));
+        let started = Instant::now();
+        tracer.record(OpenSpan {
+            cat: "compress",
+            name: "dump+compress".to_string(),
+            detail: "/nix/store/aaa-hello".to_string(),
+            started,
+        });
+
+        let events = tracer
+            .events
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].ph, "X");
+        assert_eq!(events[0].cat, "compress");
+        assert_eq!(
+            events[0].args.get("detail").map(String::as_str),
+            Some("/nix/store/aaa-hello")
+        );
+    }
+
+    #[test]
+    fn test_flag_overrides_env() -> Result<()> {
+        assert_eq!(
+            resolve_output(Some(Path::new("push.json")))?,
+            Some(PathBuf::from("push.json"))
+        );
+        Ok(())
+    }
+}